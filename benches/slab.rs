@@ -0,0 +1,263 @@
+//! Allocation throughput and latency of the `ZoneAllocator`, compared to `std::alloc`.
+//!
+//! The pages come from the system allocator, so the benchmarks have to be built
+//! without the `theseus` feature:
+//!
+//! ```text
+//! cargo bench --no-default-features
+//! ```
+//!
+//! Compare `large_heap` with and without the `prefetch` feature to see the effect of the
+//! prefetches on a heap whose meta-data doesn't fit in the caches:
+//!
+//! ```text
+//! cargo bench --no-default-features --bench slab -- large_heap --save-baseline plain
+//! cargo bench --no-default-features --features prefetch --bench slab -- large_heap --baseline plain
+//! ```
+use std::alloc::{self, Layout};
+use std::ptr::NonNull;
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use slabmalloc::*;
+
+/// Objects that are allocated (and then freed again) in one iteration.
+const BATCH: usize = 64;
+
+/// Hands out 8 KiB aligned regions of the system allocator as pages.
+struct Pager {
+    pages: Vec<*mut u8>,
+}
+
+impl Pager {
+    const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(ObjectPage8k::SIZE, ObjectPage8k::SIZE) };
+
+    fn new() -> Pager {
+        Pager { pages: Vec::new() }
+    }
+
+    fn allocate_page(&mut self) -> MappedPages {
+        let r = unsafe { alloc::alloc(Pager::LAYOUT) };
+        let region = NonNull::new(r as *mut [u8; ObjectPage8k::SIZE]).expect("Host is out of memory");
+        self.pages.push(r);
+        unsafe { MappedPages::from_raw(region) }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        for ptr in self.pages.drain(..) {
+            unsafe { alloc::dealloc(ptr, Pager::LAYOUT) };
+        }
+    }
+}
+
+/// Allocates from `zone`, refills it with a new page if it runs out of memory.
+#[inline]
+fn allocate(zone: &mut ZoneAllocator, pager: &mut Pager, layout: Layout) -> NonNull<u8> {
+    match zone.allocate(layout) {
+        Ok(ptr) => ptr,
+        Err(AllocationError::OutOfMemory) => {
+            zone.refill(layout, pager.allocate_page()).expect("Can't refill");
+            zone.allocate(layout).expect("Can't allocate after refill")
+        }
+        Err(e) => panic!("Can't allocate {:?}: {}", layout, e),
+    }
+}
+
+/// A zone using `policy` that already has the pages for a batch of `layout`,
+/// so the measurements don't include the refills.
+fn warm_zone<'a>(pager: &mut Pager, layout: Layout, policy: PageSelectionPolicy) -> ZoneAllocator<'a> {
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_page_selection_policy(policy);
+    let mut objects = Vec::with_capacity(BATCH);
+    for _ in 0..BATCH {
+        objects.push(allocate(&mut zone, pager, layout));
+    }
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    zone
+}
+
+const POLICIES: [(&str, PageSelectionPolicy); 2] = [
+    ("first-fit", PageSelectionPolicy::FirstFit),
+    ("fullest-first", PageSelectionPolicy::FullestFirst),
+];
+
+/// Allocates a batch of objects and frees every other one first,
+/// so the partial pages actually differ between the policies.
+fn size_classes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("allocate_deallocate");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for &size in ZoneAllocator::BASE_ALLOC_SIZES.iter() {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        for &(name, policy) in POLICIES.iter() {
+            let mut pager = Pager::new();
+            let mut zone = warm_zone(&mut pager, layout, policy);
+            let mut objects = Vec::with_capacity(BATCH);
+            group.bench_with_input(BenchmarkId::new(name, size), &layout, |b, &layout| {
+                b.iter(|| {
+                    for _ in 0..BATCH {
+                        objects.push(allocate(&mut zone, &mut pager, black_box(layout)));
+                    }
+                    for ptr in objects.iter().step_by(2).chain(objects.iter().skip(1).step_by(2)) {
+                        zone.deallocate(*ptr, layout).unwrap();
+                    }
+                    objects.clear();
+                })
+            });
+        }
+
+        let mut objects = Vec::with_capacity(BATCH);
+        group.bench_with_input(BenchmarkId::new("std", size), &layout, |b, &layout| {
+            b.iter(|| {
+                for _ in 0..BATCH {
+                    objects.push(unsafe { alloc::alloc(black_box(layout)) });
+                }
+                for ptr in objects.iter().step_by(2).chain(objects.iter().skip(1).step_by(2)) {
+                    unsafe { alloc::dealloc(*ptr, layout) };
+                }
+                objects.clear();
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The 99th percentile of single allocations, reported as the time per iteration.
+///
+/// Every iteration includes the refills a growing heap needs, these are the outliers.
+fn tail_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("allocate_p99");
+    for &size in ZoneAllocator::BASE_ALLOC_SIZES.iter() {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        for &(name, policy) in POLICIES.iter() {
+            group.bench_with_input(BenchmarkId::new(name, size), &layout, |b, &layout| {
+                b.iter_custom(|iters| {
+                    let mut pager = Pager::new();
+                    let mut zone = ZoneAllocator::new(0);
+                    zone.set_page_selection_policy(policy);
+                    let mut objects = Vec::with_capacity(iters as usize);
+                    let mut latencies = Vec::with_capacity(iters as usize);
+                    for _ in 0..iters {
+                        let start = Instant::now();
+                        objects.push(allocate(&mut zone, &mut pager, black_box(layout)));
+                        latencies.push(start.elapsed());
+                    }
+                    for ptr in objects {
+                        zone.deallocate(ptr, layout).unwrap();
+                    }
+                    percentile(&mut latencies, 99) * iters as u32
+                })
+            });
+        }
+
+        group.bench_with_input(BenchmarkId::new("std", size), &layout, |b, &layout| {
+            b.iter_custom(|iters| {
+                let mut objects = Vec::with_capacity(iters as usize);
+                let mut latencies = Vec::with_capacity(iters as usize);
+                for _ in 0..iters {
+                    let start = Instant::now();
+                    objects.push(unsafe { alloc::alloc(black_box(layout)) });
+                    latencies.push(start.elapsed());
+                }
+                for ptr in objects {
+                    unsafe { alloc::dealloc(ptr, layout) };
+                }
+                percentile(&mut latencies, 99) * iters as u32
+            })
+        });
+    }
+    group.finish();
+}
+
+fn percentile(latencies: &mut [Duration], p: usize) -> Duration {
+    latencies.sort_unstable();
+    latencies[(latencies.len() - 1) * p / 100]
+}
+
+/// Allocations served by a `ThreadCache` in front of the zone against the zone alone.
+fn thread_cache(c: &mut Criterion) {
+    let mut group = c.benchmark_group("thread_cache");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    for &size in ZoneAllocator::BASE_ALLOC_SIZES[..THREAD_CACHE_BINS].iter() {
+        let layout = Layout::from_size_align(size, size).unwrap();
+        let mut pager = Pager::new();
+        let mut zone = warm_zone(&mut pager, layout, PageSelectionPolicy::FirstFit);
+        let mut cache = ThreadCache::new();
+        let mut objects = Vec::with_capacity(BATCH);
+        group.bench_with_input(BenchmarkId::new("cache", size), &layout, |b, &layout| {
+            b.iter(|| {
+                for _ in 0..BATCH {
+                    let ptr = match cache.allocate(black_box(layout)) {
+                        Some(ptr) => ptr,
+                        None => {
+                            cache.refill_from(&mut zone).unwrap();
+                            cache.allocate(layout).unwrap_or_else(|| allocate(&mut zone, &mut pager, layout))
+                        }
+                    };
+                    objects.push(ptr);
+                }
+                for ptr in objects.drain(..) {
+                    if !cache.deallocate(ptr, layout) {
+                        zone.deallocate(ptr, layout).unwrap();
+                    }
+                }
+            })
+        });
+        cache.flush(&mut zone).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("zone", size), &layout, |b, &layout| {
+            b.iter(|| {
+                for _ in 0..BATCH {
+                    objects.push(allocate(&mut zone, &mut pager, black_box(layout)));
+                }
+                for ptr in objects.drain(..) {
+                    zone.deallocate(ptr, layout).unwrap();
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Number of pages of the `large_heap` benchmark (32 MiB, 1 MiB of bitfields).
+const LARGE_HEAP_PAGES: usize = 4096;
+
+/// Allocations on a heap with many partial pages that have a single free object
+/// at their end: the fullest-first policy looks at the bitfield of every page and
+/// the allocation scans a page's bitfield up to its last word, so most of the time
+/// goes to cache misses on the meta-data.
+fn large_heap(c: &mut Criterion) {
+    let mut group = c.benchmark_group("large_heap");
+    for &size in ZoneAllocator::BASE_ALLOC_SIZES[..2].iter() {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let mut pager = Pager::new();
+        let mut zone = ZoneAllocator::new(0);
+        zone.set_page_selection_policy(PageSelectionPolicy::FullestFirst);
+        let mut last_in_page = Vec::with_capacity(LARGE_HEAP_PAGES);
+        for _ in 0..LARGE_HEAP_PAGES {
+            zone.refill(layout, pager.allocate_page()).expect("Can't refill");
+            let mut last = None;
+            while let Ok(ptr) = zone.allocate(layout) {
+                last = Some(ptr);
+            }
+            last_in_page.push(last.expect("Page can't hold an object"));
+        }
+        for ptr in last_in_page {
+            zone.deallocate(ptr, layout).unwrap();
+        }
+
+        group.bench_with_input(BenchmarkId::new("fullest-first", size), &layout, |b, &layout| {
+            b.iter(|| {
+                let ptr = zone.allocate(black_box(layout)).unwrap();
+                zone.deallocate(ptr, layout).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, size_classes, tail_latency, thread_cache, large_heap);
+criterion_main!(benches);
@@ -1,7 +1,13 @@
 //! A minimal example that implements the GlobalAlloc trait.
+//!
+//! The pages come from the system allocator, so the example has to be built
+//! without the `theseus` feature:
+//!
+//! ```text
+//! cargo run --no-default-features --features log --example global_alloc
+//! ```
 
 use core::alloc::{GlobalAlloc, Layout};
-use core::mem::transmute;
 use core::ptr::{self, NonNull};
 use slabmalloc::*;
 use spin::Mutex;
@@ -11,18 +17,18 @@ use std::alloc::System;
 ///
 /// It's a ZoneAllocator wrapped inside a Mutex.
 #[global_allocator]
-static SLAB_ALLOC: SafeZoneAllocator = SafeZoneAllocator(Mutex::new(ZoneAllocator::new()));
+static SLAB_ALLOC: SafeZoneAllocator = SafeZoneAllocator(Mutex::new(ZoneAllocator::new(0)));
 
 /// To use a ZoneAlloactor we require a lower-level allocator
 /// (not provided by this crate) that can supply the allocator
-/// with backing memory for `LargeObjectPage` and `ObjectPage` structs.
+/// with backing memory for the pages of its base and large size classes.
 ///
 /// In our dummy implementation we just rely on the OS system allocator `alloc::System`.
 struct Pager;
 
 impl Pager {
-    const BASE_PAGE_SIZE: usize = 4096;
-    const LARGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+    const BASE_PAGE_SIZE: usize = ObjectPage8k::SIZE;
+    const LARGE_PAGE_SIZE: usize = ObjectPage32k::SIZE;
 
     /// Allocates a given `page_size`.
     fn alloc_page(&mut self, page_size: usize) -> Option<*mut u8> {
@@ -50,42 +56,37 @@ impl Pager {
         unsafe { System.dealloc(ptr, layout) };
     }
 
-    /// Allocates a new ObjectPage from the System.
-    fn allocate_page(&mut self) -> Option<&'static mut ObjectPage<'static>> {
-        self.alloc_page(Pager::BASE_PAGE_SIZE)
-            .map(|r| unsafe { transmute(r as usize) })
+    /// Allocates a page for the base size classes from the System.
+    fn allocate_page(&mut self) -> Option<MappedPages> {
+        self.alloc_page(Pager::BASE_PAGE_SIZE).map(|r| unsafe {
+            MappedPages::from_raw(NonNull::new_unchecked(r as *mut [u8; ObjectPage8k::SIZE]))
+        })
     }
 
-    /// Release a ObjectPage back to the System.
+    /// Release a page of the base size classes back to the System.
     #[allow(unused)]
-    fn release_page(&mut self, p: &'static mut ObjectPage<'static>) {
-        self.dealloc_page(p as *const ObjectPage as *mut u8, Pager::BASE_PAGE_SIZE);
+    fn release_page(&mut self, mp: MappedPages) {
+        self.dealloc_page(mp.as_ptr(), Pager::BASE_PAGE_SIZE);
     }
 
-    /// Allocates a new LargeObjectPage from the system.
-    fn allocate_large_page(&mut self) -> Option<&'static mut LargeObjectPage<'static>> {
-        self.alloc_page(Pager::LARGE_PAGE_SIZE)
-            .map(|r| unsafe { transmute(r as usize) })
+    /// Allocates a page for the large size classes from the system.
+    fn allocate_large_page(&mut self) -> Option<MappedPages> {
+        self.alloc_page(Pager::LARGE_PAGE_SIZE).map(|r| unsafe {
+            MappedPages::from_raw_pages(NonNull::new_unchecked(r as *mut [u8; ObjectPage32k::SIZE]))
+        })
     }
 
-    /// Release a LargeObjectPage back to the System.
+    /// Release a page of the large size classes back to the System.
     #[allow(unused)]
-    fn release_large_page(&mut self, p: &'static mut LargeObjectPage<'static>) {
-        self.dealloc_page(
-            p as *const LargeObjectPage as *mut u8,
-            Pager::LARGE_PAGE_SIZE,
-        );
+    fn release_large_page(&mut self, mp: MappedPages) {
+        self.dealloc_page(mp.as_ptr(), Pager::LARGE_PAGE_SIZE);
     }
 }
 
-/// A pager for GlobalAlloc.
-static mut PAGER: Pager = Pager;
-
 /// A SafeZoneAllocator that wraps the ZoneAllocator in a Mutex.
 ///
 /// Note: This is not very scalable since we use a single big lock
-/// around the allocator. There are better ways make the ZoneAllocator
-/// thread-safe directly, but they are not implemented yet.
+/// around the allocator, see `ShardedZoneAllocator` for a lock per size class.
 pub struct SafeZoneAllocator(Mutex<ZoneAllocator<'static>>);
 
 unsafe impl GlobalAlloc for SafeZoneAllocator {
@@ -94,44 +95,33 @@ unsafe impl GlobalAlloc for SafeZoneAllocator {
             Pager::BASE_PAGE_SIZE => {
                 // Best to use the underlying backend directly to allocate pages
                 // to avoid fragmentation
-                PAGER.allocate_page().expect("Can't allocate page?") as *mut _ as *mut u8
+                Pager.alloc_page(Pager::BASE_PAGE_SIZE).expect("Can't allocate page?")
             }
             Pager::LARGE_PAGE_SIZE => {
                 // Best to use the underlying backend directly to allocate large
                 // to avoid fragmentation
-                PAGER.allocate_large_page().expect("Can't allocate page?") as *mut _ as *mut u8
+                Pager.alloc_page(Pager::LARGE_PAGE_SIZE).expect("Can't allocate page?")
             }
             0..=ZoneAllocator::MAX_ALLOC_SIZE => {
                 let mut zone_allocator = self.0.lock();
                 match zone_allocator.allocate(layout) {
                     Ok(nptr) => nptr.as_ptr(),
                     Err(AllocationError::OutOfMemory) => {
-                        if layout.size() <= ZoneAllocator::MAX_BASE_ALLOC_SIZE {
-                            PAGER.allocate_page().map_or(ptr::null_mut(), |page| {
-                                zone_allocator
-                                    .refill(layout, page)
-                                    .expect("Could not refill?");
-                                zone_allocator
-                                    .allocate(layout)
-                                    .expect("Should succeed after refill")
-                                    .as_ptr()
-                            })
-                        } else {
-                            // layout.size() <= ZoneAllocator::MAX_ALLOC_SIZE
-                            PAGER
-                                .allocate_large_page()
-                                .map_or(ptr::null_mut(), |large_page| {
-                                    zone_allocator
-                                        .refill_large(layout, large_page)
-                                        .expect("Could not refill?");
-                                    zone_allocator
-                                        .allocate(layout)
-                                        .expect("Should succeed after refill")
-                                        .as_ptr()
-                                })
-                        }
+                        let page = match ZoneAllocator::size_class_index(layout) {
+                            Some(idx) if idx < ZoneAllocator::MAX_BASE_SIZE_CLASSES => Pager.allocate_page(),
+                            _ => Pager.allocate_large_page(),
+                        };
+                        page.map_or(ptr::null_mut(), |page| {
+                            zone_allocator
+                                .refill(layout, page)
+                                .expect("Could not refill?");
+                            zone_allocator
+                                .allocate(layout)
+                                .expect("Should succeed after refill")
+                                .as_ptr()
+                        })
                     }
-                    Err(AllocationError::InvalidLayout) => panic!("Can't allocate this size"),
+                    Err(e) => panic!("Can't allocate this size: {:?}", e),
                 }
             }
             _ => unimplemented!("Can't handle it, probably needs another allocator."),
@@ -153,7 +143,7 @@ unsafe impl GlobalAlloc for SafeZoneAllocator {
                 }
 
                 // An proper reclamation strategy could be implemented here
-                // to release empty pages back from the ZoneAllocator to the PAGER
+                // to release empty pages back from the ZoneAllocator to the Pager
             }
             _ => unimplemented!("Can't handle it, probably needs another allocator."),
         }
@@ -161,7 +151,7 @@ unsafe impl GlobalAlloc for SafeZoneAllocator {
 }
 
 fn main() {
-    let _r = env_logger::init();
+    env_logger::init();
 
     let mut v1: Vec<u64> = Vec::with_capacity(1024);
     for i in 0..2048 {
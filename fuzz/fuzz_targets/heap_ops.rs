@@ -0,0 +1,142 @@
+//! Interprets the input as a sequence of heap operations on a `ZoneAllocator`
+//! and checks every step against a model of the live objects.
+//!
+//! Run with `cargo fuzz run heap_ops` from the crate root.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use core::alloc::Layout;
+use core::ptr::{self, NonNull};
+use std::alloc;
+use std::collections::HashSet;
+
+use slabmalloc::*;
+
+/// Hands out 8 KiB aligned regions of the system allocator as pages.
+struct Pager {
+    pages: HashSet<*mut u8>,
+}
+
+impl Pager {
+    const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(ObjectPage8k::SIZE, ObjectPage8k::SIZE) };
+
+    fn allocate_page(&mut self) -> MappedPages {
+        let r = unsafe { alloc::alloc(Pager::LAYOUT) };
+        let region = NonNull::new(r as *mut [u8; ObjectPage8k::SIZE]).expect("Host is out of memory");
+        self.pages.insert(r);
+        unsafe { MappedPages::from_raw(region) }
+    }
+
+    fn release_page(&mut self, mp: MappedPages) {
+        for offset in (0..mp.size_in_bytes()).step_by(ObjectPage8k::SIZE) {
+            let ptr = unsafe { mp.as_ptr().add(offset) };
+            assert!(self.pages.remove(&ptr), "The zone returned a page it didn't get");
+            unsafe { alloc::dealloc(ptr, Pager::LAYOUT) };
+        }
+    }
+}
+
+impl Drop for Pager {
+    fn drop(&mut self) {
+        for ptr in self.pages.drain() {
+            unsafe { alloc::dealloc(ptr, Pager::LAYOUT) };
+        }
+    }
+}
+
+/// Reads the operations from the fuzzer input.
+struct Input<'d> {
+    data: &'d [u8],
+}
+
+impl<'d> Input<'d> {
+    fn byte(&mut self) -> Option<u8> {
+        let (first, rest) = self.data.split_first()?;
+        self.data = rest;
+        Some(*first)
+    }
+
+    fn size(&mut self) -> Option<usize> {
+        let size = u16::from_le_bytes([self.byte()?, self.byte()?]) as usize;
+        Some(size % ZoneAllocator::MAX_BASE_ALLOC_SIZE + 1)
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut input = Input { data };
+    let mut pager = Pager { pages: HashSet::new() };
+    let mut zone = ZoneAllocator::new(0);
+    // (object, layout, fill byte)
+    let mut live: Vec<(NonNull<u8>, Layout, u8)> = Vec::new();
+
+    while let Some(op) = input.byte() {
+        match op % 6 {
+            0 | 1 => {
+                let (size, shift) = match (input.size(), input.byte()) {
+                    (Some(size), Some(shift)) => (size, shift % 7),
+                    _ => break,
+                };
+                let layout = Layout::from_size_align(size, 1 << shift).unwrap();
+                match zone.allocate(layout) {
+                    Ok(obj) => {
+                        let addr = obj.as_ptr() as usize;
+                        assert_eq!(addr % layout.align(), 0, "{:p} is not aligned", obj);
+                        for (other, other_layout, _) in live.iter() {
+                            let start = other.as_ptr() as usize;
+                            assert!(
+                                addr + size <= start || start + other_layout.size() <= addr,
+                                "{:p} overlaps {:p}",
+                                obj,
+                                other
+                            );
+                        }
+                        unsafe { ptr::write_bytes(obj.as_ptr(), op, size) };
+                        live.push((obj, layout, op));
+                    }
+                    Err(AllocationError::OutOfMemory)
+                    | Err(AllocationError::InvalidLayout)
+                    | Err(AllocationError::TooLarge) => {}
+                    Err(e) => panic!("Unexpected error {:?}", e),
+                }
+            }
+            2 | 3 => {
+                let idx = match input.byte() {
+                    Some(idx) => idx as usize,
+                    None => break,
+                };
+                if live.is_empty() {
+                    continue;
+                }
+                let (obj, layout, fill) = live.swap_remove(idx % live.len());
+                let contents = unsafe { core::slice::from_raw_parts(obj.as_ptr(), layout.size()) };
+                assert!(contents.iter().all(|b| *b == fill), "{:p} was overwritten", obj);
+                zone.deallocate(obj, layout).expect("Can't free a live object");
+            }
+            4 => {
+                let size = match input.size() {
+                    Some(size) => size,
+                    None => break,
+                };
+                let layout = Layout::from_size_align(size, 1).unwrap();
+                // A rejected page (e.g., class limit, or a size that doesn't fit with red zones)
+                // stays with the pager and is freed when it's dropped.
+                let _ = zone.refill(layout, pager.allocate_page());
+            }
+            _ => {
+                if let Some(mp) = zone.retrieve_empty_page(0) {
+                    pager.release_page(mp);
+                }
+            }
+        }
+    }
+    zone.verify().expect("The heap is inconsistent");
+
+    // All pages have to come back once every object is freed
+    for (obj, layout, _) in live.drain(..) {
+        zone.deallocate(obj, layout).expect("Can't free a live object");
+    }
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(zone.pages(), 0, "Pages are lost");
+});
@@ -0,0 +1,91 @@
+//! A Box-like smart pointer for objects allocated from a slab allocator.
+
+use crate::*;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+
+/// Owns a `T` allocated from a `SharedAllocator`.
+///
+/// The layout is computed from `T` at compile time and the object
+/// is dropped and its slot returned to the allocator when the
+/// `SlabBox` goes out of scope.
+pub struct SlabBox<'h, T, A: SharedAllocator + ?Sized> {
+    ptr: NonNull<T>,
+    heap: &'h A,
+    phantom: PhantomData<T>,
+}
+
+impl<'h, T, A: SharedAllocator + ?Sized> SlabBox<'h, T, A> {
+    /// Allocates memory for a `T` from `heap` and moves `value` into it.
+    pub fn new(value: T, heap: &'h A) -> Result<SlabBox<'h, T, A>, AllocationError> {
+        let ptr = heap.allocate(Layout::new::<T>())?.cast::<T>();
+        unsafe { ptr.as_ptr().write(value) };
+
+        Ok(SlabBox {
+            ptr,
+            heap,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Consumes the `SlabBox` without dropping the object or freeing its memory.
+    ///
+    /// The pointer can be turned back into a `SlabBox` with `from_raw`.
+    pub fn into_raw(b: SlabBox<'h, T, A>) -> NonNull<T> {
+        let ptr = b.ptr;
+        mem::forget(b);
+        ptr
+    }
+
+    /// Constructs a `SlabBox` from a pointer returned by `into_raw`.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by `into_raw` of a `SlabBox` that
+    /// allocated from `heap`, and must not be used afterwards.
+    pub unsafe fn from_raw(ptr: NonNull<T>, heap: &'h A) -> SlabBox<'h, T, A> {
+        SlabBox {
+            ptr,
+            heap,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<'h, T, A: SharedAllocator + ?Sized> Deref for SlabBox<'h, T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<'h, T, A: SharedAllocator + ?Sized> DerefMut for SlabBox<'h, T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<'h, T, A: SharedAllocator + ?Sized> Drop for SlabBox<'h, T, A> {
+    fn drop(&mut self) {
+        unsafe { ptr::drop_in_place(self.ptr.as_ptr()) };
+        if let Err(e) = self.heap.deallocate(self.ptr.cast::<u8>(), Layout::new::<T>()) {
+            error!("SlabBox failed to free {:p}: {}", self.ptr, e);
+        }
+    }
+}
+
+impl<'h, T: fmt::Debug, A: SharedAllocator + ?Sized> fmt::Debug for SlabBox<'h, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'h, T: fmt::Display, A: SharedAllocator + ?Sized> fmt::Display for SlabBox<'h, T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+// Same rules as for Box<T>, the allocator is only used through a shared reference.
+unsafe impl<'h, T: Send, A: SharedAllocator + Sync + ?Sized> Send for SlabBox<'h, T, A> {}
+unsafe impl<'h, T: Sync, A: SharedAllocator + Sync + ?Sized> Sync for SlabBox<'h, T, A> {}
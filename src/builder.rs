@@ -0,0 +1,350 @@
+//! A builder to configure a `ZoneAllocator` in one place.
+//!
+//! `ZoneAllocator::new` creates a zone with the default policies, which then have
+//! to be changed one by one with the `set_*` functions. The builder collects all
+//! settings and applies them when the zone is built.
+
+use crate::*;
+
+/// Configures and creates a `ZoneAllocator`.
+///
+/// # Example
+/// ```ignore
+/// let zone = ZoneAllocatorBuilder::new(core_id)
+///     .page_selection_policy(PageSelectionPolicy::FullestFirst)
+///     .max_pages_per_class(64)
+///     .build()?;
+/// ```
+///
+/// # Notes
+/// The size classes are chosen at compile time: `ZoneAllocator::BASE_ALLOC_SIZES` routes
+/// layouts in `const fn`s, and the `prune-1024-class` and `prune-2048-class` features fold
+/// unused classes into the next bigger one. Every class can get its own page cap with
+/// `max_pages_for_size` and its own retention with `slab_empty_pages_threshold`.
+pub struct ZoneAllocatorBuilder<'a> {
+    heap_id: usize,
+    max_pages_per_class: usize,
+    class_max_pages: [Option<usize>; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+    budget_bytes: usize,
+    slab_empty_threshold: usize,
+    emergency_reserve: usize,
+    reuse_policy: PageReusePolicy,
+    selection_policy: PageSelectionPolicy,
+    rebalance_interval: usize,
+    randomized_slots: Option<u64>,
+    zero_on_free: ZeroOnFree,
+    cache_coloring: bool,
+    required_flags: PageFlags,
+    quarantine: usize,
+    verify_interval: usize,
+    sample_rate: usize,
+    fallback: Option<&'a dyn FallbackAllocator>,
+    cross_heap: Option<&'a dyn CrossHeapFree>,
+    remote_inbox: Option<&'a RemoteFreeInbox>,
+    page_pools: Option<(&'a [EmptyPagePool<'a>], usize)>,
+    node: Option<usize>,
+    reclaimer: Option<(&'a dyn PageReclaimer, usize, usize)>,
+    idle_decay: Option<(&'a dyn ClockSource, u64)>,
+    page_quarantine: bool,
+    watermarks: Option<(&'a dyn WatermarkListener, usize, usize, usize)>,
+    #[cfg(feature = "shadow-hooks")]
+    shadow: Option<&'a dyn ShadowHooks>,
+    #[cfg(feature = "mte")]
+    memory_tagging: bool,
+    #[cfg(feature = "latency-stats")]
+    latency_clock: Option<&'a dyn ClockSource>,
+    /// First invalid setting, reported by `build`.
+    error: Option<AllocationError>,
+}
+
+impl<'a> ZoneAllocatorBuilder<'a> {
+    /// Starts the configuration of a zone for the heap `heap_id`,
+    /// all other settings are the defaults of `ZoneAllocator::new`.
+    pub fn new(heap_id: usize) -> ZoneAllocatorBuilder<'a> {
+        ZoneAllocatorBuilder {
+            heap_id,
+            max_pages_per_class: 0,
+            class_max_pages: [None; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+            budget_bytes: 0,
+            slab_empty_threshold: 0,
+            emergency_reserve: 0,
+            reuse_policy: PageReusePolicy::Lifo,
+            selection_policy: PageSelectionPolicy::FirstFit,
+            rebalance_interval: 0,
+            randomized_slots: None,
+            zero_on_free: ZeroOnFree::Disabled,
+            cache_coloring: false,
+            required_flags: PageFlags::empty(),
+            quarantine: 0,
+            verify_interval: 0,
+            sample_rate: 0,
+            fallback: None,
+            cross_heap: None,
+            remote_inbox: None,
+            page_pools: None,
+            node: None,
+            reclaimer: None,
+            idle_decay: None,
+            page_quarantine: false,
+            watermarks: None,
+            #[cfg(feature = "shadow-hooks")]
+            shadow: None,
+            #[cfg(feature = "mte")]
+            memory_tagging: false,
+            #[cfg(feature = "latency-stats")]
+            latency_clock: None,
+            error: None,
+        }
+    }
+
+    /// Page cap of every size class (see `ZoneAllocator::set_max_pages_per_class`).
+    pub fn max_pages_per_class(mut self, max_pages: usize) -> Self {
+        self.max_pages_per_class = max_pages;
+        self
+    }
+
+    /// Page cap of the size class serving objects of `size`, overrides `max_pages_per_class`.
+    ///
+    /// `build` fails with `AllocationError::TooLarge` if no size class serves `size`.
+    pub fn max_pages_for_size(mut self, size: usize, max_pages: usize) -> Self {
+        match ZoneAllocator::get_slab(size) {
+            Slab::Base(idx) => self.class_max_pages[idx] = Some(max_pages),
+            Slab::Large(idx) => self.class_max_pages[ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx] = Some(max_pages),
+            Slab::Unsupported => self.error = self.error.or(Some(AllocationError::TooLarge)),
+        }
+        self
+    }
+
+    /// Memory budget of the zone (see `ZoneAllocator::set_total_budget_bytes`).
+    pub fn total_budget_bytes(mut self, budget_bytes: usize) -> Self {
+        self.budget_bytes = budget_bytes;
+        self
+    }
+
+    /// Number of empty pages every base size class keeps (see `ZoneAllocator::set_slab_empty_pages_threshold`).
+    pub fn slab_empty_pages_threshold(mut self, pages: usize) -> Self {
+        self.slab_empty_threshold = pages;
+        self
+    }
+
+    /// Number of empty pages every size class holds back for `try_allocate` (see `ZoneAllocator::set_emergency_reserve`).
+    pub fn emergency_reserve(mut self, pages: usize) -> Self {
+        self.emergency_reserve = pages;
+        self
+    }
+
+    /// Order in which empty pages are reused (see `SCAllocator::set_page_reuse_policy`).
+    pub fn page_reuse_policy(mut self, policy: PageReusePolicy) -> Self {
+        self.reuse_policy = policy;
+        self
+    }
+
+    /// Which partial page serves the next allocation (see `SCAllocator::set_page_selection_policy`).
+    pub fn page_selection_policy(mut self, policy: PageSelectionPolicy) -> Self {
+        self.selection_policy = policy;
+        self
+    }
+
+    /// Automatic rebalance interval (see `SCAllocator::set_rebalance_interval`).
+    pub fn rebalance_interval(mut self, interval: usize) -> Self {
+        self.rebalance_interval = interval;
+        self
+    }
+
+    /// Randomized slot selection (see `ZoneAllocator::set_randomized_slots`).
+    pub fn randomized_slots(mut self, seed: Option<u64>) -> Self {
+        self.randomized_slots = seed;
+        self
+    }
+
+    /// Scrubbing of freed memory (see `SCAllocator::set_zero_on_free`).
+    pub fn zero_on_free(mut self, mode: ZeroOnFree) -> Self {
+        self.zero_on_free = mode;
+        self
+    }
+
+    /// Rotating offsets for the first object of fresh pages (see `SCAllocator::set_cache_coloring`).
+    pub fn cache_coloring(mut self, enabled: bool) -> Self {
+        self.cache_coloring = enabled;
+        self
+    }
+
+    /// Flags the pages of all size classes must have (see `SCAllocator::set_required_flags`).
+    pub fn required_flags(mut self, flags: PageFlags) -> Self {
+        self.required_flags = flags;
+        self
+    }
+
+    /// Number of quarantined objects per size class (see `SCAllocator::set_quarantine`).
+    pub fn quarantine(mut self, limit: usize) -> Self {
+        self.quarantine = limit;
+        self
+    }
+
+    /// Periodic heap verification in debug builds (see `ZoneAllocator::set_verify_interval`).
+    pub fn verify_interval(mut self, interval: usize) -> Self {
+        self.verify_interval = interval;
+        self
+    }
+
+    /// Sampling rate of the allocation profiler (see `AllocationProfiler::set_sample_rate`).
+    pub fn profiler_sample_rate(mut self, rate: usize) -> Self {
+        self.sample_rate = rate;
+        self
+    }
+
+    /// Allocator for layouts bigger than `ZoneAllocator::MAX_ALLOC_SIZE` (see `ZoneAllocator::set_fallback`).
+    pub fn fallback(mut self, fallback: &'a dyn FallbackAllocator) -> Self {
+        self.fallback = Some(fallback);
+        self
+    }
+
+    /// Where objects of other heaps are forwarded to (see `ZoneAllocator::set_cross_heap_free`).
+    pub fn cross_heap_free(mut self, cross_heap: &'a dyn CrossHeapFree) -> Self {
+        self.cross_heap = Some(cross_heap);
+        self
+    }
+
+    /// Inbox for objects of this zone freed on other heaps (see `ZoneAllocator::set_remote_free_inbox`).
+    pub fn remote_free_inbox(mut self, inbox: &'a RemoteFreeInbox) -> Self {
+        self.remote_inbox = Some(inbox);
+        self
+    }
+
+    /// Shared pool of empty pages and how many empty pages the zone retains
+    /// (see `ZoneAllocator::set_empty_page_pool`).
+    pub fn empty_page_pool(mut self, pool: &'a EmptyPagePool<'a>, max_empty_pages: usize) -> Self {
+        self.page_pools = Some((core::slice::from_ref(pool), max_empty_pages));
+        self
+    }
+
+    /// Shared pools of empty pages (one per NUMA node) and how many empty pages the zone retains
+    /// (see `ZoneAllocator::set_node_page_pools`).
+    pub fn node_page_pools(mut self, pools: &'a [EmptyPagePool<'a>], max_empty_pages: usize) -> Self {
+        self.page_pools = Some((pools, max_empty_pages));
+        self
+    }
+
+    /// NUMA node of the zone (see `ZoneAllocator::set_node`).
+    pub fn node(mut self, node: usize) -> Self {
+        self.node = Some(node);
+        self
+    }
+
+    /// Receiver of the surplus empty pages, how many empty pages the zone retains and how many
+    /// pages a deallocation hands over at most (see `ZoneAllocator::set_page_reclaimer`).
+    pub fn page_reclaimer(mut self, reclaimer: &'a dyn PageReclaimer, max_empty_pages: usize, max_pages_per_call: usize) -> Self {
+        self.reclaimer = Some((reclaimer, max_empty_pages, max_pages_per_call));
+        self
+    }
+
+    /// Clock and idle time after which `maintain` releases empty pages (see `ZoneAllocator::set_idle_decay`).
+    pub fn idle_decay(mut self, clock: &'a dyn ClockSource, max_idle_ticks: u64) -> Self {
+        self.idle_decay = Some((clock, max_idle_ticks));
+        self
+    }
+
+    /// Whether retrieved pages are held back for a `maintain` cycle (see `ZoneAllocator::set_page_quarantine`).
+    pub fn page_quarantine(mut self, enabled: bool) -> Self {
+        self.page_quarantine = enabled;
+        self
+    }
+
+    /// Listener of the live bytes, the watermarks and how often they are checked
+    /// (see `ZoneAllocator::set_watermarks`).
+    pub fn watermarks(mut self, listener: &'a dyn WatermarkListener, low_bytes: usize, high_bytes: usize, interval: usize) -> Self {
+        self.watermarks = Some((listener, low_bytes, high_bytes, interval));
+        self
+    }
+
+    /// Hooks of a sanitizer's shadow memory (see `ZoneAllocator::set_shadow_hooks`).
+    #[cfg(feature = "shadow-hooks")]
+    pub fn shadow_hooks(mut self, hooks: &'a dyn ShadowHooks) -> Self {
+        self.shadow = Some(hooks);
+        self
+    }
+
+    /// Whether allocations are tagged with MTE (see `ZoneAllocator::set_memory_tagging`).
+    #[cfg(feature = "mte")]
+    pub fn memory_tagging(mut self, enabled: bool) -> Self {
+        self.memory_tagging = enabled;
+        self
+    }
+
+    /// Clock that times every operation (see `ZoneAllocator::set_latency_clock`).
+    #[cfg(feature = "latency-stats")]
+    pub fn latency_clock(mut self, clock: &'a dyn ClockSource) -> Self {
+        self.latency_clock = Some(clock);
+        self
+    }
+
+    /// Creates the zone, fails if one of the settings is invalid.
+    pub fn build(self) -> Result<ZoneAllocator<'a>, AllocationError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let mut zone = ZoneAllocator::new(self.heap_id);
+        zone.set_max_pages_per_class(self.max_pages_per_class);
+        for (slab, max_pages) in zone.small_slabs.iter_mut().zip(self.class_max_pages.iter()) {
+            if let Some(max_pages) = max_pages {
+                slab.set_max_pages(*max_pages);
+            }
+        }
+        let large_max_pages = &self.class_max_pages[ZoneAllocator::MAX_BASE_SIZE_CLASSES..];
+        for (slab, max_pages) in zone.big_slabs.iter_mut().zip(large_max_pages.iter()) {
+            if let Some(max_pages) = max_pages {
+                slab.set_max_pages(*max_pages);
+            }
+        }
+        zone.set_total_budget_bytes(self.budget_bytes);
+        zone.set_slab_empty_pages_threshold(self.slab_empty_threshold);
+        zone.set_emergency_reserve(self.emergency_reserve);
+        zone.set_page_reuse_policy(self.reuse_policy);
+        zone.set_page_selection_policy(self.selection_policy);
+        zone.set_rebalance_interval(self.rebalance_interval);
+        zone.set_randomized_slots(self.randomized_slots);
+        zone.set_zero_on_free(self.zero_on_free);
+        zone.set_cache_coloring(self.cache_coloring);
+        zone.set_required_flags(self.required_flags);
+        zone.set_quarantine(self.quarantine)?;
+        zone.set_verify_interval(self.verify_interval);
+        zone.profiler_mut().set_sample_rate(self.sample_rate);
+        if let Some(fallback) = self.fallback {
+            zone.set_fallback(fallback);
+        }
+        if let Some(cross_heap) = self.cross_heap {
+            zone.set_cross_heap_free(cross_heap);
+        }
+        if let Some(inbox) = self.remote_inbox {
+            zone.set_remote_free_inbox(inbox);
+        }
+        if let Some((pools, max_empty_pages)) = self.page_pools {
+            zone.set_node_page_pools(pools, max_empty_pages);
+        }
+        if let Some(node) = self.node {
+            zone.set_node(node);
+        }
+        if let Some((reclaimer, max_empty_pages, max_pages_per_call)) = self.reclaimer {
+            zone.set_page_reclaimer(reclaimer, max_empty_pages, max_pages_per_call);
+        }
+        if let Some((clock, max_idle_ticks)) = self.idle_decay {
+            zone.set_idle_decay(clock, max_idle_ticks);
+        }
+        zone.set_page_quarantine(self.page_quarantine);
+        if let Some((listener, low_bytes, high_bytes, interval)) = self.watermarks {
+            zone.set_watermarks(listener, low_bytes, high_bytes, interval);
+        }
+        #[cfg(feature = "shadow-hooks")]
+        if let Some(hooks) = self.shadow {
+            zone.set_shadow_hooks(hooks);
+        }
+        #[cfg(feature = "mte")]
+        zone.set_memory_tagging(self.memory_tagging);
+        #[cfg(feature = "latency-stats")]
+        if let Some(clock) = self.latency_clock {
+            zone.set_latency_clock(clock);
+        }
+        Ok(zone)
+    }
+}
@@ -0,0 +1,160 @@
+//! A small per-thread (or per-task) cache of free objects in front of a `ZoneAllocator`.
+//!
+//! The cache keeps a handful of objects for the smallest size classes so
+//! most allocations and deallocations don't have to touch the (locked) zone.
+//! The zone is only accessed in batches through `refill_from` and `flush`.
+
+use crate::*;
+
+/// Number of size classes a `ThreadCache` holds objects for (8, 16, 32 and 64 bytes).
+pub const THREAD_CACHE_BINS: usize = 4;
+
+/// Maximum number of objects a `ThreadCache` holds per size class.
+pub const THREAD_CACHE_DEPTH: usize = 16;
+
+/// The cached objects of one size class.
+struct CacheBin {
+    /// Layout the objects were allocated with from the zone
+    /// (size and alignment are both the size of the class).
+    layout: Layout,
+    /// Stack of cached objects.
+    slots: [Option<NonNull<u8>>; THREAD_CACHE_DEPTH],
+    /// Number of cached objects.
+    len: usize,
+}
+
+impl CacheBin {
+    const fn new(size: usize) -> CacheBin {
+        CacheBin {
+            layout: unsafe { Layout::from_size_align_unchecked(size, size) },
+            slots: [None; THREAD_CACHE_DEPTH],
+            len: 0,
+        }
+    }
+
+    fn pop(&mut self) -> Option<NonNull<u8>> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        self.slots[self.len].take()
+    }
+
+    fn push(&mut self, ptr: NonNull<u8>) -> bool {
+        if self.len == THREAD_CACHE_DEPTH {
+            return false;
+        }
+        self.slots[self.len] = Some(ptr);
+        self.len += 1;
+        true
+    }
+}
+
+/// A per-thread front-end for the hottest (smallest) size classes of a `ZoneAllocator`.
+///
+/// `allocate` and `deallocate` only operate on the cache and never touch the zone,
+/// so they don't need the zone lock. If the cache can't serve a request
+/// the caller takes the lock and either calls `refill_from` (allocation)
+/// or frees directly to the zone (deallocation).
+///
+/// # Note
+/// With the `red-zones` feature the cache is disabled (it never serves or keeps objects)
+/// since objects of the same class may be laid out differently depending on their alignment.
+pub struct ThreadCache {
+    bins: [CacheBin; THREAD_CACHE_BINS],
+}
+
+// The cached objects are owned by the cache.
+unsafe impl Send for ThreadCache {}
+
+impl ThreadCache {
+    /// Creates an empty cache.
+    pub const fn new() -> ThreadCache {
+        ThreadCache {
+            bins: [
+                CacheBin::new(1 << 3),
+                CacheBin::new(1 << 4),
+                CacheBin::new(1 << 5),
+                CacheBin::new(1 << 6),
+            ],
+        }
+    }
+
+    /// Finds the smallest bin that can hold objects of `layout`.
+    fn bin_for(&mut self, layout: Layout) -> Option<&mut CacheBin> {
+        if cfg!(feature = "red-zones") || layout.size() == 0 {
+            return None;
+        }
+        self.bins
+            .iter_mut()
+            .find(|bin| layout.size() <= bin.layout.size() && layout.align() <= bin.layout.align())
+    }
+
+    /// Returns a cached object that fits `layout`,
+    /// or `None` if the cache needs to be refilled first (see `refill_from`).
+    #[inline]
+    pub fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        self.bin_for(layout).and_then(|bin| bin.pop())
+    }
+
+    /// Puts `ptr` (an object of `layout` allocated from the zone this cache refills from)
+    /// into the cache.
+    ///
+    /// Returns `false` if the object can't be cached, in that case
+    /// it has to be freed directly to the zone.
+    #[inline]
+    pub fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        match self.bin_for(layout) {
+            Some(bin) => bin.push(ptr),
+            None => false,
+        }
+    }
+
+    /// Fills every bin up to `THREAD_CACHE_DEPTH` objects from `zone`.
+    ///
+    /// Stops early if the zone runs out of memory.
+    pub fn refill_from(&mut self, zone: &mut ZoneAllocator) -> Result<(), AllocationError> {
+        if cfg!(feature = "red-zones") {
+            return Ok(());
+        }
+
+        let mut objects = [NonNull::dangling(); THREAD_CACHE_DEPTH];
+        for bin in self.bins.iter_mut() {
+            let missing = THREAD_CACHE_DEPTH - bin.len;
+            let count = zone.allocate_many(bin.layout, missing, &mut objects)?;
+            for ptr in objects[..count].iter() {
+                bin.push(*ptr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all cached objects to `zone`.
+    pub fn flush(&mut self, zone: &mut ZoneAllocator) -> Result<(), AllocationError> {
+        for bin in self.bins.iter_mut() {
+            while let Some(ptr) = bin.pop() {
+                zone.deallocate(ptr, bin.layout)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of objects currently held by the cache.
+    pub fn cached(&self) -> usize {
+        self.bins.iter().map(|bin| bin.len).sum()
+    }
+}
+
+impl Default for ThreadCache {
+    fn default() -> ThreadCache {
+        ThreadCache::new()
+    }
+}
+
+impl Drop for ThreadCache {
+    fn drop(&mut self) {
+        if self.cached() > 0 {
+            error!("ThreadCache dropped without flush, leaking {} objects", self.cached());
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! A compact binary export of the statistics of a `ZoneAllocator` (see `ZoneAllocator::stats_to_bytes`).
+//!
+//! The layout is stable within a format version, so tools outside of the kernel and the
+//! shell can parse the heap state of any crate that uses the allocator. All fields are
+//! little-endian, counts and sizes are `u64`.
+//!
+//! | Offset | Size | Field |
+//! |-------:|-----:|-------|
+//! | 0 | 4 | `STATS_MAGIC` |
+//! | 4 | 2 | `STATS_FORMAT_VERSION` |
+//! | 6 | 2 | `STATS_RECORD_SIZE` |
+//! | 8 | 4 | number of size classes |
+//! | 12 | 4 | reserved (0) |
+//! | 16 | 8 | `Utilization::live_bytes` |
+//! | 24 | 8 | `Utilization::capacity_bytes` |
+//! | 32 | 8 | `Utilization::pages` |
+//! | 40 | | one record per size class (see `ZoneAllocator::size_class_stats`) |
+//!
+//! A record holds `object_size`, flags (bit 0: `pruned`), `allocations`, `requested_bytes`,
+//! `pages`, `page_bytes` and `page_transitions` of its `SizeClassStats`, in that order.
+//! Newer versions only append fields to the header or the records, so a parser
+//! should step over the records with the record size of the header.
+
+use crate::*;
+
+/// First bytes of an export.
+pub const STATS_MAGIC: [u8; 4] = *b"SLAB";
+
+/// Version of the layout of an export.
+pub const STATS_FORMAT_VERSION: u16 = 1;
+
+/// Size of the header of an export.
+pub const STATS_HEADER_SIZE: usize = 40;
+
+/// Size of the record of a size class.
+pub const STATS_RECORD_SIZE: usize = 56;
+
+/// Size of the export of a zone.
+pub const STATS_EXPORT_SIZE: usize =
+    STATS_HEADER_SIZE + STATS_RECORD_SIZE * (ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES);
+
+/// Appends little-endian fields to a buffer that is known to be large enough.
+struct StatsWriter<'b> {
+    buf: &'b mut [u8],
+    offset: usize,
+}
+
+impl<'b> StatsWriter<'b> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.buf[self.offset..self.offset + bytes.len()].copy_from_slice(bytes);
+        self.offset += bytes.len();
+    }
+
+    fn put_u64(&mut self, value: usize) {
+        self.put(&(value as u64).to_le_bytes());
+    }
+}
+
+/// Writes the export of `utilization` and `classes` into `buf`, returns its size
+/// (0 if `buf` is too small, then nothing is written).
+pub(crate) fn write_stats(buf: &mut [u8], utilization: Utilization, classes: &[SizeClassStats]) -> usize {
+    let size = STATS_HEADER_SIZE + STATS_RECORD_SIZE * classes.len();
+    if buf.len() < size {
+        return 0;
+    }
+    let mut writer = StatsWriter { buf, offset: 0 };
+    writer.put(&STATS_MAGIC);
+    writer.put(&STATS_FORMAT_VERSION.to_le_bytes());
+    writer.put(&(STATS_RECORD_SIZE as u16).to_le_bytes());
+    writer.put(&(classes.len() as u32).to_le_bytes());
+    writer.put(&0u32.to_le_bytes());
+    writer.put_u64(utilization.live_bytes);
+    writer.put_u64(utilization.capacity_bytes);
+    writer.put_u64(utilization.pages);
+    for class in classes {
+        writer.put_u64(class.object_size);
+        writer.put_u64(class.pruned as usize);
+        writer.put_u64(class.allocations);
+        writer.put_u64(class.requested_bytes);
+        writer.put_u64(class.pages);
+        writer.put_u64(class.page_bytes);
+        writer.put_u64(class.page_transitions);
+    }
+    debug_assert_eq!(writer.offset, size);
+    size
+}
@@ -0,0 +1,86 @@
+//! Failure injection (only with the `fail-injection` feature).
+//!
+//! Makes a `ZoneAllocator` report `AllocationError::OutOfMemory` on purpose, so
+//! the out-of-memory paths of its users can be tested deterministically without
+//! actually exhausting the heap.
+
+/// Decides which allocations of a `ZoneAllocator` fail (see `ZoneAllocator::failure_injector_mut`).
+///
+/// Both modes can be active at the same time, an allocation fails if either says so.
+/// Zero-sized allocations never fail.
+pub struct FailureInjector {
+    /// Number of upcoming allocations that fail.
+    fail_next: usize,
+    /// Fail one out of `fail_every` allocations (0 disables it).
+    fail_every: usize,
+    /// Allocations seen since the last periodic failure.
+    countdown: usize,
+    /// Total number of failures injected so far.
+    injected: usize,
+}
+
+impl FailureInjector {
+    /// Creates an injector that doesn't fail any allocation.
+    pub const fn new() -> FailureInjector {
+        FailureInjector {
+            fail_next: 0,
+            fail_every: 0,
+            countdown: 0,
+            injected: 0,
+        }
+    }
+
+    /// Fails the next `n` allocations.
+    pub fn fail_next(&mut self, n: usize) {
+        self.fail_next = n;
+    }
+
+    /// Fails every `k`-th allocation from now on (0 disables it).
+    pub fn fail_every(&mut self, k: usize) {
+        self.fail_every = k;
+        self.countdown = 0;
+    }
+
+    /// Stops injecting failures.
+    pub fn disable(&mut self) {
+        self.fail_next(0);
+        self.fail_every(0);
+    }
+
+    /// Number of allocations that still fail because of `fail_next`.
+    pub fn pending(&self) -> usize {
+        self.fail_next
+    }
+
+    /// Total number of failures injected so far.
+    pub fn injected(&self) -> usize {
+        self.injected
+    }
+
+    /// Called for every allocation, returns `true` if it has to fail.
+    #[inline]
+    pub(crate) fn should_fail(&mut self) -> bool {
+        let mut fail = false;
+        if self.fail_next > 0 {
+            self.fail_next -= 1;
+            fail = true;
+        }
+        if self.fail_every > 0 {
+            self.countdown += 1;
+            if self.countdown >= self.fail_every {
+                self.countdown = 0;
+                fail = true;
+            }
+        }
+        if fail {
+            self.injected += 1;
+        }
+        fail
+    }
+}
+
+impl Default for FailureInjector {
+    fn default() -> FailureInjector {
+        FailureInjector::new()
+    }
+}
@@ -0,0 +1,89 @@
+//! Latency histograms of the allocations and deallocations of a `ZoneAllocator`.
+//!
+//! With the `latency-stats` feature, a zone with a latency clock (see `ZoneAllocator::set_latency_clock`)
+//! reads the clock before and after every allocation and deallocation of a size class and counts
+//! the difference in one of `LATENCY_BUCKETS` power-of-two buckets of the class.
+
+use crate::*;
+use core::cmp;
+
+/// Number of buckets of a `LatencyHistogram`.
+pub const LATENCY_BUCKETS: usize = 24;
+
+/// The time stamp counter of the CPU, a cheap clock for `ZoneAllocator::set_latency_clock`.
+#[cfg(target_arch = "x86_64")]
+pub struct TimestampCounter;
+
+#[cfg(target_arch = "x86_64")]
+impl ClockSource for TimestampCounter {
+    fn now(&self) -> u64 {
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+}
+
+/// How long the allocations and deallocations of a size class took, in ticks of the latency clock.
+///
+/// Bucket `i` counts the operations that took `2^i` to `2^(i+1) - 1` ticks (bucket 0 also counts
+/// those that took no time at all), the last bucket counts everything longer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Latencies of the allocations.
+    pub allocate: [u64; LATENCY_BUCKETS],
+    /// Latencies of the deallocations.
+    pub deallocate: [u64; LATENCY_BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Creates an empty histogram.
+    pub const fn new() -> LatencyHistogram {
+        LatencyHistogram {
+            allocate: [0; LATENCY_BUCKETS],
+            deallocate: [0; LATENCY_BUCKETS],
+        }
+    }
+
+    /// The bucket of an operation that took `ticks`.
+    pub fn bucket(ticks: u64) -> usize {
+        cmp::min(63 - (ticks | 1).leading_zeros() as usize, LATENCY_BUCKETS - 1)
+    }
+
+    /// Counts an allocation (`TraceOp::Allocate`) or deallocation (`TraceOp::Deallocate`) that took `ticks`.
+    pub(crate) fn record(&mut self, op: TraceOp, ticks: u64) {
+        let buckets = match op {
+            TraceOp::Allocate => &mut self.allocate,
+            TraceOp::Deallocate => &mut self.deallocate,
+            _ => return,
+        };
+        buckets[LatencyHistogram::bucket(ticks)] += 1;
+    }
+
+    /// Upper bound (in ticks) of the latency that `percent` percent of the operations counted
+    /// in `buckets` stayed below, e.g., 99 for the tail latency. `None` if nothing was counted.
+    ///
+    /// The last bucket has no upper bound, `u64::MAX` is returned for it.
+    pub fn percentile(buckets: &[u64; LATENCY_BUCKETS], percent: u64) -> Option<u64> {
+        let total: u64 = buckets.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        // Rounded up, so the 100th percentile is the slowest operation
+        let rank = cmp::max((total * cmp::min(percent, 100)).div_ceil(100), 1);
+        let mut seen = 0;
+        for (idx, count) in buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(match idx {
+                    idx if idx == LATENCY_BUCKETS - 1 => u64::MAX,
+                    idx => (2 << idx) - 1,
+                });
+            }
+        }
+        None
+    }
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> LatencyHistogram {
+        LatencyHistogram::new()
+    }
+}
@@ -22,76 +22,399 @@
 //! Some changes made for the Theseus OS heap:
 //!  * A `ObjectPage8k` that is 8 KiB in size and contains allocated objects and associated meta-data.
 //!  * return_page() function which allow the ZoneAllocator to return empty pages on request.
-#![allow(unused_features)]
-#![cfg_attr(feature = "unstable", feature(const_fn))]
-#![cfg_attr(
-    test,
-    feature(
-        prelude_import,
-        test,
-        raw,
-        c_void_variant,
-        core_intrinsics,
-        vec_remove_item
-    )
-)]
+//!
+//! With the `out-of-band-metadata` feature the 8 KiB pages of the `ZoneAllocator` keep their
+//! meta-data in a separate arena (see `OutOfBandPage8k`), so objects can use the whole page.
+//!
+//! Messages and `HeapEvent`s are logged with the `log` crate (the `log` feature, on by default),
+//! with `defmt` (the `defmt` feature) or not at all (without both features).
+//!
+//! Pages are backed by Theseus' `MappedPages` with the `theseus` feature (the default).
+//! Without it, the caller supplies the memory of every page as a raw region
+//! (see `MappedPages::from_raw`), so the crate can be used outside of Theseus.
 #![no_std]
 #![crate_name = "slabmalloc"]
 #![crate_type = "lib"]
 
+#[cfg(feature = "theseus")]
 extern crate memory;
 
+mod boxed;
+mod builder;
+mod cache;
+mod export;
+#[cfg(feature = "fail-injection")]
+mod inject;
+#[cfg(feature = "latency-stats")]
+mod latency;
+mod lockfree;
+mod logging;
+#[cfg(feature = "mte")]
+mod mte;
+#[cfg(feature = "out-of-band-metadata")]
+mod oob;
 mod pages;
+mod pool;
+mod profile;
+mod quarantine;
+mod registry;
+#[cfg(not(feature = "theseus"))]
+mod region;
+mod remote;
 mod sc;
+#[cfg(feature = "shadow-hooks")]
+mod shadow;
+mod sharded;
+mod stats;
+mod tags;
+mod trace;
 mod zone;
 
+pub use boxed::*;
+pub use builder::*;
+pub use cache::*;
+pub use export::*;
+#[cfg(feature = "fail-injection")]
+pub use inject::*;
+#[cfg(feature = "latency-stats")]
+pub use latency::*;
+pub use lockfree::*;
+pub use logging::*;
+#[cfg(feature = "mte")]
+pub use mte::*;
+#[cfg(feature = "out-of-band-metadata")]
+pub use oob::*;
 pub use pages::*;
+pub use pool::*;
+pub use profile::*;
+pub use quarantine::*;
+pub use registry::*;
+#[cfg(not(feature = "theseus"))]
+pub use region::*;
+pub use remote::*;
 pub use sc::*;
+#[cfg(feature = "shadow-hooks")]
+pub use shadow::*;
+pub use sharded::*;
+pub use stats::*;
+pub use tags::*;
+pub use trace::*;
 pub use zone::*;
 
 #[cfg(test)]
 #[macro_use]
 extern crate std;
-#[cfg(test)]
-extern crate test;
 
-#[cfg(test)]
+// The tests allocate their pages on the host, which only works with caller supplied regions.
+#[cfg(all(test, not(feature = "theseus")))]
 mod tests;
 
 use core::alloc::Layout;
 use core::fmt;
 use core::mem;
 use core::ptr::{self, NonNull};
-use memory::MappedPages;
+#[cfg(feature = "theseus")]
+use memory::{MappedPages, PhysicalAddress};
+
+/// Flags of the mapping backing a page, Theseus' `EntryFlags` with the `theseus` feature.
+#[cfg(feature = "theseus")]
+pub type PageFlags = memory::EntryFlags;
+
+/// Flags of the mapping backing a page (see `MappedPages::with_flags`).
+#[cfg(not(feature = "theseus"))]
+pub type PageFlags = RegionFlags;
 
-use log::{error};
+use logging::{error, log_event};
 
-#[cfg(target_arch = "x86_64")]
+/// Size of a cache line, 128 bytes on cores that fetch lines in pairs (e.g., Apple's aarch64 cores).
+#[cfg(any(all(target_arch = "aarch64", target_vendor = "apple"), target_arch = "powerpc64"))]
+#[allow(unused)]
+const CACHE_LINE_SIZE: usize = 128;
+
+/// Size of a cache line (x86_64, riscv64 and most aarch64 cores).
+#[cfg(not(any(all(target_arch = "aarch64", target_vendor = "apple"), target_arch = "powerpc64")))]
+#[allow(unused)]
 const CACHE_LINE_SIZE: usize = 64;
 
-// #[cfg(target_arch = "x86_64")]
+/// A `debug_assert!` that walks a page list, compiled out with the `minimal` feature
+/// (it is O(n) in the length of the list).
+macro_rules! debug_assert_list {
+    ($($arg:tt)*) => {
+        if !cfg!(feature = "minimal") {
+            debug_assert!($($arg)*);
+        }
+    };
+}
+
+pub(crate) use debug_assert_list;
+
+/// Hints the CPU to fetch the cache line at `ptr` (only with the `prefetch` feature).
+///
+/// Never faults, so `ptr` doesn't have to be valid.
+#[inline(always)]
+#[allow(unused_variables)]
+fn prefetch<T>(ptr: *const T) {
+    #[cfg(all(feature = "prefetch", target_arch = "x86_64"))]
+    unsafe {
+        core::arch::x86_64::_mm_prefetch(ptr as *const i8, core::arch::x86_64::_MM_HINT_T0);
+    }
+    #[cfg(all(feature = "prefetch", target_arch = "aarch64"))]
+    unsafe {
+        core::arch::asm!("prfm pldl1keep, [{}]", in(reg) ptr, options(nostack, readonly, preserves_flags));
+    }
+    // riscv64 needs the Zicbop extension for prefetches, which is not a baseline target feature
+}
+
 // const BASE_PAGE_SIZE: usize = 4096;
 
-#[cfg(target_arch = "x86_64")]
+/// Size of a large page (a 2 MiB block on aarch64 with a 4 KiB granule).
 #[allow(unused)]
 const LARGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
 
-#[cfg(target_arch = "x86_64")]
+/// A virtual address. Pages are found by masking off the low bits of an object address,
+/// which also holds for sign-extended (higher half) addresses, e.g., with Sv39/Sv48 on riscv64.
 type VAddr = usize;
 
 /// Error that can be returned for `allocation` and `deallocation` requests.
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AllocationError {
     /// Can't satisfy the allocation request for Layout because the allocator
     /// does not have enough memory (you may be able to `refill` it).
     OutOfMemory,
     /// Allocator can't deal with the provided size of the Layout.
     InvalidLayout,
+    /// The request can't be served without blocking or unbounded work
+    /// (returned by the `try_allocate` functions).
+    WouldBlock,
+    /// The Layout is bigger than the largest size class, it has to be
+    /// served by a different allocator.
+    TooLarge,
+    /// The object passed to `deallocate` is not currently allocated.
+    DoubleFree,
+    /// The pointer passed to `deallocate` was not handed out by this allocator.
+    InvalidPointer,
+    /// The page still contains allocated objects.
+    PageNotEmpty,
+    /// The MappedPages can't be turned into an allocable page (the reason is attached).
+    InvalidPage(&'static str),
+    /// The meta-data of the page holding the object is corrupted.
+    CorruptedPage,
+    /// The red zone around an object was overwritten (only with the `red-zones` feature).
+    RedZoneViolation,
+    /// `verify` found an inconsistency in the heap (the violated invariant is attached).
+    HeapCorrupted(&'static str),
+    /// The page cap of the size class or the memory budget of the zone is reached.
+    LimitExceeded,
+    /// The object belongs to the heap with the attached id, not to the one it was freed to.
+    WrongHeap(usize),
+    /// The object lies in a page that was already retrieved and sits in the page quarantine
+    /// (see `ZoneAllocator::set_page_quarantine`).
+    QuarantinedPage,
+}
+
+impl AllocationError {
+    /// Returns a static description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AllocationError::OutOfMemory => "out of memory",
+            AllocationError::InvalidLayout => "invalid layout",
+            AllocationError::WouldBlock => "allocation would block",
+            AllocationError::TooLarge => "layout is too large for the slab allocator",
+            AllocationError::DoubleFree => "double free",
+            AllocationError::InvalidPointer => "invalid pointer",
+            AllocationError::PageNotEmpty => "page is not empty",
+            AllocationError::InvalidPage(reason) => reason,
+            AllocationError::CorruptedPage => "page meta-data is corrupted",
+            AllocationError::RedZoneViolation => "red zone was overwritten",
+            AllocationError::HeapCorrupted(reason) => reason,
+            AllocationError::LimitExceeded => "page limit or memory budget exceeded",
+            AllocationError::WrongHeap(_) => "object was freed to the wrong heap",
+            AllocationError::QuarantinedPage => "object lies in a retrieved page",
+        }
+    }
+}
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Lets Theseus call sites that still deal in `&'static str` errors use `?`.
+impl From<AllocationError> for &'static str {
+    fn from(err: AllocationError) -> &'static str {
+        err.as_str()
+    }
+}
+
+/// An allocator the `ZoneAllocator` delegates to for layouts bigger
+/// than `ZoneAllocator::MAX_ALLOC_SIZE` (e.g., a buddy or page allocator).
+///
+/// The methods take `&self` since the fallback is usually shared between
+/// many zones and has to do its own locking.
+pub trait FallbackAllocator: Sync {
+    /// Allocate a block of memory described by `layout`.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError>;
+    /// Deallocate a block of memory that was returned by `allocate` with the same `layout`.
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError>;
+
+    /// Allocate on behalf of the caller described by `ctx` (see `ZoneAllocator::allocate_in`),
+    /// e.g., from the page pool of its core. The default ignores the context.
+    fn allocate_in(&self, layout: Layout, ctx: &dyn AllocationContext) -> Result<NonNull<u8>, AllocationError> {
+        let _ = ctx;
+        self.allocate(layout)
+    }
+
+    /// Deallocate on behalf of the caller described by `ctx` (see `ZoneAllocator::deallocate_in`).
+    /// The default ignores the context.
+    fn deallocate_in(&self, ptr: NonNull<u8>, layout: Layout, ctx: &dyn AllocationContext) -> Result<(), AllocationError> {
+        let _ = ctx;
+        self.deallocate(ptr, layout)
+    }
+}
+
+/// Receives objects that were freed to a heap they don't belong to
+/// (e.g., an object allocated on one core and freed on another).
+///
+/// It is usually shared by all per-core heaps and forwards the object to the heap
+/// with the id `heap_id`, so it has to do its own synchronization.
+pub trait CrossHeapFree: Sync {
+    /// Frees `ptr` (allocated with `layout`) on behalf of the heap `heap_id`.
+    fn forward(&self, heap_id: usize, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError>;
+}
+
+/// Receives the surplus empty pages of a zone as objects are freed
+/// (see `ZoneAllocator::set_page_reclaimer`), e.g., to unmap them or hand them to the page allocator.
+///
+/// It is usually shared by all per-core heaps, so it has to do its own synchronization.
+pub trait PageReclaimer: Sync {
+    /// Takes ownership of the empty page (or 32 KiB slab) `mp`.
+    fn reclaim(&self, mp: MappedPages);
+}
+
+/// Lets a plain function (e.g., in a `static`) be used as the reclaimer.
+impl PageReclaimer for fn(MappedPages) {
+    fn reclaim(&self, mp: MappedPages) {
+        self(mp)
+    }
+}
+
+/// A monotonic clock supplied by the embedder, e.g., the timer ticks of the kernel
+/// (see `ZoneAllocator::set_idle_decay`) or a cycle counter (see `ZoneAllocator::set_latency_clock`).
+pub trait ClockSource: Sync {
+    /// Current time in ticks, never goes backwards.
+    fn now(&self) -> u64;
+}
+
+/// Lets a plain function (e.g., in a `static`) be used as the clock.
+impl ClockSource for fn() -> u64 {
+    fn now(&self) -> u64 {
+        self()
+    }
+}
+
+/// A watermark of the live memory of a zone (see `ZoneAllocator::set_watermarks`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Watermark {
+    /// The live bytes rose to the high watermark, e.g., time to evict caches.
+    High,
+    /// The live bytes fell back to the low watermark after the high one was crossed.
+    Low,
+}
+
+/// Is told when the live memory of a zone crosses its watermarks (see `ZoneAllocator::set_watermarks`),
+/// e.g., to start eviction or reclaim without a polling task.
+///
+/// It is called from `allocate` and `deallocate` with the zone borrowed, so it must not use the zone.
+pub trait WatermarkListener: Sync {
+    /// The zone crossed `watermark`, `utilization` is what it measured.
+    fn crossed(&self, watermark: Watermark, utilization: Utilization);
+}
+
+/// Lets a plain function (e.g., in a `static`) be used as the listener.
+impl WatermarkListener for fn(Watermark, Utilization) {
+    fn crossed(&self, watermark: Watermark, utilization: Utilization) {
+        self(watermark, utilization)
+    }
+}
+
+/// Describes the caller of an allocation or deallocation (e.g., its core, interrupt depth
+/// or task) for `ZoneAllocator::allocate_in` and `ZoneAllocator::deallocate_in`.
+///
+/// The zone bases its policies on the context, forwards it to the fallback allocator and
+/// stamps its core and task into the events of a running trace (see `TraceEvent`),
+/// so contextual decisions don't need thread-locals or globals. All methods have defaults,
+/// `()` is the empty context.
+pub trait AllocationContext {
+    /// Id of the core the caller runs on, if known.
+    fn core_id(&self) -> Option<usize> {
+        None
+    }
+
+    /// Number of interrupt handlers the caller is nested in. Allocations within an
+    /// interrupt handler are done in bounded time (see `ZoneAllocator::try_allocate`).
+    fn interrupt_depth(&self) -> usize {
+        0
+    }
+
+    /// Id of the task the caller runs in, if known.
+    fn task_id(&self) -> Option<usize> {
+        None
+    }
+
+    /// How long the objects the caller allocates are expected to live (see `ZoneAllocator::allocate_with_hint`).
+    fn alloc_hint(&self) -> AllocHint {
+        AllocHint::ShortLived
+    }
+
+    /// Memory tier the caller's objects should preferably be in (see `ZoneAllocator::allocate_with_tier_preference`).
+    fn memory_tier(&self) -> Option<MemoryTier> {
+        None
+    }
+
+    /// Called after `ptr` was allocated with `layout` for the caller, e.g., to trace or account it per task.
+    fn on_allocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = (ptr, layout);
+    }
+
+    /// Called after `ptr` (allocated with `layout`) was freed by the caller.
+    fn on_deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = (ptr, layout);
+    }
+}
+
+/// No context, the zone behaves like `allocate` and `deallocate`.
+impl AllocationContext for () {}
+
+/// Tells whether the memory right after a page is an unmapped guard page
+/// (see `SCAllocator::set_guard_pages`), e.g., by walking the page table.
+pub trait GuardPageCheck: Sync {
+    /// Returns `true` if the page following `mp` is not mapped.
+    fn has_guard_page(&self, mp: &MappedPages) -> bool;
+}
+
+/// Lets a plain function (e.g., in a `static`) be used as the check.
+impl GuardPageCheck for fn(&MappedPages) -> bool {
+    fn has_guard_page(&self, mp: &MappedPages) -> bool {
+        self(mp)
+    }
+}
+
+/// An allocator that can be used through a shared reference,
+/// typically a `ZoneAllocator` behind a lock.
+///
+/// This is what `SlabBox` allocates from and returns its memory to.
+pub trait SharedAllocator {
+    /// Allocate a block of memory described by `layout`.
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError>;
+    /// Deallocate a block of memory that was returned by `allocate` with the same `layout`.
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError>;
 }
 
 pub unsafe trait Allocator<'a> {
-    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, &'static str>;
-    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), &'static str>;
+    #[track_caller]
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocationError>;
+    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError>;
     // unsafe fn refill_large(
     //     &mut self,
     //     layout: Layout,
@@ -101,5 +424,5 @@ pub unsafe trait Allocator<'a> {
         &mut self,
         layout: Layout,
         mp: MappedPages,
-    ) -> Result<(), &'static str>;
+    ) -> Result<(), AllocationError>;
 }
@@ -0,0 +1,177 @@
+//! Lock-free stacks of free objects in front of the smallest size classes of a `ShardedZoneAllocator`.
+//!
+//! A freed object of an 8 to 64 byte class is pushed onto the (Treiber) stack of its class
+//! instead of taking the lock of the class, and allocations pop from the stack first.
+//! The stack is threaded through the free objects, the objects stay marked as
+//! allocated in their pages until they are flushed.
+//!
+//! The head of a stack packs the address of the top object with a tag that changes on
+//! every push and pop, so a pop that raced with a pop and a push of the same object
+//! (the ABA problem) fails its compare-and-swap. The objects are 8-byte aligned, so the
+//! 48-bit address takes 45 bits and the tag 19 bits: a pop can only go wrong if it is
+//! stalled between reading the head and its compare-and-swap while exactly a multiple of
+//! 2^19 (524288) pushes and pops happen on the same stack and its top object ends up on top again.
+//!
+//! The first word of a stacked object holds the address of the next object along with
+//! `STACKED_MARK`, so freeing an object that is still on a stack is caught as a double free.
+
+use crate::*;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Number of size classes with a lock-free free stack (8, 16, 32 and 64 bytes).
+pub const FREE_STACK_CLASSES: usize = 4;
+
+/// Number of bits of a (canonical) address that are kept, the upper bits are sign-extended.
+const CANONICAL_BITS: u32 = 48;
+
+/// Mask of the kept bits of an address.
+const CANONICAL_MASK: u64 = (1 << CANONICAL_BITS) - 1;
+
+/// Number of low address bits that are 0 for every stacked object.
+const ALIGN_BITS: u32 = 3;
+
+/// Number of bits of the head of a stack that hold the address of the top object, the rest hold the tag.
+const ADDRESS_BITS: u32 = CANONICAL_BITS - ALIGN_BITS;
+
+/// Mask of the address bits of a head.
+const ADDRESS_MASK: u64 = (1 << ADDRESS_BITS) - 1;
+
+/// Upper bits of the first word of a stacked object, the lower bits hold the address of the next object.
+///
+/// It is no canonical address, so it doesn't show up in pointers a stacked object held before it was freed.
+const STACKED_MARK: u64 = 0x57AC << CANONICAL_BITS;
+
+/// Size of the link to the next object at the start of a stacked object.
+#[cfg(feature = "debug-poison")]
+pub(crate) const LINK_SIZE: usize = mem::size_of::<u64>();
+
+/// A lock-free stack of the free objects of one size class.
+pub struct FreeStack {
+    /// Tag (upper 19 bits) and address of the top object (0 if empty).
+    /// The first word of every object holds the address of the next one.
+    head: AtomicU64,
+    /// Number of objects on the stack (may be off while pushes and pops are in flight).
+    len: AtomicUsize,
+    /// Allocations served from the stack.
+    pub(crate) allocations: AtomicUsize,
+    /// Objects that were freed onto the stack.
+    pub(crate) deallocations: AtomicUsize,
+}
+
+impl FreeStack {
+    /// Creates an empty stack.
+    pub const fn new() -> FreeStack {
+        FreeStack {
+            head: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Packs `addr` with the tag following the one of `head`.
+    ///
+    /// Returns `None` if `addr` isn't 8-byte aligned or can't be restored from its
+    /// lower `CANONICAL_BITS` bits (e.g., with 5-level paging).
+    fn pack(addr: VAddr, head: u64) -> Option<u64> {
+        if addr as u64 & ((1 << ALIGN_BITS) - 1) != 0 || FreeStack::canonical(addr as u64) != addr {
+            return None;
+        }
+        let packed = (addr as u64 >> ALIGN_BITS) & ADDRESS_MASK;
+        let tag = (head >> ADDRESS_BITS).wrapping_add(1) << ADDRESS_BITS;
+        Some(tag | packed)
+    }
+
+    /// The address of the top object in `head`.
+    fn unpack(head: u64) -> VAddr {
+        FreeStack::canonical((head & ADDRESS_MASK) << ALIGN_BITS)
+    }
+
+    /// Sign-extends the lower `CANONICAL_BITS` bits of `bits` like a canonical address.
+    fn canonical(bits: u64) -> VAddr {
+        let shift = 64 - CANONICAL_BITS;
+        (((bits << shift) as i64) >> shift) as VAddr
+    }
+
+    /// The first word of the object at `addr`, which links it to the next object while it is stacked.
+    fn link(addr: VAddr) -> &'static AtomicU64 {
+        unsafe { &*(addr as *const AtomicU64) }
+    }
+
+    /// Returns `true` if the object `ptr` (which must be an object of this size class)
+    /// looks like it is on a free stack, i.e., its first word carries `STACKED_MARK`.
+    pub fn is_stacked(ptr: NonNull<u8>) -> bool {
+        FreeStack::link(ptr.as_ptr() as VAddr).load(Ordering::Relaxed) & !CANONICAL_MASK == STACKED_MARK
+    }
+
+    /// Pushes `ptr` unless the stack already holds `limit` objects.
+    ///
+    /// Returns `false` if the object was not pushed.
+    pub fn push(&self, ptr: NonNull<u8>, limit: usize) -> bool {
+        if self.len.fetch_add(1, Ordering::Relaxed) >= limit {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+
+        let addr = ptr.as_ptr() as VAddr;
+        let next = FreeStack::link(addr);
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let new = match FreeStack::pack(addr, head) {
+                Some(new) => new,
+                None => {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    return false;
+                }
+            };
+            next.store(FreeStack::unpack(head) as u64 & CANONICAL_MASK | STACKED_MARK, Ordering::Relaxed);
+            match self.head.compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return true,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Pops the most recently pushed object, `None` if the stack is empty.
+    ///
+    /// The link in the first word of the object is cleared.
+    pub fn pop(&self) -> Option<NonNull<u8>> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            let addr = FreeStack::unpack(head);
+            if addr == 0 {
+                return None;
+            }
+            // The object may have been popped (and written to) by someone else in the meantime,
+            // the tag makes the exchange fail in that case.
+            let next = FreeStack::canonical(FreeStack::link(addr).load(Ordering::Relaxed));
+            // `next` was packed when it was pushed, so this can't fail
+            let new = FreeStack::pack(next, head).unwrap_or(0);
+            match self.head.compare_exchange_weak(head, new, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => {
+                    // Pops that raced with us may still read the link, but their exchange fails
+                    FreeStack::link(addr).store(0, Ordering::Relaxed);
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    return NonNull::new(addr as *mut u8);
+                }
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Number of objects on the stack.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    /// Returns `true` if the stack holds no objects.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for FreeStack {
+    fn default() -> FreeStack {
+        FreeStack::new()
+    }
+}
@@ -0,0 +1,100 @@
+//! The logging facade of the crate.
+//!
+//! Messages and `HeapEvent`s go to one of three backends, picked at compile time:
+//!  * `defmt` (with the `defmt` feature), messages are sent without their arguments
+//!    since they are formatted with `core::fmt`.
+//!  * `log` (with the `log` feature, the default).
+//!  * Nothing at all (without either feature).
+
+use crate::*;
+
+/// A structured event of a `ZoneAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapEvent {
+    /// A page was added to the size class of `object_size`.
+    PageRefilled { heap_id: usize, object_size: usize, page: VAddr },
+    /// An empty page of the size class of `object_size` was handed back.
+    PageReturned { heap_id: usize, object_size: usize, page: VAddr },
+    /// An allocation of `size` bytes failed since the heap is out of memory.
+    OutOfMemory { heap_id: usize, size: usize, align: usize },
+}
+
+impl fmt::Display for HeapEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeapEvent::PageRefilled { heap_id, object_size, page } => {
+                write!(f, "heap {}: page {:#x} refilled size class {}", heap_id, page, object_size)
+            }
+            HeapEvent::PageReturned { heap_id, object_size, page } => {
+                write!(f, "heap {}: page {:#x} returned from size class {}", heap_id, page, object_size)
+            }
+            HeapEvent::OutOfMemory { heap_id, size, align } => {
+                write!(f, "heap {}: out of memory for {} bytes (align {})", heap_id, size, align)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for HeapEvent {
+    fn format(&self, f: defmt::Formatter) {
+        match *self {
+            HeapEvent::PageRefilled { heap_id, object_size, page } => defmt::write!(
+                f,
+                "heap {=usize}: page {=usize:#x} refilled size class {=usize}",
+                heap_id,
+                page,
+                object_size
+            ),
+            HeapEvent::PageReturned { heap_id, object_size, page } => defmt::write!(
+                f,
+                "heap {=usize}: page {=usize:#x} returned from size class {=usize}",
+                heap_id,
+                page,
+                object_size
+            ),
+            HeapEvent::OutOfMemory { heap_id, size, align } => defmt::write!(
+                f,
+                "heap {=usize}: out of memory for {=usize} bytes (align {=usize})",
+                heap_id,
+                size,
+                align
+            ),
+        }
+    }
+}
+
+/// Logs `event`, out of memory as a warning and page movements at debug level.
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn log_event(event: HeapEvent) {
+    #[cfg(feature = "defmt")]
+    match event {
+        HeapEvent::OutOfMemory { .. } => defmt::warn!("{}", event),
+        _ => defmt::debug!("{}", event),
+    }
+    #[cfg(all(feature = "log", not(feature = "defmt")))]
+    match event {
+        HeapEvent::OutOfMemory { .. } => log::warn!("{}", event),
+        _ => log::debug!("{}", event),
+    }
+}
+
+/// Logs an error message with the backend of the crate (see the module documentation).
+macro_rules! error {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        #[cfg(feature = "defmt")]
+        {
+            $(let _ = &$arg;)*
+            defmt::error!("{=str}", $fmt);
+        }
+        #[cfg(all(feature = "log", not(feature = "defmt")))]
+        log::error!($fmt $(, $arg)*);
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        {
+            $(let _ = &$arg;)*
+        }
+    }};
+}
+
+pub(crate) use error;
@@ -0,0 +1,166 @@
+//! Memory tagging with the Arm Memory Tagging Extension (only with the `mte` feature).
+//!
+//! Every allocation gets a random 4-bit tag in the top byte of its pointer, and the
+//! 16-byte granules of the object are colored with the same tag in tag memory
+//! (see `ZoneAllocator::set_memory_tagging`). Freed objects are colored with tag 0,
+//! which is never handed out, so the hardware faults on a use-after-free or an
+//! overflow into the rest of the slot. Tag memory doubles as the per-slot tag storage:
+//! a free whose pointer doesn't carry the tag of the object is rejected.
+//!
+//! The pages have to be mapped as Normal Tagged memory by the kernel. On other
+//! architectures objects stay untagged (the tests tag pointers in software instead).
+
+use crate::*;
+
+/// Number of bytes that share a tag.
+pub const MTE_GRANULE: usize = 16;
+
+/// Bit position of the tag in a pointer.
+const TAG_SHIFT: u32 = 56;
+
+/// Mask of the tag bits of a pointer.
+const TAG_MASK: usize = 0xf << TAG_SHIFT;
+
+/// Tags `IRG` must not generate: 0 is the tag of free memory.
+#[cfg(target_arch = "aarch64")]
+const EXCLUDED_TAGS: u64 = 1 << 0;
+
+/// Returns the tag of `addr`.
+#[inline]
+pub fn tag_of(addr: VAddr) -> u8 {
+    ((addr & TAG_MASK) >> TAG_SHIFT) as u8
+}
+
+/// Returns `addr` with the tag bits cleared.
+#[inline]
+pub fn untagged(addr: VAddr) -> VAddr {
+    addr & !TAG_MASK
+}
+
+/// Returns `addr` with the tag `tag`.
+#[inline]
+pub fn with_tag(addr: VAddr, tag: u8) -> VAddr {
+    untagged(addr) | ((tag as usize & 0xf) << TAG_SHIFT)
+}
+
+/// Number of bytes covered by the granules of an object of `size` bytes.
+#[inline]
+pub(crate) fn tagged_size(size: usize) -> usize {
+    (size + MTE_GRANULE - 1) & !(MTE_GRANULE - 1)
+}
+
+/// Returns `addr` with a random tag other than 0 (`IRG`).
+#[cfg(target_arch = "aarch64")]
+#[inline]
+fn random_tag(addr: VAddr) -> VAddr {
+    let tagged: usize;
+    unsafe {
+        core::arch::asm!(
+            ".arch_extension memtag",
+            "irg {0}, {1}, {2}",
+            out(reg) tagged,
+            in(reg) addr,
+            in(reg) EXCLUDED_TAGS,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    tagged
+}
+
+/// Colors the granules of `size` bytes at `addr` with the tag of `addr` (`STG`).
+#[cfg(target_arch = "aarch64")]
+#[inline]
+unsafe fn set_tag(addr: VAddr, size: usize) {
+    for granule in (addr..addr + size).step_by(MTE_GRANULE) {
+        core::arch::asm!(".arch_extension memtag", "stg {0}, [{0}]", in(reg) granule, options(nostack, preserves_flags));
+    }
+}
+
+/// Returns the tag of the granule at `addr` in tag memory (`LDG`).
+#[cfg(target_arch = "aarch64")]
+#[inline]
+unsafe fn memory_tag(addr: VAddr) -> Option<u8> {
+    let mut tagged = addr;
+    core::arch::asm!(
+        ".arch_extension memtag",
+        "ldg {0}, [{1}]",
+        inout(reg) tagged,
+        in(reg) untagged(addr),
+        options(readonly, nostack, preserves_flags)
+    );
+    Some(tag_of(tagged))
+}
+
+/// Tags pointers in software, so the tests can check that the zone strips them again.
+#[cfg(all(test, not(target_arch = "aarch64")))]
+fn random_tag(addr: VAddr) -> VAddr {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    with_tag(addr, (NEXT.fetch_add(1, Ordering::Relaxed) % 15 + 1) as u8)
+}
+
+/// Leaves objects untagged without MTE.
+#[cfg(not(any(test, target_arch = "aarch64")))]
+fn random_tag(addr: VAddr) -> VAddr {
+    addr
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+unsafe fn set_tag(_addr: VAddr, _size: usize) {}
+
+/// Tag memory is unknown without MTE.
+#[cfg(not(target_arch = "aarch64"))]
+unsafe fn memory_tag(_addr: VAddr) -> Option<u8> {
+    None
+}
+
+/// Colors the object of `size` bytes at `ptr` (which must be granule aligned)
+/// with a random tag and returns the tagged pointer.
+///
+/// # Safety
+/// `ptr` must be a freshly allocated object of at least `tagged_size(size)` bytes.
+pub(crate) unsafe fn tag_object(ptr: NonNull<u8>, size: usize) -> NonNull<u8> {
+    let tagged = random_tag(ptr.as_ptr() as VAddr);
+    set_tag(tagged, tagged_size(size));
+    NonNull::new_unchecked(tagged as *mut u8)
+}
+
+/// Recolors the object at `ptr` after it was resized in place from `old_size` to `new_size` bytes,
+/// the granules it gave up get tag 0.
+///
+/// # Safety
+/// `ptr` must be an allocated object whose slot holds `new_size` bytes.
+pub(crate) unsafe fn resize_tag(ptr: NonNull<u8>, old_size: usize, new_size: usize) {
+    let addr = ptr.as_ptr() as VAddr;
+    if tag_of(addr) == 0 {
+        return;
+    }
+    let (old, new) = (tagged_size(old_size), tagged_size(new_size));
+    if new > old {
+        set_tag(addr + old, new - old);
+    } else {
+        set_tag(untagged(addr) + new, old - new);
+    }
+}
+
+/// Colors the object of `size` bytes at `ptr` with tag 0 again and returns the untagged pointer.
+///
+/// Fails with `AllocationError::DoubleFree` if the tag of `ptr` is not the tag of the object,
+/// i.e., the object was already freed (or `ptr` is a stale pointer of an earlier allocation).
+/// Untagged pointers are returned as is.
+///
+/// # Safety
+/// `ptr` must point into a page of the heap.
+pub(crate) unsafe fn untag_object(ptr: NonNull<u8>, size: usize) -> Result<NonNull<u8>, AllocationError> {
+    let addr = ptr.as_ptr() as VAddr;
+    let tag = tag_of(addr);
+    if tag == 0 {
+        return Ok(ptr);
+    }
+    if matches!(memory_tag(addr), Some(stored) if stored != tag) {
+        error!("{:p} is freed with tag {}, the object has a different one", ptr, tag);
+        return Err(AllocationError::DoubleFree);
+    }
+    set_tag(untagged(addr), tagged_size(size));
+    Ok(NonNull::new_unchecked(untagged(addr) as *mut u8))
+}
@@ -0,0 +1,287 @@
+//! Pages that keep their meta-data out of band (with the `out-of-band-metadata` feature).
+//!
+//! The bitfield, list links and `MappedPages` of an `OutOfBandPage8k` live in a separate
+//! meta-data arena instead of at the end of the page. All 8 KiB of a page hold objects
+//! (so the biggest base size class is a power of two), and a buffer overflow in an object
+//! can't reach the allocator meta-data.
+//!
+//! The arena covers one contiguous virtual range (e.g., the kernel heap) and has an entry
+//! for every 8 KiB page of it, so the meta-data of an object is found with a subtraction
+//! and a shift, like the in-band meta-data is found by masking the address.
+
+use crate::*;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// The meta-data arena of all `OutOfBandPage8k` pages.
+///
+/// It has to be set up with `MetadataArena::init` before the first page is handed to an allocator.
+pub static METADATA_ARENA: MetadataArena = MetadataArena::new();
+
+/// Maps the pages of a virtual range to their meta-data entries.
+pub struct MetadataArena {
+    /// Start of the range the pages come from.
+    start: AtomicUsize,
+    /// Number of pages in the range (0 until the arena is initialized).
+    pages: AtomicUsize,
+    /// Address of the first entry.
+    entries: AtomicUsize,
+}
+
+impl MetadataArena {
+    /// Size of the meta-data of one page.
+    pub const ENTRY_SIZE: usize = mem::size_of::<OutOfBandPage8k>();
+
+    const fn new() -> MetadataArena {
+        MetadataArena {
+            start: AtomicUsize::new(0),
+            pages: AtomicUsize::new(0),
+            entries: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of bytes of meta-data needed for a range of `size` bytes.
+    pub const fn bytes_for(size: usize) -> usize {
+        size / OutOfBandPage8k::SIZE * MetadataArena::ENTRY_SIZE
+    }
+
+    /// Sets up the arena for the pages in `[start, start + size)`, with their meta-data in `entries`
+    /// (`MetadataArena::bytes_for(size)` bytes).
+    ///
+    /// Fails with `AllocationError::InvalidLayout` if the arena is already set up,
+    /// `start` is not aligned to `OutOfBandPage8k::SIZE` or `entries` is not aligned for an entry.
+    ///
+    /// # Safety
+    /// `entries` has to be valid, writable and not used by anything else for the rest of the
+    /// program, and must not lie in the range.
+    pub unsafe fn init(&self, start: VAddr, size: usize, entries: NonNull<u8>) -> Result<(), AllocationError> {
+        let entries = entries.as_ptr() as usize;
+        if !start.is_multiple_of(OutOfBandPage8k::SIZE) || !entries.is_multiple_of(mem::align_of::<OutOfBandPage8k>()) {
+            return Err(AllocationError::InvalidLayout);
+        }
+        if self.pages.load(Ordering::Acquire) != 0 {
+            error!("The meta-data arena is already set up");
+            return Err(AllocationError::InvalidLayout);
+        }
+        ptr::write_bytes(entries as *mut u8, 0, MetadataArena::bytes_for(size));
+        self.start.store(start, Ordering::Relaxed);
+        self.entries.store(entries, Ordering::Relaxed);
+        self.pages.store(size / OutOfBandPage8k::SIZE, Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns `true` if the arena has an entry for the page containing `addr`.
+    pub fn covers(&self, addr: VAddr) -> bool {
+        let pages = self.pages.load(Ordering::Acquire);
+        addr.wrapping_sub(self.start.load(Ordering::Relaxed)) / OutOfBandPage8k::SIZE < pages
+    }
+
+    /// The entry of the page containing `addr`, null if it lies outside the range.
+    fn entry(&self, addr: VAddr) -> *mut u8 {
+        let pages = self.pages.load(Ordering::Acquire);
+        let idx = addr.wrapping_sub(self.start.load(Ordering::Relaxed)) / OutOfBandPage8k::SIZE;
+        if idx >= pages {
+            return ptr::null_mut();
+        }
+        (self.entries.load(Ordering::Relaxed) + idx * MetadataArena::ENTRY_SIZE) as *mut u8
+    }
+
+    /// Start of the page described by the entry at `entry`.
+    fn page_of(&self, entry: usize) -> VAddr {
+        let idx = (entry - self.entries.load(Ordering::Relaxed)) / MetadataArena::ENTRY_SIZE;
+        self.start.load(Ordering::Relaxed) + idx * OutOfBandPage8k::SIZE
+    }
+}
+
+/// The meta-data of an 8 KiB page whose objects take up the whole page.
+///
+/// # Notes
+/// An object of this type lives in the `METADATA_ARENA`, never in the page itself,
+/// so pages have to come from the range of the arena.
+#[repr(C)]
+pub struct OutOfBandPage8k<'a> {
+    pub mp: MappedPages,
+
+    /// Offset of the first object (see `AllocablePage::color`).
+    color: u32,
+
+    /// NUMA node of the page's memory (see `AllocablePage::node`).
+    node: u16,
+
+    /// Memory tier of the page's memory (see `AllocablePage::tier`).
+    tier: u16,
+
+    /// Set to `PAGE_MAGIC` while the page is part of a heap.
+    magic: u64,
+
+    pub heap_id: usize,
+
+    /// Next element in list (used by `PageList`).
+    next: Rawlink<OutOfBandPage8k<'a>>,
+    /// Previous element in  list (used by `PageList`)
+    prev: Rawlink<OutOfBandPage8k<'a>>,
+
+    /// A bit-field to track free/allocated memory within the page.
+    pub(crate) bitfield: [AtomicU64; 16],
+}
+
+unsafe impl<'a> Send for OutOfBandPage8k<'a> {}
+unsafe impl<'a> Sync for OutOfBandPage8k<'a> {}
+
+impl<'a> AllocablePage for OutOfBandPage8k<'a> {
+    const SIZE: usize = 8192;
+    /// The meta-data is not part of the page.
+    const METADATA_SIZE: usize = 0;
+    /// Not applicable, the heap id is not part of the page.
+    const HEAP_ID_OFFSET: usize = 0;
+    const BITFIELD_WORDS: usize = 16;
+
+    /// Creates the meta-data for the page `mp`, see `write_in_place` to put it into the arena.
+    fn new(mp: MappedPages, heap_id: usize) -> Result<OutOfBandPage8k<'a>, AllocationError> {
+        check_mapped_pages(&mp, Self::SIZE)?;
+        Ok(OutOfBandPage8k::new_unchecked(mp, heap_id))
+    }
+
+    /// Writes the meta-data of `mp` into its entry of the arena.
+    ///
+    /// Fails with `AllocationError::InvalidPage` if `mp` lies outside the arena.
+    unsafe fn write_in_place(mp: MappedPages, heap_id: usize) -> Result<*mut OutOfBandPage8k<'a>, AllocationError> {
+        check_mapped_pages(&mp, Self::SIZE)?;
+        let page = Self::write_unmapped(mp.start_address().value(), heap_id)?;
+        (*page).mp = mp;
+        Ok(page)
+    }
+
+    /// Fails with `AllocationError::InvalidPage` if `start` lies outside the arena.
+    unsafe fn write_unmapped(start: VAddr, heap_id: usize) -> Result<*mut OutOfBandPage8k<'a>, AllocationError> {
+        let page = Self::from_address(start);
+        if page.is_null() {
+            error!("Page {:#x} is not covered by the meta-data arena", start);
+            return Err(AllocationError::InvalidPage("page lies outside the meta-data arena"));
+        }
+        page.write(Self::new_unchecked(MappedPages::empty(), heap_id));
+        ptr::write_bytes(start as *mut u8, 0, Self::SIZE);
+        Ok(page)
+    }
+
+    fn data_start(&self) -> VAddr {
+        METADATA_ARENA.page_of(self as *const Self as usize)
+    }
+
+    fn from_address(addr: VAddr) -> *mut Self {
+        METADATA_ARENA.entry(addr) as *mut Self
+    }
+
+    fn retrieve_mapped_pages(&mut self) -> MappedPages {
+        let mut mp = MappedPages::empty();
+        core::mem::swap(&mut self.mp, &mut mp);
+        self.magic = 0;
+        mp
+    }
+
+    fn mapped_pages(&self) -> &MappedPages {
+        &self.mp
+    }
+
+    fn set_mapped_pages(&mut self, mp: MappedPages) {
+        // The stale `MappedPages` must not be dropped, they may still map the page
+        unsafe { ptr::write(&mut self.mp, mp) };
+    }
+
+    fn clear_metadata(&mut self) {
+        self.color = 0;
+        self.node = 0;
+        self.tier = 0;
+        self.magic = 0;
+        self.heap_id = 0;
+        self.next = Rawlink::default();
+        self.prev = Rawlink::default();
+        for bf in &self.bitfield {
+            bf.store(0, Ordering::SeqCst);
+        }
+    }
+
+    fn set_heap_id(&mut self, heap_id: usize) {
+        self.heap_id = heap_id;
+    }
+
+    fn heap_id(&self) -> usize {
+        self.heap_id
+    }
+
+    fn magic(&self) -> u64 {
+        self.magic
+    }
+
+    fn color(&self) -> usize {
+        self.color as usize
+    }
+
+    fn set_color(&mut self, color: usize) {
+        self.color = color as u32;
+    }
+
+    fn node(&self) -> usize {
+        self.node as usize
+    }
+
+    fn set_node(&mut self, node: usize) {
+        self.node = node as u16;
+    }
+
+    fn tier(&self) -> MemoryTier {
+        MemoryTier::from_tag(self.tier)
+    }
+
+    fn set_tier(&mut self, tier: MemoryTier) {
+        self.tier = tier as u16;
+    }
+
+    fn bitfield(&self) -> &[AtomicU64] {
+        &self.bitfield
+    }
+
+    fn bitfield_mut(&mut self) -> &mut [AtomicU64] {
+        &mut self.bitfield
+    }
+
+    fn prev(&mut self) -> &mut Rawlink<Self> {
+        &mut self.prev
+    }
+
+    fn next(&mut self) -> &mut Rawlink<Self> {
+        &mut self.next
+    }
+
+    fn buffer_size() -> usize {
+        OutOfBandPage8k::SIZE
+    }
+}
+
+impl<'a> OutOfBandPage8k<'a> {
+    /// The meta-data of a fresh page holding `mp` (which is not checked).
+    fn new_unchecked(mp: MappedPages, heap_id: usize) -> OutOfBandPage8k<'a> {
+        OutOfBandPage8k {
+            mp,
+            color: 0,
+            node: 0,
+            tier: 0,
+            magic: PAGE_MAGIC,
+            heap_id,
+            next: Rawlink::default(),
+            prev: Rawlink::default(),
+            bitfield: [const { AtomicU64::new(0) }; 16],
+        }
+    }
+}
+
+impl<'a> Default for OutOfBandPage8k<'a> {
+    fn default() -> OutOfBandPage8k<'a> {
+        unsafe { mem::MaybeUninit::zeroed().assume_init() }
+    }
+}
+
+impl<'a> fmt::Debug for OutOfBandPage8k<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OutOfBandPage8k")
+    }
+}
@@ -1,6 +1,121 @@
 use crate::*;
+use core::cmp;
 use core::sync::atomic::{AtomicU64, Ordering};
 
+/// Byte pattern written into freed objects when the `debug-poison` feature is enabled.
+#[cfg(feature = "debug-poison")]
+pub const POISON_BYTE: u8 = 0xDE;
+
+/// Fills `size` bytes starting at `addr` with `POISON_BYTE`.
+#[cfg(feature = "debug-poison")]
+pub(crate) fn poison(addr: usize, size: usize) {
+    unsafe { ptr::write_bytes(addr as *mut u8, POISON_BYTE, size) };
+}
+
+/// Checks that the `size` bytes starting at `addr` still contain `POISON_BYTE`
+/// and reports a use-after-free write otherwise.
+#[cfg(feature = "debug-poison")]
+pub(crate) fn check_poison(addr: usize, size: usize) -> bool {
+    let object = unsafe { core::slice::from_raw_parts(addr as *const u8, size) };
+    match object.iter().position(|b| *b != POISON_BYTE) {
+        Some(offset) => {
+            error!(
+                "Use-after-free detected: freed object {:#x} was modified at offset {}",
+                addr, offset
+            );
+            false
+        }
+        None => true,
+    }
+}
+
+/// Byte pattern that fills the red zones around objects when the `red-zones` feature is enabled.
+#[cfg(feature = "red-zones")]
+pub const RED_ZONE_BYTE: u8 = 0xCA;
+
+/// Minimum size of the red zone in front of and behind every object.
+#[cfg(feature = "red-zones")]
+pub const RED_ZONE_SIZE: usize = mem::size_of::<u64>();
+
+/// Size of the red zone in front of an object described by `layout`.
+///
+/// It is at least `RED_ZONE_SIZE` but grows to the alignment of `layout`
+/// so the object itself stays aligned within the slot.
+#[cfg(feature = "red-zones")]
+pub(crate) const fn red_zone_front(layout: Layout) -> usize {
+    if layout.align() > RED_ZONE_SIZE {
+        layout.align()
+    } else {
+        RED_ZONE_SIZE
+    }
+}
+
+/// Returns the layout of the slot that holds an object of `layout` together with its red zones.
+#[cfg(feature = "red-zones")]
+pub const fn red_zone_layout(layout: Layout) -> Layout {
+    let size = red_zone_front(layout) + layout.size() + RED_ZONE_SIZE;
+    unsafe { Layout::from_size_align_unchecked(size, layout.align()) }
+}
+
+/// Fills the red zones of a slot at `slot` (of `slot_size` bytes) that holds an object of `layout`
+/// and returns the address of the object.
+///
+/// The front red zone starts at the slot, the back red zone covers everything
+/// from the end of the object to the end of the slot.
+#[cfg(feature = "red-zones")]
+pub(crate) fn write_red_zones(slot: usize, slot_size: usize, layout: Layout) -> usize {
+    let front = red_zone_front(layout);
+    let back = slot + front + layout.size();
+    unsafe {
+        ptr::write_bytes(slot as *mut u8, RED_ZONE_BYTE, front);
+        ptr::write_bytes(back as *mut u8, RED_ZONE_BYTE, slot + slot_size - back);
+    }
+    slot + front
+}
+
+/// Returns the slot address of an object at `object` that was allocated with `layout`.
+#[cfg(feature = "red-zones")]
+pub(crate) fn red_zone_slot(object: usize, layout: Layout) -> Option<usize> {
+    object.checked_sub(red_zone_front(layout))
+}
+
+/// Checks that the red zones of a slot holding an object of `layout` are intact
+/// and reports a buffer overflow otherwise.
+#[cfg(feature = "red-zones")]
+pub(crate) fn check_red_zones(slot: usize, slot_size: usize, layout: Layout) -> bool {
+    let front = red_zone_front(layout);
+    let back = front + layout.size();
+    let zones = [(0, front), (back, slot_size - back)];
+    zones.iter().all(|&(offset, len)| check_red_zone(slot, offset, len))
+}
+
+/// Checks the guard words at the very start and end of a slot.
+///
+/// Used when the layout of the object in the slot is unknown (e.g., during `verify`).
+#[cfg(feature = "red-zones")]
+pub(crate) fn check_slot_guards(slot: usize, slot_size: usize) -> bool {
+    check_red_zone(slot, 0, RED_ZONE_SIZE) && check_red_zone(slot, slot_size - RED_ZONE_SIZE, RED_ZONE_SIZE)
+}
+
+#[cfg(feature = "red-zones")]
+fn check_red_zone(slot: usize, offset: usize, len: usize) -> bool {
+    let zone = unsafe { core::slice::from_raw_parts((slot + offset) as *const u8, len) };
+    match zone.iter().position(|b| *b != RED_ZONE_BYTE) {
+        Some(pos) => {
+            error!(
+                "Red zone of slot {:#x} was overwritten at offset {}",
+                slot,
+                offset + pos
+            );
+            false
+        }
+        None => true,
+    }
+}
+
+/// Magic value stored in the meta-data of every page that belongs to a heap ("SLABPAGE").
+pub const PAGE_MAGIC: u64 = 0x534C_4142_5041_4745;
+
 /// A trait defining bitfield operations we need for tracking allocated objects within a page.
 pub(crate) trait Bitfield {
     fn initialize(&mut self, for_size: usize, capacity: usize);
@@ -13,9 +128,11 @@ pub(crate) trait Bitfield {
     ) -> Option<(usize, usize)>;
     fn is_allocated(&self, idx: usize) -> bool;
     fn set_bit(&self, idx: usize);
-    fn clear_bit(&self, idx: usize);
+    fn clear_bit(&self, idx: usize) -> bool;
     fn is_full(&self) -> bool;
     fn all_free(&self, relevant_bits: usize) -> bool;
+    fn free_slots(&self) -> usize;
+    fn nth_free(&self, n: usize) -> Option<usize>;
 }
 
 /// Implementation of bit operations on u64 slices.
@@ -59,6 +176,7 @@ impl Bitfield for [AtomicU64] {
         for (base_idx, b) in self.iter().enumerate() {
             let bitval = b.load(Ordering::Relaxed);
             if bitval == u64::max_value() {
+                prefetch(self.as_ptr().wrapping_add(base_idx + 1));
                 continue;
             } else {
                 let negated = !bitval;
@@ -66,7 +184,6 @@ impl Bitfield for [AtomicU64] {
                 let idx: usize = base_idx * 64 + first_free;
                 let offset = idx * layout.size();
 
-                // TODO(bad): psize needs to be passed as arg
                 let offset_inside_data_area = offset <= (page_size - metadata_size - layout.size());
                 if !offset_inside_data_area {
                     return None;
@@ -100,11 +217,13 @@ impl Bitfield for [AtomicU64] {
     }
 
     /// Clears bit number `idx` in the bit-field.
+    ///
+    /// Returns `true` if the bit was set before.
     #[inline(always)]
-    fn clear_bit(&self, idx: usize) {
+    fn clear_bit(&self, idx: usize) -> bool {
         let base_idx = idx / 64;
         let bit_idx = idx % 64;
-        self[base_idx].fetch_and(!(1 << bit_idx), Ordering::Relaxed);
+        self[base_idx].fetch_and(!(1 << bit_idx), Ordering::Relaxed) & (1 << bit_idx) != 0
     }
 
     /// Checks if we could allocate more objects of a given `alloc_size` within the
@@ -148,6 +267,56 @@ impl Bitfield for [AtomicU64] {
 
         true
     }
+
+    /// Index of the `n`-th free slot (counting from 0).
+    #[inline(always)]
+    fn nth_free(&self, mut n: usize) -> Option<usize> {
+        for (base_idx, b) in self.iter().enumerate() {
+            let mut free = !b.load(Ordering::Relaxed);
+            let count = free.count_ones() as usize;
+            if n >= count {
+                n -= count;
+                continue;
+            }
+            // Drop the lowest `n` free bits
+            for _ in 0..n {
+                free &= free - 1;
+            }
+            return Some(base_idx * 64 + free.trailing_zeros() as usize);
+        }
+        None
+    }
+
+    /// Number of free slots (slots that don't exist in the page are always marked allocated).
+    #[inline(always)]
+    fn free_slots(&self) -> usize {
+        self.iter()
+            .map(|bitmap| bitmap.load(Ordering::Relaxed).count_zeros() as usize)
+            .sum()
+    }
+}
+
+/// The kind of memory backing a page (see `AllocablePage::tier`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryTier {
+    /// Regular DRAM (the default).
+    Dram,
+    /// A slower tier, e.g., CXL-attached or persistent memory.
+    Slow,
+}
+
+impl MemoryTier {
+    /// Number of tiers.
+    pub const COUNT: usize = 2;
+
+    /// The tier stored as `tag` in the meta-data of a page.
+    pub(crate) fn from_tag(tag: u16) -> MemoryTier {
+        if tag == MemoryTier::Slow as u16 {
+            MemoryTier::Slow
+        } else {
+            MemoryTier::Dram
+        }
+    }
 }
 
 /// This trait is used to define a page from which objects are allocated
@@ -168,15 +337,57 @@ pub trait AllocablePage {
 
     const HEAP_ID_OFFSET: usize;
 
-    fn new(mp: MappedPages, heap_id: usize) -> Result<Self, &'static str>
+    /// Number of 64-bit words of the bitfield (one bit per object).
+    ///
+    /// A page holds at most `BITFIELD_WORDS * 64` objects, whatever their size.
+    const BITFIELD_WORDS: usize = 8;
+
+    fn new(mp: MappedPages, heap_id: usize) -> Result<Self, AllocationError>
+    where
+        Self: core::marker::Sized;
+
+    /// Turns `mp` into a page that lives at the start of `mp` (with `mp` in its meta-data).
+    ///
+    /// The default builds the page with `new` and moves it into place,
+    /// pages that are too big to be built on the stack initialize it in place instead.
+    ///
+    /// # Safety
+    /// `mp` must not be in use by anything else.
+    unsafe fn write_in_place(mp: MappedPages, heap_id: usize) -> Result<*mut Self, AllocationError>
+    where
+        Self: core::marker::Sized,
+    {
+        let page = mp.start_address().value() as *mut Self;
+        page.write(Self::new(mp, heap_id)?);
+        Ok(page)
+    }
+    /// Turns the `SIZE` bytes at `start` into a page that is not backed by `MappedPages` (yet),
+    /// e.g., a page of a bootstrap buffer (see `SCAllocator::refill_bootstrap`).
+    ///
+    /// # Safety
+    /// The memory at `start` has to be aligned to `SIZE`, writable, not in use by anything else
+    /// and valid for the rest of the program.
+    unsafe fn write_unmapped(start: VAddr, heap_id: usize) -> Result<*mut Self, AllocationError>
     where
         Self: core::marker::Sized;
     fn retrieve_mapped_pages(&mut self) -> MappedPages;
+    /// The `MappedPages` backing the page.
+    fn mapped_pages(&self) -> &MappedPages;
+    /// Hands the page the `MappedPages` that cover it (e.g., a page created by `write_unmapped`).
+    ///
+    /// The previous `MappedPages` in the meta-data (empty or stale) is forgotten, not dropped.
+    fn set_mapped_pages(&mut self, mp: MappedPages);
+    /// Returns `true` if the page is not backed by `MappedPages` (see `write_unmapped`).
+    fn is_unmapped(&self) -> bool {
+        self.mapped_pages().size_in_bytes() == 0
+    }
     fn clear_metadata(&mut self);
     fn set_heap_id(&mut self, heap_id: usize);
     fn heap_id(&self) -> usize;
-    fn bitfield(&self) -> &[AtomicU64; 8];
-    fn bitfield_mut(&mut self) -> &mut [AtomicU64; 8];
+    /// The magic value stored in the page meta-data (`PAGE_MAGIC` for a valid page).
+    fn magic(&self) -> u64;
+    fn bitfield(&self) -> &[AtomicU64];
+    fn bitfield_mut(&mut self) -> &mut [AtomicU64];
     fn prev(&mut self) -> &mut Rawlink<Self>
     where
         Self: core::marker::Sized;
@@ -185,10 +396,64 @@ pub trait AllocablePage {
         Self: core::marker::Sized;
     fn buffer_size() -> usize;
 
+    /// Start of the data area of the page.
+    ///
+    /// The meta-data of the default pages is at the end of the page itself,
+    /// so this is the address of the page.
+    fn data_start(&self) -> VAddr {
+        (self as *const Self as *const u8) as VAddr
+    }
+
+    /// Offset of the first object from `data_start` (see `SCAllocator::set_cache_coloring`).
+    ///
+    /// Pages that don't store a color always start with their first object.
+    fn color(&self) -> usize {
+        0
+    }
+
+    /// Sets the offset of the first object, only while the page holds no objects.
+    fn set_color(&mut self, _color: usize) {}
+
+    /// NUMA node the memory of the page belongs to (see `ZoneAllocator::set_node`).
+    ///
+    /// Pages that don't store a node are on node 0.
+    fn node(&self) -> usize {
+        0
+    }
+
+    /// Tags the page with the NUMA node of its memory.
+    fn set_node(&mut self, _node: usize) {}
+
+    /// Memory tier the page belongs to (see `ZoneAllocator::allocate_with_tier_preference`).
+    ///
+    /// Pages that don't store a tier are DRAM.
+    fn tier(&self) -> MemoryTier {
+        MemoryTier::Dram
+    }
+
+    /// Tags the page with the memory tier of its memory.
+    fn set_tier(&mut self, _tier: MemoryTier) {}
+
+    /// Address of the first object of the page.
+    fn objects_start(&self) -> VAddr {
+        self.data_start() + self.color()
+    }
+
+    /// Returns the page (i.e., its meta-data) of the object at `addr`,
+    /// or null if no page of this type can hold `addr`.
+    ///
+    /// `addr` may also be the start of the page.
+    fn from_address(addr: VAddr) -> *mut Self
+    where
+        Self: core::marker::Sized,
+    {
+        (addr & !(Self::SIZE - 1)) as *mut Self
+    }
+
     /// Tries to find a free block within `data` that satisfies `alignment` requirement.
     fn first_fit(&self, layout: Layout) -> Option<(usize, usize)> {
-        let base_addr = (&*self as *const Self as *const u8) as usize;
-        self.bitfield().first_fit(base_addr, layout, Self::SIZE, Self::METADATA_SIZE)
+        let base_addr = self.objects_start();
+        self.bitfield().first_fit(base_addr, layout, Self::SIZE, Self::METADATA_SIZE + self.color())
     }
 
     /// Tries to allocate an object within this page.
@@ -198,12 +463,53 @@ pub trait AllocablePage {
         match self.first_fit(layout) {
             Some((idx, addr)) => {
                 self.bitfield().set_bit(idx);
+                #[cfg(feature = "debug-poison")]
+                check_poison(addr, layout.size());
                 addr as *mut u8
             }
             None => ptr::null_mut(),
         }
     }
 
+    /// Tries to allocate an object in a free slot picked by `random`
+    /// rather than the first free slot (see `SCAllocator::set_randomized_slots`).
+    ///
+    /// Falls back to `allocate` if the picked slot doesn't satisfy the alignment of `layout`.
+    /// In case the slab is full, returns a null ptr.
+    fn allocate_random(&mut self, layout: Layout, random: u64) -> *mut u8 {
+        let free = self.free_objects();
+        if free == 0 {
+            return ptr::null_mut();
+        }
+        let base_addr = self.objects_start();
+        match self.bitfield().nth_free(random as usize % free) {
+            Some(idx) if (base_addr + idx * layout.size()).is_multiple_of(layout.align()) => {
+                let addr = base_addr + idx * layout.size();
+                self.bitfield().set_bit(idx);
+                #[cfg(feature = "debug-poison")]
+                check_poison(addr, layout.size());
+                addr as *mut u8
+            }
+            _ => self.allocate(layout),
+        }
+    }
+
+    /// Checks that the page meta-data still carries `PAGE_MAGIC`.
+    ///
+    /// A mismatch means the page was never handed to a heap,
+    /// or its meta-data got overwritten (e.g., by a wild write).
+    fn is_intact(&self) -> bool {
+        if self.magic() == PAGE_MAGIC {
+            true
+        } else {
+            error!(
+                "Page {:p} has a corrupted header (magic = {:#x})",
+                self as *const Self, self.magic()
+            );
+            false
+        }
+    }
+
     /// Checks if we can still allocate more objects of a given layout within the page.
     fn is_full(&self) -> bool {
         self.bitfield().is_full()
@@ -214,23 +520,56 @@ pub trait AllocablePage {
         self.bitfield().all_free(relevant_bits)
     }
 
+    /// Number of objects that can still be allocated within the page.
+    fn free_objects(&self) -> usize {
+        self.bitfield().free_slots()
+    }
+
+    /// Invokes `f` with the address of every allocated object within this page.
+    ///
+    /// # Arguments
+    ///  * `obj_size`: Object size of the `SCAllocator` this page belongs to.
+    ///  * `relevant_bits`: Number of objects that fit in the page.
+    fn for_each_allocated<F: FnMut(NonNull<u8>)>(&self, obj_size: usize, relevant_bits: usize, mut f: F) {
+        let base_addr = self.objects_start();
+        for idx in 0..relevant_bits {
+            if self.bitfield().is_allocated(idx) {
+                if let Some(ptr) = NonNull::new((base_addr + idx * obj_size) as *mut u8) {
+                    f(ptr);
+                }
+            }
+        }
+    }
+
     /// Deallocates a memory object within this page.
-    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), &'static str> {
+    ///
+    /// Returns an error and leaves the bitfield untouched if the object
+    /// is not marked as allocated (i.e., it was already freed).
+    /// The bit is checked and cleared in one atomic operation, so of two racing
+    /// frees of the same object only one succeeds.
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
         // trace!(
         //     "AllocablePage deallocating ptr = {:p} with {:?}",
         //     ptr,
         //     layout
         // );
-        let page_offset = (ptr.as_ptr() as usize) & (Self::SIZE - 1);
-        assert!(page_offset % layout.size() == 0);
+        let page_offset = (ptr.as_ptr() as usize).wrapping_sub(self.objects_start());
+        if !page_offset.is_multiple_of(layout.size()) || page_offset >= Self::SIZE - Self::METADATA_SIZE - self.color() {
+            error!("{:p} is not a valid object address for size {}", ptr, layout.size());
+            return Err(AllocationError::InvalidPointer);
+        }
         let idx = page_offset / layout.size();
-        assert!(
-            self.bitfield().is_allocated(idx),
-            "{:p} not marked allocated?",
-            ptr
-        );
 
-        self.bitfield().clear_bit(idx);
+        // Poisoned while the object is still marked allocated, so it can't be handed out again meanwhile
+        #[cfg(feature = "debug-poison")]
+        if self.bitfield().is_allocated(idx) {
+            poison(ptr.as_ptr() as usize, layout.size());
+        }
+
+        if !self.bitfield().clear_bit(idx) {
+            error!("Double free detected: {:p} is not marked allocated", ptr);
+            return Err(AllocationError::DoubleFree);
+        }
         Ok(())
     }
 }
@@ -254,6 +593,18 @@ pub struct ObjectPage8k<'a> {
     
     pub mp: MappedPages,
 
+    /// Offset of the first object (see `AllocablePage::color`).
+    color: u32,
+
+    /// NUMA node of the page's memory (see `AllocablePage::node`).
+    node: u16,
+
+    /// Memory tier of the page's memory (see `AllocablePage::tier`).
+    tier: u16,
+
+    /// Set to `PAGE_MAGIC` while the page is part of a heap.
+    magic: u64,
+
     pub heap_id: usize,
 
     /// Next element in list (used by `PageList`).
@@ -262,7 +613,8 @@ pub struct ObjectPage8k<'a> {
     prev: Rawlink<ObjectPage8k<'a>>,
 
     /// A bit-field to track free/allocated memory within `data`.
-    pub(crate) bitfield: [AtomicU64; 8],
+    /// It has a bit for every 8-byte object of the page, so the 8- and 16-byte size classes use the whole page.
+    pub(crate) bitfield: [AtomicU64; 16],
 }
 
 
@@ -272,39 +624,39 @@ unsafe impl<'a> Sync for ObjectPage8k<'a> {}
 
 impl<'a> AllocablePage for ObjectPage8k<'a> {
     const SIZE: usize = 8192;
-    const METADATA_SIZE: usize = core::mem::size_of::<MappedPages>() + core::mem::size_of::<usize>() + (2*core::mem::size_of::<Rawlink<ObjectPage8k<'a>>>()) + (8*8);
-    const HEAP_ID_OFFSET: usize = Self::SIZE - (core::mem::size_of::<usize>() + (2*core::mem::size_of::<Rawlink<ObjectPage8k<'a>>>()) + (8*8));
+    const METADATA_SIZE: usize = core::mem::size_of::<MappedPages>() + core::mem::size_of::<u32>() + (2*core::mem::size_of::<u16>()) + core::mem::size_of::<u64>() + core::mem::size_of::<usize>() + (2*core::mem::size_of::<Rawlink<ObjectPage8k<'a>>>()) + (16*8);
+    const HEAP_ID_OFFSET: usize = Self::SIZE - (core::mem::size_of::<usize>() + (2*core::mem::size_of::<Rawlink<ObjectPage8k<'a>>>()) + (16*8));
+    const BITFIELD_WORDS: usize = 16;
 
     /// Creates a new 8KiB allocable page and stores the MappedPages object in the metadata portion.
     /// This function checks that the given mapped pages is aligned at a 8KiB boundary, writable and has a size of 8KiB.
-    fn new(mp: MappedPages, heap_id: usize) -> Result<ObjectPage8k<'a>, &'static str> {
+    fn new(mp: MappedPages, heap_id: usize) -> Result<ObjectPage8k<'a>, AllocationError> {
         let vaddr = mp.start_address().value();
         
         if vaddr % Self::SIZE != 0 {
             error!("The mapped pages for the heap are not aligned at 8k bytes");
-            return Err("The mapped pages for the heap are not aligned at 8k bytes");
+            return Err(AllocationError::InvalidPage("The mapped pages for the heap are not aligned at 8k bytes"));
         }
 
         // check that the mapped pages is writable
         if !mp.flags().is_writable() {
             error!("Tried to convert to an allocable page but MappedPages weren't writable (flags: {:?})",  mp.flags());
-            return Err("Trying to create an allocable page but MappedPages were not writable");
+            return Err(AllocationError::InvalidPage("Trying to create an allocable page but MappedPages were not writable"));
         }
         
         // check that the mapped pages size is equal in size to the page
         if Self::SIZE != mp.size_in_bytes() {
             error!("MappedPages of size {} cannot be converted to an allocable page", mp.size_in_bytes());
-            return Err("MappedPages size does not equal allocable page size");
+            return Err(AllocationError::InvalidPage("MappedPages size does not equal allocable page size"));
         }
 
-        Ok( ObjectPage8k {
-            data: [0; ObjectPage8k::SIZE -ObjectPage8k::METADATA_SIZE],
-            mp: mp,
-            heap_id: heap_id,
-            next: Rawlink::default(),
-            prev: Rawlink::default(),
-            bitfield: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0),AtomicU64::new(0) ],
-        })
+        Ok(ObjectPage8k::with_mapped_pages(mp, heap_id))
+    }
+
+    unsafe fn write_unmapped(start: VAddr, heap_id: usize) -> Result<*mut ObjectPage8k<'a>, AllocationError> {
+        let page = start as *mut ObjectPage8k<'a>;
+        page.write(ObjectPage8k::with_mapped_pages(MappedPages::empty(), heap_id));
+        Ok(page)
     }
 
     /// Returns the MappedPages object that was stored in the metadata portion of the page,
@@ -314,11 +666,25 @@ impl<'a> AllocablePage for ObjectPage8k<'a> {
     fn retrieve_mapped_pages(&mut self) -> MappedPages {
         let mut mp = MappedPages::empty();
         core::mem::swap(&mut self.mp, &mut mp);
+        self.magic = 0;
         mp
     }
 
+    fn mapped_pages(&self) -> &MappedPages {
+        &self.mp
+    }
+
+    fn set_mapped_pages(&mut self, mp: MappedPages) {
+        // The stale `MappedPages` must not be dropped, they may still map the page
+        unsafe { ptr::write(&mut self.mp, mp) };
+    }
+
     /// clears the metadata section of the page
     fn clear_metadata(&mut self) {
+        self.color = 0;
+        self.node = 0;
+        self.tier = 0;
+        self.magic = 0;
         self.heap_id = 0;
         self.next = Rawlink::default();
         self.prev = Rawlink::default();
@@ -335,10 +701,38 @@ impl<'a> AllocablePage for ObjectPage8k<'a> {
         self.heap_id
     }
 
-    fn bitfield(&self) -> &[AtomicU64; 8] {
+    fn magic(&self) -> u64 {
+        self.magic
+    }
+
+    fn color(&self) -> usize {
+        self.color as usize
+    }
+
+    fn set_color(&mut self, color: usize) {
+        self.color = color as u32;
+    }
+
+    fn node(&self) -> usize {
+        self.node as usize
+    }
+
+    fn set_node(&mut self, node: usize) {
+        self.node = node as u16;
+    }
+
+    fn tier(&self) -> MemoryTier {
+        MemoryTier::from_tag(self.tier)
+    }
+
+    fn set_tier(&mut self, tier: MemoryTier) {
+        self.tier = tier as u16;
+    }
+
+    fn bitfield(&self) -> &[AtomicU64] {
         &self.bitfield
     }
-    fn bitfield_mut(&mut self) -> &mut [AtomicU64; 8] {
+    fn bitfield_mut(&mut self) -> &mut [AtomicU64] {
         &mut self.bitfield
     }
 
@@ -355,6 +749,34 @@ impl<'a> AllocablePage for ObjectPage8k<'a> {
     }
 }
 
+/// The page type of the base size classes of the `ZoneAllocator`,
+/// `OutOfBandPage8k` with the `out-of-band-metadata` feature.
+#[cfg(not(feature = "out-of-band-metadata"))]
+pub type BasePage<'a> = ObjectPage8k<'a>;
+
+/// The page type of the base size classes of the `ZoneAllocator`,
+/// `OutOfBandPage8k` with the `out-of-band-metadata` feature.
+#[cfg(feature = "out-of-band-metadata")]
+pub type BasePage<'a> = OutOfBandPage8k<'a>;
+
+impl<'a> ObjectPage8k<'a> {
+    /// A fresh page holding `mp` (which is not checked).
+    fn with_mapped_pages(mp: MappedPages, heap_id: usize) -> ObjectPage8k<'a> {
+        ObjectPage8k {
+            data: [0; ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE],
+            mp,
+            color: 0,
+            node: 0,
+            tier: 0,
+            magic: PAGE_MAGIC,
+            heap_id,
+            next: Rawlink::default(),
+            prev: Rawlink::default(),
+            bitfield: [const { AtomicU64::new(0) }; 16],
+        }
+    }
+}
+
 impl<'a> Default for ObjectPage8k<'a> {
     fn default() -> ObjectPage8k<'a> {
         unsafe { mem::MaybeUninit::zeroed().assume_init() }
@@ -367,28 +789,281 @@ impl<'a> fmt::Debug for ObjectPage8k<'a> {
     }
 }
 
-/// A list of pages.
-pub(crate) struct PageList<'a, T: AllocablePage> {
+/// Checks that `mp` can hold a page of `size` bytes:
+/// it has to be aligned to `size`, writable and exactly `size` bytes big.
+pub(crate) fn check_mapped_pages(mp: &MappedPages, size: usize) -> Result<(), AllocationError> {
+    if !mp.start_address().value().is_multiple_of(size) {
+        error!("The mapped pages for the heap are not aligned at {} bytes", size);
+        return Err(AllocationError::InvalidPage("The mapped pages for the heap are not aligned at the page size"));
+    }
+    if !mp.flags().is_writable() {
+        error!("Tried to convert to an allocable page but MappedPages weren't writable (flags: {:?})", mp.flags());
+        return Err(AllocationError::InvalidPage("Trying to create an allocable page but MappedPages were not writable"));
+    }
+    if size != mp.size_in_bytes() {
+        error!("MappedPages of size {} cannot be converted to an allocable page", mp.size_in_bytes());
+        return Err(AllocationError::InvalidPage("MappedPages size does not equal allocable page size"));
+    }
+    Ok(())
+}
+
+/// The physical address `addr` (an address within `mp`) is mapped to.
+#[cfg(feature = "theseus")]
+pub(crate) fn physical_address(mp: &MappedPages, addr: VAddr) -> Option<PhysicalAddress> {
+    let offset = addr.checked_sub(mp.start_address().value())?;
+    if offset >= mp.size_in_bytes() {
+        return None;
+    }
+    let vaddr = memory::VirtualAddress::new(addr)?;
+    memory::get_kernel_mmi_ref()?.lock().page_table.translate(vaddr)
+}
+
+/// The physical address `addr` (an address within `mp`) is mapped to.
+#[cfg(not(feature = "theseus"))]
+pub(crate) fn physical_address(mp: &MappedPages, addr: VAddr) -> Option<PhysicalAddress> {
+    mp.translate(addr)
+}
+
+/// Defines an `AllocablePage` of `$size` bytes with `$words` bitfield words,
+/// laid out like `ObjectPage8k` (data first, meta-data at the end of the page).
+macro_rules! object_page {
+    ($(#[$attr:meta])* $name:ident, $size:expr, $words:expr) => {
+        $(#[$attr])*
+        #[repr(C)]
+        pub struct $name<'a> {
+            /// Holds memory objects.
+            #[allow(dead_code)]
+            data: [u8; $name::SIZE - $name::METADATA_SIZE],
+
+            pub mp: MappedPages,
+
+            /// Offset of the first object (see `AllocablePage::color`).
+            color: u32,
+
+            /// NUMA node of the page's memory (see `AllocablePage::node`).
+            node: u16,
+
+            /// Memory tier of the page's memory (see `AllocablePage::tier`).
+            tier: u16,
+
+            /// Set to `PAGE_MAGIC` while the page is part of a heap.
+            magic: u64,
+
+            pub heap_id: usize,
+
+            /// Next element in list (used by `PageList`).
+            next: Rawlink<$name<'a>>,
+            /// Previous element in  list (used by `PageList`)
+            prev: Rawlink<$name<'a>>,
+
+            /// A bit-field to track free/allocated memory within `data`.
+            pub(crate) bitfield: [AtomicU64; $words],
+        }
+
+        unsafe impl<'a> Send for $name<'a> {}
+        unsafe impl<'a> Sync for $name<'a> {}
+
+        impl<'a> AllocablePage for $name<'a> {
+            const SIZE: usize = $size;
+            const METADATA_SIZE: usize = core::mem::size_of::<MappedPages>() + core::mem::size_of::<u32>() + (2*core::mem::size_of::<u16>()) + core::mem::size_of::<u64>() + core::mem::size_of::<usize>() + (2*core::mem::size_of::<Rawlink<$name<'a>>>()) + ($words*8);
+            const HEAP_ID_OFFSET: usize = Self::SIZE - (core::mem::size_of::<usize>() + (2*core::mem::size_of::<Rawlink<$name<'a>>>()) + ($words*8));
+            const BITFIELD_WORDS: usize = $words;
+
+            /// Creates a new page and stores the MappedPages object in the metadata portion.
+            ///
+            /// The page is built on the stack, `write_in_place` avoids that.
+            fn new(mp: MappedPages, heap_id: usize) -> Result<$name<'a>, AllocationError> {
+                check_mapped_pages(&mp, Self::SIZE)?;
+                Ok($name {
+                    data: [0; $name::SIZE - $name::METADATA_SIZE],
+                    mp,
+                    color: 0,
+                    node: 0,
+                    tier: 0,
+                    magic: PAGE_MAGIC,
+                    heap_id,
+                    next: Rawlink::default(),
+                    prev: Rawlink::default(),
+                    bitfield: [const { AtomicU64::new(0) }; $words],
+                })
+            }
+
+            unsafe fn write_in_place(mp: MappedPages, heap_id: usize) -> Result<*mut $name<'a>, AllocationError> {
+                check_mapped_pages(&mp, Self::SIZE)?;
+                let page = Self::write_unmapped(mp.start_address().value(), heap_id)?;
+                ptr::addr_of_mut!((*page).mp).write(mp);
+                Ok(page)
+            }
+
+            unsafe fn write_unmapped(start: VAddr, heap_id: usize) -> Result<*mut $name<'a>, AllocationError> {
+                let page = start as *mut $name<'a>;
+                ptr::write_bytes(page as *mut u8, 0, Self::SIZE - Self::METADATA_SIZE);
+                ptr::addr_of_mut!((*page).mp).write(MappedPages::empty());
+                ptr::addr_of_mut!((*page).color).write(0);
+                ptr::addr_of_mut!((*page).node).write(0);
+                ptr::addr_of_mut!((*page).tier).write(0);
+                ptr::addr_of_mut!((*page).magic).write(PAGE_MAGIC);
+                ptr::addr_of_mut!((*page).heap_id).write(heap_id);
+                ptr::addr_of_mut!((*page).next).write(Rawlink::default());
+                ptr::addr_of_mut!((*page).prev).write(Rawlink::default());
+                let bitfield = ptr::addr_of_mut!((*page).bitfield) as *mut AtomicU64;
+                for idx in 0..$words {
+                    bitfield.add(idx).write(AtomicU64::new(0));
+                }
+                Ok(page)
+            }
+
+            fn retrieve_mapped_pages(&mut self) -> MappedPages {
+                let mut mp = MappedPages::empty();
+                core::mem::swap(&mut self.mp, &mut mp);
+                self.magic = 0;
+                mp
+            }
+
+            fn mapped_pages(&self) -> &MappedPages {
+                &self.mp
+            }
+
+            fn set_mapped_pages(&mut self, mp: MappedPages) {
+                // The stale `MappedPages` must not be dropped, they may still map the page
+                unsafe { ptr::write(&mut self.mp, mp) };
+            }
+
+            fn clear_metadata(&mut self) {
+                self.color = 0;
+                self.node = 0;
+                self.tier = 0;
+                self.magic = 0;
+                self.heap_id = 0;
+                self.next = Rawlink::default();
+                self.prev = Rawlink::default();
+                for bf in &self.bitfield {
+                    bf.store(0, Ordering::SeqCst);
+                }
+            }
+
+            fn set_heap_id(&mut self, heap_id: usize) {
+                self.heap_id = heap_id;
+            }
+
+            fn heap_id(&self) -> usize {
+                self.heap_id
+            }
+
+            fn magic(&self) -> u64 {
+                self.magic
+            }
+
+            fn color(&self) -> usize {
+                self.color as usize
+            }
+
+            fn set_color(&mut self, color: usize) {
+                self.color = color as u32;
+            }
+
+            fn node(&self) -> usize {
+                self.node as usize
+            }
+
+            fn set_node(&mut self, node: usize) {
+                self.node = node as u16;
+            }
+
+            fn tier(&self) -> MemoryTier {
+                MemoryTier::from_tag(self.tier)
+            }
+
+            fn set_tier(&mut self, tier: MemoryTier) {
+                self.tier = tier as u16;
+            }
+
+            fn bitfield(&self) -> &[AtomicU64] {
+                &self.bitfield
+            }
+
+            fn bitfield_mut(&mut self) -> &mut [AtomicU64] {
+                &mut self.bitfield
+            }
+
+            fn prev(&mut self) -> &mut Rawlink<Self> {
+                &mut self.prev
+            }
+
+            fn next(&mut self) -> &mut Rawlink<Self> {
+                &mut self.next
+            }
+
+            fn buffer_size() -> usize {
+                $name::SIZE - $name::METADATA_SIZE
+            }
+        }
+
+        impl<'a> fmt::Debug for $name<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, stringify!($name))
+            }
+        }
+    };
+}
+
+object_page!(
+    /// Holds allocated data within a 16 KiB page (e.g., one page with a 16 KiB translation granule).
+    ///
+    /// Same layout as `ObjectPage8k`, with a bitfield big enough for 2048 objects of 8 bytes.
+    /// Use it with an `SCAllocator<ObjectPage16k>`, the `ZoneAllocator` always uses `BasePage`.
+    ObjectPage16k,
+    16 * 1024,
+    32
+);
+
+object_page!(
+    /// Holds allocated data within a 64 KiB page (e.g., one page with a 64 KiB translation granule).
+    ///
+    /// Same layout as `ObjectPage8k`, with a bitfield big enough for 8192 objects of 8 bytes.
+    /// Use it with an `SCAllocator<ObjectPage64k>`, the `ZoneAllocator` always uses `ObjectPage8k`.
+    ObjectPage64k,
+    64 * 1024,
+    128
+);
+
+object_page!(
+    /// Holds allocated data within a slab of four contiguous 8 KiB pages (32 KiB, aligned to 32 KiB).
+    ///
+    /// Used by the `ZoneAllocator` for objects bigger than `ZoneAllocator::MAX_BASE_ALLOC_SIZE`
+    /// (see `ZoneAllocator::LARGE_ALLOC_SIZES`), a slab holds only a few of them,
+    /// so it has the same bitfield as `ObjectPage8k`.
+    ObjectPage32k,
+    32 * 1024,
+    8
+);
+
+/// A doubly-linked list of pages, threaded through the links of the pages (see `AllocablePage::next`).
+///
+/// The pages of an `SCAllocator` sit in one list per `PageState` (see `SCAllocator::page_list`).
+/// Pages can be inspected with `iter` and moved around with a `CursorMut`.
+pub struct PageList<'a, T: AllocablePage> {
     /// Points to the head of the list.
     pub(crate) head: Option<&'a mut T>,
+    /// Points to the last element of the list.
+    tail: Rawlink<T>,
     /// Number of elements in the list.
     pub(crate) elements: usize,
+    /// Fewest elements the list had since the last `reset_low_water`.
+    low_water: usize,
 }
 
-impl<'a, T: AllocablePage> PageList<'a, T> {
-    #[cfg(feature = "unstable")]
-    pub(crate) const fn new() -> PageList<'a, T> {
-        PageList {
-            head: None,
-            elements: 0,
-        }
-    }
+// The tail is just another link to a page owned by the list.
+unsafe impl<'a, T: AllocablePage + Send> Send for PageList<'a, T> {}
 
-    #[cfg(not(feature = "unstable"))]
-    pub(crate) fn new() -> PageList<'a, T> {
+impl<'a, T: AllocablePage> PageList<'a, T> {
+    /// Creates an empty list.
+    pub const fn new() -> PageList<'a, T> {
         PageList {
             head: None,
+            tail: Rawlink { p: ptr::null_mut() },
             elements: 0,
+            low_water: 0,
         }
     }
 
@@ -404,11 +1079,44 @@ impl<'a, T: AllocablePage> PageList<'a, T> {
         }
     }
 
+    /// Number of pages in the list.
+    pub fn len(&self) -> usize {
+        self.elements
+    }
+
+    /// Iterates over the pages from front to back, use `rev` for the other direction.
+    pub fn iter(&self) -> PageIter<'_, T> {
+        PageIter {
+            front: self.head_ptr(),
+            back: self.tail.p,
+            remaining: self.elements,
+            phantom: core::marker::PhantomData,
+        }
+    }
+
+    /// Returns a cursor on the first page of the list.
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, 'a, T> {
+        let current = self.head_ptr();
+        CursorMut { list: self, current }
+    }
+
+    /// Returns a cursor on the last page of the list.
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, 'a, T> {
+        let current = self.tail.p;
+        CursorMut { list: self, current }
+    }
+
     /// Inserts `new_head` at the front of the list.
-    pub(crate) fn insert_front<'b>(&'b mut self, mut new_head: &'a mut T) {
+    ///
+    /// # Safety
+    /// `new_head` must not be on a list and has to stay in place while it is on this one.
+    /// If the list is one of an `SCAllocator` (see `SCAllocator::page_list_mut`), `new_head` has to
+    /// be a page of that allocator (aligned to `T::SIZE`, with its heap id) in the state of the list.
+    pub unsafe fn insert_front<'b>(&'b mut self, mut new_head: &'a mut T) {
         match self.head {
             None => {
                 *new_head.prev() = Rawlink::none();
+                self.tail = Rawlink::some(new_head);
                 self.head = Some(new_head);
             }
             Some(ref mut head) => {
@@ -422,6 +1130,59 @@ impl<'a, T: AllocablePage> PageList<'a, T> {
         self.elements += 1;
     }
 
+    /// Inserts `new_tail` at the end of the list.
+    ///
+    /// # Safety
+    /// See `insert_front`.
+    pub unsafe fn insert_back(&mut self, new_tail: &'a mut T) {
+        match self.tail.resolve_mut() {
+            None => self.insert_front(new_tail),
+            Some(tail) => {
+                *new_tail.prev() = Rawlink::some(tail);
+                *new_tail.next() = Rawlink::none();
+                *tail.next() = Rawlink::some(new_tail);
+                self.tail = Rawlink::some(new_tail);
+                self.elements += 1;
+            }
+        }
+    }
+
+    /// Moves all pages of `other` to the end of this list, keeping their order.
+    ///
+    /// # Safety
+    /// Every page of `other` has to be fit for this list, see `insert_front`.
+    pub unsafe fn append(&mut self, other: &mut PageList<'a, T>) {
+        let other_head = match other.head.take() {
+            Some(head) => head,
+            None => return,
+        };
+        match self.tail.resolve_mut() {
+            None => {
+                *other_head.prev() = Rawlink::none();
+                self.head = Some(other_head);
+            }
+            Some(tail) => {
+                *other_head.prev() = Rawlink::some(tail);
+                *tail.next() = Rawlink::some(other_head);
+            }
+        }
+        self.tail = other.tail.take();
+        self.elements += other.elements;
+        other.elements = 0;
+        other.low_water = 0;
+    }
+
+    /// Fewest elements the list had since the last `reset_low_water`,
+    /// i.e., the number of pages that stayed in the list all along.
+    pub(crate) fn low_water(&self) -> usize {
+        cmp::min(self.low_water, self.elements)
+    }
+
+    /// Starts a new period for `low_water` with the current elements.
+    pub(crate) fn reset_low_water(&mut self) {
+        self.low_water = self.elements;
+    }
+
     /// Removes `slab_page` from the list.
     pub(crate) fn remove_from_list(&mut self, slab_page: &mut T) {
         unsafe {
@@ -438,7 +1199,12 @@ impl<'a, T: AllocablePage> PageList<'a, T> {
             }
 
             match slab_page.next().resolve_mut() {
-                None => (),
+                None => {
+                    self.tail = match slab_page.prev().resolve_mut() {
+                        None => Rawlink::none(),
+                        Some(prev) => Rawlink::some(prev),
+                    };
+                }
                 Some(next) => {
                     *next.prev() = match slab_page.prev().resolve_mut() {
                         None => Rawlink::none(),
@@ -451,10 +1217,11 @@ impl<'a, T: AllocablePage> PageList<'a, T> {
         *slab_page.prev() = Rawlink::none();
         *slab_page.next() = Rawlink::none();
         self.elements -= 1;
+        self.low_water = cmp::min(self.low_water, self.elements);
     }
 
-    /// Removes `slab_page` from the list.
-    pub(crate) fn pop<'b>(&'b mut self) -> Option<&'a mut T> {
+    /// Removes the first page of the list.
+    pub fn pop<'b>(&'b mut self) -> Option<&'a mut T> {
         match self.head {
             None => None,
             Some(ref mut head) => {
@@ -464,8 +1231,12 @@ impl<'a, T: AllocablePage> PageList<'a, T> {
                 let _ = self.head.as_mut().map(|n| {
                     *n.prev() = Rawlink::none();
                 });
+                if self.head.is_none() {
+                    self.tail = Rawlink::none();
+                }
 
                 self.elements -= 1;
+                self.low_water = cmp::min(self.low_water, self.elements);
                 new_head.map(|node| {
                     *node.prev() = Rawlink::none();
                     *node.next() = Rawlink::none();
@@ -486,9 +1257,202 @@ impl<'a, T: AllocablePage> PageList<'a, T> {
         false
     }
 
-    pub(crate) fn is_empty(&self) -> bool {
+    /// Returns `true` if the list has no pages.
+    pub fn is_empty(&self) -> bool {
         self.elements == 0
     }
+
+    /// The first page of the list (null if the list is empty).
+    pub(crate) fn head_ptr(&self) -> *mut T {
+        match self.head {
+            None => ptr::null_mut(),
+            Some(ref head) => *head as *const T as *mut T,
+        }
+    }
+
+    /// The page after `page` in its list (null for the last page).
+    ///
+    /// # Safety
+    /// `page` has to be on a list and nothing else may access its links meanwhile.
+    pub(crate) unsafe fn next_ptr(page: *mut T) -> *mut T {
+        (*page).next().p
+    }
+
+    /// The page before `page` in its list (null for the first page), see `next_ptr`.
+    unsafe fn prev_ptr(page: *mut T) -> *mut T {
+        (*page).prev().p
+    }
+
+    /// Links the chain of `count` pages from `first` to `last` in between
+    /// `prev` and `next` (null for the ends of the list), which have to be adjacent.
+    unsafe fn link_between(&mut self, prev: *mut T, next: *mut T, first: *mut T, last: *mut T, count: usize) {
+        *(*first).prev() = Rawlink { p: prev };
+        *(*last).next() = Rawlink { p: next };
+        match prev.as_mut() {
+            None => self.head = first.as_mut(),
+            Some(prev) => *prev.next() = Rawlink { p: first },
+        }
+        match next.as_mut() {
+            None => self.tail = Rawlink { p: last },
+            Some(next) => *next.prev() = Rawlink { p: last },
+        }
+        self.elements += count;
+    }
+}
+
+impl<'a, T: AllocablePage> Default for PageList<'a, T> {
+    fn default() -> PageList<'a, T> {
+        PageList::new()
+    }
+}
+
+/// Iterates over the pages of a `PageList` (see `PageList::iter`).
+pub struct PageIter<'l, T: AllocablePage> {
+    front: *mut T,
+    back: *mut T,
+    remaining: usize,
+    phantom: core::marker::PhantomData<&'l T>,
+}
+
+impl<'l, T: AllocablePage + 'l> Iterator for PageIter<'l, T> {
+    type Item = &'l T;
+
+    fn next(&mut self) -> Option<&'l T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let page = self.front;
+        // The list is borrowed, so the links don't change while we walk them
+        unsafe {
+            self.front = PageList::<T>::next_ptr(page);
+            page.as_ref()
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'l, T: AllocablePage + 'l> DoubleEndedIterator for PageIter<'l, T> {
+    fn next_back(&mut self) -> Option<&'l T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let page = self.back;
+        unsafe {
+            self.back = PageList::<T>::prev_ptr(page);
+            page.as_ref()
+        }
+    }
+}
+
+impl<'l, T: AllocablePage + 'l> ExactSizeIterator for PageIter<'l, T> {}
+
+/// A cursor over a `PageList` that can move in both directions and take out, insert
+/// or splice in pages at its position.
+///
+/// Like the cursors of `LinkedList`, it is either on a page or on the "ghost" position
+/// past the ends of the list, from where it wraps around to the other end.
+pub struct CursorMut<'c, 'a, T: AllocablePage> {
+    list: &'c mut PageList<'a, T>,
+    /// The page the cursor is on, null for the ghost position.
+    current: *mut T,
+}
+
+impl<'c, 'a, T: AllocablePage> CursorMut<'c, 'a, T> {
+    /// The page the cursor is on, `None` on the ghost position.
+    pub fn current(&self) -> Option<&T> {
+        unsafe { self.current.as_ref() }
+    }
+
+    /// Moves to the next page (from the last page to the ghost position, from there to the first page).
+    pub fn move_next(&mut self) {
+        self.current = match self.current.is_null() {
+            true => self.list.head_ptr(),
+            false => unsafe { PageList::<T>::next_ptr(self.current) },
+        };
+    }
+
+    /// Moves to the previous page (from the first page to the ghost position, from there to the last page).
+    pub fn move_prev(&mut self) {
+        self.current = match self.current.is_null() {
+            true => self.list.tail.p,
+            false => unsafe { PageList::<T>::prev_ptr(self.current) },
+        };
+    }
+
+    /// Takes the current page out of the list and moves on to the next one.
+    pub fn remove_current(&mut self) -> Option<&'a mut T> {
+        let page = unsafe { self.current.as_mut()? };
+        self.current = unsafe { PageList::<T>::next_ptr(self.current) };
+        self.list.remove_from_list(page);
+        Some(page)
+    }
+
+    /// The pages around the current position: `(current, next)` for inserting after it,
+    /// `(prev, current)` for inserting before it, the ends of the list on the ghost position.
+    fn neighbours(&self, after: bool) -> (*mut T, *mut T) {
+        unsafe {
+            match (self.current.is_null(), after) {
+                (true, true) => (ptr::null_mut(), self.list.head_ptr()),
+                (true, false) => (self.list.tail.p, ptr::null_mut()),
+                (false, true) => (self.current, PageList::<T>::next_ptr(self.current)),
+                (false, false) => (PageList::<T>::prev_ptr(self.current), self.current),
+            }
+        }
+    }
+
+    /// Inserts `page` after the current page (at the front on the ghost position).
+    ///
+    /// # Safety
+    /// See `PageList::insert_front`.
+    pub unsafe fn insert_after(&mut self, page: &'a mut T) {
+        let (prev, next) = self.neighbours(true);
+        let page = page as *mut T;
+        self.list.link_between(prev, next, page, page, 1);
+    }
+
+    /// Inserts `page` before the current page (at the back on the ghost position).
+    ///
+    /// # Safety
+    /// See `PageList::insert_front`.
+    pub unsafe fn insert_before(&mut self, page: &'a mut T) {
+        let (prev, next) = self.neighbours(false);
+        let page = page as *mut T;
+        self.list.link_between(prev, next, page, page, 1);
+    }
+
+    /// Moves all pages of `other` after the current page (at the front on the ghost position),
+    /// keeping their order.
+    ///
+    /// # Safety
+    /// See `PageList::append`.
+    pub unsafe fn splice_after(&mut self, other: &mut PageList<'a, T>) {
+        let (prev, next) = self.neighbours(true);
+        self.splice(prev, next, other);
+    }
+
+    /// Moves all pages of `other` before the current page (at the back on the ghost position),
+    /// keeping their order.
+    ///
+    /// # Safety
+    /// See `PageList::append`.
+    pub unsafe fn splice_before(&mut self, other: &mut PageList<'a, T>) {
+        let (prev, next) = self.neighbours(false);
+        self.splice(prev, next, other);
+    }
+
+    unsafe fn splice(&mut self, prev: *mut T, next: *mut T, other: &mut PageList<'a, T>) {
+        if other.is_empty() {
+            return;
+        }
+        let (first, last, count) = (other.head_ptr(), other.tail.p, other.elements);
+        *other = PageList::new();
+        self.list.link_between(prev, next, first, last, count);
+    }
 }
 
 /// Iterate over all the pages inside a slab allocator
@@ -506,7 +1470,11 @@ impl<'a, P: AllocablePage + 'a> Iterator for ObjectPageIterMut<'a, P> {
             self.head.resolve_mut().map(|next| {
                 self.head = match next.next().resolve_mut() {
                     None => Rawlink::none(),
-                    Some(ref mut sp) => Rawlink::some(*sp),
+                    Some(ref mut sp) => {
+                        // The caller most likely looks at the bitfield of the following page next
+                        prefetch(sp.bitfield().as_ptr());
+                        Rawlink::some(*sp)
+                    }
                 };
                 next
             })
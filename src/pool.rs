@@ -0,0 +1,95 @@
+//! A pool of empty pages shared by several zones.
+//!
+//! Per-core heaps tend to hoard the pages that became empty on their core,
+//! while another core has to go back to the frame allocator. Zones that are
+//! connected to an `EmptyPagePool` (see `ZoneAllocator::set_empty_page_pool`)
+//! overflow their surplus of empty pages into the pool and take pages from
+//! it before they report `AllocationError::OutOfMemory`.
+
+use crate::*;
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Empty pages that any zone may take.
+///
+/// The pages stay formatted as `BasePage` (with their `MappedPages` in the
+/// page meta-data) and are linked through their own list pointers,
+/// so the pool doesn't need any memory besides the pages.
+pub struct EmptyPagePool<'a> {
+    /// Protects `pages`, the pool is only held for a few pointer updates.
+    locked: AtomicBool,
+    pages: UnsafeCell<PageList<'a, BasePage<'a>>>,
+}
+
+unsafe impl<'a> Sync for EmptyPagePool<'a> {}
+
+impl<'a> EmptyPagePool<'a> {
+    /// The heap id of pages in the pool, frees to such a page fail with `AllocationError::WrongHeap`.
+    pub const HEAP_ID: usize = usize::MAX;
+
+    /// Creates an empty pool.
+    pub const fn new() -> EmptyPagePool<'a> {
+        EmptyPagePool {
+            locked: AtomicBool::new(false),
+            pages: UnsafeCell::new(PageList::new()),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the pages of the pool.
+    fn with_pages<R, F: FnOnce(&mut PageList<'a, BasePage<'a>>) -> R>(&self, f: F) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let ret = f(unsafe { &mut *self.pages.get() });
+        self.locked.store(false, Ordering::Release);
+        ret
+    }
+
+    /// Adds a fresh page to the pool (e.g., to seed it at boot).
+    ///
+    /// The same checks as in `SCAllocator::refill` apply to `mp`.
+    pub fn add_page(&self, mp: MappedPages) -> Result<(), AllocationError> {
+        self.add_page_on_node(mp, 0)
+    }
+
+    /// Adds a fresh page of the NUMA node `node` to the pool
+    /// (see `ZoneAllocator::set_node_page_pools`).
+    pub fn add_page_on_node(&self, mp: MappedPages, node: usize) -> Result<(), AllocationError> {
+        let page = SCAllocator::<BasePage<'a>>::create_allocable_page(mp, EmptyPagePool::HEAP_ID)?;
+        page.set_node(node);
+        self.push(page);
+        Ok(())
+    }
+
+    /// Removes a page from the pool and returns its `MappedPages`,
+    /// e.g., to give the memory back to the frame allocator.
+    pub fn retrieve_page(&self) -> Option<MappedPages> {
+        self.pop().map(|page| page.retrieve_mapped_pages())
+    }
+
+    /// Number of pages in the pool.
+    pub fn pages(&self) -> usize {
+        self.with_pages(|pages| pages.elements)
+    }
+
+    /// Hands an empty page that was removed from a zone over to the pool.
+    pub(crate) fn push(&self, page: &'a mut BasePage<'a>) {
+        page.set_heap_id(EmptyPagePool::HEAP_ID);
+        self.with_pages(move |pages| unsafe { pages.insert_front(page) });
+    }
+
+    /// Takes a page out of the pool.
+    pub(crate) fn pop(&self) -> Option<&'a mut BasePage<'a>> {
+        self.with_pages(|pages| pages.pop())
+    }
+}
+
+impl<'a> Default for EmptyPagePool<'a> {
+    fn default() -> EmptyPagePool<'a> {
+        EmptyPagePool::new()
+    }
+}
@@ -0,0 +1,108 @@
+//! A sampling allocation profiler.
+//!
+//! Every `sample_rate`-th allocation served by a `ZoneAllocator` is recorded
+//! (size, size class and call-site) into a small ring buffer. This is cheap enough
+//! to leave enabled in production kernels and still gives a rough heap profile.
+
+use core::panic::Location;
+
+/// Number of samples the profiler keeps before it starts overwriting the oldest ones.
+pub const PROFILER_BUFFER_SIZE: usize = 64;
+
+/// A single recorded allocation.
+#[derive(Debug, Clone, Copy)]
+pub struct AllocationSample {
+    /// Size of the requested `Layout`.
+    pub size: usize,
+    /// Object size of the `SCAllocator` that served the request.
+    pub size_class: usize,
+    /// The call-site that requested the allocation.
+    pub location: &'static Location<'static>,
+}
+
+/// Records every Nth allocation into a fixed-size ring buffer.
+pub struct AllocationProfiler {
+    /// Record one out of `sample_rate` allocations (0 disables sampling).
+    sample_rate: usize,
+    /// Allocations seen since the last recorded sample.
+    countdown: usize,
+    /// Ring buffer of recorded samples.
+    samples: [Option<AllocationSample>; PROFILER_BUFFER_SIZE],
+    /// Total number of samples recorded so far (also the next write position).
+    recorded: usize,
+}
+
+impl AllocationProfiler {
+    /// Creates a disabled profiler.
+    pub const fn new() -> AllocationProfiler {
+        AllocationProfiler {
+            sample_rate: 0,
+            countdown: 0,
+            samples: [None; PROFILER_BUFFER_SIZE],
+            recorded: 0,
+        }
+    }
+
+    /// Sets the sampling rate: one out of every `rate` allocations gets recorded.
+    ///
+    /// A rate of 0 disables the profiler.
+    pub fn set_sample_rate(&mut self, rate: usize) {
+        self.sample_rate = rate;
+        self.countdown = 0;
+    }
+
+    /// Returns the current sampling rate (0 means disabled).
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
+    /// Called for every allocation, records it if it is the Nth one.
+    #[inline(always)]
+    pub(crate) fn on_allocation(&mut self, size: usize, size_class: usize, location: &'static Location<'static>) {
+        if self.sample_rate == 0 {
+            return;
+        }
+
+        self.countdown += 1;
+        if self.countdown < self.sample_rate {
+            return;
+        }
+        self.countdown = 0;
+
+        self.samples[self.recorded % PROFILER_BUFFER_SIZE] = Some(AllocationSample {
+            size,
+            size_class,
+            location,
+        });
+        self.recorded += 1;
+    }
+
+    /// Total number of samples recorded (including ones that were already overwritten).
+    pub fn recorded(&self) -> usize {
+        self.recorded
+    }
+
+    /// Iterates over the samples still in the buffer, oldest first.
+    pub fn samples(&self) -> impl Iterator<Item = &AllocationSample> {
+        let (start, len) = if self.recorded > PROFILER_BUFFER_SIZE {
+            (self.recorded % PROFILER_BUFFER_SIZE, PROFILER_BUFFER_SIZE)
+        } else {
+            (0, self.recorded)
+        };
+
+        (0..len).filter_map(move |i| self.samples[(start + i) % PROFILER_BUFFER_SIZE].as_ref())
+    }
+
+    /// Drops all recorded samples, keeping the sampling rate.
+    pub fn clear(&mut self) {
+        self.samples = [None; PROFILER_BUFFER_SIZE];
+        self.recorded = 0;
+        self.countdown = 0;
+    }
+}
+
+impl Default for AllocationProfiler {
+    fn default() -> AllocationProfiler {
+        AllocationProfiler::new()
+    }
+}
@@ -0,0 +1,98 @@
+//! A quarantine for empty pages on their way back to the system.
+//!
+//! A page handed back by `ZoneAllocator::retrieve_empty_page` is usually unmapped right away,
+//! so a late free of an object that used to live in it faults. With the page quarantine
+//! enabled (see `ZoneAllocator::set_page_quarantine`) the page is poisoned and held back
+//! for one `ZoneAllocator::maintain` cycle instead. Frees into it are reported as
+//! `AllocationError::QuarantinedPage`, and writes to it are detected when it leaves the quarantine.
+
+use crate::*;
+
+/// Maximum number of pages a zone holds in its page quarantine.
+pub const PAGE_QUARANTINE_CAPACITY: usize = 8;
+
+/// Byte pattern written into quarantined pages.
+pub const PAGE_POISON_BYTE: u8 = 0xD5;
+
+/// A quarantined page: its memory, whether it is a 32 KiB slab, and the cycle it was quarantined in.
+type QuarantinedPage = (MappedPages, bool, u64);
+
+/// The empty pages of a zone that wait for their last `maintain` cycle.
+pub(crate) struct PageQuarantine {
+    pages: [Option<QuarantinedPage>; PAGE_QUARANTINE_CAPACITY],
+    /// Number of quarantined pages.
+    len: usize,
+    /// Whether retrieved pages go through the quarantine.
+    pub(crate) enabled: bool,
+    /// Number of `maintain` cycles so far.
+    cycle: u64,
+    /// Late frees and writes into quarantined pages caught so far.
+    pub(crate) violations: usize,
+}
+
+impl PageQuarantine {
+    pub(crate) const fn new() -> PageQuarantine {
+        const EMPTY: Option<QuarantinedPage> = None;
+        PageQuarantine {
+            pages: [EMPTY; PAGE_QUARANTINE_CAPACITY],
+            len: 0,
+            enabled: false,
+            cycle: 0,
+            violations: 0,
+        }
+    }
+
+    pub(crate) fn is_full(&self) -> bool {
+        self.len == PAGE_QUARANTINE_CAPACITY
+    }
+
+    /// Starts the next `maintain` cycle, the pages quarantined so far are ready to leave.
+    pub(crate) fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    /// Poisons `mp` and holds it back until the next cycle.
+    pub(crate) fn push(&mut self, mp: MappedPages, large: bool) {
+        debug_assert!(!self.is_full());
+        unsafe { ptr::write_bytes(mp.start_address().value() as *mut u8, PAGE_POISON_BYTE, mp.size_in_bytes()) };
+        if let Some(slot) = self.pages.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some((mp, large, self.cycle));
+            self.len += 1;
+        }
+    }
+
+    /// Takes the oldest page (a 32 KiB slab if `large` is set) that was quarantined before
+    /// the current cycle, or any page of that kind if `force` is set.
+    ///
+    /// Reports a violation if the page was written to while it was quarantined.
+    pub(crate) fn pop(&mut self, large: bool, force: bool) -> Option<MappedPages> {
+        if self.len == 0 {
+            return None;
+        }
+        let cycle = self.cycle;
+        let slot = self
+            .pages
+            .iter_mut()
+            .filter(|slot| matches!(slot, Some((_, l, c)) if *l == large && (force || *c < cycle)))
+            .min_by_key(|slot| slot.as_ref().map_or(0, |page| page.2))?;
+        let (mp, _, _) = slot.take()?;
+        self.len -= 1;
+
+        let start = mp.start_address().value();
+        let page = unsafe { core::slice::from_raw_parts(start as *const u8, mp.size_in_bytes()) };
+        if let Some(offset) = page.iter().position(|b| *b != PAGE_POISON_BYTE) {
+            error!("Quarantined page {:#x} was modified at offset {}", start, offset);
+            self.violations += 1;
+        }
+        Some(mp)
+    }
+
+    /// Does `addr` lie in a quarantined page?
+    pub(crate) fn contains(&self, addr: VAddr) -> bool {
+        self.len > 0
+            && self.pages.iter().flatten().any(|(mp, _, _)| {
+                let start = mp.start_address().value();
+                addr >= start && addr < start + mp.size_in_bytes()
+            })
+    }
+}
@@ -0,0 +1,191 @@
+//! Memory regions supplied by the caller, used instead of Theseus' `MappedPages`
+//! if the crate is built without the `theseus` feature.
+//!
+//! The allocator only needs a page-aligned, writable chunk of memory of
+//! `ObjectPage8k::SIZE` bytes for every page, so userspace programs and other
+//! kernels hand it raw `NonNull<[u8; ObjectPage8k::SIZE]>` regions instead
+//! (or bigger ones for `ObjectPage16k` and `ObjectPage64k`).
+
+use crate::*;
+
+/// A region of memory owned by the allocator.
+///
+/// It has the same interface the allocator uses from Theseus' `MappedPages`,
+/// so all functions taking or returning `MappedPages` work the same with both backends.
+/// Dropping a region does not free the memory, use `as_ptr` and `size_in_bytes`
+/// to give it back to wherever it came from.
+#[derive(Debug)]
+pub struct MappedPages {
+    start: usize,
+    size: usize,
+    /// Physical address of the start of the region (0 if it is not known).
+    phys: usize,
+    /// How the region is mapped.
+    flags: RegionFlags,
+}
+
+// The region is exclusively owned, like a `Box`.
+unsafe impl Send for MappedPages {}
+
+impl MappedPages {
+    /// Turns `region` into a page that can be passed to `refill`.
+    ///
+    /// # Safety
+    /// The memory has to be valid, writable, aligned to `ObjectPage8k::SIZE` and not
+    /// be used by anything else until it is handed back by the allocator.
+    pub unsafe fn from_raw(region: NonNull<[u8; ObjectPage8k::SIZE]>) -> MappedPages {
+        MappedPages {
+            start: region.as_ptr() as usize,
+            size: ObjectPage8k::SIZE,
+            phys: 0,
+            flags: RegionFlags::DEFAULT,
+        }
+    }
+
+    /// Turns `region` into a page for one of the bigger page types
+    /// (`N` is `ObjectPage16k::SIZE` or `ObjectPage64k::SIZE`).
+    ///
+    /// # Safety
+    /// The memory has to be valid, writable, aligned to `N` and not
+    /// be used by anything else until it is handed back by the allocator.
+    pub unsafe fn from_raw_pages<const N: usize>(region: NonNull<[u8; N]>) -> MappedPages {
+        MappedPages {
+            start: region.as_ptr() as usize,
+            size: N,
+            phys: 0,
+            flags: RegionFlags::DEFAULT,
+        }
+    }
+
+    /// A region without any memory.
+    pub const fn empty() -> MappedPages {
+        MappedPages { start: 0, size: 0, phys: 0, flags: RegionFlags::empty() }
+    }
+
+    /// Start of the region.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.start as *mut u8
+    }
+
+    /// Start address of the region.
+    pub fn start_address(&self) -> VirtualAddress {
+        VirtualAddress(self.start)
+    }
+
+    /// Size of the region in bytes (a multiple of the page size).
+    pub fn size_in_bytes(&self) -> usize {
+        self.size
+    }
+
+    /// Records that the region is physically contiguous and starts at `phys`,
+    /// so `translate` (and `ZoneAllocator::physical_address_of`) work for it.
+    pub fn with_physical_address(mut self, phys: PhysicalAddress) -> MappedPages {
+        self.phys = phys.value();
+        self
+    }
+
+    /// The physical address `addr` is mapped to, `None` if `addr` lies outside the region
+    /// or its physical address is not known.
+    pub fn translate(&self, addr: VAddr) -> Option<PhysicalAddress> {
+        let offset = addr.wrapping_sub(self.start);
+        if self.phys == 0 || offset >= self.size {
+            return None;
+        }
+        Some(PhysicalAddress(self.phys + offset))
+    }
+
+    /// Records how the region is mapped (`RegionFlags::DEFAULT` unless set),
+    /// e.g., `RegionFlags::NO_CACHE` for memory shared with a device.
+    pub fn with_flags(mut self, flags: RegionFlags) -> MappedPages {
+        self.flags = flags;
+        self
+    }
+
+    /// How the region is mapped.
+    pub fn flags(&self) -> RegionFlags {
+        self.flags
+    }
+
+    /// Appends `mp` to this region if it starts right where this one ends,
+    /// otherwise `mp` is handed back.
+    pub fn merge(&mut self, mp: MappedPages) -> Result<(), (&'static str, MappedPages)> {
+        if self.size == 0 || mp.start != self.start + self.size {
+            return Err(("regions are not contiguous", mp));
+        }
+        if self.flags != mp.flags {
+            return Err(("regions are mapped with different flags", mp));
+        }
+        // The merged region only has a physical address if it is physically contiguous too
+        if self.phys == 0 || mp.phys != self.phys + self.size {
+            self.phys = 0;
+        }
+        self.size += mp.size;
+        Ok(())
+    }
+}
+
+/// A virtual address (see `MappedPages::start_address`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct VirtualAddress(usize);
+
+impl VirtualAddress {
+    pub fn value(&self) -> usize {
+        self.0
+    }
+}
+
+/// A physical address (see `MappedPages::translate`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PhysicalAddress(usize);
+
+impl PhysicalAddress {
+    pub const fn new(addr: usize) -> PhysicalAddress {
+        PhysicalAddress(addr)
+    }
+
+    pub fn value(&self) -> usize {
+        self.0
+    }
+}
+
+/// How a region is mapped (see `MappedPages::flags`).
+///
+/// The bits are the ones of an x86_64 page table entry, like Theseus' `EntryFlags`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionFlags(u64);
+
+impl RegionFlags {
+    pub const WRITABLE: RegionFlags = RegionFlags(1 << 1);
+    pub const WRITE_THROUGH: RegionFlags = RegionFlags(1 << 3);
+    pub const NO_CACHE: RegionFlags = RegionFlags(1 << 4);
+    pub const NO_EXECUTE: RegionFlags = RegionFlags(1 << 63);
+
+    /// Flags of a region unless `MappedPages::with_flags` says otherwise:
+    /// writable, cached and not executable.
+    pub const DEFAULT: RegionFlags = RegionFlags(RegionFlags::WRITABLE.0 | RegionFlags::NO_EXECUTE.0);
+
+    pub const fn empty() -> RegionFlags {
+        RegionFlags(0)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns `true` if all flags of `other` are set.
+    pub const fn contains(&self, other: RegionFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.contains(RegionFlags::WRITABLE)
+    }
+}
+
+impl core::ops::BitOr for RegionFlags {
+    type Output = RegionFlags;
+
+    fn bitor(self, other: RegionFlags) -> RegionFlags {
+        RegionFlags(self.0 | other.0)
+    }
+}
@@ -0,0 +1,140 @@
+//! A registry of named `SCAllocator`s, e.g., for a `slabinfo` shell command.
+//!
+//! An allocator is registered with its name and object size, the registry keeps a pointer
+//! to its operation counters. Since the counters are atomics (see `SCCounters`), the
+//! registered allocators can be listed from any thread while they are in use.
+
+use crate::*;
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+/// Maximum number of allocators in a `SlabRegistry`.
+pub const SLAB_REGISTRY_CAPACITY: usize = 64;
+
+/// The registry of the named allocators of the system.
+pub static SLAB_REGISTRY: SlabRegistry = SlabRegistry::new();
+
+/// A registered allocator as seen by `SlabRegistry::iter`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabInfo {
+    /// Name of the allocator (see `SCAllocator::set_name`).
+    pub name: &'static str,
+    /// Size of the objects of the allocator.
+    pub object_size: usize,
+    /// Snapshot of the operation counters of the allocator.
+    pub stats: SCStats,
+}
+
+/// An entry of the registry, its fields are only valid while `state` is `READY`.
+struct RegistryEntry {
+    state: AtomicUsize,
+    name: AtomicPtr<u8>,
+    name_len: AtomicUsize,
+    object_size: AtomicUsize,
+    counters: AtomicPtr<SCCounters>,
+}
+
+impl RegistryEntry {
+    const FREE: usize = 0;
+    const BUSY: usize = 1;
+    const READY: usize = 2;
+
+    const fn new() -> RegistryEntry {
+        RegistryEntry {
+            state: AtomicUsize::new(RegistryEntry::FREE),
+            name: AtomicPtr::new(ptr::null_mut()),
+            name_len: AtomicUsize::new(0),
+            object_size: AtomicUsize::new(0),
+            counters: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    fn info(&self) -> Option<SlabInfo> {
+        if self.state.load(Ordering::Acquire) != RegistryEntry::READY {
+            return None;
+        }
+        // Safety: the name was a `&'static str` and the allocator is valid
+        // while it is registered (see `SlabRegistry::register`).
+        unsafe {
+            let name = core::slice::from_raw_parts(self.name.load(Ordering::Relaxed), self.name_len.load(Ordering::Relaxed));
+            Some(SlabInfo {
+                name: core::str::from_utf8_unchecked(name),
+                object_size: self.object_size.load(Ordering::Relaxed),
+                stats: (*self.counters.load(Ordering::Relaxed)).snapshot(),
+            })
+        }
+    }
+}
+
+/// A fixed number of slots for named allocators.
+pub struct SlabRegistry {
+    entries: [RegistryEntry; SLAB_REGISTRY_CAPACITY],
+}
+
+impl SlabRegistry {
+    /// Creates an empty registry.
+    pub const fn new() -> SlabRegistry {
+        SlabRegistry {
+            entries: [const { RegistryEntry::new() }; SLAB_REGISTRY_CAPACITY],
+        }
+    }
+
+    /// Adds `sc` under its current name.
+    ///
+    /// Fails with `AllocationError::LimitExceeded` if all `SLAB_REGISTRY_CAPACITY` slots are taken.
+    ///
+    /// # Safety
+    /// `sc` must stay at its address until it is unregistered,
+    /// and must not be unregistered while another thread iterates over the registry.
+    pub unsafe fn register<P: AllocablePage>(&self, sc: &SCAllocator<'_, P>) -> Result<(), AllocationError> {
+        for entry in self.entries.iter() {
+            if entry
+                .state
+                .compare_exchange(RegistryEntry::FREE, RegistryEntry::BUSY, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                entry.name.store(sc.name().as_ptr() as *mut u8, Ordering::Relaxed);
+                entry.name_len.store(sc.name().len(), Ordering::Relaxed);
+                entry.object_size.store(sc.size(), Ordering::Relaxed);
+                entry.counters.store(sc.counters() as *const SCCounters as *mut SCCounters, Ordering::Relaxed);
+                entry.state.store(RegistryEntry::READY, Ordering::Release);
+                return Ok(());
+            }
+        }
+        error!("The slab registry is full, can't register {}", sc.name());
+        Err(AllocationError::LimitExceeded)
+    }
+
+    /// Removes `sc` from the registry, returns `false` if it wasn't registered.
+    pub fn unregister<P: AllocablePage>(&self, sc: &SCAllocator<'_, P>) -> bool {
+        let counters = sc.counters() as *const SCCounters as *mut SCCounters;
+        self.entries.iter().any(|entry| {
+            entry.state.load(Ordering::Acquire) == RegistryEntry::READY
+                && entry.counters.load(Ordering::Relaxed) == counters
+                && entry
+                    .state
+                    .compare_exchange(RegistryEntry::READY, RegistryEntry::FREE, Ordering::AcqRel, Ordering::Relaxed)
+                    .is_ok()
+        })
+    }
+
+    /// Returns the registered allocators.
+    pub fn iter(&self) -> impl Iterator<Item = SlabInfo> + '_ {
+        self.entries.iter().filter_map(RegistryEntry::info)
+    }
+
+    /// Number of registered allocators.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns `true` if no allocator is registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for SlabRegistry {
+    fn default() -> SlabRegistry {
+        SlabRegistry::new()
+    }
+}
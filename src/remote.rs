@@ -0,0 +1,132 @@
+//! Forwarding of objects that are freed on a different heap than the one they belong to.
+//!
+//! Every heap owns a `RemoteFreeInbox`. Other heaps push the objects of that heap
+//! into the inbox (a lock-free stack per size class that is threaded through
+//! the freed objects themselves), and the owning `ZoneAllocator` frees them
+//! on its next allocation. No memory is needed besides the objects.
+
+use crate::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Objects freed to other heaps that belong to one heap.
+///
+/// The inbox of a heap is registered with `ZoneAllocator::set_remote_free_inbox`,
+/// other heaps reach it through `RemoteFreeInboxes`.
+pub struct RemoteFreeInbox {
+    /// Head of the stack of slots per size class (0 if empty), the large size classes
+    /// follow the base size classes. The first word of every slot points to the next one.
+    heads: [AtomicUsize; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+}
+
+impl RemoteFreeInbox {
+    /// Creates an empty inbox.
+    pub const fn new() -> RemoteFreeInbox {
+        RemoteFreeInbox {
+            heads: [
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+                AtomicUsize::new(0),
+            ],
+        }
+    }
+
+    /// Hands over `ptr` (allocated with `layout`) to the heap owning this inbox.
+    ///
+    /// With the `red-zones` feature the red zones are checked here,
+    /// since the object's slot is reused to link it into the inbox.
+    pub fn push(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        let idx = match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => idx,
+            Slab::Large(idx) => ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx,
+            Slab::Unsupported => return Err(AllocationError::TooLarge),
+        };
+
+        #[cfg(feature = "red-zones")]
+        let ptr = {
+            let slot = red_zone_slot(ptr.as_ptr() as usize, layout).ok_or(AllocationError::InvalidPointer)?;
+            if !check_red_zones(slot, ZoneAllocator::class_geometry(idx).0, layout) {
+                return Err(AllocationError::RedZoneViolation);
+            }
+            NonNull::new(slot as *mut u8).ok_or(AllocationError::InvalidPointer)?
+        };
+
+        let slot = ptr.as_ptr() as *mut usize;
+        let mut head = self.heads[idx].load(Ordering::Relaxed);
+        loop {
+            unsafe { slot.write(head) };
+            match self.heads[idx].compare_exchange_weak(head, slot as usize, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return Ok(()),
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Returns `true` if there are objects waiting to be freed.
+    pub fn is_empty(&self) -> bool {
+        self.heads.iter().all(|head| head.load(Ordering::Relaxed) == 0)
+    }
+
+    /// Frees all objects in the inbox to `zone`, returns how many there were.
+    ///
+    /// Objects that can't be freed (e.g., double frees) are logged and skipped.
+    pub(crate) fn drain(&self, zone: &mut ZoneAllocator) -> usize {
+        let mut count = 0;
+        for (idx, head) in self.heads.iter().enumerate() {
+            // Taking the whole stack at once avoids the ABA problem
+            let mut slot = head.swap(0, Ordering::Acquire);
+            while let Some(ptr) = NonNull::new(slot as *mut u8) {
+                slot = unsafe { (slot as *const usize).read() };
+                let res = match idx.checked_sub(ZoneAllocator::MAX_BASE_SIZE_CLASSES) {
+                    None => zone.small_slabs[idx].deallocate_slot(ptr),
+                    Some(idx) => zone.big_slabs[idx].deallocate_slot(ptr),
+                };
+                if let Err(e) = res {
+                    error!("Failed to free {:p} handed over from another heap: {}", ptr, e);
+                }
+                count += 1;
+            }
+        }
+        count
+    }
+}
+
+impl Default for RemoteFreeInbox {
+    fn default() -> RemoteFreeInbox {
+        RemoteFreeInbox::new()
+    }
+}
+
+/// The inboxes of all heaps, indexed by heap id.
+///
+/// Register it with `ZoneAllocator::set_cross_heap_free` so objects of other heaps
+/// are forwarded to their heap's inbox.
+pub struct RemoteFreeInboxes<'i> {
+    inboxes: &'i [RemoteFreeInbox],
+}
+
+impl<'i> RemoteFreeInboxes<'i> {
+    /// `inboxes[id]` must be the inbox of the heap with heap id `id`.
+    pub const fn new(inboxes: &'i [RemoteFreeInbox]) -> RemoteFreeInboxes<'i> {
+        RemoteFreeInboxes { inboxes }
+    }
+}
+
+impl<'i> CrossHeapFree for RemoteFreeInboxes<'i> {
+    fn forward(&self, heap_id: usize, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        match self.inboxes.get(heap_id) {
+            Some(inbox) => inbox.push(ptr, layout),
+            None => Err(AllocationError::WrongHeap(heap_id)),
+        }
+    }
+}
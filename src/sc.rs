@@ -1,6 +1,7 @@
 //! A SCAllocator that can allocate fixed size objects.
 
 use crate::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// A genius(?) const min()
 ///
@@ -12,15 +13,423 @@ use crate::*;
 ///
 /// # Source
 /// https://stackoverflow.com/questions/53619695/calculating-maximum-value-of-a-set-of-constant-expressions-at-compile-time
-#[cfg(feature = "unstable")]
 const fn cmin(a: usize, b: usize) -> usize {
     [a, b][(a > b) as usize]
 }
 
-/// The boring variant of min (not const).
-#[cfg(not(feature = "unstable"))]
-fn cmin(a: usize, b: usize) -> usize {
-    core::cmp::min(a, b)
+/// Size of the call-site entry kept for every object (only with the `callsites` feature).
+#[cfg(feature = "callsites")]
+const CALLSITE_SIZE: usize = mem::size_of::<usize>();
+#[cfg(not(feature = "callsites"))]
+const CALLSITE_SIZE: usize = 0;
+
+/// Size of the generation counter kept for every object (only with the `generations` feature).
+#[cfg(feature = "generations")]
+const GENERATION_SIZE: usize = mem::size_of::<u32>();
+#[cfg(not(feature = "generations"))]
+const GENERATION_SIZE: usize = 0;
+
+/// Bytes a page needs for every object besides the object itself
+/// (its call-site entry with the `callsites` feature and its generation with `generations`).
+pub(crate) const OBJECT_OVERHEAD: usize = CALLSITE_SIZE + GENERATION_SIZE;
+
+/// Number of bytes of the data area (`capacity`) of a page that hold objects of `size`.
+///
+/// With the `callsites` or `generations` feature the rest of the data area holds the
+/// generation table followed by the call-site table, unless not even a single object
+/// and its entries fit in a page.
+#[allow(unused_variables)]
+const fn object_capacity(size: usize, capacity: usize) -> usize {
+    #[cfg(any(feature = "callsites", feature = "generations"))]
+    let capacity = {
+        let n = capacity / (size + OBJECT_OVERHEAD);
+        if n == 0 {
+            capacity
+        } else {
+            n * size
+        }
+    };
+    capacity
+}
+
+/// Controls if an `SCAllocator` scrubs memory that is given back to it.
+///
+/// Scrubbing makes sure stale data (e.g., keys or capabilities) never leaks
+/// into subsequent allocations handed to a different component.
+///
+/// # Note
+/// The `debug-poison` feature takes precedence: with it, freed objects are always
+/// overwritten with `POISON_BYTE` instead of zeroes (whatever the mode), so use-after-free
+/// writes are still detected. Stale data doesn't leak either way, but objects that are
+/// handed out again read `POISON_BYTE` rather than zeroes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroOnFree {
+    /// Freed memory is left as is.
+    Disabled,
+    /// Objects are zeroed on deallocation.
+    Objects,
+    /// Objects are zeroed on deallocation and all object slots
+    /// of a page are zeroed once the page becomes empty.
+    ObjectsAndPages,
+}
+
+/// What a deallocation did to the page the object was returned to (see `SCAllocator::deallocate_ext`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageTransition {
+    /// The page stayed in its list (or the object was only parked in the quarantine).
+    Unchanged,
+    /// The page was full and can serve allocations again.
+    FullToPartial,
+    /// The page became empty and can be reclaimed.
+    Empty,
+}
+
+/// An object and the room its size class has left after it was allocated (see `SCAllocator::allocate_ext`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocationInfo {
+    /// The allocated object.
+    pub ptr: NonNull<u8>,
+    /// Free slots left in the page that served the allocation.
+    pub page_free: usize,
+    /// Free slots the size class can hand out before it needs a refill. Only the serving page
+    /// and the empty pages are counted (other partial pages aren't walked), so it's a lower bound.
+    pub class_free: usize,
+}
+
+/// The order in which an `SCAllocator` reuses its empty pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageReusePolicy {
+    /// The page that became empty last is reused first,
+    /// it is likely still in the cache.
+    Lifo,
+    /// Empty pages are reused in the order they became empty, so pages
+    /// don't keep bouncing between empty and partial and can be returned sooner.
+    Fifo,
+}
+
+/// Decides which partial page serves the next allocation of an `SCAllocator`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSelectionPolicy {
+    /// Use the first partial page with room (starting with the page that served
+    /// the last allocation). This is the fastest option.
+    FirstFit,
+    /// Use the partial page with the fewest free objects (i.e., best-fit, since all
+    /// objects have the same size). Keeps the number of partial pages low so
+    /// more pages become empty and can be returned, but walks the whole partial list.
+    FullestFirst,
+}
+
+/// How long an object is expected to live (see `SCAllocator::allocate_with_hint`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocHint {
+    /// The object is freed soon, e.g., a buffer of a request (the default for `allocate`).
+    ShortLived,
+    /// The object stays allocated for a long time, e.g., a table that lives as long as a task.
+    LongLived,
+}
+
+/// The list of its `SCAllocator` a page is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageState {
+    /// The page holds no objects.
+    Empty,
+    /// The page has objects and room for more.
+    Partial,
+    /// All objects of the page are allocated.
+    Full,
+    /// The page is being evacuated, no allocations are served from it.
+    Draining,
+    /// The page holds long-lived objects (see `AllocHint`), it may be partial or full.
+    LongLived,
+}
+
+impl PageState {
+    /// All states, in the order the lists are visited by `ZoneAllocator::iter_pages`.
+    pub(crate) const ALL: [PageState; 5] =
+        [PageState::Empty, PageState::Partial, PageState::Full, PageState::Draining, PageState::LongLived];
+}
+
+/// A snapshot of one page of an allocator (see `ZoneAllocator::iter_pages`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageInfo {
+    /// Start address of the page.
+    pub start: usize,
+    /// Size of the page in bytes.
+    pub page_size: usize,
+    /// Object size of the size class the page belongs to.
+    pub object_size: usize,
+    /// Id of the heap the page belongs to.
+    pub heap_id: usize,
+    /// Number of allocated objects (including quarantined ones).
+    pub live_objects: usize,
+    /// Number of objects the page holds.
+    pub capacity: usize,
+    /// The list the page is on.
+    pub state: PageState,
+    /// NUMA node of the page's memory (see `AllocablePage::node`).
+    pub node: usize,
+    /// Memory tier of the page's memory (see `AllocablePage::tier`).
+    pub tier: MemoryTier,
+}
+
+/// How much of the memory of an allocator is in use (see `SCAllocator::utilization`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Utilization {
+    /// Bytes of the allocated objects (whole slots, including quarantined objects).
+    pub live_bytes: usize,
+    /// Bytes of all slots of the pages (retired pages excluded).
+    pub capacity_bytes: usize,
+    /// Number of pages (including retired pages).
+    pub pages: usize,
+}
+
+/// A snapshot of the operation counters of an `SCAllocator` (see `SCAllocator::stats`).
+///
+/// The counters saturate instead of wrapping around.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SCStats {
+    /// Allocations that were served.
+    pub allocations: usize,
+    /// Objects that were freed to the allocator (with `deallocate`, not `ZoneAllocator::deallocate_unlocked`).
+    pub deallocations: usize,
+    /// Pages that were added with `refill`, `refill_bootstrap` or `adopt_page`.
+    pub refills: usize,
+    /// Pages that were handed back with `retrieve_empty_page`, `remove_page`,
+    /// `reclaim_retired_page` or `take_all_pages`.
+    pub pages_retrieved: usize,
+    /// Allocations the partial pages couldn't serve, so they had to start an empty page (or failed).
+    pub slow_path: usize,
+    /// Allocations served from a page of another NUMA node (see `SCAllocator::set_local_node`).
+    pub remote_allocations: usize,
+    /// Empty pages that were started to serve allocations.
+    pub empty_to_partial: usize,
+    /// Pages that became full. Pages that hold a single object (or are filled by
+    /// `allocate_many`) go straight from empty to full, they count as both transitions.
+    pub partial_to_full: usize,
+    /// Full pages that got room again.
+    pub full_to_partial: usize,
+    /// Pages that became empty (including draining and long-lived ones,
+    /// full pages that emptied at once count as both transitions).
+    pub partial_to_empty: usize,
+}
+
+impl SCStats {
+    /// Page list transitions of all kinds. Many transitions compared to the allocations
+    /// mean that the pages of the class thrash between the lists, i.e., more empty pages
+    /// should be kept (see the `max_empty_pages` of `ZoneAllocator::set_page_reclaimer`).
+    pub fn page_transitions(&self) -> usize {
+        self.empty_to_partial
+            .saturating_add(self.partial_to_full)
+            .saturating_add(self.full_to_partial)
+            .saturating_add(self.partial_to_empty)
+    }
+}
+
+/// The operation counters of an `SCAllocator`, kept as relaxed atomics.
+///
+/// Only the owner of the allocator (through `&mut`) updates them, so a counter is
+/// never lost or double counted, but there is no ordering between the counters
+/// or with the allocator's pages: a snapshot taken while the allocator is in use may
+/// e.g. show a deallocation before the allocation it frees. Each counter on its own
+/// only ever grows, so the difference of two snapshots is a lower bound of the
+/// operations in between.
+pub struct SCCounters {
+    pub(crate) allocations: AtomicUsize,
+    pub(crate) deallocations: AtomicUsize,
+    pub(crate) refills: AtomicUsize,
+    pub(crate) pages_retrieved: AtomicUsize,
+    pub(crate) slow_path: AtomicUsize,
+    pub(crate) remote_allocations: AtomicUsize,
+    pub(crate) empty_to_partial: AtomicUsize,
+    pub(crate) partial_to_full: AtomicUsize,
+    pub(crate) full_to_partial: AtomicUsize,
+    pub(crate) partial_to_empty: AtomicUsize,
+}
+
+impl SCCounters {
+    pub(crate) const fn new() -> SCCounters {
+        SCCounters {
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+            refills: AtomicUsize::new(0),
+            pages_retrieved: AtomicUsize::new(0),
+            slow_path: AtomicUsize::new(0),
+            remote_allocations: AtomicUsize::new(0),
+            empty_to_partial: AtomicUsize::new(0),
+            partial_to_full: AtomicUsize::new(0),
+            full_to_partial: AtomicUsize::new(0),
+            partial_to_empty: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reads all counters (one after the other, see the consistency model above).
+    pub fn snapshot(&self) -> SCStats {
+        SCStats {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+            refills: self.refills.load(Ordering::Relaxed),
+            pages_retrieved: self.pages_retrieved.load(Ordering::Relaxed),
+            slow_path: self.slow_path.load(Ordering::Relaxed),
+            remote_allocations: self.remote_allocations.load(Ordering::Relaxed),
+            empty_to_partial: self.empty_to_partial.load(Ordering::Relaxed),
+            partial_to_full: self.partial_to_full.load(Ordering::Relaxed),
+            full_to_partial: self.full_to_partial.load(Ordering::Relaxed),
+            partial_to_empty: self.partial_to_empty.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Overwrites the counters with the ones of `other`, one after the other.
+    ///
+    /// Only the single writer of `self` may call this.
+    pub(crate) fn copy_from(&self, other: &SCCounters) {
+        if cfg!(feature = "minimal") {
+            return;
+        }
+        let counters = [
+            (&self.allocations, &other.allocations),
+            (&self.deallocations, &other.deallocations),
+            (&self.refills, &other.refills),
+            (&self.pages_retrieved, &other.pages_retrieved),
+            (&self.slow_path, &other.slow_path),
+            (&self.remote_allocations, &other.remote_allocations),
+            (&self.empty_to_partial, &other.empty_to_partial),
+            (&self.partial_to_full, &other.partial_to_full),
+            (&self.full_to_partial, &other.full_to_partial),
+            (&self.partial_to_empty, &other.partial_to_empty),
+        ];
+        for (counter, value) in counters {
+            counter.store(value.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    /// Adds `n` to `counter`, sticking at `usize::MAX`.
+    ///
+    /// This is a load and a store instead of a read-modify-write since there is only one writer.
+    #[inline(always)]
+    fn add(counter: &AtomicUsize, n: usize) {
+        if cfg!(feature = "minimal") {
+            return;
+        }
+        counter.store(counter.load(Ordering::Relaxed).saturating_add(n), Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for SCCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.snapshot().fmt(f)
+    }
+}
+
+/// Maximum number of freed objects an `SCAllocator` can hold back in its quarantine.
+pub const QUARANTINE_CAPACITY: usize = 32;
+
+/// A FIFO of freed objects that are not yet made available for reuse.
+///
+/// Quarantined objects stay marked as allocated in their page,
+/// they are only released once `limit` newer objects got freed.
+pub(crate) struct Quarantine {
+    /// Ring buffer of quarantined objects.
+    slots: [Option<NonNull<u8>>; QUARANTINE_CAPACITY],
+    /// Index of the oldest object.
+    head: usize,
+    /// Number of objects in the quarantine.
+    pub(crate) len: usize,
+    /// Number of objects held back before releasing the oldest one (0 disables the quarantine).
+    pub(crate) limit: usize,
+}
+
+// Quarantined objects are owned by the allocator, like the pages they live in.
+unsafe impl Send for Quarantine {}
+
+impl Quarantine {
+    pub(crate) const fn new() -> Quarantine {
+        Quarantine {
+            slots: [None; QUARANTINE_CAPACITY],
+            head: 0,
+            len: 0,
+            limit: 0,
+        }
+    }
+
+    /// Adds `ptr` to the quarantine, returns the oldest object
+    /// if the quarantine holds more than `limit` objects.
+    pub(crate) fn push(&mut self, ptr: NonNull<u8>) -> Option<NonNull<u8>> {
+        let oldest = if self.len == self.limit { self.pop() } else { None };
+        self.slots[(self.head + self.len) % QUARANTINE_CAPACITY] = Some(ptr);
+        self.len += 1;
+        oldest
+    }
+
+    /// Removes the oldest object from the quarantine.
+    pub(crate) fn pop(&mut self) -> Option<NonNull<u8>> {
+        if self.len == 0 {
+            return None;
+        }
+        let oldest = self.slots[self.head].take();
+        self.head = (self.head + 1) % QUARANTINE_CAPACITY;
+        self.len -= 1;
+        oldest
+    }
+
+    /// Is `ptr` currently quarantined?
+    pub(crate) fn contains(&self, ptr: NonNull<u8>) -> bool {
+        (0..self.len).any(|i| self.slots[(self.head + i) % QUARANTINE_CAPACITY] == Some(ptr))
+    }
+}
+
+/// Maximum number of retired pages an `SCAllocator` holds until they are reclaimed.
+pub const RETIRED_PAGES_CAPACITY: usize = 8;
+
+/// A FIFO of empty pages that were taken out of the page lists, but can't be handed
+/// back to the system yet because other cores may still be completing a free into them.
+///
+/// Every page is stamped with the epoch it was retired in.
+pub(crate) struct RetiredPages {
+    /// Ring buffer of (retire epoch, page address).
+    pages: [(u64, VAddr); RETIRED_PAGES_CAPACITY],
+    /// Index of the oldest retired page.
+    head: usize,
+    /// Number of retired pages.
+    pub(crate) len: usize,
+}
+
+impl RetiredPages {
+    pub(crate) const fn new() -> RetiredPages {
+        RetiredPages {
+            pages: [(0, 0); RETIRED_PAGES_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == RETIRED_PAGES_CAPACITY
+    }
+
+    fn push(&mut self, epoch: u64, page: VAddr) {
+        debug_assert!(!self.is_full());
+        self.pages[(self.head + self.len) % RETIRED_PAGES_CAPACITY] = (epoch, page);
+        self.len += 1;
+    }
+
+    /// Removes the oldest page if it was retired before `quiescent_epoch`.
+    fn pop_before(&mut self, quiescent_epoch: u64) -> Option<VAddr> {
+        if self.len == 0 || self.pages[self.head].0 >= quiescent_epoch {
+            return None;
+        }
+        self.pop()
+    }
+
+    /// Removes the oldest page regardless of its epoch.
+    fn pop(&mut self) -> Option<VAddr> {
+        if self.len == 0 {
+            return None;
+        }
+        let (_epoch, page) = self.pages[self.head];
+        self.head = (self.head + 1) % RETIRED_PAGES_CAPACITY;
+        self.len -= 1;
+        Some(page)
+    }
 }
 
 /// A slab allocator allocates elements of a fixed size.
@@ -34,6 +443,9 @@ fn cmin(a: usize, b: usize) -> usize {
 ///  * `slabs`: A list of pages partially allocated and still have room for more.
 ///  * `full_slabs`: A list of pages that are completely allocated.
 ///
+/// Pages that are being drained and pages of long-lived objects (see `AllocHint`)
+/// are kept in separate lists.
+///
 /// On allocation we allocate memory from `slabs`, however if the list is empty
 /// we try to reclaim a page from `empty_slabs` before we return with an out-of-memory
 /// error. If a page becomes full after the allocation we move it from `slabs` to
@@ -47,8 +459,8 @@ fn cmin(a: usize, b: usize) -> usize {
 pub struct SCAllocator<'a, P: AllocablePage> {
     /// Maximum possible allocation size for this `SCAllocator`.
     pub(crate) size: usize,
-    /// Keeps track of succeeded allocations.
-    pub(crate) allocation_count: usize,
+    /// Counts the operations of the allocator.
+    pub(crate) stats: SCCounters,
     /// max objects per page
     pub(crate) obj_per_page: usize,
     /// List of empty ObjectPages (nothing allocated in these).
@@ -57,62 +469,401 @@ pub struct SCAllocator<'a, P: AllocablePage> {
     pub(crate) slabs: PageList<'a, P>,
     /// List of full ObjectPages (everything allocated in these don't need to search them).
     pub(crate) full_slabs: PageList<'a, P>,
+    /// Whether freed objects (and empty pages) are zeroed.
+    pub(crate) zero_on_free: ZeroOnFree,
+    /// Recently freed objects that can't be reused yet.
+    pub(crate) quarantine: Quarantine,
+    /// Empty pages waiting for a grace period before they can be returned.
+    pub(crate) retired: RetiredPages,
+    /// Rebalance the page lists every `rebalance_interval` allocations (0 disables it).
+    pub(crate) rebalance_interval: usize,
+    /// Order in which empty pages are reused.
+    pub(crate) reuse_policy: PageReusePolicy,
+    /// Address of the partial page that served the last allocation (0 if there is none),
+    /// it is tried first before walking `slabs`.
+    pub(crate) mru_page: VAddr,
+    /// Which partial page serves the next allocation.
+    pub(crate) selection_policy: PageSelectionPolicy,
+    /// Pages that are being evacuated (no allocations are served from them),
+    /// they move to `empty_slabs` once their last object is freed.
+    pub(crate) draining_slabs: PageList<'a, P>,
+    /// Partial and full pages of the objects allocated with `AllocHint::LongLived`,
+    /// so they don't keep the pages of short-lived objects from emptying out.
+    pub(crate) long_lived_slabs: PageList<'a, P>,
+    /// Maximum number of pages this allocator may own (0 means unlimited).
+    pub(crate) max_pages: usize,
+    /// Number of empty pages only `try_allocate` may start (see `set_emergency_reserve`).
+    pub(crate) emergency_reserve: usize,
+    /// State of the random generator picking slots, `None` if objects are allocated first-fit.
+    pub(crate) slot_rng: Option<u64>,
+    /// `stats.allocations` when the allocation rate was last updated.
+    pub(crate) rate_mark: usize,
+    /// Allocations per `update_allocation_rate` period, smoothed over the past periods.
+    pub(crate) allocation_rate: usize,
+    /// Whether fresh pages get a rotating offset for their first object.
+    pub(crate) cache_coloring: bool,
+    /// Color (in cache lines) of the next fresh page.
+    pub(crate) next_color: usize,
+    /// Flags the mappings of all pages of this allocator must have.
+    pub(crate) required_flags: PageFlags,
+    /// Name of the allocator in the `SlabRegistry` (empty if unnamed).
+    pub(crate) name: &'static str,
+    /// NUMA node of the heap, empty pages of other nodes are only started when no local one is left.
+    pub(crate) local_node: Option<usize>,
+    /// Checks that every page is followed by a guard page, `None` if pages aren't guarded.
+    pub(crate) guard: Option<&'a dyn GuardPageCheck>,
 }
 
-/// Creates an instance of a scallocator, we do this in a macro because we
-/// re-use the code in const and non-const functions
-macro_rules! new_sc_allocator {
-    ($size:expr) => {
+impl<'a, P: AllocablePage> SCAllocator<'a, P> {
+    /// Number of objects of `size` that fit in a page.
+    pub(crate) const fn objects_per_page(size: usize) -> usize {
+        cmin(object_capacity(size, P::SIZE - P::METADATA_SIZE) / size, P::BITFIELD_WORDS * 64)
+    }
+
+    /// Create a new SCAllocator.
+    pub const fn new(size: usize) -> SCAllocator<'a, P> {
         SCAllocator {
-            size: $size,
-            allocation_count: 0,
-            obj_per_page: cmin((P::SIZE - P::METADATA_SIZE) / $size, 8 * 64),
+            size,
+            stats: SCCounters::new(),
+            obj_per_page: SCAllocator::<P>::objects_per_page(size),
             empty_slabs: PageList::new(),
             slabs: PageList::new(),
             full_slabs: PageList::new(),
+            zero_on_free: ZeroOnFree::Disabled,
+            quarantine: Quarantine::new(),
+            retired: RetiredPages::new(),
+            rebalance_interval: 0,
+            reuse_policy: PageReusePolicy::Lifo,
+            mru_page: 0,
+            selection_policy: PageSelectionPolicy::FirstFit,
+            draining_slabs: PageList::new(),
+            long_lived_slabs: PageList::new(),
+            max_pages: 0,
+            emergency_reserve: 0,
+            slot_rng: None,
+            rate_mark: 0,
+            allocation_rate: 0,
+            cache_coloring: false,
+            next_color: 0,
+            required_flags: PageFlags::empty(),
+            name: "",
+            local_node: None,
+            guard: None,
         }
-    };
-}
+    }
 
-impl<'a, P: AllocablePage> SCAllocator<'a, P> {
-    const _REBALANCE_COUNT: usize = 10_000;
+    /// Returns the maximum supported object size of this allocator.
+    pub fn size(&self) -> usize {
+        self.size
+    }
 
-    /// Create a new SCAllocator.
-    #[cfg(feature = "unstable")]
-    pub const fn new(size: usize) -> SCAllocator<'a, P> {
-        new_sc_allocator!(size)
+    /// Names the allocator, the name is picked up by `SlabRegistry::register`.
+    pub fn set_name(&mut self, name: &'static str) {
+        self.name = name;
     }
 
-    #[cfg(not(feature = "unstable"))]
-    pub fn new(size: usize) -> SCAllocator<'a, P> {
-        new_sc_allocator!(size)
+    /// Returns the name of the allocator (empty if it has none).
+    pub fn name(&self) -> &'static str {
+        self.name
     }
 
-    /// Returns the maximum supported object size of this allocator.
-    pub fn size(&self) -> usize {
-        self.size
+    /// Sets whether freed objects and empty pages are zeroed.
+    /// With the `debug-poison` feature they are poisoned instead (see `ZeroOnFree`).
+    pub fn set_zero_on_free(&mut self, mode: ZeroOnFree) {
+        self.zero_on_free = mode;
+    }
+
+    /// Returns whether freed objects and empty pages are zeroed.
+    pub fn zero_on_free(&self) -> ZeroOnFree {
+        self.zero_on_free
+    }
+
+    /// Sets the order in which empty pages are reused (`PageReusePolicy::Lifo` by default).
+    ///
+    /// Only affects pages that become empty (or are added) afterwards.
+    pub fn set_page_reuse_policy(&mut self, policy: PageReusePolicy) {
+        self.reuse_policy = policy;
+    }
+
+    /// Returns the order in which empty pages are reused.
+    pub fn page_reuse_policy(&self) -> PageReusePolicy {
+        self.reuse_policy
+    }
+
+    /// Sets which partial page serves the next allocation (`PageSelectionPolicy::FirstFit` by default).
+    pub fn set_page_selection_policy(&mut self, policy: PageSelectionPolicy) {
+        self.selection_policy = policy;
+    }
+
+    /// Returns which partial page serves the next allocation.
+    pub fn page_selection_policy(&self) -> PageSelectionPolicy {
+        self.selection_policy
+    }
+
+    /// Limits the number of pages this allocator owns, `refill` fails with
+    /// `AllocationError::LimitExceeded` once the limit is reached (0, the default, means unlimited).
+    ///
+    /// Pages the allocator already owns are kept if it is over the new limit.
+    pub fn set_max_pages(&mut self, max_pages: usize) {
+        self.max_pages = max_pages;
+    }
+
+    /// Returns the maximum number of pages this allocator may own (0 means unlimited).
+    pub fn max_pages(&self) -> usize {
+        self.max_pages
+    }
+
+    /// Holds back `pages` empty pages for `try_allocate`, so interrupt handlers can still
+    /// allocate once the partial pages are used up (0, the default, disables the reserve).
+    ///
+    /// `allocate` and the functions that hand out empty pages (e.g., `retrieve_empty_page`)
+    /// leave the last `pages` empty pages alone, so refilled pages top up the reserve first.
+    pub fn set_emergency_reserve(&mut self, pages: usize) {
+        self.emergency_reserve = pages;
+    }
+
+    /// Returns the number of empty pages held back for `try_allocate`.
+    pub fn emergency_reserve(&self) -> usize {
+        self.emergency_reserve
+    }
+
+    /// Returns `true` if there is an empty page beyond the emergency reserve.
+    fn has_spare_empty_page(&self) -> bool {
+        self.empty_slabs.elements > self.emergency_reserve
+    }
+
+    /// Number of pages owned by this allocator (in any list, including retired pages).
+    pub fn pages(&self) -> usize {
+        self.empty_slabs.elements
+            + self.slabs.elements
+            + self.full_slabs.elements
+            + self.draining_slabs.elements
+            + self.long_lived_slabs.elements
+            + self.retired.len
+    }
+
+    /// Returns how much of the memory of this allocator is in use.
+    ///
+    /// Only the bitfields of the partial, draining and long-lived pages are read, full pages
+    /// count as full (so objects freed with `ZoneAllocator::deallocate_unlocked` still count
+    /// until the next rebalance).
+    pub fn utilization(&self) -> Utilization {
+        let used = |page: &P| self.obj_per_page - core::cmp::min(page.free_objects(), self.obj_per_page);
+        let live_objects = self.full_slabs.elements * self.obj_per_page
+            + self.slabs.iter().map(used).sum::<usize>()
+            + self.draining_slabs.iter().map(used).sum::<usize>()
+            + self.long_lived_slabs.iter().map(used).sum::<usize>();
+        let pages = self.pages();
+        Utilization {
+            live_bytes: live_objects * self.size,
+            capacity_bytes: (pages - self.retired.len) * self.obj_per_page * self.size,
+            pages,
+        }
+    }
+
+    /// The list of pages in `state`.
+    pub fn page_list(&self, state: PageState) -> &PageList<'a, P> {
+        match state {
+            PageState::Empty => &self.empty_slabs,
+            PageState::Partial => &self.slabs,
+            PageState::Full => &self.full_slabs,
+            PageState::Draining => &self.draining_slabs,
+            PageState::LongLived => &self.long_lived_slabs,
+        }
+    }
+
+    /// The list of pages in `state`, e.g., for a policy that reorders the partial pages.
+    ///
+    /// # Safety
+    /// Pages may be reordered freely. A page taken out of the list is no longer owned by
+    /// the allocator, and only pages of this allocator in the right state may be put back
+    /// (`verify` checks that every page is on the right list).
+    pub unsafe fn page_list_mut(&mut self, state: PageState) -> &mut PageList<'a, P> {
+        // The page of the last allocation may be taken out
+        self.mru_page = 0;
+        match state {
+            PageState::Empty => &mut self.empty_slabs,
+            PageState::Partial => &mut self.slabs,
+            PageState::Full => &mut self.full_slabs,
+            PageState::Draining => &mut self.draining_slabs,
+            PageState::LongLived => &mut self.long_lived_slabs,
+        }
+    }
+
+    /// Describes `page`, which is on the list of `state`.
+    pub(crate) fn page_info(&self, page: &P, state: PageState) -> PageInfo {
+        PageInfo {
+            start: page.data_start(),
+            page_size: P::SIZE,
+            object_size: self.size,
+            heap_id: page.heap_id(),
+            live_objects: self.obj_per_page - core::cmp::min(page.free_objects(), self.obj_per_page),
+            capacity: self.obj_per_page,
+            state,
+            node: page.node(),
+            tier: page.tier(),
+        }
+    }
+
+    /// Returns `true` if the allocator owns as many pages as it may.
+    pub fn at_page_limit(&self) -> bool {
+        self.max_pages != 0 && self.pages() >= self.max_pages
+    }
+
+    /// Makes allocations pick a pseudo-random free slot within a page instead of the first
+    /// free one, so consecutive allocations are not placed next to each other
+    /// (which makes heap spraying harder). `None` switches back to first-fit.
+    ///
+    /// The `seed` should come from a proper random source of the embedder.
+    pub fn set_randomized_slots(&mut self, seed: Option<u64>) {
+        // xorshift gets stuck at 0
+        self.slot_rng = seed.map(|seed| if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed });
+    }
+
+    /// Offsets the first object of successive fresh pages by a rotating multiple of the
+    /// cache-line size, so the objects with the same index in different pages don't all
+    /// map to the same cache sets. The offsets come out of the bytes a page can't fill with
+    /// objects anyway, so the number of objects per page stays the same.
+    ///
+    /// Only pages added afterwards are colored, pages keep their offset until they leave the allocator.
+    /// While coloring is enabled, layouts aligned to more than a cache line are rejected
+    /// with `AllocationError::InvalidLayout`.
+    pub fn set_cache_coloring(&mut self, enabled: bool) {
+        self.cache_coloring = enabled;
+    }
+
+    /// Returns whether fresh pages are colored.
+    pub fn cache_coloring(&self) -> bool {
+        self.cache_coloring
+    }
+
+    /// Number of different offsets the unused bytes of a page leave room for.
+    pub fn colors(&self) -> usize {
+        let capacity = object_capacity(self.size, P::SIZE - P::METADATA_SIZE);
+        (capacity - self.obj_per_page * self.size) / CACHE_LINE_SIZE + 1
+    }
+
+    /// Returns the offset for the next fresh page (0 if coloring is disabled).
+    fn next_color(&mut self) -> usize {
+        if self.guard.is_some() {
+            // the last object ends where the guard page starts
+            return object_capacity(self.size, P::SIZE - P::METADATA_SIZE) - self.obj_per_page * self.size;
+        }
+        if !self.cache_coloring {
+            return 0;
+        }
+        let color = self.next_color % self.colors();
+        self.next_color = (color + 1) % self.colors();
+        color * CACHE_LINE_SIZE
+    }
+
+    /// Requires the `MappedPages` of every page added to this allocator to have (at least) `flags`,
+    /// e.g., no-cache pages for DMA buffers. Pages that don't have them are rejected by
+    /// `refill` and `merge` with `AllocationError::InvalidPage`.
+    ///
+    /// Pages the allocator already owns are not checked.
+    pub fn set_required_flags(&mut self, flags: PageFlags) {
+        self.required_flags = flags;
+    }
+
+    /// Returns the flags the pages of this allocator must have.
+    pub fn required_flags(&self) -> PageFlags {
+        self.required_flags
+    }
+
+    /// Checks that `mp` has the flags (and the guard page) required by this allocator.
+    fn check_flags(&self, mp: &MappedPages) -> Result<(), AllocationError> {
+        if !mp.flags().contains(self.required_flags) {
+            error!(
+                "SCAllocator({}) requires pages with {:?}, got {:?}",
+                self.size, self.required_flags, mp.flags()
+            );
+            return Err(AllocationError::InvalidPage("MappedPages lack the flags required by the allocator"));
+        }
+        if matches!(self.guard, Some(guard) if !guard.has_guard_page(mp)) {
+            error!("SCAllocator({}) requires pages that are followed by a guard page", self.size);
+            return Err(AllocationError::InvalidPage("MappedPages are not followed by an unmapped guard page"));
+        }
+        Ok(())
+    }
+
+    /// Requires every page added to this allocator to be followed by an unmapped guard page
+    /// (as told by `check`), and places the objects of fresh pages flush against it.
+    /// An object that overflows the end of the last slot of a page then faults right away,
+    /// like with an electric fence. `None` stops requiring guard pages.
+    ///
+    /// `refill` and `merge` reject pages without a guard page with `AllocationError::InvalidPage`,
+    /// pages the allocator already owns are not checked. With the `callsites` feature,
+    /// the call-site table of a page sits between its last object and the guard page.
+    ///
+    /// Fails with `AllocationError::InvalidPage` if `P` keeps its meta-data at the end
+    /// of the page (only pages with out-of-band meta-data can be guarded).
+    pub fn set_guard_pages(&mut self, check: Option<&'a dyn GuardPageCheck>) -> Result<(), AllocationError> {
+        if check.is_some() && P::METADATA_SIZE != 0 {
+            error!("SCAllocator({}) can't guard pages with in-band meta-data", self.size);
+            return Err(AllocationError::InvalidPage("guard pages need pages with out-of-band meta-data"));
+        }
+        self.guard = check;
+        Ok(())
+    }
+
+    /// Returns `true` if the pages of this allocator must be followed by a guard page.
+    pub fn guard_pages(&self) -> bool {
+        self.guard.is_some()
+    }
+
+    /// Returns the next pseudo-random number if slots are randomized (xorshift64).
+    fn next_random(&mut self) -> Option<u64> {
+        self.slot_rng.as_mut().map(|state| {
+            let mut x = *state;
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            *state = x;
+            x
+        })
+    }
+
+    /// Allocates an object in `page`, first-fit or at a random slot (see `set_randomized_slots`).
+    fn allocate_in_page(page: &mut P, layout: Layout, random: Option<u64>) -> *mut u8 {
+        match random {
+            Some(random) => page.allocate_random(layout, random),
+            None => page.allocate(layout),
+        }
     }
 
     /// Add a new ObjectPage.
     fn insert_partial_slab(&mut self, new_head: &'a mut P) {
-        self.slabs.insert_front(new_head);
+        unsafe { self.slabs.insert_front(new_head) };
     }
 
-    /// Add page to empty list.
+    /// Add page to empty list, where it goes depends on the `PageReusePolicy`
+    /// (empty pages are always taken from the front).
     fn insert_empty(&mut self, new_head: &'a mut P) {
         assert_eq!(
-            new_head as *const P as usize % P::SIZE,
+            new_head.data_start() % P::SIZE,
             0,
             "Inserted page is not aligned to page-size."
         );
-        self.empty_slabs.insert_front(new_head);
+        match self.reuse_policy {
+            PageReusePolicy::Lifo => unsafe { self.empty_slabs.insert_front(new_head) },
+            PageReusePolicy::Fifo => unsafe { self.empty_slabs.insert_back(new_head) },
+        }
     }
 
+    /// Removes an empty page that is backed by `MappedPages`, pages of a bootstrap
+    /// buffer stay with the allocator until they are adopted (see `adopt_mapped_pages`).
     fn remove_empty(&mut self) -> Option<&'a mut P> {
-        self.empty_slabs.pop()
+        if !self.has_spare_empty_page() {
+            return None;
+        }
+        let page = self.empty_slabs.iter_mut().find(|page| !page.is_unmapped())?;
+        self.empty_slabs.remove_from_list(page);
+        Some(page)
     }
 
     fn remove_partial(&mut self) -> Option<&'a mut P> {
+        self.mru_page = 0;
         self.slabs.pop()
     }
 
@@ -120,71 +871,242 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         self.full_slabs.pop()
     }
     
-    // /// Since `dealloc` can not reassign pages without requiring a lock
-    // /// we check slabs and full slabs periodically as part of `alloc`
-    // /// and move them to the empty or partially allocated slab lists.
-    // pub(crate) fn check_page_assignments(&mut self) {
-    //     for slab_page in self.full_slabs.iter_mut() {
-    //         if !slab_page.is_full() {
-    //             // We need to move it from self.full_slabs -> self.slabs
-    //             // trace!("move {:p} full -> partial", slab_page);
-    //             self.move_full_to_partial(slab_page);
-    //         }
-    //     }
-
-    //     for slab_page in self.slabs.iter_mut() {
-    //         if slab_page.is_empty(self.obj_per_page) {
-    //             // We need to move it from self.slabs -> self.empty_slabs
-    //             // trace!("move {:p} partial -> empty", slab_page);
-    //             self.move_to_empty(slab_page);
-    //         }
-    //     }
-    // }
+    /// Since `ZoneAllocator::deallocate_unlocked` can not reassign pages without requiring a lock
+    /// we check slabs and full slabs as part of `allocate` (when the partial pages are exhausted)
+    /// and move them to the empty or partially allocated slab lists.
+    ///
+    /// Returns the number of pages that were moved.
+    pub(crate) fn check_page_assignments(&mut self) -> usize {
+        let mut moved = 0;
+        for slab_page in self.full_slabs.iter_mut() {
+            if !slab_page.is_full() {
+                // We need to move it from self.full_slabs -> self.slabs
+                // trace!("move {:p} full -> partial", slab_page);
+                self.move_full_to_partial(slab_page);
+                moved += 1;
+            }
+        }
+
+        for slab_page in self.slabs.iter_mut() {
+            if slab_page.is_empty(self.obj_per_page) {
+                // We need to move it from self.slabs -> self.empty_slabs
+                // trace!("move {:p} partial -> empty", slab_page);
+                self.move_to_empty(slab_page);
+                moved += 1;
+            }
+        }
+
+        for slab_page in self.draining_slabs.iter_mut() {
+            if slab_page.is_empty(self.obj_per_page) {
+                self.draining_slabs.remove_from_list(slab_page);
+                self.insert_empty(slab_page);
+                SCCounters::add(&self.stats.partial_to_empty, 1);
+                moved += 1;
+            }
+        }
+
+        for slab_page in self.long_lived_slabs.iter_mut() {
+            if slab_page.is_empty(self.obj_per_page) {
+                self.long_lived_slabs.remove_from_list(slab_page);
+                self.insert_empty(slab_page);
+                SCCounters::add(&self.stats.partial_to_empty, 1);
+                moved += 1;
+            }
+        }
+        moved
+    }
+
+    /// Moves pages whose objects were freed without the lock to the list that matches
+    /// their bitfield (full -> partial, partial -> empty).
+    ///
+    /// Returns the number of pages that were moved.
+    pub fn rebalance(&mut self) -> usize {
+        self.check_page_assignments()
+    }
+
+    /// Rebalance the page lists automatically every `interval` allocations
+    /// (0, the default, disables it).
+    ///
+    /// Regardless of this setting, `allocate` rebalances before it takes a page
+    /// from `empty_slabs`. The interval is counted with the operation counters,
+    /// so it has no effect with the `minimal` feature.
+    pub fn set_rebalance_interval(&mut self, interval: usize) {
+        self.rebalance_interval = interval;
+    }
+
+    /// Returns the automatic rebalance interval (0 means disabled).
+    pub fn rebalance_interval(&self) -> usize {
+        self.rebalance_interval
+    }
+
+    /// Returns a snapshot of the operation counters of this allocator.
+    ///
+    /// The counters are relaxed atomics, see `SCCounters` for what a snapshot
+    /// taken while the allocator is in use guarantees. They stay 0 with the `minimal` feature.
+    pub fn stats(&self) -> SCStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns the operation counters of this allocator.
+    ///
+    /// Other threads can read them through this reference while the allocator is in use.
+    pub fn counters(&self) -> &SCCounters {
+        &self.stats
+    }
+
+    /// Sets the NUMA node of the heap this allocator belongs to (`None` if the system has a single node).
+    ///
+    /// New pages are started on the local node as long as it has empty pages, and allocations
+    /// served from pages of other nodes are counted in `SCStats::remote_allocations`.
+    /// Partial pages are used regardless of their node.
+    pub fn set_local_node(&mut self, node: Option<usize>) {
+        self.local_node = node;
+    }
+
+    /// Returns the NUMA node of the heap (see `set_local_node`).
+    pub fn local_node(&self) -> Option<usize> {
+        self.local_node
+    }
+
+    /// Takes an empty page to start, one of the local node if there is one.
+    fn pop_empty(&mut self) -> Option<&'a mut P> {
+        if !self.has_spare_empty_page() {
+            return None;
+        }
+        if let Some(node) = self.local_node {
+            if let Some(page) = self.empty_slabs.iter_mut().find(|page| page.node() == node) {
+                self.empty_slabs.remove_from_list(page);
+                SCCounters::add(&self.stats.empty_to_partial, 1);
+                return Some(page);
+            }
+        }
+        let page = self.empty_slabs.pop()?;
+        SCCounters::add(&self.stats.empty_to_partial, 1);
+        Some(page)
+    }
+
+    /// Returns `true` if `page` is on another node than the heap (see `set_local_node`).
+    #[inline]
+    fn is_remote(&self, page: &P) -> bool {
+        matches!(self.local_node, Some(node) if page.node() != node)
+    }
+
+    /// Counts the allocation of `ptr` if it was served from a page of another node.
+    #[inline]
+    fn count_remote(&self, ptr: *mut u8) {
+        if self.local_node.is_some() && !ptr.is_null() && self.is_remote(unsafe { &*P::from_address(ptr as VAddr) }) {
+            SCCounters::add(&self.stats.remote_allocations, 1);
+        }
+    }
+
+    /// Returns the smoothed number of allocations per period (see `update_allocation_rate`).
+    pub fn allocation_rate(&self) -> usize {
+        self.allocation_rate
+    }
+
+    /// Ends the current period: the allocations made since the last call are blended into
+    /// the allocation rate (with the same weight as all previous periods together).
+    ///
+    /// Returns the new rate.
+    pub fn update_allocation_rate(&mut self) -> usize {
+        let allocations = self.stats.allocations.load(Ordering::Relaxed);
+        let recent = allocations - self.rate_mark;
+        self.rate_mark = allocations;
+        self.allocation_rate = self.allocation_rate / 2 + recent - recent / 2;
+        self.allocation_rate
+    }
+
+    /// Stops serving allocations from the page containing `addr`, so it empties
+    /// over time as its objects are freed. Once empty, the page is moved to `empty_slabs`
+    /// where it can be retrieved (e.g., for memory hot-unplug or to shrink the heap).
+    ///
+    /// Note that an empty page may be used for allocations again,
+    /// so the caller should retrieve it before the next allocation.
+    ///
+    /// Returns `AllocationError::InvalidPointer` if the page does not belong to this allocator.
+    pub fn drain_page(&mut self, addr: VAddr) -> Result<(), AllocationError> {
+        // A single pass over the lists finds the page along with the list it is on
+        let (state, page) = match self.find_page(addr) {
+            Some((PageState::Draining, _)) => return Ok(()),
+            Some(found) => found,
+            None if self.empty_slabs.contains(P::from_address(addr)) => return Ok(()),
+            None => return Err(AllocationError::InvalidPointer),
+        };
+        // Also forgets the page of the last allocation, which may be this one
+        unsafe { self.page_list_mut(state).remove_from_list(page) };
+
+        if page.is_empty(self.obj_per_page) {
+            self.insert_empty(page);
+        } else {
+            unsafe { self.draining_slabs.insert_front(page) };
+        }
+        Ok(())
+    }
+
+    /// Number of pages that are being drained (see `drain_page`).
+    pub fn draining_pages(&self) -> usize {
+        self.draining_slabs.elements
+    }
 
     /// Move a page from `slabs` to `empty_slabs`.
     fn move_to_empty(&mut self, page: &'a mut P) {
         let page_ptr = page as *const P;
+        // A corrupted header gets reported, but moving the page can't fail
+        let _ = page.is_intact();
 
-        debug_assert!(self.slabs.contains(page_ptr));
-        debug_assert!(
+        debug_assert_list!(self.slabs.contains(page_ptr));
+        debug_assert_list!(
             !self.empty_slabs.contains(page_ptr),
-            "Page {:p} already in emtpy_slabs",
+            "Page {:p} already in empty_slabs",
             page_ptr
         );
 
         self.slabs.remove_from_list(page);
-        self.empty_slabs.insert_front(page);
+        if self.mru_page == page_ptr as VAddr {
+            self.mru_page = 0;
+        }
+        self.insert_empty(page);
+        SCCounters::add(&self.stats.partial_to_empty, 1);
 
-        debug_assert!(!self.slabs.contains(page_ptr));
-        debug_assert!(self.empty_slabs.contains(page_ptr));
+        debug_assert_list!(!self.slabs.contains(page_ptr));
+        debug_assert_list!(self.empty_slabs.contains(page_ptr));
     }
 
     /// Move a page from `full_slabs` to `slab`.
     fn move_partial_to_full(&mut self, page: &'a mut P) {
         let page_ptr = page as *const P;
+        // A corrupted header gets reported, but moving the page can't fail
+        let _ = page.is_intact();
 
-        debug_assert!(self.slabs.contains(page_ptr));
-        debug_assert!(!self.full_slabs.contains(page_ptr));
+        debug_assert_list!(self.slabs.contains(page_ptr));
+        debug_assert_list!(!self.full_slabs.contains(page_ptr));
 
         self.slabs.remove_from_list(page);
-        self.full_slabs.insert_front(page);
+        if self.mru_page == page_ptr as VAddr {
+            self.mru_page = 0;
+        }
+        unsafe { self.full_slabs.insert_front(page) };
+        SCCounters::add(&self.stats.partial_to_full, 1);
 
-        debug_assert!(!self.slabs.contains(page_ptr));
-        debug_assert!(self.full_slabs.contains(page_ptr));
+        debug_assert_list!(!self.slabs.contains(page_ptr));
+        debug_assert_list!(self.full_slabs.contains(page_ptr));
     }
 
     /// Move a page from `full_slabs` to `slab`.
     fn move_full_to_partial(&mut self, page: &'a mut P) {
         let page_ptr = page as *const P;
+        // A corrupted header gets reported, but moving the page can't fail
+        let _ = page.is_intact();
 
-        debug_assert!(!self.slabs.contains(page_ptr));
-        debug_assert!(self.full_slabs.contains(page_ptr));
+        debug_assert_list!(!self.slabs.contains(page_ptr));
+        debug_assert_list!(self.full_slabs.contains(page_ptr));
 
         self.full_slabs.remove_from_list(page);
-        self.slabs.insert_front(page);
+        unsafe { self.slabs.insert_front(page) };
+        SCCounters::add(&self.stats.full_to_partial, 1);
 
-        debug_assert!(self.slabs.contains(page_ptr));
-        debug_assert!(!self.full_slabs.contains(page_ptr));
+        debug_assert_list!(self.slabs.contains(page_ptr));
+        debug_assert_list!(!self.full_slabs.contains(page_ptr));
     }
 
     /// Tries to allocate a block of memory with respect to the `layout`.
@@ -199,28 +1121,69 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         // If not we can get away with a singly-linked list and have 8 more bytes
         // for the bitfield in an ObjectPage.
 
-        for slab_page in self.slabs.iter_mut() {
-            let ptr = slab_page.allocate(sc_layout);
-            if !ptr.is_null() {
-                if slab_page.is_full() {
-                    // trace!("move {:p} partial -> full", slab_page);
-                    self.move_partial_to_full(slab_page);
+        if self.selection_policy == PageSelectionPolicy::FullestFirst {
+            let fullest = self
+                .slabs
+                .iter_mut()
+                .filter(|page| page.free_objects() > 0)
+                .min_by_key(|page| page.free_objects());
+            if let Some(slab_page) = fullest {
+                let ptr = self.allocate_from_partial(slab_page, sc_layout);
+                if !ptr.is_null() {
+                    return ptr;
                 }
-                self.allocation_count += 1;
-                return ptr;
-            } else {
-                continue;
             }
+            // Alignment may not fit in the fullest page, fall back to first-fit
         }
 
-        // // Periodically rebalance page-lists (since dealloc can't do it for us)
-        // if self.allocation_count % SCAllocator::<P>::REBALANCE_COUNT == 0 {
-        //     self.check_page_assignments();
-        // }
+        // The page that served the last allocation likely still has room
+        if self.mru_page != 0 {
+            let slab_page = unsafe { mem::transmute::<VAddr, &'a mut P>(self.mru_page) };
+            let ptr = self.allocate_from_partial(slab_page, sc_layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+
+        for slab_page in self.slabs.iter_mut() {
+            let ptr = self.allocate_from_partial(slab_page, sc_layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+
+        // Rebalance page-lists (since unlocked deallocations can't do it for us)
+        // and retry if a full page got some space back
+        if !self.full_slabs.is_empty() && self.check_page_assignments() > 0 {
+            for slab_page in self.slabs.iter_mut() {
+                let ptr = self.allocate_from_partial(slab_page, sc_layout);
+                if !ptr.is_null() {
+                    return ptr;
+                }
+            }
+        }
 
         ptr::null_mut()
     }
 
+    /// Allocates an object from `slab_page` (which is in `slabs`) and
+    /// remembers the page for the next allocation, unless the page became full.
+    fn allocate_from_partial(&mut self, slab_page: &'a mut P, sc_layout: Layout) -> *mut u8 {
+        let random = self.next_random();
+        let ptr = Self::allocate_in_page(slab_page, sc_layout, random);
+        if ptr.is_null() {
+            return ptr;
+        }
+        if slab_page.is_full() {
+            // trace!("move {:p} partial -> full", slab_page);
+            self.move_partial_to_full(slab_page);
+        } else {
+            self.mru_page = slab_page as *mut P as VAddr;
+        }
+        SCCounters::add(&self.stats.allocations, 1);
+        ptr
+    }
+
     pub fn heap_id(&self) -> Option<usize> {
         if let Some(head) = &self.empty_slabs.head {
             return Some(head.heap_id())
@@ -236,12 +1199,17 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
     }
 
     /// removes all of the pages from the lists of `allocator` and adds them to this allocator.
-    pub fn merge(&mut self, allocator: &mut SCAllocator<'a, P>, heap_id: usize) -> Result<(), &'static str> {
+    ///
+    /// Nothing is moved if one of the pages lacks the flags required by this allocator
+    /// or the pages would exceed its page limit (see `check_merge`).
+    pub fn merge(&mut self, allocator: &mut SCAllocator<'a, P>, heap_id: usize) -> Result<(), AllocationError> {
+        self.check_merge(allocator)?;
+
         while !allocator.empty_slabs.is_empty() {
-            match allocator.remove_empty() {
+            match allocator.empty_slabs.pop() {
                 Some(new_head) =>{
                     new_head.set_heap_id(heap_id);
-                    self.empty_slabs.insert_front(new_head)
+                    self.insert_empty(new_head)
                 }
                 None => {
                     break;
@@ -253,7 +1221,7 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
             match allocator.remove_partial() {
                 Some(new_head) =>{
                     new_head.set_heap_id(heap_id);
-                    self.slabs.insert_front(new_head)
+                    unsafe { self.slabs.insert_front(new_head) }
                 }
                 None => {
                     break;
@@ -265,7 +1233,7 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
             match allocator.remove_full() {
                 Some(new_head) =>{
                     new_head.set_heap_id(heap_id);
-                    self.full_slabs.insert_front(new_head)
+                    unsafe { self.full_slabs.insert_front(new_head) }
                 }
                 None => {
                     break;
@@ -273,40 +1241,559 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
             }
         }
 
+        while let Some(new_head) = allocator.draining_slabs.pop() {
+            new_head.set_heap_id(heap_id);
+            unsafe { self.draining_slabs.insert_front(new_head) }
+        }
+
+        while let Some(new_head) = allocator.long_lived_slabs.pop() {
+            new_head.set_heap_id(heap_id);
+            unsafe { self.long_lived_slabs.insert_front(new_head) }
+        }
+
         Ok(())
 
     }
 
+    /// Checks that the pages of `allocator` can be moved to this allocator (see `merge`):
+    /// they have to carry the flags required by this allocator (`AllocationError::InvalidPage`),
+    /// and this allocator may not end up with more than its `max_pages` (`AllocationError::LimitExceeded`).
+    /// Retired pages are not moved, so they don't count.
+    pub(crate) fn check_merge(&mut self, allocator: &mut SCAllocator<'a, P>) -> Result<(), AllocationError> {
+        let moved = allocator.pages() - allocator.retired_pages();
+        if self.max_pages != 0 && self.pages() + moved > self.max_pages {
+            error!("SCAllocator({}) can't take {} more pages, its limit is {}", self.size, moved, self.max_pages);
+            return Err(AllocationError::LimitExceeded);
+        }
+        for list in [
+            &mut allocator.empty_slabs,
+            &mut allocator.slabs,
+            &mut allocator.full_slabs,
+            &mut allocator.draining_slabs,
+            &mut allocator.long_lived_slabs,
+        ] {
+            for page in list.iter_mut() {
+                self.check_flags(page.mapped_pages())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves all pages of `allocator` to this allocator, keeping their list (empty,
+    /// partial, full, draining or long-lived) and order, and tags them with `heap_id`.
+    ///
+    /// Unlike `merge`, the lists are spliced rather than moved page by page.
+    /// Returns the number of live objects that were moved.
+    /// Retired pages stay with `allocator` and still have to be reclaimed from there.
+    ///
+    /// Nothing is moved if the pages fail the checks of `merge` (see `check_merge`).
+    pub fn migrate_from(&mut self, allocator: &mut SCAllocator<'a, P>, heap_id: usize) -> Result<usize, AllocationError> {
+        self.check_merge(allocator)?;
+        let mut live = 0;
+        allocator.for_each_allocated(|_ptr, _size| live += 1);
+
+        Self::adopt_list(&mut self.empty_slabs, &mut allocator.empty_slabs, heap_id);
+        Self::adopt_list(&mut self.slabs, &mut allocator.slabs, heap_id);
+        Self::adopt_list(&mut self.full_slabs, &mut allocator.full_slabs, heap_id);
+        Self::adopt_list(&mut self.draining_slabs, &mut allocator.draining_slabs, heap_id);
+        Self::adopt_list(&mut self.long_lived_slabs, &mut allocator.long_lived_slabs, heap_id);
+        allocator.mru_page = 0;
+
+        Ok(live)
+    }
+
+    /// Tags all pages of `other` with `heap_id` and appends them to `list`.
+    fn adopt_list(list: &mut PageList<'a, P>, other: &mut PageList<'a, P>, heap_id: usize) {
+        for page in other.iter_mut() {
+            page.set_heap_id(heap_id);
+        }
+        unsafe { list.append(other) };
+    }
+
+    /// Invokes `f` with the address and object size of every live allocation
+    /// in this allocator.
+    ///
+    /// Only partial, full, draining and long-lived pages are visited since empty pages
+    /// can't contain any allocations.
+    ///
+    /// Objects sitting in the quarantine are not reported.
+    pub fn for_each_allocated<F: FnMut(NonNull<u8>, usize)>(&mut self, mut f: F) {
+        let size = self.size;
+        let obj_per_page = self.obj_per_page;
+        let quarantine = &self.quarantine;
+        let mut visit = |ptr: NonNull<u8>| {
+            if !quarantine.contains(ptr) {
+                f(ptr, size)
+            }
+        };
+        for slab_page in self.slabs.iter_mut() {
+            slab_page.for_each_allocated(size, obj_per_page, &mut visit);
+        }
+        for slab_page in self.full_slabs.iter_mut() {
+            slab_page.for_each_allocated(size, obj_per_page, &mut visit);
+        }
+        for slab_page in self.draining_slabs.iter_mut() {
+            slab_page.for_each_allocated(size, obj_per_page, &mut visit);
+        }
+        for slab_page in self.long_lived_slabs.iter_mut() {
+            slab_page.for_each_allocated(size, obj_per_page, &mut visit);
+        }
+    }
+
+    /// Invokes `f` with every live object of this allocator as a `T`, e.g., to list
+    /// all live task structs of a cache that only holds tasks, without keeping a registry.
+    ///
+    /// Fails with `AllocationError::InvalidLayout` if this allocator can't hold a `T`.
+    /// Objects sitting in the quarantine are not visited.
+    ///
+    /// # Safety
+    /// Every live object of this allocator has to be an initialized `T` allocated with
+    /// `Layout::new::<T>()`, and no other reference to any of them may be in use while `f` runs.
+    pub unsafe fn for_each_live<T, F: FnMut(&mut T)>(&mut self, mut f: F) -> Result<(), AllocationError> {
+        let layout = Layout::new::<T>();
+        #[cfg(feature = "red-zones")]
+        let (layout, offset) = (red_zone_layout(layout), red_zone_front(layout));
+        #[cfg(not(feature = "red-zones"))]
+        let offset = 0;
+        self.check_layout(layout)?;
+        self.for_each_allocated(|slot, _size| f(&mut *((slot.as_ptr() as usize + offset) as *mut T)));
+        Ok(())
+    }
+
+    /// Returns the address of the call-site entry of the object in `slot`,
+    /// `None` if the objects of this size class don't leave room for a call-site table.
+    #[cfg(feature = "callsites")]
+    fn callsite_entry(slot: usize, size: usize, obj_per_page: usize) -> Option<*mut usize> {
+        let capacity = P::SIZE - P::METADATA_SIZE;
+        if obj_per_page * (size + OBJECT_OVERHEAD) > capacity {
+            return None;
+        }
+        let page = slot & !(P::SIZE - 1);
+        let idx = (slot - page) / size;
+        Some((page + capacity - (obj_per_page - idx) * CALLSITE_SIZE) as *mut usize)
+    }
+
+    /// Returns the address of the generation counter of the object in `slot`,
+    /// `None` if the objects of this size class don't leave room for a generation table.
+    #[cfg(feature = "generations")]
+    fn generation_entry(slot: usize, size: usize, obj_per_page: usize) -> Option<*mut u32> {
+        let capacity = P::SIZE - P::METADATA_SIZE;
+        if obj_per_page * (size + OBJECT_OVERHEAD) > capacity {
+            return None;
+        }
+        let page = slot & !(P::SIZE - 1);
+        let idx = (slot - page) / size;
+        let table_end = page + capacity - obj_per_page * CALLSITE_SIZE;
+        Some((table_end - (obj_per_page - idx) * GENERATION_SIZE) as *mut u32)
+    }
+
+    /// Returns the generation of the object at `addr`, an object of `size` bytes in a page of type `P`
+    /// (0 if its size class doesn't leave room for a generation table).
+    #[cfg(feature = "generations")]
+    pub(crate) fn generation_at(addr: VAddr, size: usize) -> u32 {
+        Self::generation_entry(addr, size, Self::objects_per_page(size)).map_or(0, |entry| unsafe { entry.read() })
+    }
+
+    /// Bumps the generation of the object at `addr` (an object of `size` bytes in a page of type `P`)
+    /// as it is freed, so handles to it no longer validate.
+    #[cfg(feature = "generations")]
+    pub(crate) fn bump_generation(addr: VAddr, size: usize) {
+        if let Some(entry) = Self::generation_entry(addr, size, Self::objects_per_page(size)) {
+            unsafe { entry.write(entry.read().wrapping_add(1)) };
+        }
+    }
+
+    /// Like `allocate`, but also returns the generation of the object,
+    /// to be checked with `validate_handle` later on.
+    #[cfg(feature = "generations")]
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn allocate_with_generation(&mut self, layout: Layout) -> Result<(NonNull<u8>, u32), AllocationError> {
+        let ptr = self.allocate(layout)?;
+        Ok((ptr, Self::generation_at(ptr.as_ptr() as VAddr, self.size)))
+    }
+
+    /// Returns `true` if `ptr` still points to the live object that had generation `expected`
+    /// when it was allocated, i.e., it wasn't freed (and maybe handed out again) since.
+    ///
+    /// The page of `ptr` is looked up in the page lists, so this takes time linear in the number of pages.
+    #[cfg(feature = "generations")]
+    pub fn validate_handle(&mut self, ptr: NonNull<u8>, expected: u32) -> bool {
+        let addr = ptr.as_ptr() as VAddr;
+        let page = P::from_address(addr & !(P::SIZE - 1));
+        self.owns_page(page) && self.handle_matches(unsafe { &*page }, addr, expected)
+    }
+
+    /// Returns `true` if the object at `addr` in `page` (a page of this allocator)
+    /// is allocated and still has generation `expected`.
+    #[cfg(feature = "generations")]
+    pub(crate) fn handle_matches(&self, page: &P, addr: VAddr, expected: u32) -> bool {
+        let idx = match addr.checked_sub(page.objects_start()) {
+            Some(offset) => offset / self.size,
+            None => return false,
+        };
+        let slot = page.objects_start() + idx * self.size;
+        idx < self.obj_per_page
+            && page.bitfield().is_allocated(idx)
+            && NonNull::new(slot as *mut u8).is_some_and(|slot| !self.quarantine.contains(slot))
+            && Self::generation_at(addr, self.size) == expected
+    }
+
+    /// Remembers that the object in `slot` was allocated from `location`.
+    #[cfg(feature = "callsites")]
+    fn record_callsite(&self, slot: usize, location: &'static core::panic::Location<'static>) {
+        if let Some(entry) = Self::callsite_entry(slot, self.size, self.obj_per_page) {
+            unsafe { entry.write(location as *const _ as usize) };
+        }
+    }
+
+    /// Like `for_each_allocated`, but also passes the call-site that allocated
+    /// every object (if it was recorded), e.g., to find the origin of leaked objects.
+    #[cfg(feature = "callsites")]
+    pub fn for_each_allocated_callsite<F>(&mut self, mut f: F)
+    where
+        F: FnMut(NonNull<u8>, usize, Option<&'static core::panic::Location<'static>>),
+    {
+        let obj_per_page = self.obj_per_page;
+        self.for_each_allocated(|ptr, size| {
+            let location = Self::callsite_entry(ptr.as_ptr() as usize, size, obj_per_page)
+                .and_then(|entry| unsafe { (entry.read() as *const core::panic::Location<'static>).as_ref() });
+            f(ptr, size, location)
+        });
+    }
+
+    /// Checks the red zones of all live objects in this allocator
+    /// and returns the number of slots with a corrupted red zone.
+    #[cfg(feature = "red-zones")]
+    pub fn check_red_zones(&mut self) -> usize {
+        let mut corrupted = 0;
+        self.for_each_allocated(|slot, size| {
+            if !check_slot_guards(slot.as_ptr() as usize, size) {
+                corrupted += 1;
+            }
+        });
+        corrupted
+    }
+
+    /// Checks the integrity of this allocator.
+    ///
+    /// Verifies that every page is in exactly one list, that the list lengths
+    /// match their element counts, that the bitfield of a page matches the list it is in
+    /// (e.g., pages in `empty_slabs` have no allocations), and that the page meta-data
+    /// is sane (alignment, magic, `heap_id`, list links).
+    ///
+    /// Every violation is logged, the first one found is returned.
+    /// Pages that changed state through unlocked deallocations are moved
+    /// to their list first (see `rebalance`).
+    pub fn verify(&mut self, heap_id: usize) -> Result<(), AllocationError> {
+        // Pages only reach their list after unlocked deallocations are accounted for
+        self.check_page_assignments();
+
+        let obj_per_page = self.obj_per_page;
+        let SCAllocator { empty_slabs, slabs, full_slabs, draining_slabs, long_lived_slabs, .. } = self;
+
+        let mut ret = Self::verify_list(empty_slabs, [slabs, full_slabs, draining_slabs, long_lived_slabs], heap_id, "empty", |page| {
+            page.is_empty(obj_per_page)
+        });
+        ret = ret.and(Self::verify_list(slabs, [empty_slabs, full_slabs, draining_slabs, long_lived_slabs], heap_id, "partial", |page| {
+            !page.is_full()
+        }));
+        ret = ret.and(Self::verify_list(full_slabs, [empty_slabs, slabs, draining_slabs, long_lived_slabs], heap_id, "full", |page| {
+            page.is_full()
+        }));
+        ret = ret.and(Self::verify_list(draining_slabs, [empty_slabs, slabs, full_slabs, long_lived_slabs], heap_id, "draining", |page| {
+            !page.is_empty(obj_per_page)
+        }));
+        ret = ret.and(Self::verify_list(long_lived_slabs, [empty_slabs, slabs, full_slabs, draining_slabs], heap_id, "long-lived", |page| {
+            !page.is_empty(obj_per_page)
+        }));
+
+        #[cfg(feature = "red-zones")]
+        {
+            if self.check_red_zones() > 0 {
+                ret = ret.and(Err(AllocationError::HeapCorrupted("red zone of a live object was overwritten")));
+            }
+        }
+
+        ret
+    }
+
+    /// Checks the pages of `list` (see `verify`).
+    ///
+    /// `state_ok` checks if the bitfield of a page matches `list`,
+    /// `others` are the lists a page in `list` must not be part of.
+    fn verify_list<F: Fn(&P) -> bool>(
+        list: &mut PageList<'a, P>,
+        mut others: [&mut PageList<'a, P>; 4],
+        heap_id: usize,
+        name: &str,
+        state_ok: F,
+    ) -> Result<(), AllocationError> {
+        let mut ret = Ok(());
+        let mut count = 0;
+        let mut prev: *const P = ptr::null();
+
+        for page in list.iter_mut() {
+            count += 1;
+            if count > list.elements {
+                error!("The {} list is longer than its {} elements (cycle?)", name, list.elements);
+                return ret.and(Err(AllocationError::HeapCorrupted("list length does not match element count")));
+            }
+
+            let page_ptr = page as *const P;
+            if page.data_start() % P::SIZE != 0 {
+                error!("Page {:p} in the {} list is not aligned to {}", page_ptr, name, P::SIZE);
+                ret = ret.and(Err(AllocationError::HeapCorrupted("page is not aligned to page-size")));
+            }
+            if !page.is_intact() {
+                ret = ret.and(Err(AllocationError::HeapCorrupted("page header is corrupted")));
+            }
+            if page.heap_id() != heap_id {
+                error!("Page {:p} in the {} list has heap_id {}, expected {}", page_ptr, name, page.heap_id(), heap_id);
+                ret = ret.and(Err(AllocationError::HeapCorrupted("page belongs to a different heap")));
+            }
+            let page_prev = unsafe { page.prev().resolve_mut() }.map_or(ptr::null(), |p| p as *const P);
+            if page_prev != prev {
+                error!("Page {:p} in the {} list has prev {:p}, expected {:p}", page_ptr, name, page_prev, prev);
+                ret = ret.and(Err(AllocationError::HeapCorrupted("list links are inconsistent")));
+            }
+            if others.iter_mut().any(|other| other.contains(page_ptr)) {
+                error!("Page {:p} in the {} list is part of another list", page_ptr, name);
+                ret = ret.and(Err(AllocationError::HeapCorrupted("page is in more than one list")));
+            }
+            if !state_ok(page) {
+                error!("Page {:p} in the {} list has a mismatching bitfield", page_ptr, name);
+                ret = ret.and(Err(AllocationError::HeapCorrupted("bitfield does not match page list")));
+            }
+            prev = page_ptr;
+        }
+
+        if count != list.elements {
+            error!("The {} list has {} pages but {} elements", name, count, list.elements);
+            ret = ret.and(Err(AllocationError::HeapCorrupted("list length does not match element count")));
+        }
+
+        ret
+    }
+
     /// Creates an allocable page given a MappedPages object and returns a reference to the allocable page.
     /// The MappedPages object is stored within the metadata of the allocable page.
-    fn create_allocable_page(mp: MappedPages, heap_id: usize) -> Result<&'a mut P, &'static str> {
-        let vaddr = mp.start_address().value();
-
+    pub(crate) fn create_allocable_page(mp: MappedPages, heap_id: usize) -> Result<&'a mut P, AllocationError> {
         // create page and store the MappedPages object
-        let page = P::new(mp, heap_id)?;
-        let page_ref: &'a mut P = unsafe { core::mem::transmute(vaddr) } ; // not unsafe because the allocable page was only create by a mapped page that fit the criteria
-        unsafe { (page_ref as *mut P).write(page); }
-
-        Ok(page_ref) 
+        let page = unsafe { P::write_in_place(mp, heap_id)? };
+        Ok(unsafe { &mut *page }) // the allocable page was only created by a mapped page that fit the criteria
     }
 
     /// Refill the SCAllocator
-    pub fn refill(&mut self, mp: MappedPages, heap_id: usize) -> Result<(), &'static str> {
+    ///
+    /// The page is tagged with the local node (see `set_local_node`), use `refill_on_node`
+    /// for memory of another node.
+    pub fn refill(&mut self, mp: MappedPages, heap_id: usize) -> Result<(), AllocationError> {
+        self.refill_on_node(mp, heap_id, self.local_node.unwrap_or(0))
+    }
+
+    /// Refills the allocator with `mp`, whose memory belongs to the NUMA node `node`.
+    pub fn refill_on_node(&mut self, mp: MappedPages, heap_id: usize, node: usize) -> Result<(), AllocationError> {
+        self.refill_placed(mp, heap_id, node, MemoryTier::Dram)
+    }
+
+    /// Refills the allocator with `mp`, whose memory belongs to the NUMA node `node`
+    /// and the memory tier `tier` (see `allocate_on_tier`).
+    pub fn refill_placed(&mut self, mp: MappedPages, heap_id: usize, node: usize, tier: MemoryTier) -> Result<(), AllocationError> {
+        if self.at_page_limit() {
+            error!("SCAllocator({}) already owns its maximum of {} pages", self.size, self.max_pages);
+            return Err(AllocationError::LimitExceeded);
+        }
+        self.check_flags(&mp)?;
         let page = Self::create_allocable_page(mp, heap_id)?;
-        page.bitfield_mut().initialize(self.size, P::SIZE - P::METADATA_SIZE);
+        page.set_node(node);
+        page.set_tier(tier);
+        self.insert_fresh_page(page);
+        SCCounters::add(&self.stats.refills, 1);
+
+        Ok(())
+    }
+
+    /// Seeds the allocator with pages carved out of `region`, before `MappedPages` can be
+    /// created (e.g., for the heap of early boot code that runs before frame allocation).
+    ///
+    /// Every `P::SIZE` aligned page that fits into `region` is added as an empty page
+    /// (up to the page cap of the allocator), the number of pages is returned.
+    /// The pages work like any other page, but they are not handed out by
+    /// `retrieve_empty_page` and friends until they are given their `MappedPages`
+    /// with `adopt_mapped_pages`.
+    ///
+    /// Fails with `AllocationError::InvalidPage` if `region` doesn't hold an aligned page
+    /// or the allocator requires flags or guard pages (see `set_required_flags`
+    /// and `set_guard_pages`), and with
+    /// `AllocationError::LimitExceeded` if the allocator is at its page cap.
+    pub fn refill_bootstrap<const N: usize>(&mut self, region: &'static mut [u8; N], heap_id: usize) -> Result<usize, AllocationError> {
+        if !PageFlags::empty().contains(self.required_flags) || self.guard.is_some() {
+            error!("SCAllocator({}) requires pages with {:?} (or guard pages)", self.size, self.required_flags);
+            return Err(AllocationError::InvalidPage("bootstrap pages lack the flags required by the allocator"));
+        }
+        let end = region.as_ptr() as usize + N;
+        let mut start = (region.as_ptr() as usize + P::SIZE - 1) & !(P::SIZE - 1);
+        if start + P::SIZE > end {
+            return Err(AllocationError::InvalidPage("bootstrap region does not hold an aligned page"));
+        }
+        if self.at_page_limit() {
+            return Err(AllocationError::LimitExceeded);
+        }
+
+        let mut pages = 0;
+        while start + P::SIZE <= end && !self.at_page_limit() {
+            // The region is borrowed for the rest of the program and the page lies within it
+            let page = unsafe { &mut *P::write_unmapped(start, heap_id)? };
+            self.insert_fresh_page(page);
+            start += P::SIZE;
+            pages += 1;
+        }
+        SCCounters::add(&self.stats.refills, pages);
+        Ok(pages)
+    }
+
+    /// Hands the `MappedPages` covering a page added by `refill_bootstrap` to the page,
+    /// once the memory subsystem is up. From then on it is an ordinary page
+    /// that can be returned with `retrieve_empty_page`.
+    ///
+    /// `mp` is handed back if it doesn't cover exactly a bootstrap page of this allocator
+    /// (`AllocationError::InvalidPointer`) or isn't suitable for a page (`AllocationError::InvalidPage`).
+    pub fn adopt_mapped_pages(&mut self, mp: MappedPages) -> Result<(), (AllocationError, MappedPages)> {
+        if let Err(e) = check_mapped_pages(&mp, P::SIZE).and_then(|_| self.check_flags(&mp)) {
+            return Err((e, mp));
+        }
+        let page = P::from_address(mp.start_address().value());
+        if page.is_null() || !self.owns_page(page) || unsafe { !(*page).is_unmapped() } {
+            return Err((AllocationError::InvalidPointer, mp));
+        }
+        unsafe { (*page).set_mapped_pages(mp) };
+        Ok(())
+    }
+
+    /// Adds the page in `mp` with the objects its bitfield records as allocated, e.g., a page
+    /// of a pre-built boot heap image or of another allocator instance. Unlike `refill`,
+    /// the meta-data is kept and the page goes to the list that matches its fill level.
+    ///
+    /// The `MappedPages` stored in the meta-data are replaced by `mp` (the stale ones
+    /// are not dropped). Fails with `AllocationError::CorruptedPage` if the meta-data lacks
+    /// `PAGE_MAGIC` or doesn't fit objects of this allocator's size, `mp` is handed back on failure.
+    ///
+    /// # Safety
+    /// The meta-data has to be that of a page of this allocator's object size, and the
+    /// objects it records as allocated must be owned by someone (they will be freed to this allocator).
+    pub unsafe fn adopt_page(&mut self, mp: MappedPages, heap_id: usize) -> Result<(), (AllocationError, MappedPages)> {
+        if self.at_page_limit() {
+            error!("SCAllocator({}) already owns its maximum of {} pages", self.size, self.max_pages);
+            return Err((AllocationError::LimitExceeded, mp));
+        }
+        if let Err(e) = check_mapped_pages(&mp, P::SIZE).and_then(|_| self.check_flags(&mp)) {
+            return Err((e, mp));
+        }
+        let page = P::from_address(mp.start_address().value());
+        if page.is_null() {
+            return Err((AllocationError::InvalidPage("page has no meta-data"), mp));
+        }
+        let page = &mut *page;
+        if !page.is_intact() {
+            return Err((AllocationError::CorruptedPage, mp));
+        }
+        // The bits past the objects of this size are always set, and the color has to leave room for all objects
+        let bitfield = page.bitfield();
+        if (self.obj_per_page..P::BITFIELD_WORDS * 64).any(|idx| !bitfield.is_allocated(idx))
+            || page.color() % CACHE_LINE_SIZE != 0
+            || page.color() / CACHE_LINE_SIZE >= self.colors()
+        {
+            error!("Page {:#x} was not laid out for objects of size {}", mp.start_address().value(), self.size);
+            return Err((AllocationError::CorruptedPage, mp));
+        }
+
+        page.set_mapped_pages(mp);
+        page.set_heap_id(heap_id);
         *page.prev() = Rawlink::none();
         *page.next() = Rawlink::none();
-        // trace!("adding page to SCAllocator {:p}", page);
-        self.insert_empty(page);
+        // Free slots are expected to hold the poison pattern
+        #[cfg(feature = "debug-poison")]
+        for idx in (0..self.obj_per_page).filter(|&idx| !page.bitfield().is_allocated(idx)) {
+            poison(page.objects_start() + idx * self.size, self.size);
+        }
+
+        if page.is_empty(self.obj_per_page) {
+            self.insert_empty(page);
+        } else if page.is_full() {
+            unsafe { self.full_slabs.insert_front(page) };
+        } else {
+            self.insert_partial_slab(page);
+        }
+        SCCounters::add(&self.stats.refills, 1);
+        Ok(())
+    }
 
+    /// Adds an empty page of another allocator (or of an `EmptyPagePool`) to this
+    /// allocator and tags it with `heap_id`.
+    ///
+    /// The page is handed back if the allocator already owns as many pages as it may
+    /// or the page lacks the flags (or the guard page) required by this allocator.
+    pub(crate) fn adopt_empty_page(&mut self, page: &'a mut P, heap_id: usize) -> Result<(), &'a mut P> {
+        let mp = page.mapped_pages();
+        if self.at_page_limit()
+            || !mp.flags().contains(self.required_flags)
+            || matches!(self.guard, Some(guard) if !guard.has_guard_page(mp))
+        {
+            return Err(page);
+        }
+        page.set_heap_id(heap_id);
+        self.insert_fresh_page(page);
         Ok(())
     }
 
+    /// Returns the size of the object at `addr` in `page` (a page of this allocator),
+    /// or `None` if `addr` is not the start of an allocated object.
+    pub(crate) fn object_size_at(&self, page: &P, addr: VAddr) -> Option<usize> {
+        let offset = addr.checked_sub(page.objects_start())?;
+        let idx = offset / self.size;
+        if offset % self.size != 0 || idx >= self.obj_per_page || !page.bitfield().is_allocated(idx) {
+            return None;
+        }
+        Some(self.size)
+    }
+
+    /// Returns `true` if `page` belongs to this allocator (retired pages don't).
+    pub(crate) fn owns_page(&mut self, page: *const P) -> bool {
+        self.slabs.contains(page)
+            || self.full_slabs.contains(page)
+            || self.empty_slabs.contains(page)
+            || self.draining_slabs.contains(page)
+            || self.long_lived_slabs.contains(page)
+    }
+
+    /// Removes an empty page from the allocator without unmapping it.
+    pub(crate) fn take_empty_page(&mut self) -> Option<&'a mut P> {
+        self.remove_empty()
+    }
+
+    /// Formats `page` for objects of this allocator and adds it to the empty pages.
+    fn insert_fresh_page(&mut self, page: &'a mut P) {
+        let color = self.next_color();
+        page.set_color(color);
+        page.bitfield_mut().initialize(self.size, self.obj_per_page * self.size);
+        // Fresh pages are poisoned too, so the check in `allocate` holds for every object
+        #[cfg(feature = "debug-poison")]
+        poison(page.data_start(), P::SIZE - P::METADATA_SIZE);
+        *page.prev() = Rawlink::none();
+        *page.next() = Rawlink::none();
+        // trace!("adding page to SCAllocator {:p}", page);
+        self.insert_empty(page);
+    }
+
     /// Returns an empty page from the allocator if available.
     /// It removes the MappedPages object from the heap pages where it is stored.
+    ///
+    /// The page is returned immediately, use `retire_empty_page` if other cores
+    /// may still be freeing objects into it.
     pub fn retrieve_empty_page(&mut self) -> Option<MappedPages> {
         match self.remove_empty(){
             Some(page) => {
+                SCCounters::add(&self.stats.pages_retrieved, 1);
                 Some(page.retrieve_mapped_pages()) //safe because the page has been removed from the heap's linked lists
             }
             None => {
@@ -315,6 +1802,126 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         }
     }
 
+    /// Returns the page that starts at `addr` if (and only if) it is empty.
+    ///
+    /// Fails with `AllocationError::PageNotEmpty` if the page still holds objects
+    /// (see `drain_page`) and with `AllocationError::InvalidPointer` if the page
+    /// does not belong to this allocator.
+    pub fn remove_page(&mut self, addr: VAddr) -> Result<MappedPages, AllocationError> {
+        if !addr.is_multiple_of(P::SIZE) {
+            return Err(AllocationError::InvalidPointer);
+        }
+        let page_ptr = P::from_address(addr) as *const P;
+        if self.empty_slabs.contains(page_ptr) {
+            let page = unsafe { &mut *(page_ptr as *mut P) };
+            if page.is_unmapped() {
+                return Err(AllocationError::InvalidPage("bootstrap page is not backed by MappedPages"));
+            }
+            self.empty_slabs.remove_from_list(page);
+            SCCounters::add(&self.stats.pages_retrieved, 1);
+            //safe because the page has been removed from the heap's linked lists
+            return Ok(page.retrieve_mapped_pages());
+        }
+        if self.slabs.contains(page_ptr)
+            || self.full_slabs.contains(page_ptr)
+            || self.draining_slabs.contains(page_ptr)
+            || self.long_lived_slabs.contains(page_ptr)
+        {
+            return Err(AllocationError::PageNotEmpty);
+        }
+        Err(AllocationError::InvalidPointer)
+    }
+
+    /// Removes every page (including retired pages and pages that still hold objects)
+    /// from this allocator and hands its `MappedPages` to `f`.
+    ///
+    /// Returns the number of objects that were still allocated, i.e., leaked.
+    /// Quarantined objects are not counted. Bootstrap pages that were never adopted
+    /// are dropped from the allocator without calling `f`.
+    pub fn take_all_pages<F: FnMut(MappedPages)>(&mut self, mut f: F) -> usize {
+        // Quarantined objects are still marked allocated in their page
+        let mut quarantined = 0;
+        while self.quarantine.pop().is_some() {
+            quarantined += 1;
+        }
+        self.mru_page = 0;
+
+        let mut leaked = 0;
+        let obj_per_page = self.obj_per_page;
+        let mut lists = [
+            &mut self.empty_slabs,
+            &mut self.slabs,
+            &mut self.full_slabs,
+            &mut self.draining_slabs,
+            &mut self.long_lived_slabs,
+        ];
+        for list in lists.iter_mut() {
+            while let Some(page) = list.pop() {
+                leaked += obj_per_page - core::cmp::min(page.free_objects(), obj_per_page);
+                if !page.is_unmapped() {
+                    SCCounters::add(&self.stats.pages_retrieved, 1);
+                    f(page.retrieve_mapped_pages());
+                }
+            }
+        }
+        while let Some(page) = self.retired.pop() {
+            let page = unsafe { mem::transmute::<VAddr, &'a mut P>(page) };
+            SCCounters::add(&self.stats.pages_retrieved, 1);
+            f(page.retrieve_mapped_pages());
+        }
+        leaked.saturating_sub(quarantined)
+    }
+
+    /// Takes an empty page out of the allocator without returning it yet.
+    ///
+    /// Unlike `retrieve_empty_page`, the page is kept in a retired list stamped with `epoch`
+    /// (the current epoch of the embedder), so cores that are still in the middle of
+    /// freeing into the page can finish. Retired pages are returned by `reclaim_retired_page`.
+    ///
+    /// Returns `false` if there is no empty page or the retired list is full.
+    pub fn retire_empty_page(&mut self, epoch: u64) -> bool {
+        if self.retired.is_full() {
+            return false;
+        }
+        match self.remove_empty() {
+            Some(page) => {
+                self.retired.push(epoch, page as *mut P as VAddr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the oldest retired page, if it was retired in an epoch
+    /// before `quiescent_epoch`.
+    ///
+    /// `quiescent_epoch` must be an epoch all cores have passed a quiescent point in
+    /// (i.e., no core can still be using a page retired before it).
+    pub fn reclaim_retired_page(&mut self, quiescent_epoch: u64) -> Option<MappedPages> {
+        let page = self.retired.pop_before(quiescent_epoch)?;
+        let page = unsafe { mem::transmute::<VAddr, &'a mut P>(page) };
+        SCCounters::add(&self.stats.pages_retrieved, 1);
+        Some(page.retrieve_mapped_pages())
+    }
+
+    /// Number of retired pages that were not reclaimed yet.
+    pub fn retired_pages(&self) -> usize {
+        self.retired.len
+    }
+
+    /// Checks that objects of `layout` can be served by this allocator.
+    fn check_layout(&self, layout: Layout) -> Result<(), AllocationError> {
+        if layout.size() > self.size || self.size > (P::SIZE - P::METADATA_SIZE) || layout.align() > P::SIZE {
+            error!("SCAllocator({}) can't serve {:?}", self.size, layout);
+            return Err(AllocationError::InvalidLayout);
+        }
+        if self.cache_coloring && layout.align() > CACHE_LINE_SIZE {
+            error!("SCAllocator({}) colors its pages and can't align to {}", self.size, layout.align());
+            return Err(AllocationError::InvalidLayout);
+        }
+        Ok(())
+    }
+
     /// Allocates a block of memory descriped by `layout`.
     ///
     /// Returns a pointer to a valid region of memory or an
@@ -322,44 +1929,105 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
     ///
     /// The function may also move around pages between lists
     /// (empty -> partial or partial -> full).
-    pub fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, &'static str> {
+    ///
+    /// Only the common case (the page that served the last allocation has room) is inlined,
+    /// everything else is left to `allocate_slow`.
+    #[inline]
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        self.allocate_with_hint(layout, AllocHint::ShortLived)
+    }
+
+    /// Allocates a block of memory described by `layout` from the pages of objects
+    /// with the lifetime `hint`.
+    ///
+    /// Long-lived objects are packed into their own pages (`long_lived_slabs`), so they don't
+    /// pin the pages of short-lived objects, which can then empty out and be returned.
+    /// Both take their pages from the same empty list, and `deallocate` works for either.
+    ///
+    /// Long-lived allocations walk their pages (including the full ones) first-fit,
+    /// they are meant to be rare compared to short-lived ones.
+    #[inline]
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn allocate_with_hint(&mut self, layout: Layout, hint: AllocHint) -> Result<NonNull<u8>, AllocationError> {
+        self.allocate_placed(layout, hint, None)
+    }
+
+    /// Like `allocate`, but also returns how many free slots are left in the page that served
+    /// the object and in the whole size class, e.g., to refill ahead of time without taking
+    /// a separate look at the statistics.
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn allocate_ext(&mut self, layout: Layout) -> Result<AllocationInfo, AllocationError> {
+        let ptr = self.allocate(layout)?;
+        Ok(self.allocation_info(ptr, ptr.as_ptr() as VAddr))
+    }
+
+    /// Describes the room left after `ptr` (at address `addr`, without any MTE tag) was allocated.
+    pub(crate) fn allocation_info(&self, ptr: NonNull<u8>, addr: VAddr) -> AllocationInfo {
+        let page = unsafe { &*P::from_address(addr & !(P::SIZE - 1)) };
+        let page_free = page.free_objects();
+        AllocationInfo {
+            ptr,
+            page_free,
+            class_free: page_free + self.empty_slabs.elements * self.obj_per_page,
+        }
+    }
+
+    /// Allocates a block of memory described by `layout` from a page of the memory tier `tier`
+    /// (see `refill_placed`), or from a page of another tier if the tier has no room left.
+    ///
+    /// Walks the partial pages for one of `tier` instead of using the page of the last allocation,
+    /// so it is slower than `allocate`.
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn allocate_on_tier(&mut self, layout: Layout, tier: MemoryTier) -> Result<NonNull<u8>, AllocationError> {
+        self.allocate_placed(layout, AllocHint::ShortLived, Some(tier))
+    }
+
+    /// Allocates from the pages of `hint`, preferring the memory tier `tier` for short-lived objects.
+    #[inline]
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub(crate) fn allocate_placed(&mut self, layout: Layout, hint: AllocHint, tier: Option<MemoryTier>) -> Result<NonNull<u8>, AllocationError> {
         // trace!(
         //     "SCAllocator({}) is trying to allocate {:?}, {}",
         //     self.size,
         //     layout, 
         //     P::SIZE - CACHE_LINE_SIZE
         // );
-        assert!(layout.size() <= self.size);
-        assert!(self.size <= (P::SIZE - CACHE_LINE_SIZE));
+        #[cfg(feature = "red-zones")]
+        let (layout, object_layout) = (red_zone_layout(layout), layout);
+        self.check_layout(layout)?;
         let new_layout = unsafe { Layout::from_size_align_unchecked(self.size, layout.align()) };
-        assert!(new_layout.size() >= layout.size());
-
-        let ptr = {
-            // Try to allocate from partial slabs,
-            // if we fail check if we have empty pages and allocate from there
-            let ptr = self.try_allocate_from_pagelist(new_layout);
-            if ptr.is_null() && self.empty_slabs.head.is_some() {
-                // Re-try allocation in empty page
-                let empty_page = self.empty_slabs.pop().expect("We checked head.is_some()");
-                debug_assert!(!self.empty_slabs.contains(empty_page));
-
-                let ptr = empty_page.allocate(layout);
-                debug_assert!(!ptr.is_null(), "Allocation must have succeeded here.");
-
-                // trace!(
-                //     "move {:p} empty -> partial empty count {}",
-                //     empty_page,
-                //     self.empty_slabs.elements
-                // );
-                // Move empty page to partial pages
-                self.insert_partial_slab(empty_page);
-                ptr
-            } else {
-                ptr
+
+        let ptr = match (hint, tier) {
+            (AllocHint::ShortLived, None) => match self.allocate_fast(new_layout) {
+                ptr if ptr.is_null() => self.allocate_slow(layout, new_layout),
+                ptr => ptr,
+            },
+            (AllocHint::ShortLived, Some(tier)) => self.allocate_from_tier(layout, new_layout, tier),
+            (AllocHint::LongLived, _) => self.allocate_long_lived(new_layout),
+        };
+        self.count_remote(ptr);
+
+        #[cfg(feature = "callsites")]
+        {
+            if !ptr.is_null() {
+                self.record_callsite(ptr as usize, core::panic::Location::caller());
             }
+        }
+
+        #[cfg(feature = "red-zones")]
+        let ptr = if ptr.is_null() {
+            ptr
+        } else {
+            write_red_zones(ptr as usize, self.size, object_layout) as *mut u8
         };
 
-        let res = NonNull::new(ptr).ok_or("AllocationError::OutOfMemory");
+        let res = NonNull::new(ptr).ok_or(AllocationError::OutOfMemory);
+
+        // Periodically rebalance page-lists (since unlocked deallocations can't do it for us)
+        if res.is_ok() && !cfg!(feature = "minimal") && self.rebalance_interval > 0 && self.stats.allocations.load(Ordering::Relaxed).is_multiple_of(self.rebalance_interval) {
+            self.check_page_assignments();
+        }
 
         // if !ptr.is_null() {
         //     trace!(
@@ -372,14 +2040,324 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         res
     }
 
+    /// Allocates from the page that served the last allocation, null if it has no room
+    /// (or the selection policy has to look at all pages).
+    #[inline]
+    fn allocate_fast(&mut self, sc_layout: Layout) -> *mut u8 {
+        if self.mru_page == 0 || self.selection_policy == PageSelectionPolicy::FullestFirst {
+            return ptr::null_mut();
+        }
+        let slab_page = unsafe { mem::transmute::<VAddr, &'a mut P>(self.mru_page) };
+        self.allocate_from_partial(slab_page, sc_layout)
+    }
+
+    /// The part of `allocate` that walks the page lists and
+    /// starts an empty page if no partial page has room.
+    #[inline(never)]
+    fn allocate_slow(&mut self, layout: Layout, sc_layout: Layout) -> *mut u8 {
+        // Try to allocate from partial slabs,
+        // if we fail check if we have empty pages and allocate from there
+        let ptr = self.try_allocate_from_pagelist(sc_layout);
+        if !ptr.is_null() {
+            return ptr;
+        }
+        SCCounters::add(&self.stats.slow_path, 1);
+
+        // Re-try allocation in empty page
+        match self.pop_empty() {
+            Some(page) => self.allocate_in_empty(page, layout),
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Allocates the first object of `empty_page` (which was taken off `empty_slabs`)
+    /// and puts the page on the partial (or full) list.
+    fn allocate_in_empty(&mut self, empty_page: &'a mut P, layout: Layout) -> *mut u8 {
+        debug_assert_list!(!self.empty_slabs.contains(empty_page));
+
+        let random = self.next_random();
+        let ptr = Self::allocate_in_page(empty_page, layout, random);
+        debug_assert!(!ptr.is_null(), "Allocation must have succeeded here.");
+
+        // trace!(
+        //     "move {:p} empty -> partial empty count {}",
+        //     empty_page,
+        //     self.empty_slabs.elements
+        // );
+        // Move empty page to partial pages (or straight to the full pages
+        // for size classes that only fit one object per page)
+        if empty_page.is_full() {
+            unsafe { self.full_slabs.insert_front(empty_page) };
+            SCCounters::add(&self.stats.partial_to_full, 1);
+        } else {
+            self.insert_partial_slab(empty_page);
+        }
+        SCCounters::add(&self.stats.allocations, 1);
+        ptr
+    }
+
+    /// Allocates from the pages of the memory tier `tier` (partial ones first),
+    /// falls back to the other tiers if it has no room left.
+    #[inline(never)]
+    fn allocate_from_tier(&mut self, layout: Layout, sc_layout: Layout, tier: MemoryTier) -> *mut u8 {
+        for slab_page in self.slabs.iter_mut() {
+            if slab_page.tier() != tier {
+                continue;
+            }
+            let ptr = self.allocate_from_partial(slab_page, sc_layout);
+            if !ptr.is_null() {
+                return ptr;
+            }
+        }
+        let spare = self.has_spare_empty_page();
+        if let Some(empty_page) = self.empty_slabs.iter_mut().find(|page| spare && page.tier() == tier) {
+            self.empty_slabs.remove_from_list(empty_page);
+            SCCounters::add(&self.stats.slow_path, 1);
+            SCCounters::add(&self.stats.empty_to_partial, 1);
+            return self.allocate_in_empty(empty_page, layout);
+        }
+        self.allocate_slow(layout, sc_layout)
+    }
+
+    /// Allocates from the pages of long-lived objects, starts an empty page if none has room.
+    #[inline(never)]
+    fn allocate_long_lived(&mut self, sc_layout: Layout) -> *mut u8 {
+        for slab_page in self.long_lived_slabs.iter_mut() {
+            if slab_page.is_full() {
+                continue;
+            }
+            let random = self.next_random();
+            let ptr = Self::allocate_in_page(slab_page, sc_layout, random);
+            if !ptr.is_null() {
+                SCCounters::add(&self.stats.allocations, 1);
+                return ptr;
+            }
+        }
+        SCCounters::add(&self.stats.slow_path, 1);
+
+        let empty_page = match self.pop_empty() {
+            Some(page) => page,
+            None => return ptr::null_mut(),
+        };
+        let random = self.next_random();
+        let ptr = Self::allocate_in_page(empty_page, sc_layout, random);
+        debug_assert!(!ptr.is_null(), "Allocation must have succeeded here.");
+        unsafe { self.long_lived_slabs.insert_front(empty_page) };
+        SCCounters::add(&self.stats.allocations, 1);
+        ptr
+    }
+
+    /// Tries to allocate a block of memory described by `layout` in bounded time,
+    /// e.g., from an interrupt handler.
+    ///
+    /// Only the first page in `slabs` is considered, it is not moved to `full_slabs`
+    /// (i.e., the object can't be the last one in the page). If it has no room, an empty page
+    /// is started if the allocator has an emergency reserve (see `set_emergency_reserve`).
+    /// Fails with `AllocationError::WouldBlock` instead of walking the partial list.
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn try_allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        #[cfg(feature = "red-zones")]
+        let (layout, object_layout) = (red_zone_layout(layout), layout);
+        self.check_layout(layout)?;
+        let new_layout = unsafe { Layout::from_size_align_unchecked(self.size, layout.align()) };
+
+        let random = self.next_random();
+        let ptr = match self.slabs.head.as_mut() {
+            Some(page) => {
+                let ptr = Self::allocate_in_page(page, new_layout, random);
+                if !ptr.is_null() && page.is_full() {
+                    // Give the object back rather than moving the page
+                    let page_addr = page.objects_start();
+                    page.bitfield().clear_bit((ptr as usize - page_addr) / self.size);
+                    ptr::null_mut()
+                } else {
+                    ptr
+                }
+            }
+            None => ptr::null_mut(),
+        };
+        let ptr = if ptr.is_null() {
+            self.allocate_from_reserve(new_layout)?
+        } else {
+            SCCounters::add(&self.stats.allocations, 1);
+            ptr
+        };
+        self.count_remote(ptr);
+
+        #[cfg(feature = "callsites")]
+        self.record_callsite(ptr as usize, core::panic::Location::caller());
+
+        #[cfg(feature = "red-zones")]
+        let ptr = write_red_zones(ptr as usize, self.size, object_layout) as *mut u8;
+        Ok(unsafe { NonNull::new_unchecked(ptr) })
+    }
+
+    /// Starts an empty page for `try_allocate` if the allocator has an emergency reserve,
+    /// fails with `AllocationError::WouldBlock` otherwise (or if the reserve is used up).
+    fn allocate_from_reserve(&mut self, layout: Layout) -> Result<*mut u8, AllocationError> {
+        if self.emergency_reserve == 0 {
+            return Err(AllocationError::WouldBlock);
+        }
+        let page = self.empty_slabs.pop().ok_or(AllocationError::WouldBlock)?;
+        SCCounters::add(&self.stats.empty_to_partial, 1);
+        Ok(self.allocate_in_empty(page, layout))
+    }
+
+    /// Allocates up to `n` objects described by `layout` and writes them into `out`.
+    ///
+    /// This walks the page lists only once, which is considerably cheaper than
+    /// calling `allocate` `n` times. Returns the number of objects allocated,
+    /// which is less than `n` (or `out.len()`) if the allocator ran out of memory.
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn allocate_many(
+        &mut self,
+        layout: Layout,
+        n: usize,
+        out: &mut [NonNull<u8>],
+    ) -> Result<usize, AllocationError> {
+        #[cfg(feature = "red-zones")]
+        self.check_layout(red_zone_layout(layout))?;
+        #[cfg(not(feature = "red-zones"))]
+        self.check_layout(layout)?;
+
+        let n = core::cmp::min(n, out.len());
+        let mut count = 0;
+
+        for slab_page in self.slabs.iter_mut() {
+            if count == n {
+                break;
+            }
+            count += self.allocate_from_page(slab_page, layout, &mut out[count..n]);
+            if slab_page.is_full() {
+                self.move_partial_to_full(slab_page);
+            }
+        }
+
+        while count < n {
+            let empty_page = match self.pop_empty() {
+                Some(page) => page,
+                None => break,
+            };
+            count += self.allocate_from_page(empty_page, layout, &mut out[count..n]);
+            if empty_page.is_full() {
+                unsafe { self.full_slabs.insert_front(empty_page) };
+                SCCounters::add(&self.stats.partial_to_full, 1);
+            } else {
+                self.insert_partial_slab(empty_page);
+            }
+        }
+
+        SCCounters::add(&self.stats.allocations, count);
+        Ok(count)
+    }
+
+    /// Allocates objects described by `layout` from `page` until either
+    /// the page is full or `out` is filled, returns the number of objects allocated.
+    #[cfg_attr(feature = "callsites", track_caller)]
+    fn allocate_from_page(&mut self, page: &mut P, layout: Layout, out: &mut [NonNull<u8>]) -> usize {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(self.size, layout.align()) };
+        let mut count = 0;
+        while count < out.len() {
+            let random = self.next_random();
+            let ptr = Self::allocate_in_page(page, new_layout, random);
+            if ptr.is_null() {
+                break;
+            }
+            #[cfg(feature = "callsites")]
+            self.record_callsite(ptr as usize, core::panic::Location::caller());
+            #[cfg(feature = "red-zones")]
+            let ptr = write_red_zones(ptr as usize, self.size, layout) as *mut u8;
+            out[count] = unsafe { NonNull::new_unchecked(ptr) };
+            count += 1;
+        }
+        if self.is_remote(page) {
+            SCCounters::add(&self.stats.remote_allocations, count);
+        }
+        count
+    }
+
+    /// Checks that `ptr` can be an object handed out by this allocator:
+    /// it has to lie in a partial or full page owned by us (`page` is the base address
+    /// of the page containing `ptr`), start at a slot boundary and must not
+    /// point into the page meta-data.
+    ///
+    /// This prevents us from interpreting an arbitrary address as an `AllocablePage`:
+    /// the page is only used once it was found on one of our lists, it is returned
+    /// along with the list it is on.
+    fn validate_pointer(&mut self, ptr: NonNull<u8>, page: VAddr) -> Result<(PageState, &'a mut P), AllocationError> {
+        let (state, slab_page) = match self.find_page(page) {
+            Some(found) => found,
+            None => {
+                error!("{:p} is not within a page owned by SCAllocator({})", ptr, self.size);
+                return Err(AllocationError::InvalidPointer);
+            }
+        };
+        if !slab_page.is_intact() {
+            return Err(AllocationError::CorruptedPage);
+        }
+
+        // The objects of a colored page don't start at the page boundary
+        let data_offset = (ptr.as_ptr() as usize).wrapping_sub(slab_page.objects_start());
+        if data_offset % self.size != 0 {
+            error!("{:p} is not aligned to a slot of size {}", ptr, self.size);
+            return Err(AllocationError::InvalidPointer);
+        }
+        if data_offset / self.size >= self.obj_per_page
+            || slab_page.color() + data_offset + self.size > P::SIZE - P::METADATA_SIZE
+        {
+            error!("{:p} points into the meta-data of page {:#x}", ptr, page);
+            return Err(AllocationError::InvalidPointer);
+        }
+
+        Ok((state, slab_page))
+    }
+
+    /// Finds the page at `page` on the lists that can hold live objects
+    /// (partial, full, draining and long-lived), along with the list it is on.
+    fn find_page(&mut self, page: VAddr) -> Option<(PageState, &'a mut P)> {
+        let page_ptr = P::from_address(page) as *const P;
+        let SCAllocator { slabs, full_slabs, draining_slabs, long_lived_slabs, .. } = self;
+        let lists = [
+            (PageState::Partial, slabs),
+            (PageState::Full, full_slabs),
+            (PageState::Draining, draining_slabs),
+            (PageState::LongLived, long_lived_slabs),
+        ];
+        for (state, list) in lists {
+            if let Some(slab_page) = list.iter_mut().find(|slab_page| ptr::eq(*slab_page, page_ptr)) {
+                return Some((state, slab_page));
+            }
+        }
+        None
+    }
+
     /// Deallocates a previously allocated `ptr` described by `Layout`.
     ///
-    /// May return an error in case an invalid `layout` is provided.
+    /// May return an error in case an invalid `layout` is provided,
+    /// `ptr` was not handed out by this allocator
+    /// or `ptr` is not currently allocated (double free).
     /// The function may also move internal slab pages between lists partial -> empty
     /// or full -> partial lists.
-    pub fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), &'static str> {
-        assert!(layout.size() <= self.size);
-        assert!(self.size <= (P::SIZE - CACHE_LINE_SIZE));
+    ///
+    /// If the quarantine is enabled the object is only scrubbed and parked
+    /// in the quarantine, while the oldest quarantined object is released instead.
+    pub fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        self.deallocate_ext(ptr, layout).map(|_| ())
+    }
+
+    /// Like `deallocate`, but also tells if the page the object went back to moved
+    /// from the full to the partial list or became empty, so the caller can decide
+    /// to reclaim pages right away instead of polling `empty_pages`.
+    ///
+    /// With the quarantine enabled this is the page of the object that left the quarantine.
+    pub fn deallocate_ext(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<PageTransition, AllocationError> {
+        #[cfg(feature = "red-zones")]
+        let (ptr, layout, object_layout) = {
+            let slot = red_zone_slot(ptr.as_ptr() as usize, layout).ok_or(AllocationError::InvalidPointer)?;
+            let slot = NonNull::new(slot as *mut u8).ok_or(AllocationError::InvalidPointer)?;
+            (slot, red_zone_layout(layout), layout)
+        };
+        self.check_layout(layout)?;
         // trace!(
         //     "SCAllocator({}) is trying to deallocate ptr = {:p} layout={:?} P.size= {}",
         //     self.size,
@@ -389,26 +2367,234 @@ impl<'a, P: AllocablePage> SCAllocator<'a, P> {
         // );
 
         let page = (ptr.as_ptr() as usize) & !(P::SIZE - 1) as usize;
+        let (state, slab_page) = self.validate_pointer(ptr, page)?;
 
-        // Figure out which page we are on and construct a reference to it
-        // TODO: The linked list will have another &mut reference
-        let slab_page = unsafe { mem::transmute::<VAddr, &'a mut P>(page) };
-        let new_layout = unsafe { Layout::from_size_align_unchecked(self.size, layout.align()) };
+        // Only check live objects, a double free is reported by the page
+        #[cfg(feature = "red-zones")]
+        {
+            let idx = (ptr.as_ptr() as usize - slab_page.objects_start()) / self.size;
+            if slab_page.bitfield().is_allocated(idx)
+                && !check_red_zones(ptr.as_ptr() as usize, self.size, object_layout)
+            {
+                return Err(AllocationError::RedZoneViolation);
+            }
+        }
+
+        self.free_slot(ptr, slab_page, state)
+    }
+
+    /// Changes the layout of the object at `ptr` from `old_layout` to `new_layout` without moving it.
+    ///
+    /// Fails with `AllocationError::InvalidLayout` if this allocator can't hold an object of
+    /// `new_layout` or `ptr` isn't aligned for it, and with `AllocationError::InvalidPointer`
+    /// if `ptr` isn't a live object of this allocator. The object is left as is in that case.
+    /// With the `red-zones` feature the red zones are checked and moved to the new end of the object.
+    pub fn resize_in_place(&mut self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<(), AllocationError> {
+        #[cfg(feature = "red-zones")]
+        let (old_layout, new_layout, object_layouts) = {
+            if red_zone_front(old_layout) != red_zone_front(new_layout) {
+                return Err(AllocationError::InvalidLayout);
+            }
+            (red_zone_layout(old_layout), red_zone_layout(new_layout), (old_layout, new_layout))
+        };
+        self.check_layout(old_layout)?;
+        self.check_layout(new_layout)?;
+        if !(ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            return Err(AllocationError::InvalidLayout);
+        }
+
+        #[cfg(feature = "red-zones")]
+        let slot = red_zone_slot(ptr.as_ptr() as usize, object_layouts.0).ok_or(AllocationError::InvalidPointer)?;
+        #[cfg(not(feature = "red-zones"))]
+        let slot = ptr.as_ptr() as usize;
+        let slot = NonNull::new(slot as *mut u8).ok_or(AllocationError::InvalidPointer)?;
+        let (_, slab_page) = self.validate_pointer(slot, slot.as_ptr() as usize & !(P::SIZE - 1))?;
+        let idx = (slot.as_ptr() as usize - slab_page.objects_start()) / self.size;
+        if !slab_page.bitfield().is_allocated(idx) || self.quarantine.contains(slot) {
+            error!("{:p} is not a live object of SCAllocator({})", ptr, self.size);
+            return Err(AllocationError::InvalidPointer);
+        }
+
+        #[cfg(feature = "red-zones")]
+        {
+            let (old_object, new_object) = object_layouts;
+            if !check_red_zones(slot.as_ptr() as usize, self.size, old_object) {
+                return Err(AllocationError::RedZoneViolation);
+            }
+            write_red_zones(slot.as_ptr() as usize, self.size, new_object);
+        }
+        Ok(())
+    }
+
+    /// Deallocates the object in `slot` (the start of the slot, not the object address
+    /// if the `red-zones` feature is enabled) without checking its red zones.
+    ///
+    /// Used for objects that were handed over from another heap,
+    /// their red zones were checked (and then reused) when they were handed over.
+    pub(crate) fn deallocate_slot(&mut self, slot: NonNull<u8>) -> Result<(), AllocationError> {
+        let page = (slot.as_ptr() as usize) & !(P::SIZE - 1);
+        let (state, slab_page) = self.validate_pointer(slot, page)?;
+        self.free_slot(slot, slab_page, state).map(|_| ())
+    }
+
+    /// Marks the object `ptr` free in its page, which was freed onto a free stack of a
+    /// `ShardedZoneAllocator` before (see `ShardedZoneAllocator::flush_free_stacks`).
+    ///
+    /// The free was counted (and the object poisoned and its generation bumped) when it was stacked.
+    pub(crate) fn release_stacked(&mut self, ptr: NonNull<u8>) -> Result<(), AllocationError> {
+        let page = (ptr.as_ptr() as usize) & !(P::SIZE - 1);
+        let (state, slab_page) = self.validate_pointer(ptr, page)?;
+        self.release_in(ptr, slab_page, state).map(|_| ())
+    }
+
+    /// Frees the validated slot `ptr` in `slab_page` (on the list of `state`),
+    /// either by putting it into the quarantine or by releasing it to its page.
+    fn free_slot(&mut self, ptr: NonNull<u8>, slab_page: &'a mut P, state: PageState) -> Result<PageTransition, AllocationError> {
+        let res = self.quarantine_or_release(ptr, slab_page, state);
+        if res.is_ok() {
+            #[cfg(feature = "generations")]
+            Self::bump_generation(ptr.as_ptr() as VAddr, self.size);
+            SCCounters::add(&self.stats.deallocations, 1);
+        }
+        res
+    }
+
+    fn quarantine_or_release(&mut self, ptr: NonNull<u8>, slab_page: &'a mut P, state: PageState) -> Result<PageTransition, AllocationError> {
+        if self.quarantine.limit == 0 {
+            return self.release_in(ptr, slab_page, state);
+        }
+
+        // Quarantined objects are still marked allocated in their page
+        let idx = (ptr.as_ptr() as usize - slab_page.objects_start()) / self.size;
+        if !slab_page.bitfield().is_allocated(idx) || self.quarantine.contains(ptr) {
+            error!("Double free detected: {:p} is not marked allocated", ptr);
+            return Err(AllocationError::DoubleFree);
+        }
+
+        self.scrub(ptr);
+        match self.quarantine.push(ptr) {
+            Some(oldest) => self.release(oldest),
+            None => Ok(PageTransition::Unchanged),
+        }
+    }
+
+    /// Overwrites a quarantined object so use-after-free accesses
+    /// read garbage (or are detected with `debug-poison`).
+    fn scrub(&self, ptr: NonNull<u8>) {
+        #[cfg(feature = "debug-poison")]
+        poison(ptr.as_ptr() as usize, self.size);
+
+        #[cfg(not(feature = "debug-poison"))]
+        {
+            if self.zero_on_free != ZeroOnFree::Disabled {
+                unsafe { ptr::write_bytes(ptr.as_ptr(), 0, self.size) };
+            }
+        }
+    }
+
+    /// Returns all objects in the quarantine to their pages.
+    pub fn flush_quarantine(&mut self) -> Result<(), AllocationError> {
+        while let Some(ptr) = self.quarantine.pop() {
+            self.release(ptr)?;
+        }
+        Ok(())
+    }
 
-        let slab_page_was_full = slab_page.is_full();
-        let ret = slab_page.deallocate(ptr, new_layout);
-        debug_assert!(ret.is_ok(), "Slab page deallocate won't fail at the moment");
+    /// Sets how many freed objects are held back before they can be reused.
+    ///
+    /// The limit is capped at `QUARANTINE_CAPACITY`, 0 disables the quarantine.
+    /// Objects that no longer fit in the quarantine are released immediately.
+    pub fn set_quarantine(&mut self, limit: usize) -> Result<(), AllocationError> {
+        self.quarantine.limit = core::cmp::min(limit, QUARANTINE_CAPACITY);
+        while self.quarantine.len > self.quarantine.limit {
+            match self.quarantine.pop() {
+                Some(ptr) => {
+                    self.release(ptr)?;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Marks the slot at `ptr` (an object that left the quarantine) free in its page
+    /// and moves the page to the right list.
+    ///
+    /// `ptr` must have been validated by `deallocate`, its page is looked up again
+    /// since it may have moved to another list while the object was in the quarantine.
+    fn release(&mut self, ptr: NonNull<u8>) -> Result<PageTransition, AllocationError> {
+        let page = (ptr.as_ptr() as usize) & !(P::SIZE - 1);
+        let (state, slab_page) = self.find_page(page).ok_or(AllocationError::InvalidPointer)?;
+        self.release_in(ptr, slab_page, state)
+    }
+
+    /// Marks the slot at `ptr` free in `slab_page` (on the list of `state`)
+    /// and moves the page to the right list.
+    fn release_in(&mut self, ptr: NonNull<u8>, slab_page: &'a mut P, state: PageState) -> Result<PageTransition, AllocationError> {
+        let new_layout = unsafe { Layout::from_size_align_unchecked(self.size, 1) };
+
+        // Detect writes to the object while it was sitting in the quarantine
+        #[cfg(feature = "debug-poison")]
+        {
+            if self.quarantine.limit > 0 {
+                check_poison(ptr.as_ptr() as usize, self.size);
+            }
+        }
+
+        // Unlocked deallocations may have freed objects in a full page already,
+        // so the bitfield doesn't tell us which list the page is in
+        let slab_page_was_full = state == PageState::Full;
+        let slab_page_draining = state == PageState::Draining;
+        let slab_page_long_lived = state == PageState::LongLived;
+        // A double free leaves the page untouched, so there is nothing to rebalance.
+        slab_page.deallocate(ptr, new_layout)?;
+
+        #[cfg(not(feature = "debug-poison"))]
+        {
+            if self.zero_on_free != ZeroOnFree::Disabled {
+                unsafe { ptr::write_bytes(ptr.as_ptr(), 0, self.size) };
+            }
+        }
 
         if slab_page.is_empty(self.obj_per_page) {
-            // We need to move it from self.slabs -> self.empty_slabs
-            // trace!("move {:p} partial -> empty", slab_page);
-            self.move_to_empty(slab_page);
+            #[cfg(not(feature = "debug-poison"))]
+            {
+                // Only the slots, the generation and call-site tables behind them are kept
+                if self.zero_on_free == ZeroOnFree::ObjectsAndPages {
+                    let objects = slab_page.objects_start() as *mut u8;
+                    unsafe { ptr::write_bytes(objects, 0, self.obj_per_page * self.size) };
+                }
+            }
+
+            if slab_page_draining {
+                // The page is evacuated and can be retrieved now
+                self.draining_slabs.remove_from_list(slab_page);
+                self.insert_empty(slab_page);
+                SCCounters::add(&self.stats.partial_to_empty, 1);
+            } else if slab_page_long_lived {
+                self.long_lived_slabs.remove_from_list(slab_page);
+                self.insert_empty(slab_page);
+                SCCounters::add(&self.stats.partial_to_empty, 1);
+            } else if slab_page_was_full {
+                // Only happens for pages that hold a single object
+                // (or if the other objects were freed unlocked)
+                self.full_slabs.remove_from_list(slab_page);
+                self.insert_empty(slab_page);
+                SCCounters::add(&self.stats.full_to_partial, 1);
+                SCCounters::add(&self.stats.partial_to_empty, 1);
+            } else {
+                // We need to move it from self.slabs -> self.empty_slabs
+                // trace!("move {:p} partial -> empty", slab_page);
+                self.move_to_empty(slab_page);
+            }
+            Ok(PageTransition::Empty)
         } else if slab_page_was_full {
             // We need to move it from self.full_slabs -> self.slabs
             // trace!("move {:p} full -> partial", slab_page);
             self.move_full_to_partial(slab_page);
+            Ok(PageTransition::FullToPartial)
+        } else {
+            Ok(PageTransition::Unchanged)
         }
-
-        ret
     }
 }
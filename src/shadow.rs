@@ -0,0 +1,25 @@
+//! Shadow-memory hooks (only with the `shadow-hooks` feature).
+//!
+//! A kernel sanitizer (e.g., a KASAN-style address sanitizer) keeps shadow state for
+//! every byte of the heap. The `ZoneAllocator` is where memory changes hands, so it tells
+//! the sanitizer about every object and page through a `ShadowHooks` implementation
+//! (see `ZoneAllocator::set_shadow_hooks`). Without the feature the calls are compiled out.
+
+use crate::*;
+
+/// Receives the changes to the heap a sanitizer needs to maintain its shadow state.
+///
+/// The hooks are called with the zone locked, after the change took place, so they
+/// must not allocate from the same zone. A typical sanitizer poisons a page when it is
+/// added, unpoisons the requested bytes of an object when it is allocated (the rest of
+/// its slot stays poisoned as a red zone) and poisons them again when it is freed.
+pub trait ShadowHooks: Sync {
+    /// `size` bytes (as requested in the `Layout`) at `ptr` were handed out.
+    fn on_alloc(&self, _ptr: NonNull<u8>, _size: usize) {}
+    /// The object of `size` bytes at `ptr` was freed.
+    fn on_free(&self, _ptr: NonNull<u8>, _size: usize) {}
+    /// The page (or slab) of `size` bytes at `start` was added to the zone with `refill`.
+    fn on_page_added(&self, _start: VAddr, _size: usize) {}
+    /// The empty page (or slab) of `size` bytes at `start` was handed back by the zone.
+    fn on_page_removed(&self, _start: VAddr, _size: usize) {}
+}
@@ -0,0 +1,404 @@
+//! A thread-safe zone allocator with one lock per size class.
+//!
+//! Wrapping a `ZoneAllocator` in a single mutex serializes all allocations.
+//! The `ShardedZoneAllocator` instead puts every `SCAllocator` behind its own lock,
+//! so concurrent allocations of different sizes don't contend.
+//!
+//! The lock type is supplied by the embedder through `lock_api::RawMutex`,
+//! e.g., a preemption-aware or IRQ-safe lock in Theseus or `parking_lot` on std.
+
+use crate::*;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::ops::{Deref, DerefMut};
+use lock_api::{Mutex, MutexGuard, RawMutex};
+
+/// A zone allocator that can be shared between threads, with one lock per size class.
+///
+/// The locks are of type `lock_api::Mutex<R, _>`.
+///
+/// It has the same size classes as the `ZoneAllocator`. Operations that concern
+/// a single size class only take that class' lock. Operations that span
+/// all size classes (e.g., `merge`, `empty_pages`, `verify`) take the locks
+/// one after the other in ascending size order, never more than one at a time.
+///
+/// The smallest size classes can be put behind lock-free stacks of free objects
+/// (see `set_free_stack_limit`).
+pub struct ShardedZoneAllocator<'a, R: RawMutex> {
+    pub heap_id: usize,
+    pub(crate) small_slabs: [Mutex<R, SCAllocator<'a, BasePage<'a>>>; ZoneAllocator::MAX_BASE_SIZE_CLASSES],
+    /// The counters of every size class as of the last time its lock was released (see `stats`).
+    counters: [SCCounters; ZoneAllocator::MAX_BASE_SIZE_CLASSES],
+    /// Free objects of the smallest size classes, they are still allocated in their pages.
+    free_stacks: [FreeStack; FREE_STACK_CLASSES],
+    /// Maximum number of objects per free stack (0 disables the stacks).
+    free_stack_limit: AtomicUsize,
+}
+
+impl<'a, R: RawMutex> ShardedZoneAllocator<'a, R> {
+    pub const fn new(heap_id: usize) -> ShardedZoneAllocator<'a, R> {
+        ShardedZoneAllocator {
+            heap_id,
+            small_slabs: [
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 3)),  // 8
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 4)),  // 16
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 5)),  // 32
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 6)),  // 64
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 7)),  // 128
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 8)),  // 256
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 9)),  // 512
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 10)), // 1024
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 11)), // 2048
+                Mutex::const_new(R::INIT, SCAllocator::new(1 << 12)), // 4096
+                Mutex::const_new(R::INIT, SCAllocator::new(ZoneAllocator::MAX_BASE_ALLOC_SIZE)),
+            ],
+            counters: [
+                SCCounters::new(), SCCounters::new(), SCCounters::new(), SCCounters::new(),
+                SCCounters::new(), SCCounters::new(), SCCounters::new(), SCCounters::new(),
+                SCCounters::new(), SCCounters::new(), SCCounters::new(),
+            ],
+            free_stacks: [FreeStack::new(), FreeStack::new(), FreeStack::new(), FreeStack::new()],
+            free_stack_limit: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes the lock of the size class `idx`.
+    fn lock(&self, idx: usize) -> ClassGuard<'_, 'a, R> {
+        ClassGuard {
+            slab: self.small_slabs[idx].lock(),
+            counters: &self.counters[idx],
+        }
+    }
+
+    /// Takes the lock of the size class `idx` if it is free.
+    fn try_lock(&self, idx: usize) -> Option<ClassGuard<'_, 'a, R>> {
+        Some(ClassGuard {
+            slab: self.small_slabs[idx].try_lock()?,
+            counters: &self.counters[idx],
+        })
+    }
+
+    /// Lets the 8 to 64 byte size classes keep up to `limit` freed objects each on a
+    /// lock-free stack, allocations of these classes take objects from the stack before
+    /// they take the lock. 0 (the default) disables the stacks.
+    ///
+    /// The stacked objects stay allocated in their pages, use `flush_free_stacks`
+    /// to give them back (e.g., before pages are retrieved). A pop may still read
+    /// the first word of an object whose page just became empty, so pages have to be
+    /// returned with `retire_empty_page` and `reclaim_retired_page` while the stacks are in use.
+    ///
+    /// Frees onto a stack are checked like the ones to a page: the object has to be allocated
+    /// in an intact page of the class, and freeing an object that is still stacked fails with
+    /// `AllocationError::DoubleFree`. They are counted in `stats`, bump the generation of the
+    /// object (with the `generations` feature) and poison all but its first word (with `debug-poison`),
+    /// which is checked when it is popped again.
+    ///
+    /// # Note
+    /// The size classes of a `ShardedZoneAllocator` have no quarantine and don't zero freed objects,
+    /// so the stacks skip nothing of the locked path. With the `red-zones` feature the stacks are never used.
+    pub fn set_free_stack_limit(&self, limit: usize) {
+        self.free_stack_limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Returns the maximum number of objects per free stack (0 if they are disabled).
+    pub fn free_stack_limit(&self) -> usize {
+        self.free_stack_limit.load(Ordering::Relaxed)
+    }
+
+    /// The free stack of the size class `idx`, `None` if the class has none or they are disabled.
+    fn free_stack(&self, idx: usize) -> Option<&FreeStack> {
+        if cfg!(feature = "red-zones") || self.free_stack_limit() == 0 {
+            return None;
+        }
+        self.free_stacks.get(idx)
+    }
+
+    /// Frees all objects on the free stacks to their size classes.
+    pub fn flush_free_stacks(&self) -> Result<(), AllocationError> {
+        for (idx, stack) in self.free_stacks.iter().enumerate() {
+            let mut slab = self.lock(idx);
+            while let Some(ptr) = stack.pop() {
+                slab.release_stacked(ptr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a freed object off the free stack of the size class `idx`, if it has one.
+    fn pop_stacked(&self, idx: usize) -> Option<NonNull<u8>> {
+        let stack = self.free_stack(idx)?;
+        let ptr = stack.pop()?;
+        #[cfg(feature = "debug-poison")]
+        check_poison(ptr.as_ptr() as usize + LINK_SIZE, ZoneAllocator::BASE_ALLOC_SIZES[idx] - LINK_SIZE);
+        if !cfg!(feature = "minimal") {
+            stack.allocations.fetch_add(1, Ordering::Relaxed);
+        }
+        Some(ptr)
+    }
+
+    /// Frees `ptr` onto the free stack of the size class `idx`.
+    ///
+    /// Returns `Ok(false)` if the class has no stack or it is full, the object has to go to its page then.
+    fn push_stacked(&self, idx: usize, ptr: NonNull<u8>) -> Result<bool, AllocationError> {
+        let stack = match self.free_stack(idx) {
+            Some(stack) if stack.len() < self.free_stack_limit() => stack,
+            _ => return Ok(false),
+        };
+        let size = ZoneAllocator::BASE_ALLOC_SIZES[idx];
+        ShardedZoneAllocator::<R>::check_stackable(ptr, size)?;
+
+        // Done before the push, another thread may pop the object right away.
+        // If the stack filled up in the meantime the object is freed to its page,
+        // which bumps the generation again and poisons it again.
+        #[cfg(feature = "generations")]
+        SCAllocator::<BasePage>::bump_generation(ptr.as_ptr() as VAddr, size);
+        #[cfg(feature = "debug-poison")]
+        poison(ptr.as_ptr() as usize + LINK_SIZE, size - LINK_SIZE);
+
+        if !stack.push(ptr, self.free_stack_limit()) {
+            return Ok(false);
+        }
+        if !cfg!(feature = "minimal") {
+            stack.deallocations.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(true)
+    }
+
+    /// Checks without the lock of the class that `ptr` is an allocated object of `size` bytes
+    /// in an intact page, like `SCAllocator::deallocate` does before it frees an object.
+    fn check_stackable(ptr: NonNull<u8>, size: usize) -> Result<(), AllocationError> {
+        // The header and bitfield of a page stay in place while it has allocated objects
+        let page = unsafe { &*BasePage::from_address(ptr.as_ptr() as VAddr) };
+        if !page.is_intact() {
+            return Err(AllocationError::CorruptedPage);
+        }
+        let offset = (ptr.as_ptr() as usize).wrapping_sub(page.objects_start());
+        if offset % size != 0
+            || offset / size >= SCAllocator::<BasePage>::objects_per_page(size)
+            || page.color() + offset + size > BasePage::SIZE - BasePage::METADATA_SIZE
+        {
+            error!("{:p} is not a valid object address for size {}", ptr, size);
+            return Err(AllocationError::InvalidPointer);
+        }
+        if !page.bitfield().is_allocated(offset / size) || FreeStack::is_stacked(ptr) {
+            error!("Double free detected: {:p} is not marked allocated or already stacked", ptr);
+            return Err(AllocationError::DoubleFree);
+        }
+        Ok(())
+    }
+
+    /// Number of objects on the free stacks.
+    pub fn stacked_objects(&self) -> usize {
+        self.free_stacks.iter().map(FreeStack::len).sum()
+    }
+
+    /// Allocate a pointer to a block of memory described by `layout`.
+    ///
+    /// Only takes the lock of the size class serving `layout`, unless the class
+    /// is out of memory and has to borrow an empty page from another class.
+    pub fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        if layout.size() == 0 {
+            return Ok(ZoneAllocator::dangling(layout));
+        }
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                if let Some(ptr) = self.pop_stacked(idx) {
+                    return Ok(ptr);
+                }
+                let res = self.lock(idx).allocate(layout);
+                match res {
+                    Err(AllocationError::OutOfMemory) => {
+                        // We don't hold our lock here, `retrieve_empty_page` takes all of them
+                        let mp = self.retrieve_empty_page(0).ok_or(AllocationError::OutOfMemory)?;
+                        let mut slab = self.lock(idx);
+                        slab.refill(mp, self.heap_id)?;
+                        slab.allocate(layout)
+                    }
+                    res => res,
+                }
+            }
+            Slab::Large(_idx) => Err(AllocationError::InvalidLayout),
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Allocates a block of memory described by `layout` without spinning
+    /// on a lock or doing unbounded work (see `SCAllocator::try_allocate`).
+    ///
+    /// Fails with `AllocationError::WouldBlock` if the lock of the size class is taken.
+    pub fn try_allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        if layout.size() == 0 {
+            return Ok(ZoneAllocator::dangling(layout));
+        }
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => match self.pop_stacked(idx) {
+                Some(ptr) => Ok(ptr),
+                None => match self.try_lock(idx) {
+                    Some(mut slab) => slab.try_allocate(layout),
+                    None => Err(AllocationError::WouldBlock),
+                },
+            },
+            Slab::Large(_idx) => Err(AllocationError::InvalidLayout),
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Deallocates a pointer to a block of memory, which was
+    /// previously allocated by `allocate`.
+    pub fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        if layout.size() == 0 {
+            return Ok(());
+        }
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            // The `minimal` build trusts the caller to free objects to their own heap
+            Slab::Base(idx) if cfg!(feature = "minimal") => match self.push_stacked(idx, ptr)? {
+                true => Ok(()),
+                false => self.lock(idx).deallocate(ptr, layout),
+            },
+            Slab::Base(idx) => match ZoneAllocator::page_heap_id::<BasePage>(ptr) {
+                Some(heap_id) if heap_id != self.heap_id => Err(AllocationError::WrongHeap(heap_id)),
+                Some(_) => match self.push_stacked(idx, ptr)? {
+                    true => Ok(()),
+                    false => self.lock(idx).deallocate(ptr, layout),
+                },
+                None => Err(AllocationError::CorruptedPage),
+            },
+            Slab::Large(_idx) => Err(AllocationError::InvalidLayout),
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Deallocates `ptr` without taking any lock (see `ZoneAllocator::deallocate_unlocked`).
+    ///
+    /// # Safety
+    /// See `ZoneAllocator::deallocate_unlocked`.
+    pub unsafe fn deallocate_unlocked(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        ZoneAllocator::deallocate_unlocked(ptr, layout)
+    }
+
+    /// Refills the SCAllocator for a given Layout with an ObjectPage.
+    pub fn refill(&self, layout: Layout, mp: MappedPages) -> Result<(), AllocationError> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => self.lock(idx).refill(mp, self.heap_id),
+            Slab::Large(_idx) => Err(AllocationError::InvalidLayout),
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Removes all the pages of `allocator` and adds them to the appropriate lists in this allocator.
+    pub fn merge(&self, allocator: &mut ZoneAllocator<'a>) -> Result<(), AllocationError> {
+        for (idx, other) in allocator.small_slabs.iter_mut().enumerate() {
+            self.lock(idx).merge(other, self.heap_id)?;
+        }
+        Ok(())
+    }
+
+    /// Returns an ObjectPage from the first SCAllocator with empty pages,
+    /// if the heap has more empty pages than the threshold.
+    pub fn retrieve_empty_page(&self, heap_empty_page_threshold: usize) -> Option<MappedPages> {
+        if self.empty_pages() <= heap_empty_page_threshold {
+            return None;
+        }
+        (0..ZoneAllocator::MAX_BASE_SIZE_CLASSES).find_map(|idx| self.lock(idx).retrieve_empty_page())
+    }
+
+    /// Moves an empty page into the retired list of its size class,
+    /// if the heap has more empty pages than the threshold (see `SCAllocator::retire_empty_page`).
+    pub fn retire_empty_page(&self, heap_empty_page_threshold: usize, epoch: u64) -> bool {
+        if self.empty_pages() <= heap_empty_page_threshold {
+            return false;
+        }
+        (0..ZoneAllocator::MAX_BASE_SIZE_CLASSES).any(|idx| self.lock(idx).retire_empty_page(epoch))
+    }
+
+    /// Returns a page that was retired before `quiescent_epoch`
+    /// (see `SCAllocator::reclaim_retired_page`).
+    pub fn reclaim_retired_page(&self, quiescent_epoch: u64) -> Option<MappedPages> {
+        (0..ZoneAllocator::MAX_BASE_SIZE_CLASSES).find_map(|idx| self.lock(idx).reclaim_retired_page(quiescent_epoch))
+    }
+
+    /// Rebalances the page lists of all size classes (see `SCAllocator::rebalance`).
+    pub fn rebalance(&self) -> usize {
+        (0..ZoneAllocator::MAX_BASE_SIZE_CLASSES).map(|idx| self.lock(idx).rebalance()).sum()
+    }
+
+    /// The total number of empty pages in this allocator.
+    ///
+    /// The count is only a snapshot since the classes are locked one after the other.
+    pub fn empty_pages(&self) -> usize {
+        self.small_slabs
+            .iter()
+            .map(|slab| slab.lock().empty_slabs.elements)
+            .sum()
+    }
+
+    /// Returns a snapshot of the operation counters of the size class serving `layout`,
+    /// `None` if no base size class serves it.
+    ///
+    /// Doesn't take the lock of the class, so monitoring never stalls allocations.
+    /// The counters are copied out of the class every time its lock is released, so the
+    /// snapshot misses the operations of a lock holder that is still at work
+    /// (see `SCCounters` for the consistency of the snapshot).
+    /// Allocations and frees served by a free stack are included.
+    pub fn stats(&self, layout: Layout) -> Option<SCStats> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                let mut stats = self.counters[idx].snapshot();
+                if let Some(stack) = self.free_stacks.get(idx) {
+                    stats.allocations += stack.allocations.load(Ordering::Relaxed);
+                    stats.deallocations += stack.deallocations.load(Ordering::Relaxed);
+                }
+                Some(stats)
+            }
+            _ => None,
+        }
+    }
+
+    /// Checks the integrity of every size class (see `SCAllocator::verify`).
+    pub fn verify(&self) -> Result<(), AllocationError> {
+        let mut ret = Ok(());
+        for idx in 0..ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+            ret = ret.and(self.lock(idx).verify(self.heap_id));
+        }
+        ret
+    }
+}
+
+/// The lock of a size class of a `ShardedZoneAllocator`, publishes the counters
+/// of the class when it is released.
+struct ClassGuard<'s, 'a, R: RawMutex> {
+    slab: MutexGuard<'s, R, SCAllocator<'a, BasePage<'a>>>,
+    counters: &'s SCCounters,
+}
+
+impl<'s, 'a, R: RawMutex> Deref for ClassGuard<'s, 'a, R> {
+    type Target = SCAllocator<'a, BasePage<'a>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.slab
+    }
+}
+
+impl<'s, 'a, R: RawMutex> DerefMut for ClassGuard<'s, 'a, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.slab
+    }
+}
+
+impl<'s, 'a, R: RawMutex> Drop for ClassGuard<'s, 'a, R> {
+    fn drop(&mut self) {
+        self.counters.copy_from(&self.slab.stats);
+    }
+}
+
+impl<'a, R: RawMutex> SharedAllocator for ShardedZoneAllocator<'a, R> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        ShardedZoneAllocator::allocate(self, layout)
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        ShardedZoneAllocator::deallocate(self, ptr, layout)
+    }
+}
@@ -0,0 +1,131 @@
+//! A readable summary of the pages and objects of a `ZoneAllocator` (see `ZoneAllocator::stats`).
+//!
+//! `ZoneStats` prints as an aligned table with one row per size class that owns pages, so
+//! `info!("{}", zone.stats())` gives a quick overview of the heap:
+//!
+//! ```text
+//!   class     pages E/P/F  live bytes     capacity  util
+//!    64 B    1/   1/    0     1.2 KiB     15.5 KiB    8%
+//!   total               2     1.2 KiB     15.5 KiB    8%
+//! ```
+
+use crate::*;
+use core::fmt::Write;
+
+/// The pages and objects of a size class (see `ZoneStats`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClassUsage {
+    /// Object size of the class.
+    pub object_size: usize,
+    /// Number of empty pages.
+    pub empty_pages: usize,
+    /// Number of partial pages (including draining and long-lived pages).
+    pub partial_pages: usize,
+    /// Number of full pages.
+    pub full_pages: usize,
+    /// How much of the memory of the class is in use (see `SCAllocator::utilization`).
+    pub utilization: Utilization,
+}
+
+/// The pages and objects of every size class of a zone, the large size classes follow the base size classes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZoneStats {
+    /// The size classes.
+    pub classes: [ClassUsage; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+    /// The sum over all size classes (see `ZoneAllocator::utilization`).
+    pub total: Utilization,
+}
+
+impl ClassUsage {
+    pub(crate) fn of<P: AllocablePage>(sc: &SCAllocator<'_, P>) -> ClassUsage {
+        ClassUsage {
+            object_size: sc.size(),
+            empty_pages: sc.page_list(PageState::Empty).len(),
+            partial_pages: sc.page_list(PageState::Partial).len()
+                + sc.page_list(PageState::Draining).len()
+                + sc.page_list(PageState::LongLived).len(),
+            full_pages: sc.page_list(PageState::Full).len(),
+            utilization: sc.utilization(),
+        }
+    }
+}
+
+/// Percentage of the capacity that is in use, 0 if there is no capacity.
+fn percent(utilization: &Utilization) -> usize {
+    match utilization.capacity_bytes {
+        0 => 0,
+        capacity => utilization.live_bytes * 100 / capacity,
+    }
+}
+
+impl fmt::Display for ZoneStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:>7} {:>15} {:>11} {:>12} {:>5}", "class", "pages E/P/F", "live bytes", "capacity", "util")?;
+        for class in self.classes.iter().filter(|class| class.utilization.pages > 0) {
+            writeln!(
+                f,
+                "{:>7} {:>4}/{:>4}/{:>5} {:>11} {:>12} {:>4}%",
+                ByteSize(class.object_size),
+                class.empty_pages,
+                class.partial_pages,
+                class.full_pages,
+                ByteSize(class.utilization.live_bytes),
+                ByteSize(class.utilization.capacity_bytes),
+                percent(&class.utilization)
+            )?;
+        }
+        write!(
+            f,
+            "{:>7} {:>15} {:>11} {:>12} {:>4}%",
+            "total",
+            self.total.pages,
+            ByteSize(self.total.live_bytes),
+            ByteSize(self.total.capacity_bytes),
+            percent(&self.total)
+        )
+    }
+}
+
+/// A number of bytes that prints in B, KiB or MiB (with one decimal) and honors the width of the formatter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSize(pub usize);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const KIB: usize = 1024;
+        const MIB: usize = 1024 * KIB;
+        // Formatted first, so the padding applies to the whole text
+        let mut text = TextBuffer { bytes: [0; 32], len: 0 };
+        match self.0 {
+            bytes if bytes < KIB => write!(text, "{} B", bytes)?,
+            bytes if bytes < MIB => write!(text, "{}.{} KiB", bytes / KIB, bytes % KIB * 10 / KIB)?,
+            bytes => write!(text, "{}.{} MiB", bytes / MIB, bytes % MIB * 10 / MIB)?,
+        }
+        f.pad(text.as_str())
+    }
+}
+
+/// A fixed buffer to format short texts into without `alloc`.
+struct TextBuffer {
+    bytes: [u8; 32],
+    len: usize,
+}
+
+impl TextBuffer {
+    fn as_str(&self) -> &str {
+        // Only whole `str`s are written
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+}
+
+impl fmt::Write for TextBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        if end > self.bytes.len() {
+            return Err(fmt::Error);
+        }
+        self.bytes[self.len..end].copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
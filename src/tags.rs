@@ -0,0 +1,103 @@
+//! Per-owner accounting of live memory.
+//!
+//! Allocations made through `ZoneAllocator::allocate_tagged` carry an opaque `u64` tag
+//! (e.g., a task or crate id). The zone keeps a small table with the live bytes
+//! and objects of every tag, so memory can be attributed to its owner
+//! without wrapping the allocator.
+
+/// Number of distinct tags that can be tracked at the same time.
+pub const MAX_TAGS: usize = 32;
+
+/// Live memory of one tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TagStats {
+    /// The tag passed to `allocate_tagged`.
+    pub tag: u64,
+    /// Sum of the sizes of the live objects (as requested in their `Layout`).
+    pub live_bytes: usize,
+    /// Number of live objects.
+    pub live_objects: usize,
+}
+
+/// A fixed-size table of `TagStats`.
+///
+/// Tags are dropped from the table once they have no live objects left.
+/// If the table is full, allocations of new tags are only counted in `untracked_bytes`.
+pub struct TagAccounting {
+    entries: [Option<TagStats>; MAX_TAGS],
+    /// Live bytes of tags that didn't fit in the table.
+    untracked_bytes: usize,
+}
+
+impl TagAccounting {
+    /// Creates an empty table.
+    pub const fn new() -> TagAccounting {
+        TagAccounting {
+            entries: [None; MAX_TAGS],
+            untracked_bytes: 0,
+        }
+    }
+
+    /// Accounts an allocation of `size` bytes to `tag`.
+    pub(crate) fn on_allocation(&mut self, tag: u64, size: usize) {
+        if let Some(stats) = self.entries.iter_mut().flatten().find(|stats| stats.tag == tag) {
+            stats.live_bytes += size;
+            stats.live_objects += 1;
+            return;
+        }
+
+        match self.entries.iter_mut().find(|entry| entry.is_none()) {
+            Some(entry) => {
+                *entry = Some(TagStats {
+                    tag,
+                    live_bytes: size,
+                    live_objects: 1,
+                })
+            }
+            None => self.untracked_bytes += size,
+        }
+    }
+
+    /// Removes a deallocation of `size` bytes from the account of `tag`.
+    pub(crate) fn on_deallocation(&mut self, tag: u64, size: usize) {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_some_and(|stats| stats.tag == tag));
+
+        match entry {
+            Some(entry) => {
+                let remove = entry.as_mut().is_some_and(|stats| {
+                    stats.live_bytes = stats.live_bytes.saturating_sub(size);
+                    stats.live_objects = stats.live_objects.saturating_sub(1);
+                    stats.live_objects == 0
+                });
+                if remove {
+                    *entry = None;
+                }
+            }
+            None => self.untracked_bytes = self.untracked_bytes.saturating_sub(size),
+        }
+    }
+
+    /// Returns the live memory of `tag`, `None` if it has no live objects (or isn't tracked).
+    pub fn get(&self, tag: u64) -> Option<TagStats> {
+        self.entries.iter().flatten().find(|stats| stats.tag == tag).copied()
+    }
+
+    /// Iterates over all tags with live objects.
+    pub fn iter(&self) -> impl Iterator<Item = &TagStats> {
+        self.entries.iter().flatten()
+    }
+
+    /// Live bytes of tags that didn't fit in the table.
+    pub fn untracked_bytes(&self) -> usize {
+        self.untracked_bytes
+    }
+}
+
+impl Default for TagAccounting {
+    fn default() -> TagAccounting {
+        TagAccounting::new()
+    }
+}
@@ -1,131 +1,115 @@
-use env_logger;
-use rand;
 use std::alloc;
 use std::alloc::Layout;
 use std::collections::HashSet;
 use std::mem::{size_of, transmute};
-use std::prelude::v1::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::vec::Vec;
 
 use crate::*;
-use test::Bencher;
 
-/// A simple page allocator based on GlobalAlloc (for testing purposes).
+// Tests that hand layouts to an `SCAllocator` directly (or use the `ThreadCache`) are
+// skipped with the `red-zones` feature: there the layouts have to include the red zones.
+
+/// A page allocator for the tests that hands out 8 KiB aligned regions
+/// of the system allocator as `MappedPages`.
 struct Pager {
-    base_pages: HashSet<*mut u8>, // probably should be hash-tables
-    large_pages: HashSet<*mut u8>,
+    pages: HashSet<*mut u8>,
 }
 
 unsafe impl Send for Pager {}
 unsafe impl Sync for Pager {}
 
 impl Pager {
+    #[cfg(not(feature = "out-of-band-metadata"))]
+    const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(ObjectPage8k::SIZE, ObjectPage8k::SIZE) };
+
     pub fn new() -> Pager {
         Pager {
-            base_pages: HashSet::with_capacity(1024),
-            large_pages: HashSet::with_capacity(128),
+            pages: HashSet::with_capacity(1024),
         }
     }
-}
 
-impl Pager {
     pub fn currently_allocated(&self) -> usize {
-        self.base_pages.len() + self.large_pages.len()
+        self.pages.len()
     }
 
-    fn alloc_page(&mut self, page_size: usize) -> Option<*mut u8> {
-        let r =
-            unsafe { std::alloc::alloc(Layout::from_size_align(page_size, page_size).unwrap()) };
+    /// Allocates a new page from the system.
+    fn allocate_page(&mut self) -> Option<MappedPages> {
+        #[cfg(not(feature = "out-of-band-metadata"))]
+        let r = unsafe { alloc::alloc(Pager::LAYOUT) };
+        #[cfg(feature = "out-of-band-metadata")]
+        let r = arena_pages().pop()? as *mut u8;
+        let region = NonNull::new(r as *mut [u8; ObjectPage8k::SIZE])?;
+        self.pages.insert(r);
+        Some(unsafe { MappedPages::from_raw(region) })
+    }
 
-        if !r.is_null() {
-            match page_size {
-                BASE_PAGE_SIZE => self.base_pages.insert(r),
-                LARGE_PAGE_SIZE => self.large_pages.insert(r),
-                _ => unreachable!("invalid page-size supplied"),
-            };
-            Some(r)
-        } else {
-            None
+    /// Releases a page (or several merged ones) back to the system.
+    fn release_page(&mut self, mp: MappedPages) {
+        for offset in (0..mp.size_in_bytes()).step_by(ObjectPage8k::SIZE) {
+            let ptr = unsafe { mp.as_ptr().add(offset) };
+            assert!(self.pages.remove(&ptr), "Trying to deallocate invalid page");
+            Pager::free(ptr);
         }
     }
 
-    fn dealloc_page(&mut self, ptr: *mut u8, page_size: usize) {
-        let layout = match page_size {
-            BASE_PAGE_SIZE => {
-                assert!(
-                    self.base_pages.contains(&ptr),
-                    "Trying to deallocate invalid base-page"
-                );
-                self.base_pages.remove(&ptr);
-                Layout::from_size_align(BASE_PAGE_SIZE, BASE_PAGE_SIZE).unwrap()
-            }
-            LARGE_PAGE_SIZE => {
-                assert!(
-                    self.large_pages.contains(&ptr),
-                    "Trying to deallocate invalid large-page"
-                );
-                self.large_pages.remove(&ptr);
-                Layout::from_size_align(LARGE_PAGE_SIZE, LARGE_PAGE_SIZE).unwrap()
-            }
-            _ => unreachable!("invalid page-size supplied"),
-        };
-
-        unsafe { std::alloc::dealloc(ptr, layout) };
+    fn free(ptr: *mut u8) {
+        #[cfg(not(feature = "out-of-band-metadata"))]
+        unsafe { alloc::dealloc(ptr, Pager::LAYOUT) };
+        #[cfg(feature = "out-of-band-metadata")]
+        arena_pages().push(ptr as usize);
     }
 }
 
-trait PageProvider<'a>: Send {
-    fn allocate_page(&mut self) -> Option<&'a mut ObjectPage<'a>>;
-    fn release_page(&mut self, page: &'a mut ObjectPage<'a>);
-
-    fn allocate_large_page(&mut self) -> Option<&'a mut LargeObjectPage<'a>>;
-    fn release_large_page(&mut self, page: &'a mut LargeObjectPage<'a>);
+#[cfg(feature = "out-of-band-metadata")]
+static ARENA_INIT: std::sync::Once = std::sync::Once::new();
+#[cfg(feature = "out-of-band-metadata")]
+static ARENA_PAGES: std::sync::Mutex<Vec<usize>> = std::sync::Mutex::new(Vec::new());
+
+/// With out-of-band meta-data the pages have to lie in the range of the meta-data arena,
+/// so the pager hands out the pages of one big region instead.
+#[cfg(feature = "out-of-band-metadata")]
+fn arena_pages() -> std::sync::MutexGuard<'static, Vec<usize>> {
+    ARENA_INIT.call_once(|| unsafe {
+        const SIZE: usize = 256 * 1024 * 1024;
+        let region = alloc::alloc(Layout::from_size_align(SIZE, OutOfBandPage8k::SIZE).unwrap());
+        let entries = Layout::from_size_align(MetadataArena::bytes_for(SIZE), 64).unwrap();
+        let entries = NonNull::new(alloc::alloc(entries)).unwrap();
+        METADATA_ARENA.init(region as usize, SIZE, entries).unwrap();
+        let mut pages = ARENA_PAGES.lock().unwrap();
+        pages.extend((0..SIZE).step_by(OutOfBandPage8k::SIZE).rev().map(|offset| region as usize + offset));
+    });
+    ARENA_PAGES.lock().unwrap_or_else(|e| e.into_inner())
 }
 
-impl<'a> PageProvider<'a> for Pager {
-    /// Allocates a new ObjectPage from the system.
-    ///
-    /// Uses `mmap` to map a page and casts it to a ObjectPage.
-    fn allocate_page(&mut self) -> Option<&'a mut ObjectPage<'a>> {
-        self.alloc_page(BASE_PAGE_SIZE)
-            .map(|r| unsafe { transmute(r as usize) })
-    }
-
-    /// Release a ObjectPage back to the system.slab_page
-    ///
-    /// Uses `munmap` to release the page back to the OS.
-    fn release_page(&mut self, p: &'a mut ObjectPage<'a>) {
-        self.dealloc_page(p as *const ObjectPage as *mut u8, BASE_PAGE_SIZE);
+impl ReplayPages for Pager {
+    fn allocate_page(&mut self) -> Option<MappedPages> {
+        Pager::allocate_page(self)
     }
 
-    /// Allocates a new ObjectPage from the system.
-    ///
-    /// Uses `mmap` to map a page and casts it to a ObjectPage.
-    fn allocate_large_page(&mut self) -> Option<&'a mut LargeObjectPage<'a>> {
-        self.alloc_page(LARGE_PAGE_SIZE)
-            .map(|r| unsafe { transmute(r as usize) })
+    fn release_page(&mut self, mp: MappedPages) {
+        Pager::release_page(self, mp)
     }
+}
 
-    /// Release a LargeObjectPage back to the system.slab_page
-    ///
-    /// Uses `munmap` to release the page back to the OS.
-    fn release_large_page(&mut self, p: &'a mut LargeObjectPage<'a>) {
-        self.dealloc_page(p as *const LargeObjectPage as *mut u8, LARGE_PAGE_SIZE);
+impl Drop for Pager {
+    fn drop(&mut self) {
+        // Pages that a failed test didn't give back
+        for ptr in self.pages.drain() {
+            Pager::free(ptr);
+        }
     }
 }
 
 #[test]
 fn check_size() {
     assert_eq!(
-        BASE_PAGE_SIZE as usize,
-        size_of::<ObjectPage>(),
-        "ObjectPage should be exactly the size of a single page."
-    );
-
-    assert_eq!(
-        LARGE_PAGE_SIZE as usize,
-        size_of::<LargeObjectPage>(),
-        "LargeObjectPage should be exactly the size of a large-page."
+        ObjectPage8k::SIZE,
+        size_of::<ObjectPage8k>(),
+        "ObjectPage8k should be exactly the size of two base pages."
     );
+    assert_eq!(ObjectPage16k::SIZE, size_of::<ObjectPage16k>());
+    assert_eq!(ObjectPage64k::SIZE, size_of::<ObjectPage64k>());
 }
 
 #[test]
@@ -133,34 +117,26 @@ fn test_mmap_allocator() {
     let mut mmap = Pager::new();
 
     match mmap.allocate_page() {
-        Some(sp) => {
-            sp.bitfield.initialize(8, BASE_PAGE_SIZE - 80);
+        Some(mp) => {
+            let sp = unsafe { &mut *(mp.as_ptr() as *mut ObjectPage8k) };
+            sp.bitfield.initialize(8, ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE);
             assert!(!sp.is_full(), "Got empty slab");
             assert!(sp.is_empty(6 * 64), "Got empty slab");
-            mmap.release_page(sp)
+            mmap.release_page(mp)
         }
-        None => panic!("failed to allocate ObjectPage"),
-    }
-
-    match mmap.allocate_large_page() {
-        Some(lp) => {
-            lp.bitfield.initialize(8, LARGE_PAGE_SIZE - 80);
-            assert!(!lp.is_full(), "Got empty slab");
-            assert!(lp.is_empty(8 * 64), "Got empty slab");
-            mmap.release_large_page(lp)
-        }
-        None => panic!("failed to allocate LargeObjectPage"),
+        None => panic!("failed to allocate ObjectPage8k"),
     }
 }
 
 macro_rules! test_sc_allocation {
-    ($test:ident, $size:expr, $alignment:expr, $allocations:expr, $type:ty) => {
+    ($test:ident, $size:expr, $alignment:expr, $allocations:expr) => {
         #[test]
+        #[cfg(not(feature = "red-zones"))]
         fn $test() {
             let _ = env_logger::try_init();
             let mut mmap = Pager::new();
             {
-                let mut sa: SCAllocator<$type> = SCAllocator::new($size);
+                let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new($size);
                 let alignment = $alignment;
 
                 let mut objects: Vec<NonNull<u8>> = Vec::new();
@@ -181,12 +157,10 @@ macro_rules! test_sc_allocation {
                             // Couldn't allocate need to refill first
                             Err(AllocationError::OutOfMemory) => {
                                 let page = mmap.allocate_page().unwrap();
-                                unsafe {
-                                    sa.refill(page);
-                                }
+                                sa.refill(page, 0).expect("Can't refill");
                             }
                             // Unexpected errors
-                            Err(AllocationError::InvalidLayout) => unreachable!("Unexpected error"),
+                            Err(e) => unreachable!("Unexpected error {:?}", e),
                         }
                     }
                 }
@@ -211,7 +185,7 @@ macro_rules! test_sc_allocation {
                 }
 
                 // Make sure we can correctly deallocate:
-                let pages_allocated = sa.slabs.elements;
+                let pages_allocated = sa.pages();
 
                 // Deallocate all the objects
                 for item in objects.iter_mut() {
@@ -219,7 +193,9 @@ macro_rules! test_sc_allocation {
                 }
 
                 objects.clear();
+                vec.clear();
                 sa.check_page_assignments();
+                assert_eq!(sa.verify(0), Ok(()));
 
                 // then allocate everything again,
                 for _ in 0..$allocations {
@@ -227,29 +203,24 @@ macro_rules! test_sc_allocation {
                         match sa.allocate(layout) {
                             // Allocation was successful
                             Ok(nptr) => {
-                                unsafe {
-                                    vec.push((rand::random::<usize>(), transmute(nptr.as_ptr())))
-                                };
                                 objects.push(nptr);
                                 break;
                             }
                             // Couldn't allocate need to refill first
                             Err(AllocationError::OutOfMemory) => {
                                 let page = mmap.allocate_page().unwrap();
-                                unsafe {
-                                    sa.refill(page);
-                                }
+                                sa.refill(page, 0).expect("Can't refill");
                             }
                             // Unexpected errors
-                            Err(AllocationError::InvalidLayout) => unreachable!("Unexpected error"),
+                            Err(e) => unreachable!("Unexpected error {:?}", e),
                         }
                     }
                 }
 
                 // and make sure we do not request more pages than what we had previously
-                // println!("{} {}", pages_allocated, sa.slabs.elements);
                 assert_eq!(
-                    pages_allocated, sa.slabs.elements,
+                    pages_allocated,
+                    sa.pages(),
                     "Did not use more memory for 2nd allocation run."
                 );
 
@@ -260,9 +231,10 @@ macro_rules! test_sc_allocation {
 
                 // Drain the slab-allocator and give unused pages back to the OS
                 sa.check_page_assignments();
-                while let Some(page) = sa.empty_slabs.pop() {
+                while let Some(page) = sa.retrieve_empty_page() {
                     mmap.release_page(page);
                 }
+                assert_eq!(sa.pages(), 0, "All pages are empty once all objects are freed.");
             }
 
             // Check that we released everything to our page allocator:
@@ -275,151 +247,23 @@ macro_rules! test_sc_allocation {
     };
 }
 
-test_sc_allocation!(op_512_size8_alignment1, 8, 1, 512, ObjectPage);
-test_sc_allocation!(op_4096_size8_alignment8, 8, 8, 4096, ObjectPage);
-test_sc_allocation!(op_500_size8_alignment64, 8, 64, 500, ObjectPage);
-test_sc_allocation!(op_4096_size12_alignment1, 12, 1, 4096, ObjectPage);
-test_sc_allocation!(op_4096_size13_alignment1, 13, 1, 4096, ObjectPage);
-test_sc_allocation!(op_2000_size14_alignment1, 14, 1, 2000, ObjectPage);
-test_sc_allocation!(op_4096_size15_alignment1, 15, 1, 4096, ObjectPage);
-test_sc_allocation!(op_8000_size16_alignment1, 16, 1, 8000, ObjectPage);
-test_sc_allocation!(op_1024_size24_alignment1, 24, 1, 1024, ObjectPage);
-test_sc_allocation!(op_3090_size32_alignment1, 32, 1, 3090, ObjectPage);
-test_sc_allocation!(op_4096_size64_alignment1, 64, 1, 4096, ObjectPage);
-test_sc_allocation!(op_1000_size512_alignment1, 512, 1, 1000, ObjectPage);
-test_sc_allocation!(op_4096_size1024_alignment1, 1024, 1, 4096, ObjectPage);
-test_sc_allocation!(op_10_size2048_alignment1, 2048, 1, 10, ObjectPage);
-test_sc_allocation!(op_10000_size512_alignment1, 512, 1, 10000, ObjectPage);
-
-macro_rules! lop_allocation {
-    ($test:ident, $size:expr, $alignment:expr, $allocations:expr, $type:ty) => {
-        #[test]
-        fn $test() {
-            let _ = env_logger::try_init();
-            let mut mmap = Pager::new();
-            {
-                let mut sa: SCAllocator<$type> = SCAllocator::new($size);
-                let alignment = $alignment;
-
-                let mut objects: Vec<NonNull<u8>> = Vec::new();
-                let mut vec: Vec<(usize, &mut [usize; $size / 8])> = Vec::new();
-                let layout = Layout::from_size_align($size, alignment).unwrap();
-
-                for _ in 0..$allocations {
-                    loop {
-                        match sa.allocate(layout) {
-                            // Allocation was successful
-                            Ok(nptr) => {
-                                unsafe {
-                                    vec.push((rand::random::<usize>(), transmute(nptr.as_ptr())))
-                                };
-                                objects.push(nptr);
-                                break;
-                            }
-                            // Couldn't allocate need to refill first
-                            Err(AllocationError::OutOfMemory) => {
-                                let page = mmap.allocate_large_page().unwrap();
-                                unsafe {
-                                    sa.refill(page);
-                                }
-                            }
-                            // Unexpected errors
-                            Err(AllocationError::InvalidLayout) => unreachable!("Unexpected error"),
-                        }
-                    }
-                }
-
-                // Write the objects with a random pattern
-                for item in vec.iter_mut() {
-                    let (pattern, ref mut obj) = *item;
-                    assert!(obj.len() == $size / 8);
-                    for i in 0..obj.len() {
-                        obj[i] = pattern;
-                    }
-                }
-
-                for item in vec.iter() {
-                    let (pattern, ref obj) = *item;
-                    for i in 0..obj.len() {
-                        assert_eq!(
-                            obj[i], pattern,
-                            "No two allocations point to the same memory."
-                        );
-                    }
-                }
-
-                // Make sure we can correctly deallocate:
-                let pages_allocated = sa.slabs.elements;
-
-                // Deallocate all the objects
-                for item in objects.iter_mut() {
-                    sa.deallocate(*item, layout).expect("Can't deallocate");
-                }
-
-                objects.clear();
-                sa.check_page_assignments();
-
-                // then allocate everything again,
-                for _ in 0..$allocations {
-                    loop {
-                        match sa.allocate(layout) {
-                            // Allocation was successful
-                            Ok(nptr) => {
-                                unsafe {
-                                    vec.push((rand::random::<usize>(), transmute(nptr.as_ptr())))
-                                };
-                                objects.push(nptr);
-                                break;
-                            }
-                            // Couldn't allocate need to refill first
-                            Err(AllocationError::OutOfMemory) => {
-                                let page = mmap.allocate_large_page().unwrap();
-                                unsafe {
-                                    sa.refill(page);
-                                }
-                            }
-                            // Unexpected errors
-                            Err(AllocationError::InvalidLayout) => unreachable!("Unexpected error"),
-                        }
-                    }
-                }
-
-                // and make sure we do not request more pages than what we had previously
-                // println!("{} {}", pages_allocated, sa.slabs.elements);
-                assert_eq!(
-                    pages_allocated, sa.slabs.elements,
-                    "Did not use more memory for 2nd allocation run."
-                );
-
-                // Deallocate everything once more
-                for item in objects.iter_mut() {
-                    sa.deallocate(*item, layout).expect("Can't deallocate");
-                }
-
-                // Drain the slab-allocator and give unused pages back to the OS
-                sa.check_page_assignments();
-                while let Some(page) = sa.empty_slabs.pop() {
-                    mmap.release_large_page(page);
-                }
-            }
-
-            // Check that we released everything to our page allocator:
-            assert_eq!(
-                mmap.currently_allocated(),
-                0,
-                "Released all pages to the underlying memory manager."
-            );
-        }
-    };
-}
-
-lop_allocation!(lop_4096_3, 8, 1, 1024, LargeObjectPage);
-lop_allocation!(lop_4096_12, 4096, 4096, 2048, LargeObjectPage);
-lop_allocation!(lop_4096_13, 1 << 13, 4096, 4096, LargeObjectPage);
-lop_allocation!(lop_4096_14, 1 << 14, 4096, 4096, LargeObjectPage);
-lop_allocation!(lop_4096_15, 1 << 15, 4096, 4096, LargeObjectPage);
-lop_allocation!(lop_4096_16, 1 << 16, 4096, 4096, LargeObjectPage);
-lop_allocation!(lop_4096_17, 1 << 17, 4096, 4096, LargeObjectPage);
+test_sc_allocation!(op_512_size8_alignment1, 8, 1, 512);
+test_sc_allocation!(op_4096_size8_alignment8, 8, 8, 4096);
+test_sc_allocation!(op_500_size8_alignment64, 8, 64, 500);
+test_sc_allocation!(op_4096_size12_alignment1, 12, 1, 4096);
+test_sc_allocation!(op_4096_size13_alignment1, 13, 1, 4096);
+test_sc_allocation!(op_2000_size14_alignment1, 14, 1, 2000);
+test_sc_allocation!(op_4096_size15_alignment1, 15, 1, 4096);
+test_sc_allocation!(op_8000_size16_alignment1, 16, 1, 8000);
+test_sc_allocation!(op_1024_size24_alignment1, 24, 1, 1024);
+test_sc_allocation!(op_3090_size32_alignment1, 32, 1, 3090);
+test_sc_allocation!(op_4096_size64_alignment1, 64, 1, 4096);
+test_sc_allocation!(op_1000_size512_alignment1, 512, 1, 1000);
+test_sc_allocation!(op_4096_size1024_alignment1, 1024, 1, 4096);
+test_sc_allocation!(op_10_size2048_alignment1, 2048, 1, 10);
+test_sc_allocation!(op_10000_size512_alignment1, 512, 1, 10000);
+test_sc_allocation!(op_100_size4096_alignment4096, 4096, 4096, 100);
+test_sc_allocation!(op_100_size8104_alignment8, ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE, 8, 100);
 
 #[test]
 #[should_panic]
@@ -434,7 +278,7 @@ fn test_readme() -> Result<(), AllocationError> {
     let layout = Layout::from_size_align(object_size, alignment).unwrap();
 
     // We need something that can provide backing memory
-    // (4 KiB and 2 MiB pages) to our ZoneAllocator
+    // (8 KiB pages) to our ZoneAllocator
     // (see tests.rs for a dummy implementation).
     let mut pager = Pager::new();
     let page = pager.allocate_page().expect("Can't allocate a page");
@@ -443,47 +287,48 @@ fn test_readme() -> Result<(), AllocationError> {
     // Prematurely fill the ZoneAllocator with memory.
     // Alternatively, the allocate call would return an
     // error which we can capture to refill on-demand.
-    unsafe { zone.refill(layout, page)? };
+    zone.refill(layout, page)?;
 
     let allocated = zone.allocate(layout)?;
     zone.deallocate(allocated, layout)?;
 
+    pager.release_page(zone.retrieve_empty_page(0).expect("The page is empty again"));
     Ok(())
 }
 
 #[test]
+#[cfg(not(feature = "red-zones"))]
 fn test_readme2() -> Result<(), AllocationError> {
     let object_size = 10;
     let alignment = 8;
     let layout = Layout::from_size_align(object_size, alignment).unwrap();
 
     // We need something that can provide backing memory
-    // (4 KiB and 2 MiB pages) to our ZoneAllocator
+    // (8 KiB pages) to our ZoneAllocator
     // (see tests.rs for a dummy implementation).
     let mut pager = Pager::new();
     let page = pager.allocate_page().expect("Can't allocate a page");
 
-    let mut sa: SCAllocator<ObjectPage> = SCAllocator::new(object_size);
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(object_size);
     // Prematurely fill the SCAllocator with memory.
     // Alternatively, the allocate call would return an
     // error which we can capture to refill on-demand.
-    unsafe { sa.refill(page) };
+    sa.refill(page, 0)?;
 
     sa.allocate(layout)?;
     Ok(())
 }
 
 #[test]
+#[cfg(not(feature = "red-zones"))]
 fn test_bug1() -> Result<(), AllocationError> {
     let _ = env_logger::try_init();
 
     let mut mmap = Pager::new();
     let page = mmap.allocate_page();
 
-    let mut sa: SCAllocator<ObjectPage> = SCAllocator::new(8);
-    unsafe {
-        sa.refill(page.unwrap());
-    }
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(8);
+    sa.refill(page.unwrap(), 0)?;
 
     let ptr1 = sa.allocate(Layout::from_size_align(1, 1).unwrap())?;
     let ptr2 = sa.allocate(Layout::from_size_align(2, 1).unwrap())?;
@@ -492,99 +337,68 @@ fn test_bug1() -> Result<(), AllocationError> {
     sa.deallocate(ptr2, Layout::from_size_align(2, 1).unwrap())
 }
 
-#[bench]
-fn slabmalloc_allocate_deallocate(b: &mut Bencher) {
+#[test]
+fn zone_out_of_memory_and_page_return() {
     let _ = env_logger::try_init();
-
     let mut mmap = Pager::new();
-    let mut sa: SCAllocator<ObjectPage> = SCAllocator::new(8);
-    let layout = Layout::from_size_align(8, 1).unwrap();
-
-    let page = mmap.allocate_page();
-    unsafe {
-        sa.refill(page.unwrap());
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+
+    let mut objects = Vec::new();
+    for _ in 0..1000 {
+        let ptr = match zone.allocate(layout) {
+            Ok(ptr) => ptr,
+            Err(AllocationError::OutOfMemory) => {
+                zone.refill(layout, mmap.allocate_page().unwrap()).expect("Can't refill");
+                zone.allocate(layout).expect("Can't allocate after refill")
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+        };
+        assert_eq!(ptr.as_ptr() as usize % 8, 0, "Objects are aligned");
+        objects.push(ptr);
     }
+    assert_eq!(zone.verify(), Ok(()));
+    let pages = zone.pages();
+    assert_eq!(pages, mmap.currently_allocated());
 
-    let ptr = sa.allocate(layout).expect("Can't allocate");
-    test::black_box(ptr);
-    b.iter(|| {
-        let ptr = sa.allocate(layout).expect("Can't allocate");
-        test::black_box(ptr);
-        sa.deallocate(ptr, layout).expect("Can't deallocate");
-    });
-}
-
-#[bench]
-fn slabmalloc_allocate_deallocate_big(b: &mut Bencher) {
-    let _ = env_logger::try_init();
+    // Nothing can be returned while objects are live
+    assert!(zone.retrieve_empty_page(0).is_none() || zone.pages() < pages);
 
-    let mut mmap = Pager::new();
-    let mut sa: SCAllocator<ObjectPage> = SCAllocator::new(512);
-
-    let page = mmap.allocate_page();
-    unsafe {
-        sa.refill(page.unwrap());
+    for ptr in objects.drain(..) {
+        zone.deallocate(ptr, layout).expect("Can't deallocate");
     }
-
-    let layout = Layout::from_size_align(512, 1).unwrap();
-    let ptr = sa.allocate(layout).expect("Can't allocate");
-    test::black_box(ptr);
-
-    b.iter(|| {
-        let ptr = sa.allocate(layout).expect("Can't allocate");
-        test::black_box(ptr);
-        sa.deallocate(ptr, layout).expect("Can't deallocate");
-    });
-}
-
-#[bench]
-fn jemalloc_allocate_deallocate(b: &mut Bencher) {
-    let layout = Layout::from_size_align(8, 1).unwrap();
-    let ptr = unsafe { alloc::alloc(layout) };
-    test::black_box(ptr);
-
-    b.iter(|| unsafe {
-        let ptr = alloc::alloc(layout);
-        test::black_box(ptr);
-        alloc::dealloc(ptr, layout);
-    });
-}
-
-#[bench]
-fn jemalloc_allocate_deallocate_big(b: &mut Bencher) {
-    let layout = Layout::from_size_align(512, 1).unwrap();
-    let ptr = unsafe { alloc::alloc(layout) };
-    test::black_box(ptr);
-
-    b.iter(|| unsafe {
-        let ptr = alloc::alloc(layout);
-        test::black_box(ptr);
-        alloc::dealloc(ptr, layout);
-    });
+    assert_eq!(zone.verify(), Ok(()));
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        mmap.release_page(mp);
+    }
+    assert_eq!(zone.pages(), 0);
+    assert_eq!(mmap.currently_allocated(), 0);
 }
 
 #[test]
 pub fn check_first_fit() {
-    let op: ObjectPage = Default::default();
+    let op: ObjectPage8k = Default::default();
     let layout = Layout::from_size_align(8, 8).unwrap();
     println!("{:?}", op.first_fit(layout));
 }
 
 #[test]
 fn list_pop() {
-    let mut op1: ObjectPage = Default::default();
-    let op1_ptr = &op1 as *const ObjectPage<'_>;
-    let mut op2: ObjectPage = Default::default();
-    let op2_ptr = &op2 as *const ObjectPage<'_>;
-    let mut op3: ObjectPage = Default::default();
-    let op3_ptr = &op3 as *const ObjectPage<'_>;
-    let mut op4: ObjectPage = Default::default();
-    let op4_ptr = &op4 as *const ObjectPage<'_>;
-
-    let mut list: PageList<ObjectPage> = PageList::new();
-    list.insert_front(&mut op1);
-    list.insert_front(&mut op2);
-    list.insert_front(&mut op3);
+    let mut op1: ObjectPage8k = Default::default();
+    let op1_ptr = &op1 as *const ObjectPage8k<'_>;
+    let mut op2: ObjectPage8k = Default::default();
+    let op2_ptr = &op2 as *const ObjectPage8k<'_>;
+    let mut op3: ObjectPage8k = Default::default();
+    let op3_ptr = &op3 as *const ObjectPage8k<'_>;
+    let mut op4: ObjectPage8k = Default::default();
+    let op4_ptr = &op4 as *const ObjectPage8k<'_>;
+
+    let mut list: PageList<ObjectPage8k> = PageList::new();
+    unsafe {
+        list.insert_front(&mut op1);
+        list.insert_front(&mut op2);
+        list.insert_front(&mut op3);
+    }
 
     assert!(list.contains(op1_ptr));
     assert!(list.contains(op2_ptr));
@@ -592,21 +406,21 @@ fn list_pop() {
     assert!(!list.contains(op4_ptr));
 
     let popped = list.pop();
-    assert_eq!(popped.unwrap() as *const ObjectPage, op3_ptr);
+    assert_eq!(popped.unwrap() as *const ObjectPage8k, op3_ptr);
     assert!(!list.contains(op3_ptr));
 
     let popped = list.pop();
-    assert_eq!(popped.unwrap() as *const ObjectPage, op2_ptr);
+    assert_eq!(popped.unwrap() as *const ObjectPage8k, op2_ptr);
     assert!(!list.contains(op2_ptr));
 
-    list.insert_front(&mut op4);
+    unsafe { list.insert_front(&mut op4) };
     assert!(list.contains(op4_ptr));
     let popped = list.pop();
-    assert_eq!(popped.unwrap() as *const ObjectPage, op4_ptr);
+    assert_eq!(popped.unwrap() as *const ObjectPage8k, op4_ptr);
     assert!(!list.contains(op4_ptr));
 
     let popped = list.pop();
-    assert_eq!(popped.unwrap() as *const ObjectPage, op1_ptr);
+    assert_eq!(popped.unwrap() as *const ObjectPage8k, op1_ptr);
     assert!(!list.contains(op1_ptr));
 
     let popped = list.pop();
@@ -620,9 +434,9 @@ fn list_pop() {
 
 #[test]
 pub fn iter_empty_list() {
-    let mut new_head1: ObjectPage = Default::default();
+    let mut new_head1: ObjectPage8k = Default::default();
     let mut l = PageList::new();
-    l.insert_front(&mut new_head1);
+    unsafe { l.insert_front(&mut new_head1) };
     for _p in l.iter_mut() {}
 }
 
@@ -630,10 +444,11 @@ pub fn iter_empty_list() {
 pub fn check_is_full_8() {
     let _r = env_logger::try_init();
     let layout = Layout::from_size_align(8, 1).unwrap();
+    let capacity = ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE;
 
-    let mut page: ObjectPage = Default::default();
-    page.bitfield.initialize(8, BASE_PAGE_SIZE - 80);
-    let obj_per_page = core::cmp::min((BASE_PAGE_SIZE - 80) / 8, 8 * 64);
+    let mut page: ObjectPage8k = Default::default();
+    page.bitfield.initialize(8, capacity);
+    let obj_per_page = core::cmp::min(capacity / 8, ObjectPage8k::BITFIELD_WORDS * 64);
 
     let mut allocs = 0;
     loop {
@@ -661,10 +476,11 @@ pub fn check_is_full_8() {
 #[test]
 pub fn check_is_full_512() {
     let _r = env_logger::try_init();
-    let mut page: ObjectPage = Default::default();
-    page.bitfield.initialize(512, BASE_PAGE_SIZE - 80);
+    let capacity = ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE;
+    let mut page: ObjectPage8k = Default::default();
+    page.bitfield.initialize(512, capacity);
     let layout = Layout::from_size_align(512, 1).unwrap();
-    let obj_per_page = core::cmp::min((BASE_PAGE_SIZE - 80) / 512, 6 * 64);
+    let obj_per_page = core::cmp::min(capacity / 512, ObjectPage8k::BITFIELD_WORDS * 64);
 
     let mut allocs = 0;
     loop {
@@ -674,10 +490,3443 @@ pub fn check_is_full_512() {
 
         allocs += 1;
 
-        if allocs < (BASE_PAGE_SIZE - 80) / 512 {
+        if allocs < capacity / 512 {
             assert!(!page.is_full());
             assert!(!page.is_empty(obj_per_page));
         }
     }
     assert!(page.is_full());
 }
+
+/// The bitfield covers the whole page, so even the smallest size classes use all of it
+/// (less the call-site and generation tables).
+#[cfg(not(feature = "red-zones"))]
+#[test]
+fn small_classes_use_whole_page() {
+    let mut pager = Pager::new();
+    for &size in [8, 16].iter() {
+        let capacity = ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE;
+        let per_object = size + OBJECT_OVERHEAD;
+        let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(size);
+        assert_eq!(sa.obj_per_page, capacity / per_object);
+
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+        let layout = Layout::from_size_align(size, size).unwrap();
+        let objects: Vec<NonNull<u8>> = (0..capacity).map_while(|_| sa.allocate(layout).ok()).collect();
+        assert_eq!(objects.len(), capacity / per_object);
+        assert!(capacity - objects.len() * per_object < per_object, "Unused room for another object");
+
+        for ptr in objects {
+            sa.deallocate(ptr, layout).unwrap();
+        }
+        pager.release_page(sa.retrieve_empty_page().unwrap());
+    }
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn sc_allocate_invalid_layout() {
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+
+    let too_big = Layout::from_size_align(65, 1).unwrap();
+    assert_eq!(sa.allocate(too_big), Err(AllocationError::InvalidLayout));
+
+    let over_aligned = Layout::from_size_align(8, 2 * ObjectPage8k::SIZE).unwrap();
+    assert_eq!(sa.allocate(over_aligned), Err(AllocationError::InvalidLayout));
+
+    // A valid layout without any pages is just out of memory
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    assert_eq!(sa.allocate(layout), Err(AllocationError::OutOfMemory));
+}
+
+/// A page that still holds objects is taken over with its bitfield intact.
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn sc_adopt_populated_page() {
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut boot: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    let mp = pager.allocate_page().unwrap();
+    let region = NonNull::new(mp.start_address().value() as *mut [u8; ObjectPage8k::SIZE]).unwrap();
+    boot.refill(mp, 0).unwrap();
+    // The boot heap is abandoned with its objects still in use
+    let objects: Vec<NonNull<u8>> = (0..3).map(|_| boot.allocate(layout).unwrap()).collect();
+
+    // The bitfield doesn't fit objects of a different size, the pages are handed back
+    let mut other: SCAllocator<ObjectPage8k> = SCAllocator::new(128);
+    let mp = match unsafe { other.adopt_page(MappedPages::from_raw(region), 0) } {
+        Err((AllocationError::CorruptedPage, mp)) => mp,
+        res => panic!("unexpected result {:?}", res.map_err(|(e, _)| e)),
+    };
+    assert_eq!(mp.start_address().value(), region.as_ptr() as usize);
+
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    unsafe { sa.adopt_page(mp, 1).map_err(|(e, _)| e).unwrap() };
+    let fresh = sa.allocate(layout).unwrap();
+    assert!(!objects.contains(&fresh));
+    assert!(sa.retrieve_empty_page().is_none());
+
+    for ptr in objects {
+        sa.deallocate(ptr, layout).unwrap();
+    }
+    sa.deallocate(fresh, layout).unwrap();
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+}
+
+#[cfg(feature = "callsites")]
+#[test]
+fn record_allocation_callsites() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let (first, first_line) = (zone.allocate(layout).unwrap(), line!());
+    let (second, second_line) = (zone.allocate_tagged(layout, 3).unwrap(), line!());
+
+    let mut sites = Vec::new();
+    zone.for_each_allocated_callsite(|ptr, size, location| sites.push((ptr, size, location.map(|l| (l.file(), l.line())))));
+    sites.sort_by_key(|(ptr, _, _)| ptr.as_ptr() as usize);
+    let mut expected = vec![(first, 64, Some((file!(), first_line))), (second, 64, Some((file!(), second_line)))];
+    expected.sort_by_key(|(ptr, _, _)| ptr.as_ptr() as usize);
+    assert_eq!(sites, expected);
+
+    zone.deallocate(first, layout).unwrap();
+    zone.deallocate_tagged(second, layout, 3).unwrap();
+    zone.for_each_allocated_callsite(|_ptr, _size, _location| panic!("No objects are allocated"));
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+/// The call-site and generation tables take the room that is left after the objects,
+/// so there is none to color pages with.
+#[cfg(all(any(feature = "callsites", feature = "generations"), not(feature = "red-zones")))]
+#[test]
+fn cache_coloring_without_slack() {
+    let mut pager = Pager::new();
+    let size = 1504;
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(size);
+    sa.set_cache_coloring(true);
+    assert_eq!(sa.colors(), 1);
+
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    for _ in 0..2 {
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    }
+    let objects: Vec<NonNull<u8>> = (0..2 * sa.obj_per_page).map(|_| sa.allocate(layout).unwrap()).collect();
+    assert!(objects.iter().all(|ptr| (ptr.as_ptr() as usize % ObjectPage8k::SIZE).is_multiple_of(size)));
+
+    for ptr in objects {
+        sa.deallocate(ptr, layout).unwrap();
+    }
+    while let Some(mp) = sa.retrieve_empty_page() {
+        pager.release_page(mp);
+    }
+}
+
+/// Successive pages start their first object at rotating cache-line offsets.
+#[cfg(not(any(feature = "red-zones", feature = "callsites", feature = "generations")))]
+#[test]
+fn cache_coloring() {
+    let mut pager = Pager::new();
+    let size = 1504;
+    let capacity = ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE;
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(size);
+    sa.set_cache_coloring(true);
+    let colors = sa.colors();
+    assert_eq!(colors, (capacity - sa.obj_per_page * size) / CACHE_LINE_SIZE + 1);
+    assert!(colors > 1);
+
+    for _ in 0..colors + 1 {
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    }
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    let objects: Vec<NonNull<u8>> = (0..(colors + 1) * sa.obj_per_page).map(|_| sa.allocate(layout).unwrap()).collect();
+    assert_eq!(sa.allocate(layout), Err(AllocationError::OutOfMemory));
+
+    let mut first_objects: Vec<usize> = (0..colors + 1)
+        .map(|page| objects[page * sa.obj_per_page..(page + 1) * sa.obj_per_page].iter().map(|ptr| ptr.as_ptr() as usize % ObjectPage8k::SIZE).min().unwrap())
+        .collect();
+    for ptr in objects.iter() {
+        assert!(ptr.as_ptr() as usize % ObjectPage8k::SIZE + size <= capacity, "Object overlaps the meta-data");
+    }
+    first_objects.sort_unstable();
+    first_objects.dedup();
+    let expected: Vec<usize> = (0..colors).map(|color| color * CACHE_LINE_SIZE).collect();
+    assert_eq!(first_objects, expected);
+
+    // Colored pages can't serve layouts aligned beyond a cache line
+    let over_aligned = Layout::from_size_align(size, 2 * CACHE_LINE_SIZE).unwrap();
+    assert_eq!(sa.allocate(over_aligned), Err(AllocationError::InvalidLayout));
+
+    // Page offsets that would be slots without the color are not objects
+    let colored = objects.iter().find(|ptr| ptr.as_ptr() as usize % ObjectPage8k::SIZE == CACHE_LINE_SIZE).unwrap();
+    let page_start = NonNull::new((colored.as_ptr() as usize - CACHE_LINE_SIZE) as *mut u8).unwrap();
+    assert_eq!(sa.deallocate(page_start, layout), Err(AllocationError::InvalidPointer));
+
+    for ptr in objects {
+        sa.deallocate(ptr, layout).unwrap();
+    }
+    while let Some(mp) = sa.retrieve_empty_page() {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn sc_allocate_size_class_larger_than_page() {
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(ObjectPage8k::SIZE);
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    assert_eq!(sa.allocate(layout), Err(AllocationError::InvalidLayout));
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn sc_deallocate_invalid_layout() {
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    let too_big = Layout::from_size_align(128, 1).unwrap();
+    assert_eq!(
+        sa.deallocate(NonNull::dangling(), too_big),
+        Err(AllocationError::InvalidLayout)
+    );
+}
+
+#[test]
+fn deallocate_page_transitions() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(512, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    let mut objects = Vec::new();
+    while let Ok(ptr) = zone.allocate(layout) {
+        objects.push(ptr);
+    }
+    let last = objects.pop().unwrap();
+    assert_eq!(zone.deallocate_ext(last, layout), Ok(PageTransition::FullToPartial));
+    let first = objects.remove(0);
+    for ptr in objects {
+        assert_eq!(zone.deallocate_ext(ptr, layout), Ok(PageTransition::Unchanged));
+    }
+    assert_eq!(zone.deallocate_ext(first, layout), Ok(PageTransition::Empty));
+    assert_eq!(zone.deallocate_ext(NonNull::dangling(), Layout::new::<()>()), Ok(PageTransition::Unchanged));
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn allocate_ext_remaining_capacity() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    let obj_per_page = zone.small_slabs[idx].obj_per_page;
+
+    let mut objects = Vec::new();
+    let first = zone.allocate_ext(layout).unwrap();
+    assert_eq!(first.page_free, obj_per_page - 1);
+    assert_eq!(first.class_free, 2 * obj_per_page - 1);
+    objects.push(first.ptr);
+    for left in (0..obj_per_page - 1).rev() {
+        let info = zone.allocate_ext(layout).unwrap();
+        assert_eq!(info.page_free, left);
+        assert_eq!(info.class_free, obj_per_page + left);
+        objects.push(info.ptr);
+    }
+
+    let zero_sized = zone.allocate_ext(Layout::new::<()>()).unwrap();
+    assert_eq!((zero_sized.page_free, zero_sized.class_free), (0, 0));
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+/// An object of a typed cache.
+struct Task {
+    id: u64,
+    runs: u32,
+}
+
+#[test]
+fn for_each_live_typed() {
+    let mut pager = Pager::new();
+    let layout = Layout::new::<Task>();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+
+    let tasks: Vec<NonNull<u8>> = (0..5)
+        .map(|id| {
+            let ptr = sa.allocate(layout).unwrap();
+            unsafe { ptr.cast::<Task>().as_ptr().write(Task { id, runs: 0 }) };
+            ptr
+        })
+        .collect();
+    sa.deallocate(tasks[2], layout).unwrap();
+
+    let mut ids = Vec::new();
+    unsafe {
+        sa.for_each_live(|task: &mut Task| {
+            ids.push(task.id);
+            task.runs += 1;
+        })
+        .unwrap()
+    };
+    ids.sort_unstable();
+    assert_eq!(ids, vec![0, 1, 3, 4]);
+    assert_eq!(unsafe { tasks[4].cast::<Task>().as_ref().runs }, 1);
+    assert_eq!(unsafe { sa.for_each_live(|_: &mut [u64; 16]| {}) }, Err(AllocationError::InvalidLayout));
+
+    for (i, ptr) in tasks.into_iter().enumerate() {
+        if i != 2 {
+            sa.deallocate(ptr, layout).unwrap();
+        }
+    }
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+}
+
+#[test]
+fn page_list_cursor() {
+    let mut pager = Pager::new();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    for _ in 0..3 {
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    }
+    let pages: Vec<usize> = sa.page_list(PageState::Empty).iter().map(|page| page.data_start()).collect();
+    assert_eq!(pages.len(), 3);
+    let reversed: Vec<usize> = sa.page_list(PageState::Empty).iter().rev().map(|page| page.data_start()).collect();
+    assert_eq!(reversed, pages.iter().rev().copied().collect::<Vec<_>>());
+
+    // Move the last page to the front
+    let list = unsafe { sa.page_list_mut(PageState::Empty) };
+    let last = list.cursor_back_mut().remove_current().unwrap();
+    unsafe { list.cursor_front_mut().insert_before(last) };
+    let order: Vec<usize> = list.iter().map(|page| page.data_start()).collect();
+    assert_eq!(order, vec![pages[2], pages[0], pages[1]]);
+
+    // Take out the middle page and splice the rest in after it
+    let mut cursor = list.cursor_front_mut();
+    cursor.move_next();
+    let middle = cursor.remove_current().unwrap();
+    let mut rest = PageList::new();
+    unsafe { rest.append(list) };
+    assert!(list.is_empty());
+    unsafe {
+        list.insert_front(middle);
+        list.cursor_front_mut().splice_after(&mut rest);
+    }
+    assert!(rest.is_empty());
+    let order: Vec<usize> = list.iter().map(|page| page.data_start()).collect();
+    assert_eq!(order, vec![pages[0], pages[2], pages[1]]);
+    let mut cursor = list.cursor_back_mut();
+    cursor.move_next();
+    assert!(cursor.current().is_none());
+    cursor.move_next();
+    assert_eq!(cursor.current().map(|page| page.data_start()), Some(pages[0]));
+
+    assert_eq!(sa.verify(0), Ok(()));
+    while let Some(mp) = sa.retrieve_empty_page() {
+        pager.release_page(mp);
+    }
+}
+
+/// Frees of pointers outside the pages of an allocator are rejected without touching the memory.
+#[cfg(not(feature = "red-zones"))]
+#[test]
+fn sc_deallocate_foreign_pointers() {
+    let mut pager = Pager::new();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    let mut other: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    other.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = sa.allocate(layout).unwrap();
+    let foreign = other.allocate(layout).unwrap();
+
+    // A live object of another allocator, and memory that isn't in a page at all
+    assert_eq!(sa.deallocate(foreign, layout), Err(AllocationError::InvalidPointer));
+    let mut buffer = [0xabu8; 64];
+    let outside = NonNull::new(buffer.as_mut_ptr()).unwrap();
+    assert_eq!(sa.deallocate(outside, layout), Err(AllocationError::InvalidPointer));
+    assert!(buffer.iter().all(|&b| b == 0xab));
+
+    // Also with the quarantine, which releases objects later on
+    sa.set_quarantine(4).unwrap();
+    assert_eq!(sa.deallocate(foreign, layout), Err(AllocationError::InvalidPointer));
+    sa.deallocate(ptr, layout).unwrap();
+    sa.flush_quarantine().unwrap();
+    assert_eq!(sa.verify(0), Ok(()));
+
+    other.deallocate(foreign, layout).unwrap();
+    assert_eq!(other.verify(0), Ok(()));
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+    pager.release_page(other.retrieve_empty_page().unwrap());
+}
+
+#[test]
+fn zone_allocate_too_large() {
+    let mut zone: ZoneAllocator = Default::default();
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1).unwrap();
+    assert_eq!(zone.allocate(too_big), Err(AllocationError::TooLarge));
+    assert_eq!(
+        zone.deallocate(NonNull::dangling(), too_big),
+        Err(AllocationError::TooLarge)
+    );
+}
+
+#[test]
+fn zone_allocate_zero_sized() {
+    let mut zone: ZoneAllocator = Default::default();
+    let layout = Layout::from_size_align(0, 64).unwrap();
+
+    // Doesn't need any backing memory
+    let ptr = zone.allocate(layout).expect("Zero-sized allocation can't fail");
+    assert_eq!(ptr.as_ptr() as usize % 64, 0);
+    assert_eq!(zone.deallocate(ptr, layout), Ok(()));
+}
+
+/// Serves large layouts from the system allocator.
+struct SystemFallback;
+
+impl FallbackAllocator for SystemFallback {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        NonNull::new(unsafe { alloc::alloc(layout) }).ok_or(AllocationError::OutOfMemory)
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        unsafe { alloc::dealloc(ptr.as_ptr(), layout) };
+        Ok(())
+    }
+}
+
+static SYSTEM_FALLBACK: SystemFallback = SystemFallback;
+
+#[test]
+fn zone_fallback_for_large_layouts() {
+    let mut zone: ZoneAllocator = Default::default();
+    zone.set_fallback(&SYSTEM_FALLBACK);
+
+    let layout = Layout::from_size_align(4 * ZoneAllocator::MAX_ALLOC_SIZE, 8).unwrap();
+    let ptr = zone.allocate(layout).expect("Fallback should serve large layouts");
+    unsafe { ptr::write_bytes(ptr.as_ptr(), 0xff, layout.size()) };
+    assert_eq!(zone.deallocate(ptr, layout), Ok(()));
+}
+
+impl SharedAllocator for SystemFallback {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        FallbackAllocator::allocate(self, layout)
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        FallbackAllocator::deallocate(self, ptr, layout)
+    }
+}
+
+#[test]
+fn slab_box_drops_value() {
+    use std::rc::Rc;
+
+    let value = Rc::new(42usize);
+    {
+        let mut b = SlabBox::new(value.clone(), &SYSTEM_FALLBACK).expect("Can't allocate");
+        assert_eq!(**b, 42);
+        assert_eq!(Rc::strong_count(&value), 2);
+        *b = Rc::new(1);
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+
+    let b = SlabBox::new(value.clone(), &SYSTEM_FALLBACK).expect("Can't allocate");
+    let raw = SlabBox::into_raw(b);
+    assert_eq!(Rc::strong_count(&value), 2);
+    drop(unsafe { SlabBox::from_raw(raw, &SYSTEM_FALLBACK) });
+    assert_eq!(Rc::strong_count(&value), 1);
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn thread_cache_bins() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let mut cache = ThreadCache::new();
+    let small = Layout::from_size_align(12, 4).unwrap();
+    let large = Layout::from_size_align(128, 8).unwrap();
+    let bin_layout = Layout::from_size_align(16, 16).unwrap();
+    zone.refill(bin_layout, pager.allocate_page().unwrap()).unwrap();
+
+    assert!(cache.allocate(small).is_none(), "New cache is empty");
+
+    let ptr = zone.allocate(bin_layout).unwrap();
+    assert!(cache.deallocate(ptr, small));
+    assert!(!cache.deallocate(ptr, large), "128 bytes is not a cached class");
+    assert_eq!(cache.cached(), 1);
+
+    // Served from the 16 byte bin
+    assert!(cache.allocate(Layout::from_size_align(32, 8).unwrap()).is_none());
+    assert_eq!(cache.allocate(bin_layout), Some(ptr));
+    assert_eq!(cache.cached(), 0);
+
+    assert!(cache.deallocate(ptr, bin_layout));
+    cache.flush(&mut zone).unwrap();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn thread_cache_refill_and_flush() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let mut cache = ThreadCache::new();
+    let sizes = [8, 16, 32, 64];
+    for size in sizes {
+        zone.refill(Layout::from_size_align(size, 8).unwrap(), pager.allocate_page().unwrap()).unwrap();
+    }
+    let live = |zone: &mut ZoneAllocator| {
+        let mut live = HashSet::new();
+        zone.for_each_allocated(|ptr, _size| {
+            live.insert(ptr.as_ptr() as usize);
+        });
+        live
+    };
+
+    // Every bin is filled with objects of the zone
+    cache.refill_from(&mut zone).unwrap();
+    assert_eq!(cache.cached(), THREAD_CACHE_BINS * THREAD_CACHE_DEPTH);
+    let cached = live(&mut zone);
+    assert_eq!(cached.len(), THREAD_CACHE_BINS * THREAD_CACHE_DEPTH);
+    cache.refill_from(&mut zone).unwrap();
+    assert_eq!(live(&mut zone).len(), cached.len(), "Full bins take no objects");
+
+    // The cache hands out distinct objects of the zone, which are usable and aligned
+    let mut objects = Vec::new();
+    for size in sizes {
+        let layout = Layout::from_size_align(size, size).unwrap();
+        for _ in 0..THREAD_CACHE_DEPTH {
+            let ptr = cache.allocate(layout).unwrap();
+            assert_eq!(ptr.as_ptr() as usize % size, 0);
+            unsafe { ptr::write_bytes(ptr.as_ptr(), 0xaa, size) };
+            objects.push((ptr, layout));
+        }
+        assert!(cache.allocate(layout).is_none());
+    }
+    let handed_out: HashSet<usize> = objects.iter().map(|(ptr, _)| ptr.as_ptr() as usize).collect();
+    assert_eq!(handed_out, cached);
+
+    // Freed objects go back to the zone with the flush
+    for (ptr, layout) in objects {
+        assert!(cache.deallocate(ptr, layout));
+    }
+    cache.flush(&mut zone).unwrap();
+    assert_eq!(cache.cached(), 0);
+    assert!(live(&mut zone).is_empty());
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+fn sharded_zone_layouts() {
+    let zone: ShardedZoneAllocator<parking_lot::RawMutex> = ShardedZoneAllocator::new(0);
+
+    let zero = Layout::from_size_align(0, 8).unwrap();
+    let ptr = zone.allocate(zero).expect("Zero-sized allocation can't fail");
+    assert_eq!(zone.deallocate(ptr, zero), Ok(()));
+
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1).unwrap();
+    assert_eq!(zone.allocate(too_big), Err(AllocationError::TooLarge));
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+    assert_eq!(zone.empty_pages(), 0);
+    assert_eq!(zone.verify(), Ok(()));
+}
+
+#[test]
+#[cfg(not(feature = "minimal"))]
+fn sharded_zone_stats_without_lock() {
+    let mut pager = Pager::new();
+    let zone: ShardedZoneAllocator<parking_lot::RawMutex> = ShardedZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+
+    // Reading the counters doesn't take the lock of the class
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    let guard = zone.small_slabs[idx].lock();
+    let stats = zone.stats(layout).unwrap();
+    assert_eq!(stats.allocations, 1);
+    assert_eq!(stats.refills, 1);
+    drop(guard);
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1).unwrap();
+    assert!(zone.stats(too_big).is_none());
+
+    zone.deallocate(ptr, layout).unwrap();
+    assert_eq!(zone.stats(layout).unwrap().deallocations, 1);
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn sharded_zone_free_stacks() {
+    let mut pager = Pager::new();
+    let zone: ShardedZoneAllocator<parking_lot::RawMutex> = ShardedZoneAllocator::new(0);
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.set_free_stack_limit(2);
+
+    let objects: Vec<NonNull<u8>> = (0..3).map(|_| zone.allocate(layout).unwrap()).collect();
+    for ptr in objects.iter() {
+        zone.deallocate(*ptr, layout).unwrap();
+    }
+    // The third object didn't fit on the stack
+    assert_eq!(zone.stacked_objects(), 2);
+    assert_eq!(zone.allocate(layout), Ok(objects[1]));
+    assert_eq!(zone.try_allocate(layout), Ok(objects[0]));
+    assert_eq!(zone.stacked_objects(), 0);
+
+    zone.deallocate(objects[0], layout).unwrap();
+    zone.deallocate(objects[1], layout).unwrap();
+    assert_eq!(zone.empty_pages(), 0);
+    zone.flush_free_stacks().unwrap();
+    assert_eq!(zone.stacked_objects(), 0);
+    assert_eq!(zone.empty_pages(), 1);
+    assert_eq!(zone.verify(), Ok(()));
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+
+    // Threads allocating and freeing concurrently never get the same object
+    zone.set_free_stack_limit(64);
+    for _ in 0..4 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    let live = std::sync::Mutex::new(HashSet::new());
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for round in 0..1000 {
+                    let objects: Vec<NonNull<u8>> = (0..round % 16 + 1).map(|_| zone.allocate(layout).unwrap()).collect();
+                    for ptr in objects.iter() {
+                        assert!(live.lock().unwrap().insert(ptr.as_ptr() as usize));
+                    }
+                    for ptr in objects.iter() {
+                        assert!(live.lock().unwrap().remove(&(ptr.as_ptr() as usize)));
+                        zone.deallocate(*ptr, layout).unwrap();
+                    }
+                }
+            });
+        }
+    });
+    zone.flush_free_stacks().unwrap();
+    assert_eq!(zone.empty_pages(), 4);
+    assert_eq!(zone.verify(), Ok(()));
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn sharded_zone_free_stack_checks() {
+    let mut pager = Pager::new();
+    let zone: ShardedZoneAllocator<parking_lot::RawMutex> = ShardedZoneAllocator::new(0);
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.set_free_stack_limit(8);
+
+    let a = zone.allocate(layout).unwrap();
+    let b = zone.allocate(layout).unwrap();
+    zone.deallocate(a, layout).unwrap();
+    zone.deallocate(b, layout).unwrap();
+    // Both the top object and one further down are caught
+    assert_eq!(zone.deallocate(b, layout), Err(AllocationError::DoubleFree));
+    assert_eq!(zone.deallocate(a, layout), Err(AllocationError::DoubleFree));
+    assert_eq!(zone.stacked_objects(), 2);
+
+    // Objects that are free in their page can't be stacked
+    zone.flush_free_stacks().unwrap();
+    assert_eq!(zone.deallocate(a, layout), Err(AllocationError::DoubleFree));
+    let inside = NonNull::new(unsafe { a.as_ptr().add(8) }).unwrap();
+    let c = zone.allocate(layout).unwrap();
+    assert_eq!(zone.deallocate(inside, layout), Err(AllocationError::InvalidPointer));
+    zone.deallocate(c, layout).unwrap();
+
+    // A popped object can be freed again
+    assert_eq!(zone.allocate(layout), Ok(c));
+    zone.deallocate(c, layout).unwrap();
+    zone.flush_free_stacks().unwrap();
+    if !cfg!(feature = "minimal") {
+        let stats = zone.stats(layout).unwrap();
+        assert_eq!(stats.allocations, 4);
+        assert_eq!(stats.deallocations, 4);
+    }
+    assert_eq!(zone.verify(), Ok(()));
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn try_allocate_without_pages() {
+    let mut zone: ZoneAllocator = Default::default();
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    assert_eq!(zone.try_allocate(layout), Err(AllocationError::WouldBlock));
+
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1).unwrap();
+    assert_eq!(zone.try_allocate(too_big), Err(AllocationError::TooLarge));
+}
+
+#[test]
+fn try_allocate_from_emergency_reserve() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.set_emergency_reserve(1);
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    // The only empty page is held back for `try_allocate`
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+    assert!(zone.retrieve_empty_page(0).is_none());
+    assert_eq!(zone.pages(), 1);
+
+    let first = zone.try_allocate(layout).unwrap();
+    // Once started, the page serves `allocate` like any other
+    let second = zone.allocate(layout).unwrap();
+    assert_eq!(first.as_ptr() as usize & !(ObjectPage8k::SIZE - 1), second.as_ptr() as usize & !(ObjectPage8k::SIZE - 1));
+
+    // Without a reserve `try_allocate` doesn't start empty pages
+    zone.deallocate(first, layout).unwrap();
+    zone.deallocate(second, layout).unwrap();
+    zone.set_emergency_reserve(0);
+    assert_eq!(zone.try_allocate(layout), Err(AllocationError::WouldBlock));
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn retire_without_pages() {
+    let mut zone: ZoneAllocator = Default::default();
+    assert!(!zone.retire_empty_page(0, 1));
+    assert!(zone.reclaim_retired_page(u64::MAX).is_none());
+
+    let sc: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    assert_eq!(sc.retired_pages(), 0);
+}
+
+#[test]
+fn deallocate_unlocked_layouts() {
+    let ptr = NonNull::new(0x1000 as *mut u8).unwrap();
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1).unwrap();
+    assert_eq!(unsafe { ZoneAllocator::deallocate_unlocked(ptr, too_big) }, Err(AllocationError::TooLarge));
+
+    let zero = Layout::from_size_align(0, 8).unwrap();
+    assert_eq!(unsafe { ZoneAllocator::deallocate_unlocked(ptr, zero) }, Ok(()));
+
+    let mut sc: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    assert_eq!(sc.rebalance(), 0);
+    sc.set_rebalance_interval(128);
+    assert_eq!(sc.rebalance_interval(), 128);
+}
+
+#[test]
+fn list_insert_back() {
+    let mut op1: ObjectPage8k = Default::default();
+    let op1_ptr = &op1 as *const ObjectPage8k<'_>;
+    let mut op2: ObjectPage8k = Default::default();
+    let op2_ptr = &op2 as *const ObjectPage8k<'_>;
+    let mut op3: ObjectPage8k = Default::default();
+    let op3_ptr = &op3 as *const ObjectPage8k<'_>;
+
+    let mut list: PageList<ObjectPage8k> = PageList::new();
+    unsafe {
+        list.insert_back(&mut op1);
+        list.insert_back(&mut op2);
+        list.insert_front(&mut op3);
+    }
+
+    assert_eq!(list.pop().unwrap() as *const ObjectPage8k, op3_ptr);
+    assert_eq!(list.pop().unwrap() as *const ObjectPage8k, op1_ptr);
+
+    let popped = list.pop().unwrap();
+    assert_eq!(popped as *const ObjectPage8k, op2_ptr);
+    assert!(list.pop().is_none());
+
+    // The tail is reset once the list is empty
+    unsafe { list.insert_back(popped) };
+    assert_eq!(list.elements, 1);
+    assert_eq!(list.pop().unwrap() as *const ObjectPage8k, op2_ptr);
+}
+
+#[test]
+fn page_free_objects() {
+    let mut page: ObjectPage8k = Default::default();
+    let capacity = ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE;
+    page.bitfield.initialize(64, capacity);
+    assert_eq!(page.free_objects(), capacity / 64);
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    assert!(!page.allocate(layout).is_null());
+    assert_eq!(page.free_objects(), capacity / 64 - 1);
+}
+
+#[test]
+fn drain_unknown_page() {
+    let mut zone: ZoneAllocator = Default::default();
+    assert_eq!(zone.drain_page(0x4000), Err(AllocationError::InvalidPointer));
+    assert!(zone.remove_page(0x4000).is_err());
+    assert!(zone.retrieve_coalesced_empty_pages(4).is_none());
+    assert_eq!(zone.small_slabs[0].draining_pages(), 0);
+}
+
+/// Needs contiguous pages, which the pager does not hand out.
+#[test]
+#[cfg(not(feature = "out-of-band-metadata"))]
+fn coalesce_empty_pages_around_live_objects() {
+    let block = Layout::from_size_align(4 * ObjectPage8k::SIZE, ObjectPage8k::SIZE).unwrap();
+    let base = unsafe { alloc::alloc_zeroed(block) } as usize;
+    assert_ne!(base, 0);
+    let page = |idx: usize| unsafe {
+        MappedPages::from_raw(NonNull::new_unchecked((base + idx * ObjectPage8k::SIZE) as *mut [u8; ObjectPage8k::SIZE]))
+    };
+
+    let mut zone = ZoneAllocator::new(0);
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let medium = Layout::from_size_align(128, 8).unwrap();
+    let large = Layout::from_size_align(256, 8).unwrap();
+    zone.refill(small, page(0)).unwrap();
+    zone.refill(medium, page(1)).unwrap();
+    zone.refill(small, page(2)).unwrap();
+    zone.refill(large, page(3)).unwrap();
+    let live = zone.allocate(large).unwrap();
+    assert_eq!(live.as_ptr() as usize & !(ObjectPage8k::SIZE - 1), base + 3 * ObjectPage8k::SIZE);
+
+    // The empty pages of both classes merge, the page with the live object stays
+    let mp = zone.retrieve_coalesced_empty_pages(4).unwrap();
+    assert_eq!(mp.start_address().value(), base);
+    assert_eq!(mp.size_in_bytes(), 3 * ObjectPage8k::SIZE);
+    assert_eq!(zone.pages(), 1);
+    assert!(zone.retrieve_coalesced_empty_pages(4).is_none());
+
+    zone.deallocate(live, large).unwrap();
+    let mp = zone.retrieve_coalesced_empty_pages(4).unwrap();
+    assert_eq!(mp.start_address().value(), base + 3 * ObjectPage8k::SIZE);
+    assert_eq!(mp.size_in_bytes(), ObjectPage8k::SIZE);
+    assert_eq!(zone.pages(), 0);
+    unsafe { alloc::dealloc(base as *mut u8, block) };
+}
+
+#[test]
+fn drain_page_with_live_objects() {
+    let mut pager = Pager::new();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(512);
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    let mut objects: Vec<NonNull<u8>> = (0..sa.obj_per_page).map(|_| sa.allocate(layout).unwrap()).collect();
+    let page = objects[0].as_ptr() as VAddr & !(ObjectPage8k::SIZE - 1);
+
+    // The full page stops serving allocations, draining it again is a no-op
+    sa.drain_page(page).unwrap();
+    sa.drain_page(page).unwrap();
+    assert_eq!(sa.draining_pages(), 1);
+    assert_eq!(sa.page_list(PageState::Full).len(), 0);
+    sa.deallocate(objects.pop().unwrap(), layout).unwrap();
+    assert_eq!(sa.allocate(layout), Err(AllocationError::OutOfMemory));
+
+    // Once its last object is freed the page can be retrieved
+    for ptr in objects {
+        sa.deallocate(ptr, layout).unwrap();
+    }
+    assert_eq!(sa.draining_pages(), 0);
+    sa.drain_page(page).unwrap();
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+    assert_eq!(sa.drain_page(page), Err(AllocationError::InvalidPointer));
+}
+
+#[test]
+fn remove_page_with_live_objects() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+    let page = ptr.as_ptr() as VAddr & !(ObjectPage8k::SIZE - 1);
+
+    // Only empty pages can be removed, and only by their start address
+    assert_eq!(zone.remove_page(page).err(), Some(AllocationError::PageNotEmpty));
+    assert_eq!(zone.remove_page(page + 64).err(), Some(AllocationError::InvalidPointer));
+    assert_eq!(zone.pages(), 2);
+
+    zone.deallocate(ptr, layout).unwrap();
+    let mp = zone.remove_page(page).unwrap();
+    assert_eq!(mp.start_address().value(), page);
+    assert_eq!(zone.pages(), 1);
+    assert_eq!(zone.remove_page(page).err(), Some(AllocationError::InvalidPointer));
+    pager.release_page(mp);
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn destroy_empty_zone() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pages = 0;
+    assert_eq!(zone.destroy(false, |_mp| pages += 1), Ok(0));
+    assert_eq!(pages, 0);
+}
+
+#[test]
+fn destroy_zone_with_live_objects() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let large = Layout::from_size_align(1024, 8).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(large, pager.allocate_page().unwrap()).unwrap();
+    for _ in 0..3 {
+        zone.allocate(small).unwrap();
+    }
+    zone.allocate(large).unwrap();
+
+    // Without `force` the zone keeps its pages and objects
+    let mut pages = Vec::new();
+    assert_eq!(zone.destroy(false, |mp| pages.push(mp)), Err(AllocationError::PageNotEmpty));
+    assert!(pages.is_empty());
+    assert_eq!(zone.pages(), 3);
+
+    assert_eq!(zone.destroy(true, |mp| pages.push(mp)), Ok(4));
+    assert_eq!(pages.len(), 3);
+    assert_eq!(zone.pages(), 0);
+    for mp in pages {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn page_limits() {
+    let mut zone: ZoneAllocator = Default::default();
+    zone.set_max_pages_per_class(2);
+    zone.set_total_budget_bytes(4 * ObjectPage8k::SIZE);
+    assert_eq!(zone.total_budget_bytes(), 4 * ObjectPage8k::SIZE);
+    assert_eq!(zone.pages(), 0);
+    assert!(!zone.small_slabs[0].at_page_limit());
+
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+}
+
+#[test]
+fn refill_past_page_limits() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_max_pages_per_class(2);
+    zone.set_total_budget_bytes(3 * ObjectPage8k::SIZE);
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let medium = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+
+    // The size class is at its cap
+    let mp = pager.allocate_page().unwrap();
+    let page = mp.start_address().value();
+    let (e, mp) = zone.try_refill(small, mp).unwrap_err();
+    assert_eq!(e, AllocationError::LimitExceeded);
+    assert_eq!(mp.start_address().value(), page);
+    assert_eq!(mp.size_in_bytes(), ObjectPage8k::SIZE);
+    assert_eq!(zone.pages(), 2);
+
+    // Another size class takes it, then the zone is at its budget
+    zone.try_refill(medium, mp).unwrap();
+    let (e, mp) = zone.try_refill(medium, pager.allocate_page().unwrap()).unwrap_err();
+    assert_eq!(e, AllocationError::LimitExceeded);
+    assert_eq!(zone.pages(), 3);
+    pager.release_page(mp);
+
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+fn tag_accounting() {
+    let mut tags = TagAccounting::new();
+    tags.on_allocation(7, 64);
+    tags.on_allocation(7, 32);
+    tags.on_allocation(9, 8);
+    assert_eq!(tags.get(7), Some(TagStats { tag: 7, live_bytes: 96, live_objects: 2 }));
+    assert_eq!(tags.iter().count(), 2);
+
+    tags.on_deallocation(9, 8);
+    assert_eq!(tags.get(9), None);
+
+    for tag in 100..(100 + MAX_TAGS as u64) {
+        tags.on_allocation(tag, 16);
+    }
+    assert_eq!(tags.untracked_bytes(), 16);
+}
+
+#[test]
+fn zone_allocate_tagged() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(48, 8).unwrap();
+
+    // Failed allocations are not accounted
+    assert_eq!(zone.allocate_tagged(layout, 7), Err(AllocationError::OutOfMemory));
+    assert_eq!(zone.tag_stats().get(7), None);
+
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let first = zone.allocate_tagged(layout, 7).unwrap();
+    let second = zone.allocate_tagged(layout, 7).unwrap();
+    let other = zone.allocate_tagged(layout, 9).unwrap();
+    let untagged = zone.allocate(layout).unwrap();
+    assert_eq!(zone.tag_stats().get(7), Some(TagStats { tag: 7, live_bytes: 96, live_objects: 2 }));
+    assert_eq!(zone.tag_stats().get(9), Some(TagStats { tag: 9, live_bytes: 48, live_objects: 1 }));
+    assert_eq!(zone.tag_stats().iter().count(), 2);
+
+    zone.deallocate_tagged(first, layout, 7).unwrap();
+    assert_eq!(zone.tag_stats().get(7), Some(TagStats { tag: 7, live_bytes: 48, live_objects: 1 }));
+    // A failed deallocation leaves the account alone
+    assert!(zone.deallocate_tagged(first, layout, 7).is_err());
+    assert_eq!(zone.tag_stats().get(7), Some(TagStats { tag: 7, live_bytes: 48, live_objects: 1 }));
+
+    zone.deallocate_tagged(second, layout, 7).unwrap();
+    zone.deallocate_tagged(other, layout, 9).unwrap();
+    zone.deallocate(untagged, layout).unwrap();
+    assert_eq!(zone.tag_stats().iter().count(), 0);
+    assert_eq!(zone.tag_stats().untracked_bytes(), 0);
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn page_allocate_random() {
+    let mut page: ObjectPage8k = Default::default();
+    page.bitfield.initialize(64, 8 * 64);
+    assert_eq!(page.bitfield.nth_free(3), Some(3));
+    page.bitfield.set_bit(1);
+    assert_eq!(page.bitfield.nth_free(1), Some(2));
+    assert_eq!(page.bitfield.nth_free(7), None);
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut seen = 0;
+    for random in 0..7 {
+        let ptr = page.allocate_random(layout, random * 13);
+        assert!(!ptr.is_null());
+        seen += 1;
+    }
+    assert_eq!(seen, 7);
+    assert!(page.is_full());
+    assert!(page.allocate_random(layout, 5).is_null());
+}
+
+#[test]
+fn page_double_free() {
+    let mut page: ObjectPage8k = Default::default();
+    page.bitfield.initialize(64, ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = NonNull::new(page.allocate(layout)).unwrap();
+    let other = NonNull::new(page.allocate(layout)).unwrap();
+
+    assert_eq!(page.deallocate(ptr, layout), Ok(()));
+    assert_eq!(page.deallocate(ptr, layout), Err(AllocationError::DoubleFree));
+    assert!(page.bitfield.is_allocated(1));
+    assert_eq!(page.deallocate(other, layout), Ok(()));
+    assert!(page.is_empty((ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE) / 64));
+
+    // Of the frees racing for the same object only one wins
+    struct SharedPage<'a>(ObjectPage8k<'a>);
+    // `deallocate` only touches the atomic bitfield
+    unsafe impl Sync for SharedPage<'_> {}
+    let mut page = SharedPage(page);
+    for _ in 0..100 {
+        let ptr = page.0.allocate(layout) as usize;
+        let freed = std::sync::atomic::AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    if page.0.deallocate(NonNull::new(ptr as *mut u8).unwrap(), layout).is_ok() {
+                        freed.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        assert_eq!(freed.load(Ordering::Relaxed), 1);
+    }
+}
+
+#[test]
+fn remote_free_inbox_push() {
+    let inbox = RemoteFreeInbox::new();
+    assert!(inbox.is_empty());
+
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1).unwrap();
+    assert_eq!(inbox.push(NonNull::dangling(), too_big), Err(AllocationError::TooLarge));
+
+    let inboxes = [RemoteFreeInbox::new()];
+    let remote = RemoteFreeInboxes::new(&inboxes);
+    assert_eq!(
+        remote.forward(1, NonNull::dangling(), Layout::new::<u64>()),
+        Err(AllocationError::WrongHeap(1))
+    );
+
+    #[cfg(not(feature = "red-zones"))]
+    {
+        let mut object = 0u64;
+        let ptr = NonNull::new(&mut object as *mut u64 as *mut u8).unwrap();
+        assert_eq!(remote.forward(0, ptr, Layout::new::<u64>()), Ok(()));
+        assert!(!inboxes[0].is_empty());
+    }
+}
+
+#[test]
+#[cfg(not(feature = "minimal"))]
+fn remote_free_inbox_drain() {
+    static INBOXES: [RemoteFreeInbox; 2] = [RemoteFreeInbox::new(), RemoteFreeInbox::new()];
+    static REMOTE: RemoteFreeInboxes = RemoteFreeInboxes::new(&INBOXES);
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    let mut home = ZoneAllocator::new(0);
+    let mut other = ZoneAllocator::new(1);
+    home.set_remote_free_inbox(&INBOXES[0]);
+    other.set_cross_heap_free(&REMOTE);
+    home.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    home.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    // A full and a partial page, all but one object are freed on the other heap
+    let objects: Vec<NonNull<u8>> = (0..home.small_slabs[class].obj_per_page + 2)
+        .map(|_| home.allocate(layout).unwrap())
+        .collect();
+    let (kept, remote) = objects.split_first().unwrap();
+    unsafe { kept.as_ptr().write(0x5a) };
+    for ptr in remote {
+        other.deallocate(*ptr, layout).unwrap();
+    }
+    assert!(!INBOXES[0].is_empty());
+    assert!(INBOXES[1].is_empty());
+    assert_eq!(home.small_slabs[class].page_list(PageState::Full).len(), 1);
+
+    // The next allocation frees them
+    let ptr = home.allocate(layout).unwrap();
+    assert!(INBOXES[0].is_empty());
+    assert_eq!(home.drain_remote_frees(), 0);
+    assert_eq!(home.small_slabs[class].page_list(PageState::Full).len(), 0);
+    assert_eq!(home.small_slabs[class].page_list(PageState::Empty).len(), 1);
+    assert_eq!(unsafe { kept.as_ptr().read() }, 0x5a);
+
+    home.deallocate(*kept, layout).unwrap();
+    home.deallocate(ptr, layout).unwrap();
+    // A drain frees objects pushed without an allocation in between
+    let ptr = home.allocate(layout).unwrap();
+    other.deallocate(ptr, layout).unwrap();
+    assert_eq!(home.drain_remote_frees(), 1);
+    while let Some(mp) = home.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+fn list_append() {
+    let mut op1: ObjectPage8k = Default::default();
+    let op1_ptr = &op1 as *const ObjectPage8k<'_>;
+    let mut op2: ObjectPage8k = Default::default();
+    let op2_ptr = &op2 as *const ObjectPage8k<'_>;
+    let mut op3: ObjectPage8k = Default::default();
+    let op3_ptr = &op3 as *const ObjectPage8k<'_>;
+
+    let mut list: PageList<ObjectPage8k> = PageList::new();
+    let mut other: PageList<ObjectPage8k> = PageList::new();
+    unsafe { list.append(&mut other) };
+    assert_eq!(list.elements, 0);
+
+    unsafe {
+        other.insert_back(&mut op1);
+        other.insert_back(&mut op2);
+        list.insert_back(&mut op3);
+        list.append(&mut other);
+    }
+    assert_eq!(list.elements, 3);
+    assert_eq!(other.elements, 0);
+    assert!(other.pop().is_none());
+
+    assert_eq!(list.pop().unwrap() as *const ObjectPage8k, op3_ptr);
+    assert_eq!(list.pop().unwrap() as *const ObjectPage8k, op1_ptr);
+    assert_eq!(list.pop().unwrap() as *const ObjectPage8k, op2_ptr);
+    assert!(list.pop().is_none());
+}
+
+#[test]
+fn migrate_empty_zone() {
+    let mut zone = ZoneAllocator::new(0);
+    let mut dying = ZoneAllocator::new(1);
+    assert_eq!(zone.migrate_from(&mut dying, 0), Ok(0));
+    assert_eq!(zone.pages(), 0);
+    assert_eq!(dying.pages(), 0);
+}
+
+#[test]
+fn migrate_zone_with_live_objects() {
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let other_layout = Layout::from_size_align(128, 8).unwrap();
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    let mut zone = ZoneAllocator::new(0);
+    let mut dying = ZoneAllocator::new(1);
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let own = zone.allocate(layout).unwrap();
+    for _ in 0..3 {
+        dying.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    dying.refill(other_layout, pager.allocate_page().unwrap()).unwrap();
+
+    // A full and a partial page of the 64 byte class, a partial page of the 128 byte class
+    let mut objects: Vec<(NonNull<u8>, Layout)> = (0..dying.small_slabs[class].obj_per_page + 1)
+        .map(|_| (dying.allocate(layout).unwrap(), layout))
+        .collect();
+    objects.push((dying.allocate(other_layout).unwrap(), other_layout));
+    for (i, (ptr, _)) in objects.iter().enumerate() {
+        unsafe { ptr.as_ptr().write(i as u8) };
+    }
+
+    assert_eq!(zone.migrate_from(&mut dying, 0), Ok(objects.len()));
+    assert_eq!(dying.pages(), 0);
+    assert_eq!(zone.pages(), 5);
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Full).len(), 1);
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Partial).len(), 2);
+    assert_eq!(zone.empty_pages(), 1);
+
+    // The objects of the dying zone are now freed to this one
+    for (i, (ptr, layout)) in objects.into_iter().enumerate() {
+        assert_eq!(unsafe { ptr.as_ptr().read() }, i as u8);
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    zone.deallocate(own, layout).unwrap();
+    assert_eq!(zone.empty_pages(), 5);
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+fn migrate_past_page_limit() {
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut zone = ZoneAllocator::new(0);
+    let mut dying = ZoneAllocator::new(1);
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    dying.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = dying.allocate(layout).unwrap();
+
+    // The 64 byte class of `zone` may only own one page, nothing is moved
+    zone.set_max_pages_per_class(1);
+    assert_eq!(zone.migrate_from(&mut dying, 0), Err(AllocationError::LimitExceeded));
+    assert_eq!(zone.pages(), 1);
+    assert_eq!(dying.pages(), 1);
+    dying.deallocate(ptr, layout).unwrap();
+
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+    pager.release_page(dying.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn empty_page_pool() {
+    let pool = EmptyPagePool::new();
+    assert_eq!(pool.pages(), 0);
+    assert!(pool.retrieve_page().is_none());
+
+    let mut op: BasePage = Default::default();
+    let op_ptr = &op as *const BasePage<'_>;
+    pool.push(&mut op);
+    assert_eq!(pool.pages(), 1);
+    let page = pool.pop().unwrap();
+    assert_eq!(page as *const BasePage, op_ptr);
+    assert_eq!(page.heap_id(), EmptyPagePool::HEAP_ID);
+    assert!(pool.pop().is_none());
+
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_empty_page_pool(&pool, 1);
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+}
+
+/// Collects the pages a zone reclaims.
+struct Reclaimed(std::sync::Mutex<Vec<MappedPages>>);
+
+unsafe impl Sync for Reclaimed {}
+
+impl PageReclaimer for Reclaimed {
+    fn reclaim(&self, mp: MappedPages) {
+        self.0.lock().unwrap().push(mp);
+    }
+}
+
+#[test]
+fn zone_page_reclaimer() {
+    let mut pager = Pager::new();
+    let reclaimed = Reclaimed(std::sync::Mutex::new(Vec::new()));
+    let mut zone = ZoneAllocatorBuilder::new(0).page_reclaimer(&reclaimed, 1, 1).build().unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..3 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+
+    // One page per free until only one empty page is left
+    for left in [2, 1, 1] {
+        let ptr = zone.allocate(layout).unwrap();
+        zone.deallocate(ptr, layout).unwrap();
+        assert_eq!(zone.empty_pages(), left);
+    }
+    assert_eq!(reclaimed.0.lock().unwrap().len(), 2);
+
+    for mp in reclaimed.0.lock().unwrap().drain(..) {
+        pager.release_page(mp);
+    }
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+#[cfg(not(feature = "minimal"))]
+fn zone_misdirected_free() {
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut zone = ZoneAllocator::new(0);
+    let mut other = ZoneAllocator::new(1);
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    other.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+    let foreign = other.allocate(layout).unwrap();
+
+    assert_eq!(zone.deallocate(foreign, layout), Err(AllocationError::WrongHeap(1)));
+    // A page of the heap that doesn't serve the layout isn't misdirected
+    let wrong_class = Layout::from_size_align(128, 8).unwrap();
+    assert_eq!(zone.deallocate(ptr, wrong_class), Err(AllocationError::InvalidPointer));
+    zone.deallocate(ptr, layout).unwrap();
+    other.deallocate(foreign, layout).unwrap();
+
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+    pager.release_page(other.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn zone_page_quarantine() {
+    let mut pager = Pager::new();
+    let reclaimed = Reclaimed(std::sync::Mutex::new(Vec::new()));
+    let mut zone = ZoneAllocatorBuilder::new(0).page_reclaimer(&reclaimed, 0, 0).page_quarantine(true).build().unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+    zone.deallocate(ptr, layout).unwrap();
+
+    // The page is poisoned and held back for a cycle
+    assert_eq!(zone.maintain().bytes_released, 0);
+    assert_eq!(zone.empty_pages(), 0);
+    assert_eq!(unsafe { *ptr.as_ptr() }, PAGE_POISON_BYTE);
+    assert_eq!(zone.deallocate(ptr, layout), Err(AllocationError::QuarantinedPage));
+    assert_eq!(zone.page_quarantine_violations(), 1);
+
+    // A late write is reported once the page leaves
+    unsafe { *ptr.as_ptr() = 0 };
+    assert_eq!(zone.maintain().bytes_released, BasePage::SIZE);
+    assert_eq!(zone.page_quarantine_violations(), 2);
+
+    for mp in reclaimed.0.lock().unwrap().drain(..) {
+        assert_eq!(mp.start_address().value(), ptr.as_ptr() as usize & !(BasePage::SIZE - 1));
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn zone_page_quarantine_internal_moves() {
+    let mut pager = Pager::new();
+    let reclaimed = Reclaimed(std::sync::Mutex::new(Vec::new()));
+    let mut zone = ZoneAllocatorBuilder::new(0).page_quarantine(true).build().unwrap();
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let big = Layout::from_size_align(256, 8).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+
+    // Pages moved between size classes stay usable
+    zone.exchange_pages_within_heap(big).unwrap();
+    let ptr = zone.allocate(big).unwrap();
+    zone.deallocate(ptr, big).unwrap();
+    assert_eq!(zone.page_bytes(), 2 * ObjectPage8k::SIZE);
+
+    // Shrunk pages reach the reclaimer after a cycle in the quarantine
+    zone.set_page_reclaimer(&reclaimed, usize::MAX, 0);
+    assert_eq!(zone.shrink(0), 2 * ObjectPage8k::SIZE);
+    assert_eq!(zone.page_bytes(), 0);
+    assert!(reclaimed.0.lock().unwrap().is_empty());
+    assert_eq!(zone.maintain().bytes_released, 2 * ObjectPage8k::SIZE);
+    assert_eq!(zone.page_quarantine_violations(), 0);
+    for mp in reclaimed.0.lock().unwrap().drain(..) {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn zone_shrink() {
+    let mut pager = Pager::new();
+    let reclaimed = Reclaimed(std::sync::Mutex::new(Vec::new()));
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..3 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    let ptr = zone.allocate(layout).unwrap();
+    assert_eq!(zone.shrink(0), 0);
+
+    zone.set_page_reclaimer(&reclaimed, usize::MAX, 0);
+    assert_eq!(zone.shrink(2 * ObjectPage8k::SIZE), ObjectPage8k::SIZE);
+    assert_eq!(zone.page_bytes(), 2 * ObjectPage8k::SIZE);
+    // The page holding the object can't be released
+    assert_eq!(zone.shrink(0), ObjectPage8k::SIZE);
+    assert_eq!(zone.page_bytes(), ObjectPage8k::SIZE);
+    assert_eq!(reclaimed.0.lock().unwrap().len(), 2);
+
+    zone.deallocate(ptr, layout).unwrap();
+    assert_eq!(zone.shrink(0), ObjectPage8k::SIZE);
+    for mp in reclaimed.0.lock().unwrap().drain(..) {
+        pager.release_page(mp);
+    }
+}
+
+struct FakeClock(AtomicU64);
+
+impl ClockSource for FakeClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn zone_idle_decay() {
+    let mut pager = Pager::new();
+    let reclaimed = Reclaimed(std::sync::Mutex::new(Vec::new()));
+    let clock = FakeClock(AtomicU64::new(100));
+    let mut zone = ZoneAllocatorBuilder::new(0)
+        .page_reclaimer(&reclaimed, usize::MAX, 0)
+        .idle_decay(&clock, 10)
+        .build()
+        .unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..3 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    let ptr = zone.allocate(layout).unwrap();
+
+    clock.0.store(105, Ordering::Relaxed);
+    assert_eq!(zone.maintain().bytes_released, 0);
+    // The pages were added during the first period
+    clock.0.store(110, Ordering::Relaxed);
+    assert_eq!(zone.maintain().bytes_released, 0);
+    // The two pages that stayed empty for a whole period are released
+    clock.0.store(120, Ordering::Relaxed);
+    assert_eq!(zone.maintain().bytes_released, 2 * ObjectPage8k::SIZE);
+    assert_eq!(zone.empty_pages(), 0);
+
+    // A page that became empty during the period is kept for another one
+    zone.deallocate(ptr, layout).unwrap();
+    clock.0.store(130, Ordering::Relaxed);
+    assert_eq!(zone.maintain().bytes_released, 0);
+    clock.0.store(140, Ordering::Relaxed);
+    assert_eq!(zone.maintain().bytes_released, ObjectPage8k::SIZE);
+    assert_eq!(zone.page_bytes(), 0);
+
+    for mp in reclaimed.0.lock().unwrap().drain(..) {
+        pager.release_page(mp);
+    }
+}
+
+/// A clock that advances by its step every time it is read.
+#[cfg(feature = "latency-stats")]
+struct SteppingClock(AtomicU64, u64);
+
+#[cfg(feature = "latency-stats")]
+impl ClockSource for SteppingClock {
+    fn now(&self) -> u64 {
+        self.0.fetch_add(self.1, Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "latency-stats")]
+#[test]
+fn zone_latency_histogram() {
+    assert_eq!(LatencyHistogram::bucket(0), 0);
+    assert_eq!(LatencyHistogram::bucket(1), 0);
+    assert_eq!(LatencyHistogram::bucket(5), 2);
+    assert_eq!(LatencyHistogram::bucket(u64::MAX), LATENCY_BUCKETS - 1);
+
+    let mut pager = Pager::new();
+    let clock = SteppingClock(AtomicU64::new(0), 5);
+    let mut zone = ZoneAllocatorBuilder::new(0).latency_clock(&clock).build().unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    let ptrs: Vec<_> = (0..3).map(|_| zone.allocate(layout).unwrap()).collect();
+    for ptr in ptrs {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+
+    // The clock is read before and after every operation, so each one took 5 ticks
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    let stats = zone.size_class_stats();
+    assert_eq!(stats[class].latency.allocate[2], 3);
+    assert_eq!(stats[class].latency.deallocate[2], 3);
+    assert_eq!(stats[class].latency.allocate.iter().sum::<u64>(), 3);
+    assert_eq!(LatencyHistogram::percentile(&stats[class].latency.allocate, 99), Some(7));
+    assert_eq!(stats[class + 1].latency, LatencyHistogram::new());
+    assert_eq!(LatencyHistogram::percentile(&LatencyHistogram::new().allocate, 50), None);
+
+    zone.reset_latency_stats();
+    assert_eq!(zone.size_class_stats()[class].latency, LatencyHistogram::new());
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn zone_maintain() {
+    let mut pager = Pager::new();
+    let reclaimed = Reclaimed(std::sync::Mutex::new(Vec::new()));
+    let inbox = RemoteFreeInbox::new();
+    let mut zone = ZoneAllocatorBuilder::new(0)
+        .remote_free_inbox(&inbox)
+        .page_reclaimer(&reclaimed, 1, 0)
+        .build()
+        .unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..3 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    let ptr = zone.allocate(layout).unwrap();
+    inbox.push(ptr, layout).unwrap();
+
+    let report = zone.maintain();
+    assert_eq!(report.remote_frees, 1);
+    assert_eq!(report.bytes_released, 2 * ObjectPage8k::SIZE);
+    assert_eq!(zone.empty_pages(), 1);
+    assert_eq!(zone.maintain(), MaintenanceReport::default());
+
+    for mp in reclaimed.0.lock().unwrap().drain(..) {
+        pager.release_page(mp);
+    }
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[cfg(not(feature = "minimal"))]
+#[test]
+fn zone_suggest_refill() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let idx = match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+        Slab::Base(idx) => idx,
+        _ => unreachable!(),
+    };
+    let obj_per_page = zone.small_slabs[idx].obj_per_page;
+    assert!(zone.suggest_refill().iter().all(|&(_, pages)| pages == 0));
+
+    let mut objects = Vec::new();
+    for _ in 0..3 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    for _ in 0..3 * obj_per_page {
+        objects.push(zone.allocate(layout).unwrap());
+    }
+    zone.maintain();
+    let suggestions = zone.suggest_refill();
+    assert_eq!(suggestions[idx].0.size(), ZoneAllocator::BASE_ALLOC_SIZES[idx]);
+    assert_eq!(suggestions[idx].1, 2);
+    assert_eq!(suggestions.iter().map(|&(_, pages)| pages).sum::<usize>(), 2);
+
+    // The rate decays without allocations
+    zone.maintain();
+    assert_eq!(zone.suggest_refill()[idx].1, 1);
+
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    assert_eq!(zone.suggest_refill()[idx].1, 0);
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn zone_long_lived_hint() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..2 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+
+    let short = zone.allocate(layout).unwrap();
+    let long = zone.allocate_with_hint(layout, AllocHint::LongLived).unwrap();
+    let long_too = zone.allocate_with_hint(layout, AllocHint::LongLived).unwrap();
+    assert_ne!(short.as_ptr() as usize & !(ObjectPage8k::SIZE - 1), long.as_ptr() as usize & !(ObjectPage8k::SIZE - 1));
+    assert_eq!(long.as_ptr() as usize & !(ObjectPage8k::SIZE - 1), long_too.as_ptr() as usize & !(ObjectPage8k::SIZE - 1));
+    assert_eq!(zone.iter_pages().filter(|page| page.state == PageState::LongLived).count(), 1);
+    assert_eq!(zone.verify(), Ok(()));
+
+    // The long-lived objects don't keep the page of the short-lived one
+    zone.deallocate(short, layout).unwrap();
+    assert_eq!(zone.empty_pages(), 1);
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+
+    zone.deallocate(long, layout).unwrap();
+    zone.deallocate(long_too, layout).unwrap();
+    assert_eq!(zone.empty_pages(), 1);
+    assert_eq!(zone.verify(), Ok(()));
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn zone_numa_nodes() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_node(0);
+    assert_eq!(zone.node(), Some(0));
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    zone.refill_on_node(layout, pager.allocate_page().unwrap(), 1).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let mut nodes: Vec<usize> = zone.iter_pages().map(|page| page.node).collect();
+    nodes.sort();
+    assert_eq!(nodes, [0, 1]);
+
+    // The local page is used up before the remote one
+    let obj_per_page = zone.small_slabs[idx].obj_per_page;
+    let mut objects = Vec::new();
+    for _ in 0..obj_per_page {
+        objects.push(zone.allocate(layout).unwrap());
+    }
+    assert!(zone.iter_pages().filter(|page| page.live_objects > 0).all(|page| page.node == 0));
+    objects.push(zone.allocate(layout).unwrap());
+    #[cfg(not(feature = "minimal"))]
+    assert_eq!(zone.small_slabs[idx].stats().remote_allocations, 1);
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+
+    // Pages overflow back into the pool of their node
+    let pools = [EmptyPagePool::new(), EmptyPagePool::new()];
+    pools[1].add_page_on_node(pager.allocate_page().unwrap(), 1).unwrap();
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_node(0);
+    zone.set_node_page_pools(&pools, 0);
+    let ptr = zone.allocate(layout).unwrap();
+    assert_eq!(pools[1].pages(), 0);
+    zone.deallocate(ptr, layout).unwrap();
+    assert_eq!((pools[0].pages(), pools[1].pages()), (0, 1));
+    pager.release_page(pools[1].retrieve_page().unwrap());
+}
+
+#[test]
+fn zone_memory_tiers() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.refill_on_tier(layout, pager.allocate_page().unwrap(), MemoryTier::Slow).unwrap();
+    assert_eq!(zone.tier_footprint(), [ObjectPage8k::SIZE, ObjectPage8k::SIZE]);
+
+    let page_of = |ptr: NonNull<u8>| ptr.as_ptr() as usize & !(ObjectPage8k::SIZE - 1);
+    let tier_of = |zone: &ZoneAllocator, ptr: NonNull<u8>| zone.iter_pages().find(|page| page.start == page_of(ptr)).unwrap().tier;
+    let fast = zone.allocate_with_tier_preference(layout, MemoryTier::Dram).unwrap();
+    let slow = zone.allocate_with_tier_preference(layout, MemoryTier::Slow).unwrap();
+    let slow_too = zone.allocate_with_tier_preference(layout, MemoryTier::Slow).unwrap();
+    assert_eq!(tier_of(&zone, fast), MemoryTier::Dram);
+    assert_eq!(tier_of(&zone, slow), MemoryTier::Slow);
+    assert_eq!(page_of(slow), page_of(slow_too));
+    assert_eq!(zone.verify(), Ok(()));
+
+    for ptr in [fast, slow, slow_too] {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(zone.tier_footprint(), [0, 0]);
+}
+
+/// Pretends that only the page starting at the address it holds is followed by a guard page.
+struct GuardedPage(usize);
+
+impl GuardPageCheck for GuardedPage {
+    fn has_guard_page(&self, mp: &MappedPages) -> bool {
+        mp.start_address().value() == self.0
+    }
+}
+
+#[test]
+fn zone_guard_pages() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(192, 8).unwrap();
+    let mp = pager.allocate_page().unwrap();
+    let guarded = GuardedPage(mp.start_address().value());
+    assert_eq!(
+        zone.set_guard_pages_for(Layout::from_size_align(1 << 20, 8).unwrap(), Some(&guarded)),
+        Err(AllocationError::TooLarge)
+    );
+    if !cfg!(feature = "out-of-band-metadata") {
+        // The meta-data at the end of the page would sit between the objects and the guard page
+        assert!(matches!(zone.set_guard_pages_for(layout, Some(&guarded)), Err(AllocationError::InvalidPage(_))));
+        pager.release_page(mp);
+        return;
+    }
+
+    zone.set_guard_pages_for(layout, Some(&guarded)).unwrap();
+    assert!(matches!(zone.refill(layout, pager.allocate_page().unwrap()), Err(AllocationError::InvalidPage(_))));
+    zone.refill(layout, mp).unwrap();
+
+    // The objects are flush against the guard page
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    let size = zone.small_slabs[idx].size();
+    let mut objects = Vec::new();
+    while let Ok(ptr) = zone.allocate(layout) {
+        objects.push(ptr.as_ptr() as usize);
+    }
+    assert_eq!(objects.len(), zone.small_slabs[idx].obj_per_page);
+    let end = objects.iter().max().unwrap() + size;
+    if !cfg!(any(feature = "callsites", feature = "generations")) {
+        assert_eq!(end, guarded.0 + ObjectPage8k::SIZE);
+    }
+    for ptr in objects {
+        zone.deallocate(NonNull::new(ptr as *mut u8).unwrap(), layout).unwrap();
+    }
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn exchange_pages_between_zones() {
+    let mut zone = ZoneAllocator::new(0);
+    let mut sibling = ZoneAllocator::new(1);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    assert_eq!(zone.exchange_pages(&mut sibling, layout), Err(AllocationError::OutOfMemory));
+
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1).unwrap();
+    assert_eq!(zone.exchange_pages(&mut sibling, too_big), Err(AllocationError::TooLarge));
+
+    zone.set_max_pages_per_class(1);
+    zone.set_total_budget_bytes(ObjectPage8k::SIZE / 2);
+    assert_eq!(zone.exchange_pages(&mut sibling, layout), Err(AllocationError::LimitExceeded));
+}
+
+#[test]
+fn exchange_pages_with_live_objects() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let mut sibling = ZoneAllocator::new(1);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let other_layout = Layout::from_size_align(128, 8).unwrap();
+    sibling.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    sibling.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    sibling.refill(other_layout, pager.allocate_page().unwrap()).unwrap();
+    let kept = sibling.allocate(layout).unwrap();
+    unsafe { ptr::write_bytes(kept.as_ptr(), 0xab, layout.size()) };
+
+    // The empty page of the same class is borrowed, the partial one stays
+    zone.exchange_pages(&mut sibling, layout).unwrap();
+    assert_eq!((zone.pages(), sibling.pages()), (1, 2));
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    assert_eq!(sibling.small_slabs[class].page_list(PageState::Partial).len(), 1);
+
+    // Then the empty page of another class, which serves this class afterwards
+    zone.exchange_pages(&mut sibling, layout).unwrap();
+    assert_eq!((zone.pages(), sibling.pages()), (2, 1));
+    assert_eq!(zone.exchange_pages(&mut sibling, layout), Err(AllocationError::OutOfMemory));
+
+    let objects: Vec<NonNull<u8>> = (0..2 * zone.small_slabs[class].obj_per_page)
+        .map(|_| zone.allocate(layout).unwrap())
+        .collect();
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Full).len(), 2);
+    for ptr in objects.iter() {
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0xcd, layout.size()) };
+    }
+    // The object left with the sibling wasn't touched
+    let kept_bytes = unsafe { core::slice::from_raw_parts(kept.as_ptr(), layout.size()) };
+    assert!(kept_bytes.iter().all(|&b| b == 0xab));
+
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    sibling.deallocate(kept, layout).unwrap();
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    pager.release_page(sibling.retrieve_empty_page(0).unwrap());
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+#[cfg(not(feature = "minimal"))]
+fn sc_operation_counters() {
+    let mut pager = Pager::new();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    assert_eq!(sa.allocate(layout), Err(AllocationError::OutOfMemory));
+    sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+
+    let objects: Vec<NonNull<u8>> = (0..10).map(|_| sa.allocate(layout).unwrap()).collect();
+    for ptr in objects.iter().take(4) {
+        sa.deallocate(*ptr, layout).unwrap();
+    }
+    assert_eq!(sa.deallocate(objects[0], layout), Err(AllocationError::DoubleFree));
+    let stats = sa.stats();
+    assert_eq!(stats.allocations, 10);
+    assert_eq!(stats.deallocations, 4);
+    assert_eq!(stats.refills, 1);
+    // The failed allocation and the one that started the fresh page
+    assert_eq!(stats.slow_path, 2);
+    assert_eq!(stats.pages_retrieved, 0);
+
+    for ptr in objects.iter().skip(4) {
+        sa.deallocate(*ptr, layout).unwrap();
+    }
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+    assert_eq!(sa.stats().deallocations, 10);
+    assert_eq!(sa.stats().pages_retrieved, 1);
+
+    // Counters stick at their maximum
+    sa.stats.allocations.store(usize::MAX, Ordering::Relaxed);
+    sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    let ptr = sa.allocate(layout).unwrap();
+    assert_eq!(sa.stats().allocations, usize::MAX);
+    sa.deallocate(ptr, layout).unwrap();
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+}
+
+#[test]
+#[cfg(not(feature = "minimal"))]
+fn sc_page_transition_counters() {
+    let mut pager = Pager::new();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    let layout = Layout::from_size_align(24, 8).unwrap();
+    sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    let per_page = sa.obj_per_page;
+
+    let objects: Vec<NonNull<u8>> = (0..per_page).map(|_| sa.allocate(layout).unwrap()).collect();
+    let stats = sa.stats();
+    assert_eq!(stats.empty_to_partial, 1);
+    assert_eq!(stats.partial_to_full, 1);
+    assert_eq!(stats.full_to_partial, 0);
+
+    // Freeing the objects one by one takes the page back through the partial list
+    for ptr in objects.iter() {
+        sa.deallocate(*ptr, layout).unwrap();
+    }
+    let stats = sa.stats();
+    assert_eq!(stats.full_to_partial, 1);
+    assert_eq!(stats.partial_to_empty, 1);
+    assert_eq!(stats.page_transitions(), 4);
+
+    // Allocating and freeing a single object makes the page thrash
+    for _ in 0..3 {
+        let ptr = sa.allocate(layout).unwrap();
+        sa.deallocate(ptr, layout).unwrap();
+    }
+    assert_eq!(sa.stats().empty_to_partial, 4);
+    assert_eq!(sa.stats().partial_to_empty, 4);
+    assert_eq!(sa.stats().page_transitions(), 10);
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+}
+
+#[test]
+#[cfg(feature = "minimal")]
+fn minimal_skips_counters() {
+    let mut pager = Pager::new();
+    let mut zone: ZoneAllocator = Default::default();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+    zone.deallocate(ptr, layout).unwrap();
+
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    assert_eq!(zone.small_slabs[idx].stats(), SCStats::default());
+    assert_eq!(zone.size_class_stats()[idx].requested_bytes, 0);
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn allocation_rate() {
+    let mut sc: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    assert_eq!(sc.update_allocation_rate(), 0);
+    sc.stats.allocations.fetch_add(10, Ordering::Relaxed);
+    assert_eq!(sc.update_allocation_rate(), 5);
+    assert_eq!(sc.allocation_rate(), 5);
+    sc.stats.allocations.fetch_add(10, Ordering::Relaxed);
+    assert_eq!(sc.update_allocation_rate(), 7);
+    assert_eq!(sc.update_allocation_rate(), 3);
+
+    let mut zone: ZoneAllocator = Default::default();
+    zone.small_slabs[3].stats.allocations.fetch_add(100, Ordering::Relaxed);
+    assert_eq!(zone.rebalance_classes(), 0);
+    assert_eq!(zone.small_slabs[3].allocation_rate(), 50);
+}
+
+#[test]
+#[cfg(not(feature = "minimal"))]
+fn rebalance_classes_with_live_objects() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let idle_layout = Layout::from_size_align(128, 8).unwrap();
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    let idle_class = ZoneAllocator::size_class_index(idle_layout).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    for _ in 0..4 {
+        zone.refill(idle_layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    let mut objects: Vec<NonNull<u8>> = (0..zone.small_slabs[class].obj_per_page)
+        .map(|_| zone.allocate(layout).unwrap())
+        .collect();
+    for (i, ptr) in objects.iter().enumerate() {
+        unsafe { ptr.as_ptr().write(i as u8) };
+    }
+
+    // All empty pages go to the only class that allocated, its full page stays put
+    assert_eq!(zone.rebalance_classes(), 4);
+    assert_eq!(zone.small_slabs[idle_class].pages(), 0);
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Empty).len(), 4);
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Full).len(), 1);
+    assert_eq!(zone.pages(), 5);
+
+    // Without the caller refilling the class
+    objects.extend((0..4 * zone.small_slabs[class].obj_per_page).map(|_| zone.allocate(layout).unwrap()));
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Full).len(), 5);
+    for (i, ptr) in objects.into_iter().enumerate() {
+        if i < zone.small_slabs[class].obj_per_page {
+            assert_eq!(unsafe { ptr.as_ptr().read() }, i as u8);
+        }
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+fn split_empty_zone() {
+    let mut zone = ZoneAllocator::new(0);
+    let child = zone.split(2, 3);
+    assert_eq!(child.heap_id, 3);
+    assert_eq!(child.pages(), 0);
+    assert_eq!(zone.pages(), 0);
+}
+
+#[test]
+fn split_zone_with_live_objects() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let other_layout = Layout::from_size_align(128, 8).unwrap();
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    for _ in 0..3 {
+        zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+        zone.refill(other_layout, pager.allocate_page().unwrap()).unwrap();
+    }
+    // A full and a partial page of live objects
+    let objects: Vec<NonNull<u8>> = (0..zone.small_slabs[class].obj_per_page + 1)
+        .map(|_| zone.allocate(layout).unwrap())
+        .collect();
+    for (i, ptr) in objects.iter().enumerate() {
+        unsafe { ptr.as_ptr().write(i as u8) };
+    }
+
+    // Only the four empty pages move, to the first size classes that want one
+    let mut child = zone.split(1, 5);
+    assert_eq!(child.heap_id, 5);
+    assert_eq!(child.pages(), 4);
+    assert_eq!(child.empty_pages(), 4);
+    assert_eq!(zone.pages(), 2);
+    assert_eq!(zone.empty_pages(), 0);
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Full).len(), 1);
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Partial).len(), 1);
+
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let ptr = child.allocate(small).unwrap();
+    child.deallocate(ptr, small).unwrap();
+    for (i, ptr) in objects.into_iter().enumerate() {
+        assert_eq!(unsafe { ptr.as_ptr().read() }, i as u8);
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    for zone in [&mut zone, &mut child] {
+        while let Some(mp) = zone.retrieve_empty_page(0) {
+            pager.release_page(mp);
+        }
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+/// Spreading empty pages over the size classes leaves the pruned ones out.
+#[cfg(feature = "prune-1024-class")]
+#[test]
+fn migrate_and_split_skip_pruned_classes() {
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let pruned = ZoneAllocator::BASE_ALLOC_SIZES.iter().position(|&size| size == 1024).unwrap();
+    let mut zone = ZoneAllocator::new(0);
+    let mut dying = ZoneAllocator::new(1);
+    for _ in 0..2 * ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+        dying.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    }
+
+    assert_eq!(zone.migrate_from(&mut dying, 0), Ok(0));
+    assert_eq!(zone.empty_pages(), 2 * ZoneAllocator::MAX_BASE_SIZE_CLASSES);
+    assert_eq!(zone.small_slabs[pruned].empty_slabs.elements, 0);
+
+    let mut child = zone.split(2, 2);
+    assert_eq!(child.small_slabs[pruned].empty_slabs.elements, 0);
+    for (idx, slab) in child.small_slabs.iter().enumerate() {
+        if !ZoneAllocator::PRUNED_BASE_CLASSES[idx] {
+            assert_eq!(slab.empty_slabs.elements, 2);
+        }
+    }
+    assert_eq!(zone.small_slabs[pruned].empty_slabs.elements, 0);
+
+    for zone in [&mut zone, &mut child] {
+        while let Some(mp) = zone.retrieve_empty_page(0) {
+            pager.release_page(mp);
+        }
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+fn zone_zero_on_free() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocatorBuilder::new(0).zero_on_free(ZeroOnFree::Objects).build().unwrap();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    // Poisoning takes precedence over zeroing
+    #[cfg(feature = "debug-poison")]
+    let scrubbed = POISON_BYTE;
+    #[cfg(not(feature = "debug-poison"))]
+    let scrubbed = 0;
+    let read = |ptr: NonNull<u8>| unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()).to_vec() };
+
+    let secret = zone.allocate(layout).unwrap();
+    let other = zone.allocate(layout).unwrap();
+    unsafe { ptr::write_bytes(secret.as_ptr(), 0x42, layout.size()) };
+    unsafe { ptr::write_bytes(other.as_ptr(), 0x42, layout.size()) };
+    zone.deallocate(secret, layout).unwrap();
+    let reused = zone.allocate(layout).unwrap();
+    assert_eq!(reused, secret);
+    assert!(read(reused).iter().all(|&b| b == scrubbed));
+    assert!(read(other).iter().all(|&b| b == 0x42));
+
+    // The whole page once it is empty
+    zone.set_zero_on_free(ZeroOnFree::ObjectsAndPages);
+    unsafe { ptr::write_bytes(reused.as_ptr(), 0x42, layout.size()) };
+    zone.deallocate(reused, layout).unwrap();
+    zone.deallocate(other, layout).unwrap();
+    let first = zone.allocate(layout).unwrap();
+    let second = zone.allocate(layout).unwrap();
+    assert!(read(first).iter().chain(read(second).iter()).all(|&b| b == scrubbed));
+
+    zone.deallocate(first, layout).unwrap();
+    zone.deallocate(second, layout).unwrap();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn zone_profiler_samples() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocatorBuilder::new(0).profiler_sample_rate(2).build().unwrap();
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    let class_size = zone.small_slabs[ZoneAllocator::size_class_index(layout).unwrap()].size();
+
+    // Failed allocations are not sampled (and don't count towards the rate)
+    for _ in 0..3 {
+        assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+    }
+    assert_eq!(zone.profiler().recorded(), 0);
+
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let objects: Vec<NonNull<u8>> = (0..5).map(|_| zone.allocate(layout).unwrap()).collect();
+    assert_eq!(zone.profiler().recorded(), 2);
+    for sample in zone.profiler().samples() {
+        assert_eq!((sample.size, sample.size_class), (40, class_size));
+        assert_eq!(sample.location.file(), file!());
+    }
+
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn for_each_allocated_visits_live_objects() {
+    let mut pager = Pager::new();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for _ in 0..3 {
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+    }
+    sa.set_quarantine(4).unwrap();
+
+    // Two full pages and a partial one, with some objects freed again
+    let objects: Vec<NonNull<u8>> = (0..2 * sa.obj_per_page + 5).map(|_| sa.allocate(layout).unwrap()).collect();
+    let mut live: HashSet<usize> = objects.iter().map(|ptr| ptr.as_ptr() as usize).collect();
+    for ptr in objects.iter().step_by(7) {
+        sa.deallocate(*ptr, layout).unwrap();
+        live.remove(&(ptr.as_ptr() as usize));
+    }
+
+    // Exactly the live objects, each once, and neither the freed nor the quarantined ones
+    let mut visited = Vec::new();
+    sa.for_each_allocated(|ptr, size| {
+        assert_eq!(size, 64);
+        visited.push(ptr.as_ptr() as usize);
+    });
+    assert_eq!(visited.len(), live.len());
+    assert_eq!(visited.into_iter().collect::<HashSet<usize>>(), live);
+
+    sa.flush_quarantine().unwrap();
+    for ptr in live {
+        sa.deallocate(NonNull::new(ptr as *mut u8).unwrap(), layout).unwrap();
+    }
+    sa.flush_quarantine().unwrap();
+    let mut visited = 0;
+    sa.for_each_allocated(|_ptr, _size| visited += 1);
+    assert_eq!(visited, 0);
+    while let Some(mp) = sa.retrieve_empty_page() {
+        pager.release_page(mp);
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+fn zone_allocate_many() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let per_page = zone.small_slabs[class].obj_per_page;
+    let single = zone.allocate(layout).unwrap();
+
+    // Only as many objects as fit into the two pages, the partial one is used up first
+    let mut out = std::vec![NonNull::dangling(); 3 * per_page];
+    let n = out.len();
+    assert_eq!(zone.allocate_many(layout, n, &mut out), Ok(2 * per_page - 1));
+    assert_eq!(zone.small_slabs[class].page_list(PageState::Full).len(), 2);
+    let objects = out[..2 * per_page - 1].to_vec();
+    let unique: HashSet<usize> = objects.iter().chain(Some(&single)).map(|ptr| ptr.as_ptr() as usize).collect();
+    assert_eq!(unique.len(), 2 * per_page);
+    for ptr in objects.iter() {
+        assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0x77, layout.size()) };
+    }
+    assert_eq!(zone.allocate_many(layout, 4, &mut out), Ok(0));
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+
+    // At most `out.len()` objects, zero-sized objects need no memory
+    let zero = Layout::from_size_align(0, 8).unwrap();
+    let mut few = [NonNull::dangling(); 2];
+    assert_eq!(zone.allocate_many(zero, 5, &mut few), Ok(2));
+    let too_big = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 8).unwrap();
+    assert_eq!(zone.allocate_many(too_big, 1, &mut few), Err(AllocationError::TooLarge));
+
+    for ptr in objects.iter().chain(Some(&single)) {
+        zone.deallocate(*ptr, layout).unwrap();
+    }
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(pager.currently_allocated(), 0);
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn sc_page_reuse_policy() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let page_of = |ptr: NonNull<u8>| ptr.as_ptr() as usize & !(ObjectPage8k::SIZE - 1);
+    for (policy, reuse_first_emptied) in [(PageReusePolicy::Lifo, false), (PageReusePolicy::Fifo, true)] {
+        let mut pager = Pager::new();
+        let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+        sa.set_page_reuse_policy(policy);
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+
+        // Two full pages that become empty one after the other
+        let objects: Vec<NonNull<u8>> = (0..2 * sa.obj_per_page).map(|_| sa.allocate(layout).unwrap()).collect();
+        let first_emptied = page_of(objects[sa.obj_per_page]);
+        let (first, second): (Vec<NonNull<u8>>, Vec<NonNull<u8>>) =
+            objects.into_iter().partition(|ptr| page_of(*ptr) == first_emptied);
+        for ptr in first.into_iter().chain(second) {
+            sa.deallocate(ptr, layout).unwrap();
+        }
+        assert_eq!(sa.page_list(PageState::Empty).len(), 2);
+
+        let ptr = sa.allocate(layout).unwrap();
+        assert_eq!(page_of(ptr) == first_emptied, reuse_first_emptied, "{:?}", policy);
+        sa.deallocate(ptr, layout).unwrap();
+        while let Some(mp) = sa.retrieve_empty_page() {
+            pager.release_page(mp);
+        }
+    }
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn sc_page_selection_policy() {
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let page_of = |ptr: NonNull<u8>| ptr.as_ptr() as usize & !(ObjectPage8k::SIZE - 1);
+    for (policy, use_fullest) in [(PageSelectionPolicy::FirstFit, false), (PageSelectionPolicy::FullestFirst, true)] {
+        let mut pager = Pager::new();
+        let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+        sa.set_page_selection_policy(policy);
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+        sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+
+        // The page filled first gets one free object, the one that served
+        // the last allocation gets five
+        let mut objects: Vec<NonNull<u8>> = (0..2 * sa.obj_per_page).map(|_| sa.allocate(layout).unwrap()).collect();
+        let fullest = page_of(objects[0]);
+        let mut freed = Vec::new();
+        freed.push(objects.remove(0));
+        freed.extend(objects.drain(objects.len() - 5..));
+        for ptr in freed {
+            sa.deallocate(ptr, layout).unwrap();
+        }
+        assert_eq!(sa.page_list(PageState::Partial).len(), 2);
+
+        let ptr = sa.allocate(layout).unwrap();
+        assert_eq!(page_of(ptr) == fullest, use_fullest, "{:?}", policy);
+        if use_fullest {
+            assert_eq!(sa.page_list(PageState::Full).len(), 1);
+        }
+        objects.push(ptr);
+        for ptr in objects {
+            sa.deallocate(ptr, layout).unwrap();
+        }
+        while let Some(mp) = sa.retrieve_empty_page() {
+            pager.release_page(mp);
+        }
+        assert_eq!(pager.currently_allocated(), 0);
+    }
+}
+
+#[test]
+fn zone_builder() {
+    let zone = ZoneAllocatorBuilder::new(4)
+        .page_selection_policy(PageSelectionPolicy::FullestFirst)
+        .page_reuse_policy(PageReusePolicy::Fifo)
+        .max_pages_per_class(8)
+        .max_pages_for_size(64, 2)
+        .total_budget_bytes(16 * ObjectPage8k::SIZE)
+        .build()
+        .unwrap();
+    assert_eq!(zone.heap_id, 4);
+    assert_eq!(zone.total_budget_bytes(), 16 * ObjectPage8k::SIZE);
+    assert_eq!(zone.small_slabs[3].max_pages(), 2);
+    assert_eq!(zone.small_slabs[4].max_pages(), 8);
+    assert_eq!(zone.small_slabs[0].page_selection_policy(), PageSelectionPolicy::FullestFirst);
+
+    let res = ZoneAllocatorBuilder::new(0)
+        .max_pages_for_size(ZoneAllocator::MAX_ALLOC_SIZE + 1, 1)
+        .build();
+    assert!(matches!(res, Err(AllocationError::TooLarge)));
+}
+
+#[test]
+fn zone_slab_empty_pages_threshold() {
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut zone = ZoneAllocatorBuilder::new(0).slab_empty_pages_threshold(1).build().unwrap();
+    assert_eq!(zone.slab_empty_pages_threshold(), 1);
+
+    // The class keeps its only empty page
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    assert!(zone.retrieve_empty_page(0).is_none());
+    assert!(!zone.retire_empty_page(0, 1));
+
+    // And gives up the second one
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+    assert_eq!(zone.pages(), 1);
+
+    zone.set_slab_empty_pages_threshold(0);
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+    assert_eq!(zone.pages(), 0);
+}
+
+#[test]
+fn const_construction() {
+    static INBOX: RemoteFreeInbox = RemoteFreeInbox::new();
+    let zone: ZoneAllocator<'static> = const { ZoneAllocator::new(1) };
+    let sc: SCAllocator<'static, ObjectPage8k<'static>> = const { SCAllocator::new(64) };
+
+    assert!(INBOX.is_empty());
+    assert_eq!(zone.heap_id, 1);
+    assert_eq!(sc.size(), 64);
+}
+
+#[cfg(not(feature = "theseus"))]
+#[test]
+fn raw_regions() {
+    let mut memory = std::vec![[0u8; ObjectPage8k::SIZE]; 2];
+    let first = NonNull::new(&mut memory[0] as *mut [u8; ObjectPage8k::SIZE]).unwrap();
+    let second = NonNull::new(&mut memory[1] as *mut [u8; ObjectPage8k::SIZE]).unwrap();
+
+    let mut mp = unsafe { MappedPages::from_raw(first) };
+    assert_eq!(mp.as_ptr(), first.as_ptr() as *mut u8);
+    assert_eq!(mp.size_in_bytes(), ObjectPage8k::SIZE);
+    assert!(mp.flags().is_writable());
+
+    assert!(MappedPages::empty().merge(unsafe { MappedPages::from_raw(second) }).is_err());
+    assert!(mp.merge(unsafe { MappedPages::from_raw(second) }).is_ok());
+    assert_eq!(mp.size_in_bytes(), 2 * ObjectPage8k::SIZE);
+}
+
+/// Pages of a static buffer serve allocations before there are `MappedPages`,
+/// and are only returned once they were handed their `MappedPages`.
+#[cfg(not(feature = "out-of-band-metadata"))]
+#[test]
+fn zone_bootstrap_buffer() {
+    const SIZE: usize = 4 * ObjectPage8k::SIZE;
+    let region: &'static mut [u8; SIZE] = std::boxed::Box::leak(std::boxed::Box::new([0u8; SIZE]));
+    let first = (region.as_ptr() as usize + ObjectPage8k::SIZE - 1) & !(ObjectPage8k::SIZE - 1);
+    let mut zone: ZoneAllocator = Default::default();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let pages = zone.refill_bootstrap(layout, region).unwrap();
+    assert!(pages >= 3);
+    assert_eq!(zone.empty_pages(), pages);
+
+    let objects: Vec<NonNull<u8>> = (0..100).map(|_| zone.allocate(layout).unwrap()).collect();
+    assert!(objects.iter().all(|ptr| (ptr.as_ptr() as usize).wrapping_sub(first) < pages * ObjectPage8k::SIZE));
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    assert!(zone.retrieve_empty_page(0).is_none());
+
+    // Hand the first page its MappedPages, only that page can be returned
+    let region = NonNull::new(first as *mut [u8; ObjectPage8k::SIZE]).unwrap();
+    zone.adopt_mapped_pages(unsafe { MappedPages::from_raw(region) }).unwrap();
+    let (err, _mp) = zone.adopt_mapped_pages(unsafe { MappedPages::from_raw(region) }).unwrap_err();
+    assert_eq!(err, AllocationError::InvalidPointer);
+    let mut pager = Pager::new();
+    let (err, mp) = zone.adopt_mapped_pages(pager.allocate_page().unwrap()).unwrap_err();
+    assert_eq!(err, AllocationError::InvalidPointer);
+    pager.release_page(mp);
+
+    let mp = zone.retrieve_empty_page(0).unwrap();
+    assert_eq!(mp.start_address().value(), first);
+    assert!(zone.retrieve_empty_page(0).is_none());
+    assert_eq!(zone.empty_pages(), pages - 1);
+}
+
+/// Fills an `SCAllocator` on 16 KiB and 64 KiB pages with its smallest objects,
+/// which need all bits of the bigger bitfields.
+#[cfg(not(feature = "red-zones"))]
+#[test]
+fn big_page_variants() {
+    fn fill<P: AllocablePage, const N: usize>() {
+        let page_layout = Layout::from_size_align(N, N).unwrap();
+        let memory = unsafe { alloc::alloc(page_layout) };
+        let region = NonNull::new(memory as *mut [u8; N]).unwrap();
+
+        let mut sa: SCAllocator<P> = SCAllocator::new(8);
+        sa.refill(unsafe { MappedPages::from_raw_pages(region) }, 0).unwrap();
+        let layout = Layout::from_size_align(8, 8).unwrap();
+        let objects: Vec<NonNull<u8>> = (0..N / 8).map_while(|_| sa.allocate(layout).ok()).collect();
+        assert_eq!(objects.len(), sa.obj_per_page);
+        assert!(sa.obj_per_page > 8 * 64);
+        assert_eq!(sa.allocate(layout), Err(AllocationError::OutOfMemory));
+
+        for ptr in objects {
+            sa.deallocate(ptr, layout).unwrap();
+        }
+        let mp = sa.retrieve_empty_page().unwrap();
+        assert_eq!(mp.size_in_bytes(), N);
+        unsafe { alloc::dealloc(memory, page_layout) };
+    }
+
+    fill::<ObjectPage16k, { ObjectPage16k::SIZE }>();
+    fill::<ObjectPage64k, { ObjectPage64k::SIZE }>();
+}
+
+#[test]
+#[cfg(not(feature = "red-zones"))]
+fn zone_size_of_allocation() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let big_layout = Layout::from_size_align(3000, 8).unwrap();
+    zone.refill(big_layout, pager.allocate_page().unwrap()).unwrap();
+
+    let ptr = zone.allocate(layout).unwrap();
+    assert_eq!(zone.size_of_allocation(ptr), Some(128));
+    let big = zone.allocate(big_layout).unwrap();
+    assert_eq!(zone.size_of_allocation(big), Some(4096));
+
+    // Interior pointers, free slots and foreign memory have no size
+    assert_eq!(zone.size_of_allocation(NonNull::new(unsafe { ptr.as_ptr().add(8) }).unwrap()), None);
+    assert_eq!(zone.size_of_allocation(NonNull::new(unsafe { ptr.as_ptr().add(128) }).unwrap()), None);
+    let mut foreign = 0u64;
+    assert_eq!(zone.size_of_allocation(NonNull::from(&mut foreign).cast()), None);
+
+    zone.deallocate(ptr, layout).unwrap();
+    assert_eq!(zone.size_of_allocation(ptr), None);
+    zone.deallocate(big, big_layout).unwrap();
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+/// Class sizes can be computed at compile time, e.g., to size static reserves.
+#[test]
+fn const_layout_helpers() {
+    const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(100, 8) };
+    const CLASS: Option<usize> = ZoneAllocator::size_class_index(LAYOUT);
+    const CLASS_SIZE: Option<usize> = ZoneAllocator::get_max_size(ZoneAllocator::effective_layout(LAYOUT).size());
+    assert_eq!(CLASS, Some(4));
+    assert_eq!(CLASS_SIZE, Some(128));
+
+    // Over-aligned objects get a slot of (at least) their alignment
+    let over_aligned = Layout::from_size_align(8, 512).unwrap();
+    let effective = ZoneAllocator::effective_layout(over_aligned);
+    assert!(effective.size() >= 512);
+    assert_eq!(effective.align(), 512);
+    assert_eq!(ZoneAllocator::get_max_size(effective.size()).unwrap() % 512, 0);
+
+    let too_large = Layout::from_size_align(ZoneAllocator::MAX_ALLOC_SIZE + 1, 8).unwrap();
+    assert_eq!(ZoneAllocator::size_class_index(too_large), None);
+}
+
+#[test]
+fn zone_resize_in_place() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(90, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+
+    let grown = Layout::from_size_align(100, 8).unwrap();
+    zone.grow_in_place(ptr, layout, grown).unwrap();
+    unsafe { ptr::write_bytes(ptr.as_ptr(), 0xab, grown.size()) };
+
+    // Layouts of other size classes (or the wrong direction) need a new object
+    let too_big = Layout::from_size_align(200, 8).unwrap();
+    assert_eq!(zone.grow_in_place(ptr, grown, too_big), Err(AllocationError::InvalidLayout));
+    let too_small = Layout::from_size_align(40, 8).unwrap();
+    assert_eq!(zone.shrink_in_place(ptr, grown, too_small), Err(AllocationError::InvalidLayout));
+    assert_eq!(zone.shrink_in_place(ptr, grown, too_big), Err(AllocationError::InvalidLayout));
+    let over_aligned = Layout::from_size_align(100, 4096).unwrap();
+    assert_eq!(zone.grow_in_place(ptr, grown, over_aligned), Err(AllocationError::InvalidLayout));
+
+    let shrunk = Layout::from_size_align(70, 8).unwrap();
+    zone.shrink_in_place(ptr, grown, shrunk).unwrap();
+    // Only live objects of the zone can be resized
+    let inside = NonNull::new(unsafe { ptr.as_ptr().add(8) }).unwrap();
+    assert_eq!(zone.grow_in_place(inside, shrunk, grown), Err(AllocationError::InvalidPointer));
+    zone.deallocate(ptr, shrunk).unwrap();
+    assert_eq!(zone.grow_in_place(ptr, shrunk, grown), Err(AllocationError::InvalidPointer));
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn zone_iter_pages() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let small = Layout::from_size_align(64, 8).unwrap();
+    let big = Layout::from_size_align(4096, 8).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(big, pager.allocate_page().unwrap()).unwrap();
+    assert_eq!(zone.iter_pages().count(), zone.pages());
+
+    let objects: Vec<NonNull<u8>> = (0..5).map(|_| zone.allocate(small).unwrap()).collect();
+    let big_object = zone.allocate(big).unwrap();
+    let pages: Vec<PageInfo> = zone.iter_pages().collect();
+    assert_eq!(pages.len(), 3);
+    assert_eq!(pages.iter().map(|page| page.live_objects).sum::<usize>(), 6);
+    for page in pages.iter() {
+        assert_eq!(page.heap_id, zone.heap_id);
+        assert_eq!(page.page_size, ObjectPage8k::SIZE);
+        assert_eq!(page.start % ObjectPage8k::SIZE, 0);
+        let expected = match page.live_objects {
+            0 => PageState::Empty,
+            n if n == page.capacity => PageState::Full,
+            _ => PageState::Partial,
+        };
+        assert_eq!(page.state, expected);
+    }
+    let small_pages: Vec<&PageInfo> = pages.iter().filter(|page| page.object_size < big.size()).collect();
+    assert_eq!(small_pages.len(), 2);
+    assert_eq!(small_pages.iter().filter(|page| page.state == PageState::Empty).count(), 1);
+
+    for ptr in objects {
+        zone.deallocate(ptr, small).unwrap();
+    }
+    zone.deallocate(big_object, big).unwrap();
+    assert!(zone.iter_pages().all(|page| page.state == PageState::Empty && page.live_objects == 0));
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+    assert_eq!(zone.iter_pages().count(), 0);
+}
+
+#[test]
+fn slab_registry() {
+    let registry = SlabRegistry::new();
+    let mut pager = Pager::new();
+    let mut sa: SCAllocator<ObjectPage8k> = SCAllocator::new(64);
+    sa.set_name("test-64");
+    unsafe { registry.register(&sa).unwrap() };
+    sa.refill(pager.allocate_page().unwrap(), 0).unwrap();
+
+    let info = registry.iter().next().unwrap();
+    assert_eq!(info.name, "test-64");
+    assert_eq!(info.object_size, 64);
+    assert_eq!(info.stats, sa.stats());
+    assert!(registry.unregister(&sa));
+    assert!(!registry.unregister(&sa));
+    assert!(registry.is_empty());
+
+    let mut zone: ZoneAllocator = Default::default();
+    unsafe { zone.register_slabs(&registry).unwrap() };
+    assert_eq!(registry.len(), ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES);
+    assert!(registry.iter().map(|info| info.name).eq(ZoneAllocator::CLASS_NAMES.iter().copied()));
+    // The registry is full before all classes of a second zone are in
+    let mut zones: Vec<ZoneAllocator> = (0..SLAB_REGISTRY_CAPACITY / registry.len()).map(ZoneAllocator::new).collect();
+    let res: Result<(), AllocationError> = zones.iter_mut().try_for_each(|zone| unsafe { zone.register_slabs(&registry) });
+    assert_eq!(res, Err(AllocationError::LimitExceeded));
+    for zone in zones.iter() {
+        zone.unregister_slabs(&registry);
+    }
+    zone.unregister_slabs(&registry);
+    assert!(registry.is_empty());
+
+    pager.release_page(sa.retrieve_empty_page().unwrap());
+}
+
+#[test]
+fn heap_event_display() {
+    let refilled = HeapEvent::PageRefilled { heap_id: 1, object_size: 64, page: 0x4000 };
+    assert_eq!(format!("{}", refilled), "heap 1: page 0x4000 refilled size class 64");
+    let returned = HeapEvent::PageReturned { heap_id: 1, object_size: 64, page: 0x4000 };
+    assert_eq!(format!("{}", returned), "heap 1: page 0x4000 returned from size class 64");
+    let oom = HeapEvent::OutOfMemory { heap_id: 2, size: 100, align: 8 };
+    assert_eq!(format!("{}", oom), "heap 2: out of memory for 100 bytes (align 8)");
+}
+
+#[test]
+#[cfg(not(feature = "minimal"))]
+fn zone_size_class_stats() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let objects: Vec<NonNull<u8>> = (0..3).map(|_| zone.allocate(layout).unwrap()).collect();
+
+    let object_size = ZoneAllocator::get_max_size(ZoneAllocator::slot_size(layout)).unwrap();
+    let class = ZoneAllocator::BASE_ALLOC_SIZES.iter().position(|&size| size == object_size).unwrap();
+    let stats = zone.size_class_stats();
+    assert_eq!(stats.len(), ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES);
+    assert_eq!(stats[class].object_size, object_size);
+    assert_eq!(stats[class].allocations, 3);
+    assert_eq!(stats[class].requested_bytes, 300);
+    assert_eq!(stats[class].padding_bytes(), 3 * object_size - 300);
+    assert_eq!(stats[class].pages, 1);
+    assert_eq!(stats[class].page_bytes, ObjectPage8k::SIZE);
+    for (idx, stat) in stats.iter().enumerate().filter(|&(idx, _)| idx != class) {
+        assert_eq!((stat.allocations, stat.pages), (0, 0), "Size class {} is not empty", idx);
+    }
+    assert_eq!(stats[ZoneAllocator::MAX_BASE_SIZE_CLASSES].object_size, ZoneAllocator::LARGE_ALLOC_SIZES[0]);
+
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn zone_utilization() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    assert_eq!(zone.utilization(), Utilization::default());
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let objects: Vec<NonNull<u8>> = (0..5).map(|_| zone.allocate(layout).unwrap()).collect();
+
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    let (size, per_page) = (zone.small_slabs[idx].size(), zone.small_slabs[idx].obj_per_page);
+    let utilization = zone.utilization();
+    assert_eq!(utilization.live_bytes, 5 * size);
+    assert_eq!(utilization.capacity_bytes, 2 * per_page * size);
+    assert_eq!(utilization.pages, 2);
+    assert_eq!(utilization.pages, zone.pages());
+
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    assert_eq!(zone.utilization().live_bytes, 0);
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+#[cfg(not(feature = "minimal"))]
+fn zone_stats_to_bytes() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(100, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+
+    let mut buf = [0u8; STATS_EXPORT_SIZE];
+    assert_eq!(zone.stats_to_bytes(&mut buf[..STATS_EXPORT_SIZE - 1]), 0);
+    assert_eq!(zone.stats_to_bytes(&mut buf), STATS_EXPORT_SIZE);
+    let u16_at = |offset: usize| u16::from_le_bytes([buf[offset], buf[offset + 1]]);
+    let u64_at = |offset: usize| {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&buf[offset..offset + 8]);
+        u64::from_le_bytes(bytes) as usize
+    };
+    assert_eq!(&buf[..4], &STATS_MAGIC);
+    assert_eq!(u16_at(4), STATS_FORMAT_VERSION);
+    assert_eq!(u16_at(6) as usize, STATS_RECORD_SIZE);
+    assert_eq!(buf[8] as usize, ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES);
+    let utilization = zone.utilization();
+    assert_eq!(u64_at(16), utilization.live_bytes);
+    assert_eq!(u64_at(24), utilization.capacity_bytes);
+    assert_eq!(u64_at(32), 1);
+
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    let stats = zone.size_class_stats()[class];
+    let record = STATS_HEADER_SIZE + class * STATS_RECORD_SIZE;
+    assert_eq!(u64_at(record), stats.object_size);
+    assert_eq!(u64_at(record + 16), 1);
+    assert_eq!(u64_at(record + 24), 100);
+    assert_eq!(u64_at(record + 32), 1);
+    assert_eq!(u64_at(record + 40), ObjectPage8k::SIZE);
+    assert_eq!(u64_at(record + 48), stats.page_transitions);
+
+    zone.deallocate(ptr, layout).unwrap();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn zone_stats_display() {
+    assert_eq!(format!("{}", ByteSize(1000)), "1000 B");
+    assert_eq!(format!("{}", ByteSize(1536)), "1.5 KiB");
+    assert_eq!(format!("{:>9}", ByteSize(3 * 1024 * 1024)), "  3.0 MiB");
+
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let objects: Vec<NonNull<u8>> = (0..20).map(|_| zone.allocate(layout).unwrap()).collect();
+
+    let stats = zone.stats();
+    let class = ZoneAllocator::size_class_index(layout).unwrap();
+    assert_eq!(
+        (stats.classes[class].empty_pages, stats.classes[class].partial_pages, stats.classes[class].full_pages),
+        (1, 1, 0)
+    );
+    assert_eq!(stats.total, zone.utilization());
+    let table = format!("{}", stats);
+    let lines: Vec<&str> = table.lines().collect();
+    // The header, the only class with pages and the total
+    assert_eq!(lines.len(), 3);
+    assert!(lines.iter().all(|line| line.len() == lines[0].len()), "Columns are not aligned:\n{}", table);
+    assert!(lines[1].contains("   1/   1/    0"));
+    assert!(lines[2].starts_with("  total"));
+
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+/// Remembers the watermarks that were crossed.
+struct Crossings(std::sync::Mutex<Vec<(Watermark, usize)>>);
+
+impl WatermarkListener for Crossings {
+    fn crossed(&self, watermark: Watermark, utilization: Utilization) {
+        self.0.lock().unwrap().push((watermark, utilization.live_bytes));
+    }
+}
+
+#[test]
+fn zone_watermarks() {
+    let mut pager = Pager::new();
+    let crossings = Crossings(std::sync::Mutex::new(Vec::new()));
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    let size = ZoneAllocator::new(0).small_slabs[idx].size();
+    let mut zone = ZoneAllocatorBuilder::new(0)
+        .watermarks(&crossings, 4 * size, 10 * size, 2)
+        .build()
+        .unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    let mut objects: Vec<NonNull<u8>> = (0..9).map(|_| zone.allocate(layout).unwrap()).collect();
+    assert!(crossings.0.lock().unwrap().is_empty());
+    // The watermarks are checked after every other operation, the 10th object reaches the high one
+    objects.push(zone.allocate(layout).unwrap());
+    assert_eq!(*crossings.0.lock().unwrap(), [(Watermark::High, 10 * size)]);
+    // Staying above the watermark is not reported again
+    objects.push(zone.allocate(layout).unwrap());
+    objects.push(zone.allocate(layout).unwrap());
+    assert_eq!(crossings.0.lock().unwrap().len(), 1);
+
+    while objects.len() > 4 {
+        zone.deallocate(objects.pop().unwrap(), layout).unwrap();
+    }
+    assert_eq!(crossings.0.lock().unwrap()[1], (Watermark::Low, 4 * size));
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    assert_eq!(crossings.0.lock().unwrap().len(), 2);
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+/// A caller that counts its live objects.
+struct TaskContext {
+    interrupt_depth: usize,
+    hint: AllocHint,
+    live: core::cell::Cell<usize>,
+}
+
+impl AllocationContext for TaskContext {
+    fn interrupt_depth(&self) -> usize {
+        self.interrupt_depth
+    }
+
+    fn alloc_hint(&self) -> AllocHint {
+        self.hint
+    }
+
+    fn on_allocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        self.live.set(self.live.get() + 1);
+    }
+
+    fn on_deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        self.live.set(self.live.get() - 1);
+    }
+}
+
+#[test]
+fn zone_allocation_context() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let idx = ZoneAllocator::size_class_index(layout).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    // An interrupt handler can't start the empty page
+    let irq = TaskContext { interrupt_depth: 1, hint: AllocHint::ShortLived, live: core::cell::Cell::new(0) };
+    assert_eq!(zone.allocate_in(layout, &irq), Err(AllocationError::WouldBlock));
+    assert_eq!(irq.live.get(), 0);
+
+    let task = TaskContext { interrupt_depth: 0, hint: AllocHint::LongLived, live: core::cell::Cell::new(0) };
+    let objects: Vec<NonNull<u8>> = (0..3).map(|_| zone.allocate_in(layout, &task).unwrap()).collect();
+    assert_eq!(task.live.get(), 3);
+    assert_eq!(zone.small_slabs[idx].page_list(PageState::LongLived).len(), 1);
+    // Without a context, objects go to the pages of short-lived objects
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate_in(layout, &()).unwrap();
+    assert_eq!(zone.small_slabs[idx].page_list(PageState::Partial).len(), 1);
+
+    for ptr in objects {
+        zone.deallocate_in(ptr, layout, &task).unwrap();
+    }
+    assert_eq!(task.live.get(), 0);
+    zone.deallocate_in(ptr, layout, &()).unwrap();
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+/// A caller in an interrupt handler on core 2 that runs task 7.
+struct CoreContext;
+
+impl AllocationContext for CoreContext {
+    fn core_id(&self) -> Option<usize> {
+        Some(2)
+    }
+
+    fn interrupt_depth(&self) -> usize {
+        1
+    }
+
+    fn task_id(&self) -> Option<usize> {
+        Some(7)
+    }
+}
+
+/// Serves the large layouts of the system allocator and remembers the core of the last caller.
+struct CoreFallback(core::sync::atomic::AtomicUsize);
+
+impl FallbackAllocator for CoreFallback {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        FallbackAllocator::allocate(&SYSTEM_FALLBACK, layout)
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        FallbackAllocator::deallocate(&SYSTEM_FALLBACK, ptr, layout)
+    }
+
+    fn allocate_in(&self, layout: Layout, ctx: &dyn AllocationContext) -> Result<NonNull<u8>, AllocationError> {
+        self.0.store(ctx.core_id().unwrap_or(usize::MAX), Ordering::Relaxed);
+        self.allocate(layout)
+    }
+}
+
+#[test]
+fn zone_allocation_context_forwarding() {
+    static FALLBACK: CoreFallback = CoreFallback(core::sync::atomic::AtomicUsize::new(0));
+    let mut pager = Pager::new();
+    let mut buffer = [TraceEvent::EMPTY; 8];
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_fallback(&FALLBACK);
+    zone.start_trace(&mut buffer);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+
+    // The events of the context carry its core and task, the others don't
+    let other = zone.allocate_in(layout, &CoreContext).unwrap();
+    zone.deallocate_in(other, layout, &CoreContext).unwrap();
+    zone.deallocate(ptr, layout).unwrap();
+    let events: Vec<TraceEvent> = zone.trace().unwrap().events().copied().collect();
+    assert_eq!(events.len(), 5);
+    assert!(events[..2].iter().all(|event| event.core_id.is_none() && event.task_id.is_none()));
+    assert!(events[2..4].iter().all(|event| event.core_id == Some(2) && event.task_id == Some(7)));
+    assert_eq!(events[4].core_id, None);
+
+    // Even within an interrupt handler, large layouts go to the fallback with the context
+    let large = Layout::from_size_align(2 * ZoneAllocator::MAX_ALLOC_SIZE, 8).unwrap();
+    let ptr = zone.allocate_in(large, &CoreContext).unwrap();
+    assert_eq!(FALLBACK.0.load(Ordering::Relaxed), 2);
+    zone.deallocate_in(ptr, large, &CoreContext).unwrap();
+
+    zone.stop_trace();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+/// Objects aligned to more than their size are served by the size class of their alignment.
+#[cfg(not(feature = "red-zones"))]
+#[test]
+fn zone_over_aligned_objects() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let aligned = Layout::from_size_align(8, 64).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(aligned, pager.allocate_page().unwrap()).unwrap();
+
+    // In the 8 byte class only every 8th slot would be aligned
+    let objects: Vec<NonNull<u8>> = (0..100).map(|_| zone.allocate(aligned).unwrap()).collect();
+    assert!(objects.iter().all(|ptr| (ptr.as_ptr() as usize).is_multiple_of(64)));
+    let object = zone.allocate(small).unwrap();
+    #[cfg(not(feature = "minimal"))]
+    {
+        let stats = zone.size_class_stats();
+        assert_eq!(stats[0].allocations, 1);
+        assert_eq!(stats[3].allocations, 100);
+    }
+
+    for ptr in objects {
+        zone.deallocate(ptr, aligned).unwrap();
+    }
+    zone.deallocate(object, small).unwrap();
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+/// Objects of pruned size classes (see the `prune-*-class` features) go to the next larger class.
+#[test]
+fn zone_pruned_size_classes() {
+    for size in 1..=ZoneAllocator::MAX_BASE_ALLOC_SIZE {
+        match ZoneAllocator::get_slab(size) {
+            Slab::Base(idx) => {
+                assert!(!ZoneAllocator::PRUNED_BASE_CLASSES[idx], "Size {} went to a pruned class", size);
+                assert!(ZoneAllocator::BASE_ALLOC_SIZES[idx] >= size);
+                assert_eq!(ZoneAllocator::get_max_size(size), Some(ZoneAllocator::BASE_ALLOC_SIZES[idx]));
+            }
+            _ => unreachable!("Size {} is served by the base size classes", size),
+        }
+    }
+
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(1000, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+    let first = ZoneAllocator::BASE_ALLOC_SIZES.iter().position(|&size| size >= ZoneAllocator::slot_size(layout)).unwrap();
+    let serving = (first..ZoneAllocator::MAX_BASE_SIZE_CLASSES).find(|&idx| !ZoneAllocator::PRUNED_BASE_CLASSES[idx]).unwrap();
+    let stats = zone.size_class_stats();
+    for (idx, stat) in stats.iter().enumerate().take(ZoneAllocator::MAX_BASE_SIZE_CLASSES) {
+        assert_eq!(stat.pruned, ZoneAllocator::PRUNED_BASE_CLASSES[idx]);
+        if !cfg!(feature = "minimal") {
+            assert_eq!(stat.allocations, (idx == serving) as usize);
+        }
+    }
+    zone.deallocate(ptr, layout).unwrap();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[test]
+fn zone_physical_address_of() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let phys = 0x4000_0000;
+    let mp = pager.allocate_page().unwrap().with_physical_address(PhysicalAddress::new(phys));
+    zone.refill(layout, mp).unwrap();
+
+    let objects: Vec<NonNull<u8>> = (0..3).map(|_| zone.allocate(layout).unwrap()).collect();
+    for &ptr in objects.iter() {
+        let offset = ptr.as_ptr() as usize % ObjectPage8k::SIZE;
+        assert_eq!(zone.physical_address_of(ptr), Some(PhysicalAddress::new(phys + offset)));
+    }
+
+    // Pages without a known physical address and memory of other zones can't be translated
+    let other = Layout::from_size_align(128, 8).unwrap();
+    zone.refill(other, pager.allocate_page().unwrap()).unwrap();
+    let unknown = zone.allocate(other).unwrap();
+    assert_eq!(zone.physical_address_of(unknown), None);
+    let mut foreign = 0u64;
+    assert_eq!(zone.physical_address_of(NonNull::from(&mut foreign).cast()), None);
+
+    for ptr in objects {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    zone.deallocate(unknown, other).unwrap();
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn zone_required_flags() {
+    let mut zone: ZoneAllocator = Default::default();
+    let mut pager = Pager::new();
+    let dma = Layout::from_size_align(256, 8).unwrap();
+    let no_cache = RegionFlags::DEFAULT | RegionFlags::NO_CACHE;
+    zone.set_required_flags_for(dma, RegionFlags::NO_CACHE).unwrap();
+    assert_eq!(zone.required_flags_for(dma), Some(RegionFlags::NO_CACHE));
+    assert_eq!(
+        zone.set_required_flags_for(Layout::from_size_align(1 << 20, 8).unwrap(), RegionFlags::NO_CACHE),
+        Err(AllocationError::TooLarge)
+    );
+
+    // Cached pages are rejected by the DMA class, but still accepted by the others
+    assert!(matches!(zone.refill(dma, pager.allocate_page().unwrap()), Err(AllocationError::InvalidPage(_))));
+    let other = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(other, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(dma, pager.allocate_page().unwrap().with_flags(no_cache)).unwrap();
+
+    let buffer = zone.allocate(dma).unwrap();
+    let object = zone.allocate(other).unwrap();
+    assert_eq!(zone.page_flags_of(buffer), Some(no_cache));
+    assert_eq!(zone.page_flags_of(object), Some(RegionFlags::DEFAULT));
+    let mut foreign = 0u64;
+    assert_eq!(zone.page_flags_of(NonNull::from(&mut foreign).cast()), None);
+
+    zone.deallocate(buffer, dma).unwrap();
+    zone.deallocate(object, other).unwrap();
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+#[test]
+fn zone_large_objects() {
+    let mut zone: ZoneAllocator = Default::default();
+    let layout = Layout::from_size_align(12 * 1024, 8).unwrap();
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+
+    // A single 8 KiB page is too small for the slab
+    let mut pager = Pager::new();
+    let page = pager.allocate_page().unwrap();
+    assert!(matches!(zone.refill(layout, page), Err(AllocationError::InvalidPage(_))));
+
+    let slab_layout = Layout::from_size_align(ObjectPage32k::SIZE, ObjectPage32k::SIZE).unwrap();
+    let memory = unsafe { alloc::alloc(slab_layout) };
+    let region = NonNull::new(memory as *mut [u8; ObjectPage32k::SIZE]).unwrap();
+    zone.refill(layout, unsafe { MappedPages::from_raw_pages(region) }).unwrap();
+    assert_eq!(zone.pages(), 1);
+
+    let a = zone.allocate(layout).unwrap();
+    let b = zone.allocate(layout).unwrap();
+    for &ptr in [a, b].iter() {
+        let addr = ptr.as_ptr() as usize;
+        assert!(addr >= memory as usize && addr + layout.size() <= memory as usize + ObjectPage32k::SIZE);
+        unsafe { ptr::write_bytes(ptr.as_ptr(), 0xab, layout.size()) };
+    }
+    assert_eq!(zone.verify(), Ok(()));
+
+    zone.deallocate(a, layout).unwrap();
+    zone.deallocate(b, layout).unwrap();
+    assert_eq!(zone.verify(), Ok(()));
+    assert_eq!(zone.retrieve_empty_page(0).map(|mp| mp.size_in_bytes()), None);
+    let mp = zone.retrieve_empty_large_page(0).unwrap();
+    assert_eq!(mp.start_address().value(), memory as usize);
+    assert_eq!(mp.size_in_bytes(), ObjectPage32k::SIZE);
+    unsafe { alloc::dealloc(memory, slab_layout) };
+}
+
+#[cfg(all(feature = "out-of-band-metadata", not(feature = "red-zones")))]
+#[test]
+fn out_of_band_metadata() {
+    assert_eq!(ZoneAllocator::MAX_BASE_ALLOC_SIZE, 8192);
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(8192, 8).unwrap();
+    let page = pager.allocate_page().unwrap();
+    let start = page.start_address().value();
+    zone.refill(layout, page).unwrap();
+
+    // The object takes up the whole page, overwriting it leaves the meta-data intact
+    let ptr = zone.allocate(layout).unwrap();
+    assert_eq!(ptr.as_ptr() as usize, start);
+    unsafe { ptr::write_bytes(ptr.as_ptr(), 0xff, layout.size()) };
+    assert_eq!(zone.verify(), Ok(()));
+    zone.deallocate(ptr, layout).unwrap();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+
+    // Pages outside of the arena have no meta-data
+    let outside = unsafe { alloc::alloc(Layout::from_size_align(8192, 8192).unwrap()) };
+    assert!(!METADATA_ARENA.covers(outside as usize));
+    let mp = unsafe { MappedPages::from_raw(NonNull::new(outside as *mut [u8; 8192]).unwrap()) };
+    assert!(matches!(zone.refill(layout, mp), Err(AllocationError::InvalidPage(_))));
+    unsafe { alloc::dealloc(outside, Layout::from_size_align(8192, 8192).unwrap()) };
+}
+
+/// Page and slot lookups only mask off the low bits of an address,
+/// so they work for higher half (sign-extended) addresses, e.g., with Sv39 and Sv48.
+#[test]
+fn higher_half_addresses() {
+    let sv39 = 0xFFFF_FFC0_0000_0000usize;
+    let sv48 = 0xFFFF_8000_0000_0000usize;
+    for &base in [sv39, sv48].iter() {
+        let region = NonNull::new((base + 4 * ObjectPage8k::SIZE) as *mut [u8; ObjectPage8k::SIZE]).unwrap();
+        let mp = unsafe { MappedPages::from_raw(region) };
+        assert_eq!(mp.start_address().value(), base + 4 * ObjectPage8k::SIZE);
+
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let event = TraceEvent::new(TraceOp::Allocate, 3, layout, mp.start_address().value() + 5 * 64);
+        assert_eq!(event.page, mp.start_address().value());
+        assert_eq!(event.slot, 5);
+    }
+}
+
+#[test]
+fn trace_record_replay() {
+    let mut buffer = [TraceEvent::EMPTY; 256];
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    zone.start_trace(&mut buffer);
+
+    let small = Layout::from_size_align(24, 8).unwrap();
+    let big = Layout::from_size_align(3000, 64).unwrap();
+    zone.refill(small, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(big, pager.allocate_page().unwrap()).unwrap();
+    let mut objects = Vec::new();
+    for i in 0..40 {
+        let layout = if i % 4 == 0 { big } else { small };
+        if let Ok(ptr) = zone.allocate(layout) {
+            objects.push((ptr, layout));
+        }
+    }
+    // Every other object (including the first big one), newest first
+    for (_, (ptr, layout)) in objects.drain(..).enumerate().filter(|(i, _)| i % 2 == 0).rev() {
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    let ptr = zone.allocate(small).unwrap();
+    zone.deallocate(ptr, small).unwrap();
+
+    let trace = zone.stop_trace().unwrap();
+    assert!(trace.is_complete());
+    let events: Vec<TraceEvent> = trace.events().cloned().collect();
+    assert_eq!(events[0].op, TraceOp::Refill);
+    assert!(events.iter().any(|e| e.op == TraceOp::Deallocate && e.size_class == 9));
+
+    let mut replay_pager = Pager::new();
+    let mut replay_zone = ZoneAllocator::new(0);
+    let mut page_map = [(0, 0); 8];
+    let mut replayer = TraceReplayer::new(&mut page_map);
+    replayer
+        .replay(&mut replay_zone, events.iter(), &mut replay_pager)
+        .unwrap();
+    assert_eq!(replayer.replayed(), events.len());
+    assert_eq!(replay_zone.pages(), zone.pages());
+    replay_zone.verify().unwrap();
+
+    // An object in a slot the replay doesn't produce is reported
+    let mut tampered = events.clone();
+    let idx = tampered.iter().position(|e| e.op == TraceOp::Allocate).unwrap();
+    tampered[idx].slot += 1;
+    let mut page_map = [(0, 0); 8];
+    let mut replayer = TraceReplayer::new(&mut page_map);
+    let res = replayer.replay(&mut ZoneAllocator::new(0), tampered.iter(), &mut replay_pager);
+    assert!(matches!(res, Err(AllocationError::HeapCorrupted(_))));
+    assert_eq!(replayer.replayed(), idx);
+}
+
+/// Records the calls of the shadow hooks.
+#[cfg(feature = "shadow-hooks")]
+struct ShadowLog(std::sync::Mutex<Vec<(TraceOp, usize, usize)>>);
+
+#[cfg(feature = "shadow-hooks")]
+impl ShadowHooks for ShadowLog {
+    fn on_alloc(&self, ptr: NonNull<u8>, size: usize) {
+        self.0.lock().unwrap().push((TraceOp::Allocate, ptr.as_ptr() as usize, size));
+    }
+    fn on_free(&self, ptr: NonNull<u8>, size: usize) {
+        self.0.lock().unwrap().push((TraceOp::Deallocate, ptr.as_ptr() as usize, size));
+    }
+    fn on_page_added(&self, start: usize, size: usize) {
+        self.0.lock().unwrap().push((TraceOp::Refill, start, size));
+    }
+    fn on_page_removed(&self, start: usize, size: usize) {
+        self.0.lock().unwrap().push((TraceOp::ReleasePage, start, size));
+    }
+}
+
+#[cfg(feature = "shadow-hooks")]
+#[test]
+fn shadow_hooks() {
+    let mut pager = Pager::new();
+    let log = ShadowLog(std::sync::Mutex::new(Vec::new()));
+    let mut zone = ZoneAllocatorBuilder::new(0).shadow_hooks(&log).build().unwrap();
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    let mp = pager.allocate_page().unwrap();
+    let page = mp.start_address().value();
+    zone.refill(layout, mp).unwrap();
+    let ptr = zone.allocate(layout).unwrap();
+    let grown = Layout::from_size_align(48, 8).unwrap();
+    zone.grow_in_place(ptr, layout, grown).unwrap();
+    zone.deallocate(ptr, grown).unwrap();
+    // A failed free is not reported
+    assert!(zone.deallocate(ptr, layout).is_err());
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+
+    let addr = ptr.as_ptr() as usize;
+    assert_eq!(
+        *log.0.lock().unwrap(),
+        [
+            (TraceOp::Refill, page, ObjectPage8k::SIZE),
+            (TraceOp::Allocate, addr, 40),
+            (TraceOp::Deallocate, addr, 40),
+            (TraceOp::Allocate, addr, 48),
+            (TraceOp::Deallocate, addr, 48),
+            (TraceOp::ReleasePage, page, ObjectPage8k::SIZE),
+        ]
+    );
+}
+
+#[cfg(feature = "mte")]
+#[cfg(not(feature = "red-zones"))]
+#[test]
+fn memory_tagging() {
+    let addr = 0x1234_5670;
+    assert_eq!(tag_of(with_tag(addr, 0xa)), 0xa);
+    assert_eq!(untagged(with_tag(addr, 0xa)), addr);
+
+    // The host has no tag memory, so the objects are only tagged in their pointers (and not touched)
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_memory_tagging(true);
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    let tiny = Layout::from_size_align(8, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+    zone.refill(tiny, pager.allocate_page().unwrap()).unwrap();
+
+    let ptr = zone.allocate(layout).unwrap();
+    assert_ne!(tag_of(ptr.as_ptr() as usize), 0);
+    assert_eq!(zone.size_of_allocation(ptr), Some(64));
+    let grown = Layout::from_size_align(64, 8).unwrap();
+    zone.grow_in_place(ptr, layout, grown).unwrap();
+    // Objects of the 8 byte class share their granules, they stay untagged
+    let small = zone.allocate(tiny).unwrap();
+    assert_eq!(tag_of(small.as_ptr() as usize), 0);
+
+    zone.deallocate(ptr, grown).unwrap();
+    zone.deallocate(small, tiny).unwrap();
+    assert_eq!(zone.verify(), Ok(()));
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+#[cfg(feature = "generations")]
+#[test]
+fn generation_counters() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    let (ptr, generation) = zone.allocate_with_generation(layout).unwrap();
+    assert!(zone.validate_handle(ptr, generation));
+    assert!(!zone.validate_handle(ptr, generation.wrapping_add(1)));
+    zone.deallocate(ptr, layout).unwrap();
+    assert!(!zone.validate_handle(ptr, generation));
+
+    // The slot is handed out again, the stale handle stays invalid
+    let (again, next) = zone.allocate_with_generation(layout).unwrap();
+    assert_eq!(again, ptr);
+    assert_eq!(next, generation.wrapping_add(1));
+    assert!(!zone.validate_handle(ptr, generation));
+    assert!(zone.validate_handle(again, next));
+
+    // Objects of the unlocked path are bumped as well
+    unsafe { ZoneAllocator::deallocate_unlocked(again, layout).unwrap() };
+    assert!(!zone.validate_handle(again, next));
+
+    let mut other = ZoneAllocator::new(1);
+    assert!(!other.validate_handle(ptr, next.wrapping_add(1)));
+    while let Some(mp) = zone.retrieve_empty_page(0) {
+        pager.release_page(mp);
+    }
+}
+
+/// Zeroing an empty page leaves the generation table alone.
+#[cfg(feature = "generations")]
+#[test]
+fn generations_survive_zeroed_pages() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    zone.set_zero_on_free(ZeroOnFree::ObjectsAndPages);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    // `pin` keeps the page from emptying while the generation of the next slot goes up
+    let pin = zone.allocate(layout).unwrap();
+    let (ptr, first) = zone.allocate_with_generation(layout).unwrap();
+    zone.deallocate(ptr, layout).unwrap();
+    for _ in 0..2 {
+        let ptr = zone.allocate(layout).unwrap();
+        zone.deallocate(ptr, layout).unwrap();
+    }
+    let (ptr, generation) = zone.allocate_with_generation(layout).unwrap();
+    assert_eq!(generation, first.wrapping_add(3));
+    unsafe { ptr::write_bytes(ptr.as_ptr(), 0xab, layout.size()) };
+    // The page empties out and is zeroed
+    zone.deallocate(pin, layout).unwrap();
+    zone.deallocate(ptr, layout).unwrap();
+    assert!(!zone.validate_handle(ptr, generation));
+
+    let pin = zone.allocate(layout).unwrap();
+    let (again, next) = zone.allocate_with_generation(layout).unwrap();
+    assert_eq!(again, ptr);
+    assert_eq!(next, generation.wrapping_add(1));
+    assert!(!zone.validate_handle(ptr, generation));
+    #[cfg(not(feature = "debug-poison"))]
+    assert!(unsafe { core::slice::from_raw_parts(again.as_ptr(), layout.size()) }.iter().all(|&b| b == 0));
+
+    zone.deallocate(pin, layout).unwrap();
+    zone.deallocate(again, layout).unwrap();
+    pager.release_page(zone.retrieve_empty_page(0).unwrap());
+}
+
+#[cfg(feature = "fail-injection")]
+#[test]
+fn failure_injection() {
+    let mut pager = Pager::new();
+    let mut zone = ZoneAllocator::new(0);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    zone.refill(layout, pager.allocate_page().unwrap()).unwrap();
+
+    zone.failure_injector_mut().fail_next(2);
+    assert_eq!(zone.allocate(layout), Err(AllocationError::OutOfMemory));
+    assert_eq!(zone.try_allocate(layout), Err(AllocationError::OutOfMemory));
+    assert_eq!(zone.failure_injector_mut().pending(), 0);
+    let ptr = zone.allocate(layout).unwrap();
+    zone.deallocate(ptr, layout).unwrap();
+
+    zone.failure_injector_mut().fail_every(3);
+    let results: Vec<bool> = (0..6)
+        .map(|_| match zone.allocate(layout) {
+            Ok(ptr) => {
+                zone.deallocate(ptr, layout).unwrap();
+                true
+            }
+            Err(_) => false,
+        })
+        .collect();
+    assert_eq!(results, [true, true, false, true, true, false]);
+
+    // Zero-sized allocations never fail
+    assert!(zone.allocate(Layout::from_size_align(0, 8).unwrap()).is_ok());
+
+    zone.failure_injector_mut().disable();
+    assert!(zone.allocate(layout).is_ok());
+    assert_eq!(zone.failure_injector_mut().injected(), 4);
+}
+
+/// Randomized sequences of heap operations, checked against a model of the live objects
+/// (run with `cargo test --no-default-features --features property-tests`).
+#[cfg(feature = "property-tests")]
+mod properties {
+    use super::*;
+    use std::boxed::Box;
+    use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+
+    /// One operation on a `ZoneAllocator`.
+    #[derive(Debug, Clone)]
+    enum Op {
+        /// Allocate an object of `size` bytes aligned to `1 << align_shift`.
+        Allocate { size: usize, align_shift: u8 },
+        /// Free the live object with this index (modulo the number of live objects).
+        Deallocate(usize),
+        /// Add a page to the size class serving objects of this size.
+        Refill(usize),
+        /// Give an empty page back to the pager.
+        Retrieve,
+    }
+
+    impl Arbitrary for Op {
+        fn arbitrary(g: &mut Gen) -> Op {
+            match u8::arbitrary(g) % 8 {
+                0..=2 => Op::Allocate {
+                    size: usize::arbitrary(g) % ZoneAllocator::MAX_ALLOC_SIZE + 1,
+                    align_shift: u8::arbitrary(g) % 7,
+                },
+                3..=5 => Op::Deallocate(usize::arbitrary(g)),
+                6 => Op::Refill(usize::arbitrary(g) % ZoneAllocator::MAX_BASE_ALLOC_SIZE + 1),
+                _ => Op::Retrieve,
+            }
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Op>> {
+            let mut smaller = Vec::new();
+            match *self {
+                Op::Allocate { size, align_shift } => {
+                    if size > 1 {
+                        smaller.push(Op::Allocate { size: size / 2, align_shift });
+                    }
+                    if align_shift > 0 {
+                        smaller.push(Op::Allocate { size, align_shift: align_shift - 1 });
+                    }
+                }
+                Op::Deallocate(idx) if idx > 0 => smaller.push(Op::Deallocate(idx / 2)),
+                Op::Refill(size) if size > 1 => smaller.push(Op::Refill(size / 2)),
+                _ => {}
+            }
+            Box::new(smaller.into_iter())
+        }
+    }
+
+    /// Do the half-open byte ranges of two objects intersect?
+    fn overlap(a: (usize, usize), b: (usize, usize)) -> bool {
+        a.0 < b.0 + b.1 && b.0 < a.0 + a.1
+    }
+
+    /// Runs `ops` against a fresh zone and checks that live objects never overlap,
+    /// are aligned and keep their contents, and that no page is lost or duplicated.
+    fn check_ops(ops: Vec<Op>) -> TestResult {
+        let mut pager = Pager::new();
+        let mut zone = ZoneAllocator::new(0);
+        // (object, layout, fill byte)
+        let mut live: Vec<(NonNull<u8>, Layout, u8)> = Vec::new();
+
+        for (n, op) in ops.iter().enumerate() {
+            match *op {
+                Op::Allocate { size, align_shift } => {
+                    let layout = Layout::from_size_align(size, 1 << align_shift).unwrap();
+                    match zone.allocate(layout) {
+                        Ok(ptr) => {
+                            let addr = ptr.as_ptr() as usize;
+                            if !addr.is_multiple_of(layout.align()) {
+                                return TestResult::error(format!("{:p} is not aligned to {}", ptr, layout.align()));
+                            }
+                            if live.iter().any(|(p, l, _)| overlap((p.as_ptr() as usize, l.size()), (addr, size))) {
+                                return TestResult::error(format!("{:p} overlaps a live object", ptr));
+                            }
+                            let fill = n as u8;
+                            unsafe { ptr::write_bytes(ptr.as_ptr(), fill, size) };
+                            live.push((ptr, layout, fill));
+                        }
+                        // Too large only happens with red zones
+                        Err(AllocationError::OutOfMemory)
+                        | Err(AllocationError::InvalidLayout)
+                        | Err(AllocationError::TooLarge) => {}
+                        Err(e) => return TestResult::error(format!("Unexpected error {:?}", e)),
+                    }
+                }
+                Op::Deallocate(idx) => {
+                    if live.is_empty() {
+                        continue;
+                    }
+                    let (ptr, layout, fill) = live.swap_remove(idx % live.len());
+                    let contents = unsafe { core::slice::from_raw_parts(ptr.as_ptr(), layout.size()) };
+                    if contents.iter().any(|b| *b != fill) {
+                        return TestResult::error(format!("{:p} was overwritten", ptr));
+                    }
+                    if let Err(e) = zone.deallocate(ptr, layout) {
+                        return TestResult::error(format!("Can't free {:p}: {:?}", ptr, e));
+                    }
+                }
+                Op::Refill(size) => {
+                    let layout = Layout::from_size_align(size, 1).unwrap();
+                    // With red zones, the biggest sizes don't fit in a page anymore
+                    if let Slab::Unsupported = ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+                        continue;
+                    }
+                    let page = pager.allocate_page().expect("Host is out of memory");
+                    if let Err(e) = zone.refill(layout, page) {
+                        return TestResult::error(format!("Can't refill: {:?}", e));
+                    }
+                }
+                Op::Retrieve => {
+                    if let Some(mp) = zone.retrieve_empty_page(0) {
+                        pager.release_page(mp);
+                    }
+                }
+            }
+
+            if zone.pages() != pager.currently_allocated() {
+                return TestResult::error(format!(
+                    "The zone owns {} pages but {} were handed out",
+                    zone.pages(),
+                    pager.currently_allocated()
+                ));
+            }
+            if let Err(e) = zone.verify() {
+                return TestResult::error(format!("Heap is inconsistent: {:?}", e));
+            }
+        }
+
+        // Everything has to come back once all objects are freed
+        for (ptr, layout, _) in live.drain(..) {
+            if let Err(e) = zone.deallocate(ptr, layout) {
+                return TestResult::error(format!("Can't free {:p}: {:?}", ptr, e));
+            }
+        }
+        while let Some(mp) = zone.retrieve_empty_page(0) {
+            pager.release_page(mp);
+        }
+        TestResult::from_bool(zone.pages() == 0 && pager.currently_allocated() == 0)
+    }
+
+    #[test]
+    fn random_heap_operations() {
+        QuickCheck::new()
+            .tests(200)
+            .set_rng(Gen::new(256))
+            .quickcheck(check_ops as fn(Vec<Op>) -> TestResult);
+    }
+}
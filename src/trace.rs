@@ -0,0 +1,256 @@
+//! Recording and replaying the heap operations of a `ZoneAllocator`.
+//!
+//! A `TraceRecorder` logs every allocation, deallocation, refill and returned page
+//! of a zone into a caller-provided ring buffer (see `ZoneAllocator::start_trace`).
+//! Since the allocator is deterministic, a `TraceReplayer` can re-execute such a
+//! trace against a fresh zone (e.g., in the host test harness) and ends up with
+//! every object in the same slot, which makes heap corruptions observed in a real
+//! Theseus run reproducible.
+//!
+//! # Notes
+//! Only operations on the zone itself are recorded. Pages exchanged with other zones
+//! or an `EmptyPagePool` and frees forwarded from other heaps are not part of the trace,
+//! a replay of a zone that relies on them diverges.
+
+use crate::*;
+
+/// A recorded operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+    /// An object was handed out.
+    Allocate,
+    /// An object was freed.
+    Deallocate,
+    /// A page was added to the size class with `refill`.
+    Refill,
+    /// An empty page of the size class was returned with `retrieve_empty_page`.
+    ReleasePage,
+}
+
+/// A single operation of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEvent {
+    pub op: TraceOp,
+    /// Index of the size class (see `ZoneAllocator::BASE_ALLOC_SIZES`),
+    /// the large size classes follow the base size classes.
+    pub size_class: usize,
+    /// Index of the object within its page (0 for page operations).
+    pub slot: usize,
+    /// Address of the page at the time of recording, identifies the page within the trace.
+    pub page: usize,
+    /// Layout of the request.
+    pub layout: Layout,
+    /// Core of the caller, if the operation was done with a context that knows it
+    /// (see `ZoneAllocator::allocate_in`).
+    pub core_id: Option<usize>,
+    /// Task of the caller, if the operation was done with a context that knows it.
+    pub task_id: Option<usize>,
+}
+
+impl TraceEvent {
+    /// Placeholder to initialize trace buffers with.
+    pub const EMPTY: TraceEvent = TraceEvent {
+        op: TraceOp::Allocate,
+        size_class: 0,
+        slot: 0,
+        page: 0,
+        layout: unsafe { Layout::from_size_align_unchecked(0, 1) },
+        core_id: None,
+        task_id: None,
+    };
+
+    /// Describes the object at `addr` in size class `size_class` (or the page at `addr`).
+    pub(crate) fn new(op: TraceOp, size_class: usize, layout: Layout, addr: usize) -> TraceEvent {
+        let (object_size, page_size) = ZoneAllocator::class_geometry(size_class);
+        let page = addr & !(page_size - 1);
+        TraceEvent {
+            op,
+            size_class,
+            slot: (addr - page) / object_size,
+            page,
+            layout,
+            core_id: None,
+            task_id: None,
+        }
+    }
+}
+
+/// Logs the operations of a zone into a ring buffer.
+pub struct TraceRecorder<'b> {
+    buffer: &'b mut [TraceEvent],
+    /// Total number of events recorded (also the next write position).
+    recorded: usize,
+    /// Core and task of the caller of the running operation, stamped into its events.
+    context: (Option<usize>, Option<usize>),
+}
+
+impl<'b> TraceRecorder<'b> {
+    /// Creates a recorder that writes into `buffer`, once it is full the oldest events are overwritten.
+    pub fn new(buffer: &'b mut [TraceEvent]) -> TraceRecorder<'b> {
+        TraceRecorder { buffer, recorded: 0, context: (None, None) }
+    }
+
+    /// Sets the core and task the following events are done for.
+    pub(crate) fn set_context(&mut self, core_id: Option<usize>, task_id: Option<usize>) {
+        self.context = (core_id, task_id);
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, mut event: TraceEvent) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let len = self.buffer.len();
+        (event.core_id, event.task_id) = self.context;
+        self.buffer[self.recorded % len] = event;
+        self.recorded += 1;
+    }
+
+    /// Total number of events recorded (including ones that were already overwritten).
+    pub fn recorded(&self) -> usize {
+        self.recorded
+    }
+
+    /// Returns `true` if no event was overwritten, only complete traces can be replayed.
+    pub fn is_complete(&self) -> bool {
+        self.recorded <= self.buffer.len()
+    }
+
+    /// Iterates over the events still in the buffer, oldest first.
+    pub fn events(&self) -> impl Iterator<Item = &TraceEvent> {
+        let len = self.buffer.len();
+        let start = if self.is_complete() { 0 } else { self.recorded % len };
+        let count = core::cmp::min(self.recorded, len);
+        (0..count).map(move |i| &self.buffer[(start + i) % len])
+    }
+}
+
+/// Supplies the pages of a replay and takes them back.
+pub trait ReplayPages {
+    /// Returns a fresh page for a `Refill` event.
+    fn allocate_page(&mut self) -> Option<MappedPages>;
+    /// Returns a fresh 32 KiB slab for a `Refill` event of a large size class.
+    fn allocate_large_page(&mut self) -> Option<MappedPages> {
+        None
+    }
+    /// Takes back the page of a `ReleasePage` event.
+    fn release_page(&mut self, mp: MappedPages);
+}
+
+/// Re-executes a recorded trace against a fresh `ZoneAllocator`.
+///
+/// The pages of the replay are different from the recorded ones, the replayer
+/// keeps track of which replayed page stands for which recorded page in a caller-provided
+/// map (one entry for every page the zone holds at the same time).
+pub struct TraceReplayer<'m> {
+    /// (recorded page, replayed page) pairs.
+    pages: &'m mut [(usize, usize)],
+    /// Number of used entries in `pages`.
+    mapped: usize,
+    /// Number of events replayed.
+    replayed: usize,
+}
+
+impl<'m> TraceReplayer<'m> {
+    /// Creates a replayer that maps the pages with `page_map`.
+    pub fn new(page_map: &'m mut [(usize, usize)]) -> TraceReplayer<'m> {
+        TraceReplayer {
+            pages: page_map,
+            mapped: 0,
+            replayed: 0,
+        }
+    }
+
+    /// Number of events replayed successfully, on error this is the index of the failing event.
+    pub fn replayed(&self) -> usize {
+        self.replayed
+    }
+
+    /// Replays `trace` against `zone`.
+    ///
+    /// The pages for `Refill` events come from `pages`, which also gets the pages
+    /// of `ReleasePage` events back. Fails with `AllocationError::HeapCorrupted` as soon as the replay
+    /// diverges from the recording (e.g., an object ends up in a different slot), or with the
+    /// error of the replayed operation.
+    pub fn replay<'a, 't, I, P>(
+        &mut self,
+        zone: &mut ZoneAllocator<'a>,
+        trace: I,
+        pages: &mut P,
+    ) -> Result<(), AllocationError>
+    where
+        I: IntoIterator<Item = &'t TraceEvent>,
+        P: ReplayPages + ?Sized,
+    {
+        for event in trace {
+            match event.op {
+                TraceOp::Allocate => {
+                    let ptr = crate::Allocator::allocate(zone, event.layout)?;
+                    let replayed = TraceEvent::new(event.op, event.size_class, event.layout, ptr.as_ptr() as usize);
+                    if replayed.slot != event.slot || self.lookup(event.page) != Some(replayed.page) {
+                        return Err(AllocationError::HeapCorrupted("replayed allocation ended up in a different slot"));
+                    }
+                }
+                TraceOp::Deallocate => {
+                    let page = self
+                        .lookup(event.page)
+                        .ok_or(AllocationError::HeapCorrupted("replayed free of an object on an unknown page"))?;
+                    let slot = page + event.slot * ZoneAllocator::class_geometry(event.size_class).0;
+                    #[cfg(feature = "red-zones")]
+                    let slot = slot + red_zone_front(event.layout);
+                    let ptr = NonNull::new(slot as *mut u8).ok_or(AllocationError::InvalidPointer)?;
+                    crate::Allocator::deallocate(zone, ptr, event.layout)?;
+                }
+                TraceOp::Refill => {
+                    let mp = if event.size_class < ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+                        pages.allocate_page()
+                    } else {
+                        pages.allocate_large_page()
+                    }
+                    .ok_or(AllocationError::OutOfMemory)?;
+                    let page = mp.start_address().value();
+                    crate::Allocator::refill(zone, event.layout, mp)?;
+                    self.map(event.page, page)?;
+                }
+                TraceOp::ReleasePage => {
+                    let mp = match event.size_class.checked_sub(ZoneAllocator::MAX_BASE_SIZE_CLASSES) {
+                        None => zone.small_slabs[event.size_class].retrieve_empty_page(),
+                        Some(idx) => zone.big_slabs[idx].retrieve_empty_page(),
+                    }
+                    .ok_or(AllocationError::HeapCorrupted("replayed page release found no empty page"))?;
+                    let diverged = self.lookup(event.page) != Some(mp.start_address().value());
+                    pages.release_page(mp);
+                    if diverged {
+                        return Err(AllocationError::HeapCorrupted("replayed page release returned a different page"));
+                    }
+                    self.unmap(event.page);
+                }
+            }
+            self.replayed += 1;
+        }
+        Ok(())
+    }
+
+    fn lookup(&self, recorded: usize) -> Option<usize> {
+        self.pages[..self.mapped]
+            .iter()
+            .find(|(rec, _)| *rec == recorded)
+            .map(|(_, page)| *page)
+    }
+
+    fn map(&mut self, recorded: usize, page: usize) -> Result<(), AllocationError> {
+        if self.mapped == self.pages.len() {
+            return Err(AllocationError::OutOfMemory);
+        }
+        self.pages[self.mapped] = (recorded, page);
+        self.mapped += 1;
+        Ok(())
+    }
+
+    fn unmap(&mut self, recorded: usize) {
+        if let Some(idx) = self.pages[..self.mapped].iter().position(|(rec, _)| *rec == recorded) {
+            self.mapped -= 1;
+            self.pages.swap(idx, self.mapped);
+        }
+    }
+}
@@ -1,35 +1,12 @@
 //! A ZoneAllocator to allocate arbitrary object sizes (up to `ZoneAllocator::MAX_ALLOC_SIZE`)
 //!
+//! Objects up to `ZoneAllocator::MAX_BASE_ALLOC_SIZE` live in 8 KiB pages, bigger ones
+//! in slabs of four contiguous 8 KiB pages (`ObjectPage32k`).
+//!
 //! The ZoneAllocator achieves this by having many `SCAllocator`
 
 use crate::*;
-
-/// Creates an instance of a zone, we do this in a macro because we
-/// re-use the code in const and non-const functions
-///
-/// We can get rid of this once the const fn feature is fully stabilized.
-macro_rules! new_zone {
-    ($x:expr) => {
-        ZoneAllocator {
-            heap_id: $x,
-            // TODO(perf): We should probably pick better classes
-            // rather than powers-of-two (see SuperMalloc etc.)
-            small_slabs: [
-                SCAllocator::new(1 << 3),  // 8
-                SCAllocator::new(1 << 4),  // 16
-                SCAllocator::new(1 << 5),  // 32
-                SCAllocator::new(1 << 6),  // 64
-                SCAllocator::new(1 << 7),  // 128
-                SCAllocator::new(1 << 8),  // 256
-                SCAllocator::new(1 << 9),  // 512
-                SCAllocator::new(1 << 10), // 1024 (TODO: maybe get rid of this class?)
-                SCAllocator::new(1 << 11), // 2048 (TODO: maybe get rid of this class?)
-                SCAllocator::new(1 << 12), // 4096 
-                SCAllocator::new(ZoneAllocator::MAX_ALLOC_SIZE),    // 8104 (can't do 8192 because of metadata in ObjectPage)
-            ]
-        }
-    };
-}
+use core::panic::Location;
 
 /// A zone allocator for arbitrary sized allocations.
 ///
@@ -41,18 +18,132 @@ macro_rules! new_zone {
 /// to provide the underlying `SCAllocator` with more memory in case it runs out.
 pub struct ZoneAllocator<'a> {
     pub heap_id: usize,
-    small_slabs: [SCAllocator<'a, ObjectPage8k<'a>>; ZoneAllocator::MAX_BASE_SIZE_CLASSES],
-    // big_slabs: [SCAllocator<'a, LargeObjectPage<'a>>; ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+    pub(crate) small_slabs: [SCAllocator<'a, BasePage<'a>>; ZoneAllocator::MAX_BASE_SIZE_CLASSES],
+    /// Size classes bigger than a page, served from 32 KiB slabs.
+    pub(crate) big_slabs: [SCAllocator<'a, ObjectPage32k<'a>>; ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+    /// Samples every Nth allocation (disabled by default).
+    profiler: AllocationProfiler,
+    /// In debug builds, run `verify` after every `verify_interval` operations (0 disables it).
+    verify_interval: usize,
+    /// Number of allocations and deallocations since the last periodic `verify`.
+    operations: usize,
+    /// Serves layouts bigger than `MAX_ALLOC_SIZE`.
+    fallback: Option<&'a dyn FallbackAllocator>,
+    /// Maximum number of bytes of pages the zone may own (0 means unlimited).
+    budget_bytes: usize,
+    /// Receives objects of other heaps that are freed to this zone.
+    cross_heap: Option<&'a dyn CrossHeapFree>,
+    /// Objects of this zone that were freed to other heaps.
+    remote_inbox: Option<&'a RemoteFreeInbox>,
+    /// Shared pools (one per NUMA node) that surplus empty pages overflow into
+    /// and that are used before running out of memory.
+    page_pools: &'a [EmptyPagePool<'a>],
+    /// Number of empty pages the zone keeps before it overflows them into `page_pools`.
+    pool_threshold: usize,
+    /// A size class must have more than this number of empty pages to give one up
+    /// (see `set_slab_empty_pages_threshold`).
+    slab_empty_threshold: usize,
+    /// NUMA node of the zone, the node of the pages added with `refill` and of the preferred pool.
+    node: Option<usize>,
+    /// Receives the empty pages beyond `reclaim_threshold` as objects are freed.
+    reclaimer: Option<&'a dyn PageReclaimer>,
+    /// Number of empty pages (and 32 KiB slabs) the zone keeps before it hands them to `reclaimer`.
+    reclaim_threshold: usize,
+    /// Maximum number of pages handed to `reclaimer` per `deallocate`.
+    reclaim_batch: usize,
+    /// Time source of the idle decay (see `set_idle_decay`).
+    clock: Option<&'a dyn ClockSource>,
+    /// Ticks an empty page may sit unused before `maintain` releases it.
+    max_idle_ticks: u64,
+    /// Tick at which the current decay period started.
+    decay_start: u64,
+    /// Live memory per tag of allocations made with `allocate_tagged`.
+    tags: TagAccounting,
+    /// Is told when the live bytes cross the watermarks (see `set_watermarks`).
+    watermark_listener: Option<&'a dyn WatermarkListener>,
+    /// Live bytes at which the listener is told `Watermark::Low` (after a `Watermark::High`).
+    low_watermark: usize,
+    /// Live bytes at which the listener is told `Watermark::High`.
+    high_watermark: usize,
+    /// Number of allocations and deallocations between two checks of the watermarks.
+    watermark_interval: usize,
+    /// Operations since the last check of the watermarks.
+    watermark_operations: usize,
+    /// Whether the high watermark was crossed (and the low one not yet).
+    above_high_watermark: bool,
+    /// Bytes requested by the allocations of every size class (see `size_class_stats`).
+    requested_bytes: [usize; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+    /// Fails allocations on request (only with the `fail-injection` feature).
+    #[cfg(feature = "fail-injection")]
+    failures: FailureInjector,
+    /// Keeps the shadow memory of a sanitizer up to date (only with the `shadow-hooks` feature).
+    #[cfg(feature = "shadow-hooks")]
+    shadow: Option<&'a dyn ShadowHooks>,
+    /// Whether allocations are tagged (only with the `mte` feature).
+    #[cfg(feature = "mte")]
+    memory_tagging: bool,
+    /// Times the operations (only with the `latency-stats` feature).
+    #[cfg(feature = "latency-stats")]
+    latency_clock: Option<&'a dyn ClockSource>,
+    /// Latencies of the operations of every size class (only with the `latency-stats` feature).
+    #[cfg(feature = "latency-stats")]
+    latency: [LatencyHistogram; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+    /// Empty pages that were retrieved but are held back for a `maintain` cycle (see `set_page_quarantine`).
+    page_quarantine: PageQuarantine,
+    /// Records the operations of the zone (see `start_trace`).
+    trace: Option<TraceRecorder<'a>>,
 }
 
 impl<'a> Default for ZoneAllocator<'a> {
     fn default() -> ZoneAllocator<'a> {
-        new_zone!(0)
+        ZoneAllocator::new(0)
+    }
+}
+
+/// What a size class of a `ZoneAllocator` costs and serves, to decide whether
+/// it is worth having (see `ZoneAllocator::PRUNED_BASE_CLASSES`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeClassStats {
+    /// Object size of the class.
+    pub object_size: usize,
+    /// Whether the class is pruned (it never serves allocations then).
+    pub pruned: bool,
+    /// Number of allocations the class served.
+    pub allocations: usize,
+    /// Sum of the sizes of these allocations (as requested in their `Layout`).
+    pub requested_bytes: usize,
+    /// Number of pages (or slabs) owned by the class.
+    pub pages: usize,
+    /// Bytes of the pages owned by the class.
+    pub page_bytes: usize,
+    /// Page list transitions of the class (see `SCStats::page_transitions`).
+    pub page_transitions: usize,
+    /// Latencies of the operations of the class (only with the `latency-stats` feature).
+    #[cfg(feature = "latency-stats")]
+    pub latency: LatencyHistogram,
+}
+
+impl SizeClassStats {
+    /// Bytes the allocations wasted because their objects were rounded up to `object_size`.
+    pub fn padding_bytes(&self) -> usize {
+        (self.allocations * self.object_size).saturating_sub(self.requested_bytes)
     }
 }
 
-#[allow(dead_code)]
-enum Slab {
+/// The work done by one call of `ZoneAllocator::maintain`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceReport {
+    /// Objects freed on behalf of other heaps (see `ZoneAllocator::drain_remote_frees`).
+    pub remote_frees: usize,
+    /// Pages moved to the right list of their size class (see `ZoneAllocator::rebalance`).
+    pub pages_rebalanced: usize,
+    /// Empty pages moved between size classes (see `ZoneAllocator::rebalance_classes`).
+    pub pages_redistributed: usize,
+    /// Bytes of the empty pages handed to the reclaimer.
+    pub bytes_released: usize,
+}
+
+pub(crate) enum Slab {
     Base(usize),
     Large(usize),
     Unsupported,
@@ -60,14 +151,15 @@ enum Slab {
 
 
 impl<'a> ZoneAllocator<'a> {
-    /// Maximum size that allocated within 2 pages. (8 KiB - 88 bytes)
+    /// Maximum size that is allocated within a 32 KiB slab (32 KiB - the meta-data).
     /// This is also the maximum object size that this allocator can handle.
-    pub const MAX_ALLOC_SIZE: usize = ObjectPage8k::SIZE - ObjectPage8k::METADATA_SIZE;
+    pub const MAX_ALLOC_SIZE: usize = ObjectPage32k::SIZE - ObjectPage32k::METADATA_SIZE;
 
-    /// Maximum size which is allocated with ObjectPages8k (4 KiB pages).
+    /// Maximum size which is allocated with ObjectPages8k (2 4 KiB pages).
     ///
-    /// e.g. this is 8 KiB - 88 bytes of meta-data.
-    pub const MAX_BASE_ALLOC_SIZE: usize = ZoneAllocator::MAX_ALLOC_SIZE;
+    /// e.g. this is 8 KiB - 200 bytes of meta-data (with caller supplied regions),
+    /// or 8 KiB with the `out-of-band-metadata` feature.
+    pub const MAX_BASE_ALLOC_SIZE: usize = BasePage::SIZE - BasePage::METADATA_SIZE;
 
     /// How many allocators of type SCAllocator<ObjectPage8k> we have.
     pub const MAX_BASE_SIZE_CLASSES: usize = 11;
@@ -75,55 +167,212 @@ impl<'a> ZoneAllocator<'a> {
     /// The set of sizes the allocator has lists for.
     pub const BASE_ALLOC_SIZES: [usize; ZoneAllocator::MAX_BASE_SIZE_CLASSES] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096, ZoneAllocator::MAX_BASE_ALLOC_SIZE];
 
-    /// A slab must have greater than this number of empty pages to return one.
-    const SLAB_EMPTY_PAGES_THRESHOLD: usize = 0;
+    /// The base size classes that are compiled out of the zone, their objects are
+    /// served by the next larger class that isn't pruned (the largest class never is).
+    ///
+    /// The 1024 and 2048 byte classes are pruned with the `prune-1024-class` and
+    /// `prune-2048-class` features, see `size_class_stats` to evaluate the tradeoff.
+    pub const PRUNED_BASE_CLASSES: [bool; ZoneAllocator::MAX_BASE_SIZE_CLASSES] = [
+        false,
+        false,
+        false,
+        false,
+        false,
+        false,
+        false,
+        cfg!(feature = "prune-1024-class"),
+        cfg!(feature = "prune-2048-class"),
+        false,
+        false,
+    ];
+
+    /// How many allocators of type SCAllocator<ObjectPage32k> we have.
+    pub const MAX_LARGE_SIZE_CLASSES: usize = 3;
+
+    /// The sizes bigger than `MAX_BASE_ALLOC_SIZE` the allocator has lists for,
+    /// three, two and one object(s) per 32 KiB slab (rounded down to whole cache lines,
+    /// with room for the call-site entries and generations of the objects with the
+    /// `callsites` and `generations` features).
+    pub const LARGE_ALLOC_SIZES: [usize; ZoneAllocator::MAX_LARGE_SIZE_CLASSES] = [
+        (ZoneAllocator::MAX_ALLOC_SIZE / 3 - OBJECT_OVERHEAD) / 64 * 64,
+        (ZoneAllocator::MAX_ALLOC_SIZE / 2 - OBJECT_OVERHEAD) / 64 * 64,
+        ZoneAllocator::MAX_ALLOC_SIZE,
+    ];
+
+    /// Names of the size classes in the `SlabRegistry` (see `register_slabs`),
+    /// the large size classes follow the base size classes.
+    pub const CLASS_NAMES: [&'static str; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES] = [
+        "slab-8",
+        "slab-16",
+        "slab-32",
+        "slab-64",
+        "slab-128",
+        "slab-256",
+        "slab-512",
+        "slab-1024",
+        "slab-2048",
+        "slab-4096",
+        "slab-base-max",
+        "slab-large-3",
+        "slab-large-2",
+        "slab-large-1",
+    ];
 
-    #[cfg(feature = "unstable")]
     pub const fn new(heap_id: usize) -> ZoneAllocator<'a> {
-        new_zone!(heap_id)
+        ZoneAllocator {
+            heap_id,
+            // TODO(perf): We should probably pick better classes
+            // rather than powers-of-two (see SuperMalloc etc.)
+            small_slabs: [
+                SCAllocator::new(1 << 3),  // 8
+                SCAllocator::new(1 << 4),  // 16
+                SCAllocator::new(1 << 5),  // 32
+                SCAllocator::new(1 << 6),  // 64
+                SCAllocator::new(1 << 7),  // 128
+                SCAllocator::new(1 << 8),  // 256
+                SCAllocator::new(1 << 9),  // 512
+                SCAllocator::new(1 << 10), // 1024 (pruned with `prune-1024-class`)
+                SCAllocator::new(1 << 11), // 2048 (pruned with `prune-2048-class`)
+                SCAllocator::new(1 << 12), // 4096 
+                SCAllocator::new(ZoneAllocator::MAX_BASE_ALLOC_SIZE),    // 8104 (can't do 8192 because of metadata in ObjectPage)
+            ],
+            big_slabs: [
+                SCAllocator::new(ZoneAllocator::LARGE_ALLOC_SIZES[0]), // 3 per slab
+                SCAllocator::new(ZoneAllocator::LARGE_ALLOC_SIZES[1]), // 2 per slab
+                SCAllocator::new(ZoneAllocator::LARGE_ALLOC_SIZES[2]), // 1 per slab
+            ],
+            profiler: AllocationProfiler::new(),
+            verify_interval: 0,
+            operations: 0,
+            fallback: None,
+            budget_bytes: 0,
+            cross_heap: None,
+            remote_inbox: None,
+            page_pools: &[],
+            pool_threshold: 0,
+            slab_empty_threshold: 0,
+            node: None,
+            reclaimer: None,
+            reclaim_threshold: 0,
+            reclaim_batch: 0,
+            clock: None,
+            max_idle_ticks: 0,
+            decay_start: 0,
+            tags: TagAccounting::new(),
+            watermark_listener: None,
+            low_watermark: 0,
+            high_watermark: 0,
+            watermark_interval: 0,
+            watermark_operations: 0,
+            above_high_watermark: false,
+            requested_bytes: [0; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+            #[cfg(feature = "fail-injection")]
+            failures: FailureInjector::new(),
+            #[cfg(feature = "shadow-hooks")]
+            shadow: None,
+            #[cfg(feature = "mte")]
+            memory_tagging: false,
+            #[cfg(feature = "latency-stats")]
+            latency_clock: None,
+            #[cfg(feature = "latency-stats")]
+            latency: [LatencyHistogram::new(); ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+            page_quarantine: PageQuarantine::new(),
+            trace: None,
+        }
     }
 
-    #[cfg(not(feature = "unstable"))]
-    pub fn new(heap_id: usize) -> ZoneAllocator<'a> {
-        new_zone!(heap_id)
+
+    /// Return maximum size an object of size `current_size` can use.
+    ///
+    /// Used to optimize `realloc`. Pass the size of `effective_layout` for objects
+    /// that are aligned to more than their size.
+    pub const fn get_max_size(current_size: usize) -> Option<usize> {
+        match ZoneAllocator::get_slab(current_size) {
+            Slab::Base(idx) => Some(ZoneAllocator::BASE_ALLOC_SIZES[idx]),
+            Slab::Large(idx) => Some(ZoneAllocator::LARGE_ALLOC_SIZES[idx]),
+            Slab::Unsupported => None,
+        }
     }
 
+    /// The pointer handed out for zero-sized allocations:
+    /// a non-null address that is aligned to `layout`.
+    pub(crate) fn dangling(layout: Layout) -> NonNull<u8> {
+        unsafe { NonNull::new_unchecked(layout.align() as *mut u8) }
+    }
 
-    /// Return maximum size an object of size `current_size` can use.
+    /// The layout of the slot an object of `layout` occupies, which decides its size class.
+    /// It includes the red zones if the `red-zones` feature is enabled.
     ///
-    /// Used to optimize `realloc`.
-    pub fn get_max_size(current_size: usize) -> Option<usize> {
-        match current_size {
-            0..=8 => Some(8),
-            9..=16 => Some(16),
-            17..=32 => Some(32),
-            33..=64 => Some(64),
-            65..=128 => Some(128),
-            129..=256 => Some(256),
-            257..=512 => Some(512),
-            513..=1024 => Some(1024),
-            1025..=2048 => Some(2048),
-            2049..=4096 => Some(4096),
-            4097..=ZoneAllocator::MAX_ALLOC_SIZE => Some(ZoneAllocator::MAX_ALLOC_SIZE),
-            _ => None,
+    /// Objects aligned to more than their size get a slot of their alignment: the objects
+    /// of a power-of-two class are aligned to the class size (and the biggest base class
+    /// has a single object at the start of the page), so over-aligned objects get a class
+    /// of their own instead of only fitting every few slots of a smaller class.
+    pub const fn effective_layout(layout: Layout) -> Layout {
+        #[cfg(feature = "red-zones")]
+        let layout = red_zone_layout(layout);
+        let align = if layout.align() < ZoneAllocator::MAX_BASE_ALLOC_SIZE {
+            layout.align()
+        } else {
+            ZoneAllocator::MAX_BASE_ALLOC_SIZE
+        };
+        let size = if layout.size() > align { layout.size() } else { align };
+        // `size` is the size or the alignment of a valid layout
+        unsafe { Layout::from_size_align_unchecked(size, layout.align()) }
+    }
+
+    /// Index of the size class serving `layout` (the large size classes follow the
+    /// base size classes, like in `size_class_stats`), `None` if it is too large.
+    pub const fn size_class_index(layout: Layout) -> Option<usize> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => Some(idx),
+            Slab::Large(idx) => Some(ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx),
+            Slab::Unsupported => None,
         }
     }
 
+    /// Size of the slot an object of `layout` occupies (see `effective_layout`).
+    pub(crate) const fn slot_size(layout: Layout) -> usize {
+        ZoneAllocator::effective_layout(layout).size()
+    }
+
     /// Figure out index into zone array to get the correct slab allocator for that size.
-    fn get_slab(requested_size: usize) -> Slab {
-        match requested_size {
-            0..=8 => Slab::Base(0),
-            9..=16 => Slab::Base(1),
-            17..=32 => Slab::Base(2),
-            33..=64 => Slab::Base(3),
-            65..=128 => Slab::Base(4),
-            129..=256 => Slab::Base(5),
-            257..=512 => Slab::Base(6),
-            513..=1024 => Slab::Base(7),
-            1025..=2048 => Slab::Base(8),
-            2049..=4096 => Slab::Base(9),
-            4097..=ZoneAllocator::MAX_ALLOC_SIZE => Slab::Base(10),
-            _ => Slab::Unsupported,
+    pub(crate) const fn get_slab(requested_size: usize) -> Slab {
+        let idx = match requested_size {
+            0..=8 => 0,
+            9..=16 => 1,
+            17..=32 => 2,
+            33..=64 => 3,
+            65..=128 => 4,
+            129..=256 => 5,
+            257..=512 => 6,
+            513..=1024 => 7,
+            1025..=2048 => 8,
+            2049..=4096 => 9,
+            4097..=ZoneAllocator::MAX_BASE_ALLOC_SIZE => 10,
+            _ if requested_size <= ZoneAllocator::LARGE_ALLOC_SIZES[0] => return Slab::Large(0),
+            _ if requested_size <= ZoneAllocator::LARGE_ALLOC_SIZES[1] => return Slab::Large(1),
+            _ if requested_size <= ZoneAllocator::MAX_ALLOC_SIZE => return Slab::Large(2),
+            _ => return Slab::Unsupported,
+        };
+        Slab::Base(ZoneAllocator::unpruned_class(idx))
+    }
+
+    /// The base size class serving the objects of class `idx`, i.e.,
+    /// the next larger class that isn't pruned (see `PRUNED_BASE_CLASSES`).
+    const fn unpruned_class(mut idx: usize) -> usize {
+        while ZoneAllocator::PRUNED_BASE_CLASSES[idx] {
+            idx += 1;
+        }
+        idx
+    }
+
+    /// Object size and page size of the size class with the index `class`,
+    /// where the large size classes follow the base size classes.
+    pub(crate) fn class_geometry(class: usize) -> (usize, usize) {
+        if class < ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+            (ZoneAllocator::BASE_ALLOC_SIZES[class], ObjectPage8k::SIZE)
+        } else {
+            (ZoneAllocator::LARGE_ALLOC_SIZES[class - ZoneAllocator::MAX_BASE_SIZE_CLASSES], ObjectPage32k::SIZE)
         }
     }
 }
@@ -131,102 +380,2271 @@ impl<'a> ZoneAllocator<'a> {
 impl<'a> ZoneAllocator<'a> {
 
     /// Removes all the pages of `allocator` and adds them to the appropriate lists in this allocator.
-    pub fn merge(&mut self, allocator: &mut ZoneAllocator<'a>) -> Result<(), &'static str> {
+    pub fn merge(&mut self, allocator: &mut ZoneAllocator<'a>) -> Result<(), AllocationError> {
         for size in &ZoneAllocator::BASE_ALLOC_SIZES {
             match ZoneAllocator::get_slab(*size) {
                 Slab::Base(idx) => {
                     self.small_slabs[idx].merge(&mut allocator.small_slabs[idx], self.heap_id)?;
                 }
-                Slab::Large(_idx) => return Err("AllocationError::InvalidLayout"),
-                Slab::Unsupported => return Err("AllocationError::InvalidLayout"),
+                Slab::Large(_idx) => return Err(AllocationError::InvalidLayout),
+                Slab::Unsupported => return Err(AllocationError::InvalidLayout),
             }
         }
+        for (slab, other) in self.big_slabs.iter_mut().zip(allocator.big_slabs.iter_mut()) {
+            slab.merge(other, self.heap_id)?;
+        }
         Ok(())
     }
 
+    /// Moves all pages of `allocator` (e.g., the heap of a core that goes offline)
+    /// to this zone and tags them with `new_heap_id` (normally `self.heap_id`),
+    /// returns the number of live objects that moved.
+    ///
+    /// In contrast to `merge`, the page lists of every size class are moved as a whole,
+    /// so pages keep their classification (empty, partial, full) and order.
+    /// Afterwards the empty pages are spread evenly over the size classes.
+    ///
+    /// Pending remote frees and quarantined objects of `allocator` are freed first,
+    /// its retired pages stay with it. No page is moved if a size class can't take
+    /// the pages of `allocator` (see `SCAllocator::migrate_from`).
+    pub fn migrate_from(&mut self, allocator: &mut ZoneAllocator<'a>, new_heap_id: usize) -> Result<usize, AllocationError> {
+        for (slab, other) in self.small_slabs.iter_mut().zip(allocator.small_slabs.iter_mut()) {
+            slab.check_merge(other)?;
+        }
+        for (slab, other) in self.big_slabs.iter_mut().zip(allocator.big_slabs.iter_mut()) {
+            slab.check_merge(other)?;
+        }
+        allocator.flush_quarantine()?;
+        allocator.drain_remote_frees();
+        allocator.rebalance();
+
+        let mut live = 0;
+        for (slab, other) in self.small_slabs.iter_mut().zip(allocator.small_slabs.iter_mut()) {
+            live += slab.migrate_from(other, new_heap_id)?;
+        }
+        for (slab, other) in self.big_slabs.iter_mut().zip(allocator.big_slabs.iter_mut()) {
+            live += slab.migrate_from(other, new_heap_id)?;
+        }
+        self.balance_empty_pages();
+
+        Ok(live)
+    }
+
+    /// Creates a new zone with the heap id `new_heap_id` (e.g., for a core that comes online)
+    /// and seeds it with up to `pages_per_class` empty pages of this zone per size class,
+    /// the inverse of `merge`.
+    ///
+    /// Pages are taken from the same size class first, shortages are made up with
+    /// empty pages of the other size classes. Only empty pages are moved,
+    /// the new zone starts with the default settings. Pruned size classes get no pages.
+    pub fn split(&mut self, pages_per_class: usize, new_heap_id: usize) -> ZoneAllocator<'a> {
+        let mut zone = ZoneAllocator::new(new_heap_id);
+        for idx in 0..ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+            if ZoneAllocator::PRUNED_BASE_CLASSES[idx] {
+                continue;
+            }
+            while zone.small_slabs[idx].empty_slabs.elements < pages_per_class {
+                let src = if self.small_slabs[idx].empty_slabs.elements > 0 {
+                    idx
+                } else {
+                    match (0..ZoneAllocator::MAX_BASE_SIZE_CLASSES)
+                        .max_by_key(|&i| self.small_slabs[i].empty_slabs.elements)
+                    {
+                        Some(src) => src,
+                        None => break,
+                    }
+                };
+                let page = match self.small_slabs[src].take_empty_page() {
+                    Some(page) => page,
+                    None => break,
+                };
+                if let Err(page) = zone.small_slabs[idx].adopt_empty_page(page, new_heap_id) {
+                    let _ = self.small_slabs[src].adopt_empty_page(page, self.heap_id);
+                    break;
+                }
+            }
+        }
+        zone
+    }
+
+    /// Moves empty pages from the size classes with the most empty pages to
+    /// the ones with the fewest, until they differ by at most one page.
+    /// Pruned size classes are left out.
+    fn balance_empty_pages(&mut self) {
+        loop {
+            let mut richest = 0;
+            let mut poorest = 0;
+            for (idx, slab) in self.small_slabs.iter().enumerate() {
+                if ZoneAllocator::PRUNED_BASE_CLASSES[idx] {
+                    continue;
+                }
+                if slab.empty_slabs.elements > self.small_slabs[richest].empty_slabs.elements {
+                    richest = idx;
+                }
+                if slab.empty_slabs.elements < self.small_slabs[poorest].empty_slabs.elements
+                    && !slab.at_page_limit()
+                {
+                    poorest = idx;
+                }
+            }
+
+            if self.small_slabs[richest].empty_slabs.elements <= self.small_slabs[poorest].empty_slabs.elements + 1
+                || self.small_slabs[poorest].at_page_limit()
+            {
+                return;
+            }
+            let mp = match self.small_slabs[richest].retrieve_empty_page() {
+                Some(mp) => mp,
+                None => return,
+            };
+            if let Err(e) = self.small_slabs[poorest].refill(mp, self.heap_id) {
+                error!("Failed to move an empty page to size class {}: {}", ZoneAllocator::BASE_ALLOC_SIZES[poorest], e);
+                return;
+            }
+        }
+    }
+
     /// Returns an ObjectPage from the SCAllocator with the maximum number of empty pages,
     /// if there are more empty pages than the threshold.
+    ///
+    /// Only 8 KiB pages are returned, see `retrieve_empty_large_page` for the slabs of the large size classes.
+    ///
+    /// With the page quarantine enabled (see `set_page_quarantine`), the page is quarantined instead
+    /// and a page quarantined before the last `maintain` cycle is returned (if there is one).
     pub fn retrieve_empty_page(
         &mut self,
         heap_empty_page_threshold: usize
     ) -> Option<MappedPages> {
+        if self.page_quarantine.enabled {
+            return self.quarantine_pages(false, |zone| zone.take_empty_page(heap_empty_page_threshold));
+        }
+        self.page_quarantine.pop(false, true).or_else(|| self.take_empty_page(heap_empty_page_threshold))
+    }
+
+    /// Takes a page out of the size class with the most empty pages, if there are more than the threshold.
+    fn take_empty_page(&mut self, heap_empty_page_threshold: usize) -> Option<MappedPages> {
         if self.empty_pages() <= heap_empty_page_threshold {
             return None;
         }
         else {
-            for slab in self.small_slabs.iter_mut() {
-                let empty_pages = slab.empty_slabs.elements;
-                if empty_pages > ZoneAllocator::SLAB_EMPTY_PAGES_THRESHOLD {
-                    return slab.retrieve_empty_page()
+            for idx in 0..ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+                let empty_pages = self.small_slabs[idx].empty_slabs.elements;
+                if empty_pages > self.slab_empty_threshold && !ZoneAllocator::PRUNED_BASE_CLASSES[idx] {
+                    // Bootstrap pages can't be returned, try the next class
+                    if let Some(mp) = self.release_empty_page(idx) {
+                        return Some(mp);
+                    }
                 }
             }
         }
         None
     }
 
-    pub fn exchange_pages_within_heap(&mut self, layout: Layout) -> Result<(), &'static str> {
-        let mp = self.retrieve_empty_page(0).ok_or("Couldn't find an empty page to exchange within the heap")?;
-        self.refill(layout, mp)
-    }  
+    /// Takes an empty page out of the size class `idx` (of `small_slabs`) and records its release.
+    fn release_empty_page(&mut self, idx: usize) -> Option<MappedPages> {
+        let mp = self.small_slabs[idx].retrieve_empty_page()?;
+        let layout = Layout::from_size_align(ZoneAllocator::BASE_ALLOC_SIZES[idx], 1).unwrap();
+        self.record(TraceOp::ReleasePage, idx, layout, mp.start_address().value());
+        self.update_shadow(TraceOp::ReleasePage, mp.start_address().value(), mp.size_in_bytes());
+        log_event(HeapEvent::PageReturned {
+            heap_id: self.heap_id,
+            object_size: self.small_slabs[idx].size(),
+            page: mp.start_address().value(),
+        });
+        Some(mp)
+    }
 
-    /// The total number of empty pages in this zone allocator
-    pub fn empty_pages(&self) -> usize {
-        let mut empty_pages = 0;
-        for sca in &self.small_slabs {
-            empty_pages += sca.empty_slabs.elements;
+    /// Returns an empty 32 KiB slab of a large size class,
+    /// if the large size classes have more empty slabs than the threshold.
+    ///
+    /// Goes through the page quarantine like `retrieve_empty_page`.
+    pub fn retrieve_empty_large_page(&mut self, heap_empty_page_threshold: usize) -> Option<MappedPages> {
+        if self.page_quarantine.enabled {
+            return self.quarantine_pages(true, |zone| zone.take_empty_large_page(heap_empty_page_threshold));
         }
-        empty_pages
+        self.page_quarantine.pop(true, true).or_else(|| self.take_empty_large_page(heap_empty_page_threshold))
     }
-}
 
-unsafe impl<'a> crate::Allocator<'a> for ZoneAllocator<'a> {
-    /// Allocate a pointer to a block of memory described by `layout`.
-    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, &'static str> {
-        match ZoneAllocator::get_slab(layout.size()) {
-            Slab::Base(idx) => {
-                match self.small_slabs[idx].allocate(layout) {
-                    Ok(ptr) => Ok(ptr),
-                    Err(_e) => {
-                        self.exchange_pages_within_heap(layout)?;
-                        self.small_slabs[idx].allocate(layout)
+    /// Takes an empty 32 KiB slab out of a large size class, if there are more than the threshold.
+    fn take_empty_large_page(&mut self, heap_empty_page_threshold: usize) -> Option<MappedPages> {
+        if self.empty_large_pages() <= heap_empty_page_threshold {
+            return None;
+        }
+        let idx = (0..ZoneAllocator::MAX_LARGE_SIZE_CLASSES).find(|&idx| self.big_slabs[idx].empty_slabs.elements > 0)?;
+        self.release_empty_large_page(idx)
+    }
+
+    /// Takes an empty slab out of the large size class `idx` (of `big_slabs`) and records its release.
+    fn release_empty_large_page(&mut self, idx: usize) -> Option<MappedPages> {
+        let mp = self.big_slabs[idx].retrieve_empty_page()?;
+        let layout = Layout::from_size_align(ZoneAllocator::LARGE_ALLOC_SIZES[idx], 1).unwrap();
+        self.record(TraceOp::ReleasePage, ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, layout, mp.start_address().value());
+        self.update_shadow(TraceOp::ReleasePage, mp.start_address().value(), mp.size_in_bytes());
+        log_event(HeapEvent::PageReturned {
+            heap_id: self.heap_id,
+            object_size: self.big_slabs[idx].size(),
+            page: mp.start_address().value(),
+        });
+        Some(mp)
+    }
+
+    /// Lets the page `mp` (a 32 KiB slab if `large` is set), which was already taken out of its size class,
+    /// leave the zone. With the page quarantine enabled, `mp` is quarantined and a page that waited
+    /// for a `maintain` cycle leaves instead (if there is one). Once the quarantine is full,
+    /// `mp` leaves right away.
+    fn page_leaves(&mut self, mp: MappedPages, large: bool) -> Option<MappedPages> {
+        if !self.page_quarantine.enabled {
+            return Some(mp);
+        }
+        match self.page_quarantine.pop(large, false) {
+            Some(ready) => {
+                self.page_quarantine.push(mp, large);
+                Some(ready)
+            }
+            None if self.page_quarantine.is_full() => Some(mp),
+            None => {
+                self.page_quarantine.push(mp, large);
+                None
+            }
+        }
+    }
+
+    /// Takes an empty page out of the size class with the most empty pages (or an empty 32 KiB slab,
+    /// those first if `large_first` is set) if there are more than the threshold, bypassing the page quarantine.
+    ///
+    /// Returns the page and whether it is a 32 KiB slab.
+    fn take_any_empty_page(&mut self, threshold: usize, large_first: bool) -> Option<(MappedPages, bool)> {
+        if large_first {
+            self.take_empty_large_page(threshold)
+                .map(|mp| (mp, true))
+                .or_else(|| self.take_empty_page(threshold).map(|mp| (mp, false)))
+        } else {
+            self.take_empty_page(threshold)
+                .map(|mp| (mp, false))
+                .or_else(|| self.take_empty_large_page(threshold).map(|mp| (mp, true)))
+        }
+    }
+
+    /// Returns a quarantined page (a 32 KiB slab if `large` is set) that waited for a `maintain` cycle.
+    /// If there is none, the quarantine is filled up with the pages `take` hands out.
+    fn quarantine_pages<F>(&mut self, large: bool, mut take: F) -> Option<MappedPages>
+    where
+        F: FnMut(&mut ZoneAllocator<'a>) -> Option<MappedPages>,
+    {
+        if let Some(mp) = self.page_quarantine.pop(large, false) {
+            return Some(mp);
+        }
+        while !self.page_quarantine.is_full() {
+            match take(self) {
+                Some(mp) => self.page_quarantine.push(mp, large),
+                None => break,
+            }
+        }
+        None
+    }
+
+    /// Holds retrieved pages back for one `maintain` cycle if `enabled` (off by default), to catch late
+    /// frees into them before they are unmapped.
+    ///
+    /// `retrieve_empty_page` and `retrieve_empty_large_page` poison the pages they take and put them into
+    /// the quarantine (up to `PAGE_QUARANTINE_CAPACITY` of them), then hand them out after the next
+    /// `maintain`. Pages the zone hands to its reclaimer (`maintain`, `reclaim_surplus`, `shrink`) are
+    /// quarantined the same way, while pages that stay in the zone (`exchange_pages_within_heap`)
+    /// or leave coalesced (`retrieve_coalesced_empty_pages`) bypass it. Frees of objects in a quarantined page fail with `AllocationError::QuarantinedPage`
+    /// and writes into it are reported when it leaves, both are counted in `page_quarantine_violations`.
+    /// Pages that are still quarantined once it is disabled leave right away with the next retrievals.
+    pub fn set_page_quarantine(&mut self, enabled: bool) {
+        self.page_quarantine.enabled = enabled;
+    }
+
+    /// Number of late frees and writes into quarantined pages (see `set_page_quarantine`).
+    pub fn page_quarantine_violations(&self) -> usize {
+        self.page_quarantine.violations
+    }
+
+    /// Moves an empty page from the SCAllocator with empty pages into its retired list,
+    /// if there are more empty pages than the threshold (see `SCAllocator::retire_empty_page`).
+    ///
+    /// Returns `true` if a page was retired.
+    pub fn retire_empty_page(&mut self, heap_empty_page_threshold: usize, epoch: u64) -> bool {
+        if self.empty_pages() <= heap_empty_page_threshold {
+            return false;
+        }
+        let threshold = self.slab_empty_threshold;
+        self.small_slabs
+            .iter_mut()
+            .filter(|slab| slab.empty_slabs.elements > threshold)
+            .any(|slab| slab.retire_empty_page(epoch))
+    }
+
+    /// Returns a retired page that was retired before `quiescent_epoch`
+    /// (see `SCAllocator::reclaim_retired_page`).
+    pub fn reclaim_retired_page(&mut self, quiescent_epoch: u64) -> Option<MappedPages> {
+        self.small_slabs
+            .iter_mut()
+            .find_map(|slab| slab.reclaim_retired_page(quiescent_epoch))
+    }
+
+    /// Stops serving allocations from the page containing `addr` so it can be
+    /// retrieved once all of its objects are freed (see `SCAllocator::drain_page`).
+    pub fn drain_page(&mut self, addr: VAddr) -> Result<(), AllocationError> {
+        for slab in self.small_slabs.iter_mut() {
+            match slab.drain_page(addr) {
+                Err(AllocationError::InvalidPointer) => continue,
+                res => return res,
+            }
+        }
+        for slab in self.big_slabs.iter_mut() {
+            match slab.drain_page(addr) {
+                Err(AllocationError::InvalidPointer) => continue,
+                res => return res,
+            }
+        }
+        Err(AllocationError::InvalidPointer)
+    }
+
+    /// Returns the page that starts at `addr` if it is empty (see `SCAllocator::remove_page`).
+    pub fn remove_page(&mut self, addr: VAddr) -> Result<MappedPages, AllocationError> {
+        for slab in self.small_slabs.iter_mut() {
+            match slab.remove_page(addr) {
+                Err(AllocationError::InvalidPointer) => continue,
+                res => return res,
+            }
+        }
+        for slab in self.big_slabs.iter_mut() {
+            match slab.remove_page(addr) {
+                Err(AllocationError::InvalidPointer) => continue,
+                res => return res,
+            }
+        }
+        Err(AllocationError::InvalidPointer)
+    }
+
+    /// Returns an empty page merged with up to `max_pages - 1` empty pages that are
+    /// virtually contiguous to it (from any size class), so the frame allocator
+    /// gets back larger chunks instead of many `ObjectPage8k::SIZE` fragments.
+    ///
+    /// Returns `None` if there are no empty pages. The pages bypass the page quarantine
+    /// (see `set_page_quarantine`), which only holds single pages.
+    pub fn retrieve_coalesced_empty_pages(&mut self, max_pages: usize) -> Option<MappedPages> {
+        let mut mp = self.take_empty_page(0)?;
+        let mut pages = 1;
+
+        while pages < max_pages {
+            let start = mp.start_address().value();
+            let end = start + mp.size_in_bytes();
+
+            if let Some((idx, next)) = self.remove_page_of_any_class(end) {
+                if let Err((_e, next)) = mp.merge(next) {
+                    // Put it back where it was
+                    let _ = self.small_slabs[idx].refill(next, self.heap_id);
+                    break;
+                }
+            } else if let Some((idx, mut prev)) = start
+                .checked_sub(ObjectPage8k::SIZE)
+                .and_then(|addr| self.remove_page_of_any_class(addr))
+            {
+                match prev.merge(mp) {
+                    Ok(()) => mp = prev,
+                    Err((_e, merged)) => {
+                        let _ = self.small_slabs[idx].refill(prev, self.heap_id);
+                        mp = merged;
+                        break;
                     }
                 }
+            } else {
+                break;
             }
-            Slab::Large(_idx) => Err("AllocationError::InvalidLayout"),
-            Slab::Unsupported => Err("AllocationError::InvalidLayout"),
+            pages += 1;
         }
+
+        Some(mp)
     }
 
-    /// Deallocates a pointer to a block of memory, which was
-    /// previously allocated by `allocate`.
+    /// Removes the empty page at `addr` from whichever size class owns it.
+    fn remove_page_of_any_class(&mut self, addr: VAddr) -> Option<(usize, MappedPages)> {
+        self.small_slabs
+            .iter_mut()
+            .enumerate()
+            .find_map(|(idx, slab)| slab.remove_page(addr).ok().map(|mp| (idx, mp)))
+    }
+
+    /// Tears down the zone and hands the `MappedPages` of every page it owns to `f`.
     ///
-    /// # Arguments
-    ///  * `ptr` - Address of the memory location to free.
-    ///  * `layout` - Memory layout of the block pointed to by `ptr`.
-    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), &'static str> {
-        match ZoneAllocator::get_slab(layout.size()) {
-            Slab::Base(idx) => self.small_slabs[idx].deallocate(ptr, layout),
-            Slab::Large(_idx) => Err("AllocationError::InvalidLayout"),
-            Slab::Unsupported => Err("AllocationError::InvalidLayout"),
+    /// Unless `force` is set, the zone is only destroyed if no objects are allocated from it,
+    /// otherwise it fails with `AllocationError::PageNotEmpty` and keeps its pages
+    /// (quarantined objects are flushed first). With `force` the zone is always destroyed,
+    /// and the number of leaked objects is returned. A destroyed zone owns no pages.
+    pub fn destroy<F: FnMut(MappedPages)>(&mut self, force: bool, mut f: F) -> Result<usize, AllocationError> {
+        if let Err(e) = self.flush_quarantine() {
+            error!("Failed to flush the quarantine of heap {}: {}", self.heap_id, e);
+        }
+        self.rebalance();
+
+        let mut live = 0;
+        self.for_each_allocated(|_ptr, _size| live += 1);
+        if live > 0 && !force {
+            return Err(AllocationError::PageNotEmpty);
+        }
+
+        let mut leaked = 0;
+        for large in [false, true] {
+            while let Some(mp) = self.page_quarantine.pop(large, true) {
+                f(mp);
+            }
+        }
+        for slab in self.small_slabs.iter_mut() {
+            leaked += slab.take_all_pages(&mut f);
         }
+        for slab in self.big_slabs.iter_mut() {
+            leaked += slab.take_all_pages(&mut f);
+        }
+        if leaked > 0 {
+            error!("Destroyed heap {} while {} objects were still allocated", self.heap_id, leaked);
+        }
+        Ok(leaked)
     }
 
-    /// Refills the SCAllocator for a given Layout with an ObjectPage.
+    /// Limits the number of pages every size class may own (see `SCAllocator::set_max_pages`).
+    pub fn set_max_pages_per_class(&mut self, max_pages: usize) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_max_pages(max_pages);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_max_pages(max_pages);
+        }
+    }
+
+    /// Number of empty pages every base size class keeps when `retrieve_empty_page` or
+    /// `retire_empty_page` look for an empty page (0, the default, lets a class give up its last one).
     ///
-    /// # Safety
-    /// ObjectPage needs to be emtpy etc.
-    fn refill(
-        &mut self,
-        layout: Layout,
-        mp: MappedPages,
-    ) -> Result<(), &'static str> {
-        match ZoneAllocator::get_slab(layout.size()) {
+    /// Keeping an empty page per class saves a `refill` when the class is used again,
+    /// at the cost of memory that the heap can't hand back.
+    pub fn set_slab_empty_pages_threshold(&mut self, pages: usize) {
+        self.slab_empty_threshold = pages;
+    }
+
+    /// Returns the number of empty pages every base size class keeps (see `set_slab_empty_pages_threshold`).
+    pub fn slab_empty_pages_threshold(&self) -> usize {
+        self.slab_empty_threshold
+    }
+
+    /// Holds back `pages` empty pages in every size class for `try_allocate`
+    /// (see `SCAllocator::set_emergency_reserve`), so interrupt handlers can allocate
+    /// in bounded time even when the partial pages are used up.
+    ///
+    /// The reserve pages are neither used by `allocate` nor moved to other size classes.
+    pub fn set_emergency_reserve(&mut self, pages: usize) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_emergency_reserve(pages);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_emergency_reserve(pages);
+        }
+    }
+
+    /// Limits the memory (in bytes) of all the pages owned by the zone,
+    /// `refill` fails with `AllocationError::LimitExceeded` if a new page would exceed it
+    /// (0, the default, means unlimited).
+    ///
+    /// Once the budget is used up, allocations can only be served by moving
+    /// empty pages between size classes.
+    pub fn set_total_budget_bytes(&mut self, budget_bytes: usize) {
+        self.budget_bytes = budget_bytes;
+    }
+
+    /// Returns the memory budget of the zone in bytes (0 means unlimited).
+    pub fn total_budget_bytes(&self) -> usize {
+        self.budget_bytes
+    }
+
+    /// Number of pages owned by the zone (a 32 KiB slab of a large size class counts as one page).
+    pub fn pages(&self) -> usize {
+        self.small_slabs.iter().map(|slab| slab.pages()).sum::<usize>()
+            + self.big_slabs.iter().map(|slab| slab.pages()).sum::<usize>()
+    }
+
+    /// Returns how much of the memory of the zone is in use, summed over all size classes
+    /// (see `SCAllocator::utilization`), e.g., for a status bar or a watermark policy.
+    ///
+    /// Cheaper than `size_class_stats` or `iter_pages`, only partial pages are looked into.
+    pub fn utilization(&self) -> Utilization {
+        let mut total = Utilization::default();
+        let classes = self.small_slabs.iter().map(|slab| slab.utilization());
+        for class in classes.chain(self.big_slabs.iter().map(|slab| slab.utilization())) {
+            total.live_bytes += class.live_bytes;
+            total.capacity_bytes += class.capacity_bytes;
+            total.pages += class.pages;
+        }
+        total
+    }
+
+    /// Returns the pages and objects of every size class, prints as a readable table
+    /// (e.g., `info!("{}", zone.stats())`).
+    pub fn stats(&self) -> ZoneStats {
+        let mut stats = ZoneStats {
+            classes: [ClassUsage::default(); ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES],
+            total: Utilization::default(),
+        };
+        for (class, usage) in stats.classes.iter_mut().enumerate() {
+            *usage = if class < ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+                ClassUsage::of(&self.small_slabs[class])
+            } else {
+                ClassUsage::of(&self.big_slabs[class - ZoneAllocator::MAX_BASE_SIZE_CLASSES])
+            };
+            stats.total.live_bytes += usage.utilization.live_bytes;
+            stats.total.capacity_bytes += usage.utilization.capacity_bytes;
+            stats.total.pages += usage.utilization.pages;
+        }
+        stats
+    }
+
+    /// Iterates over all pages of the zone (retired pages excluded), class by class
+    /// and for each class over its empty, partial, full and draining pages.
+    ///
+    /// Meant for memory dashboards and debugging shells, the pages must not be
+    /// changed meanwhile (which the shared borrow ensures for this zone, but not for
+    /// `deallocate_unlocked` calls, which may make the counts outdated).
+    pub fn iter_pages(&self) -> ZonePages<'_, 'a> {
+        ZonePages {
+            zone: self,
+            class: 0,
+            lists: 0,
+            cursor: 0,
+        }
+    }
+
+    /// Number of bytes of all the pages owned by the zone.
+    pub fn page_bytes(&self) -> usize {
+        self.small_slabs.iter().map(|slab| slab.pages()).sum::<usize>() * ObjectPage8k::SIZE
+            + self.big_slabs.iter().map(|slab| slab.pages()).sum::<usize>() * ObjectPage32k::SIZE
+    }
+
+    /// Moves an empty page from another size class to the one serving `layout`.
+    ///
+    /// Fails with `AllocationError::LimitExceeded` (without moving anything)
+    /// if the size class already owns as many pages as it may.
+    pub fn exchange_pages_within_heap(&mut self, layout: Layout) -> Result<(), AllocationError> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                if self.small_slabs[idx].at_page_limit() {
+                    return Err(AllocationError::LimitExceeded);
+                }
+            }
+            Slab::Large(idx) => return self.exchange_large_pages(idx),
+            Slab::Unsupported => {}
+        }
+        // The page stays in the zone, so it doesn't go through the page quarantine
+        let mp = self.take_empty_page(0).ok_or(AllocationError::OutOfMemory)?;
+        self.refill(layout, mp)
+    }
+
+    /// Moves an empty slab from another large size class to the large size class `idx`.
+    fn exchange_large_pages(&mut self, idx: usize) -> Result<(), AllocationError> {
+        if self.big_slabs[idx].at_page_limit() {
+            return Err(AllocationError::LimitExceeded);
+        }
+        let src = (0..ZoneAllocator::MAX_LARGE_SIZE_CLASSES)
+            .filter(|&src| src != idx)
+            .max_by_key(|&src| self.big_slabs[src].empty_slabs.elements)
+            .ok_or(AllocationError::OutOfMemory)?;
+        let page = self.big_slabs[src].take_empty_page().ok_or(AllocationError::OutOfMemory)?;
+        if let Err(page) = self.big_slabs[idx].adopt_empty_page(page, self.heap_id) {
+            let _ = self.big_slabs[src].adopt_empty_page(page, self.heap_id);
+            return Err(AllocationError::LimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Moves an empty page from `other` (e.g., a sibling per-core heap) to the size class
+    /// of this zone serving `layout`, without going back to the frame allocator.
+    ///
+    /// An empty page of the same size class is preferred, otherwise the page is taken
+    /// from the size class of `other` with the most empty pages.
+    /// Fails with `AllocationError::OutOfMemory` if `other` has no empty pages and with
+    /// `AllocationError::LimitExceeded` if this zone can't take another page.
+    pub fn exchange_pages(&mut self, other: &mut ZoneAllocator<'a>, layout: Layout) -> Result<(), AllocationError> {
+        let idx = match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => idx,
+            Slab::Large(_idx) => return Err(AllocationError::InvalidLayout),
+            Slab::Unsupported => return Err(AllocationError::TooLarge),
+        };
+        if self.small_slabs[idx].at_page_limit() || self.exceeds_budget(ObjectPage8k::SIZE) {
+            return Err(AllocationError::LimitExceeded);
+        }
+
+        let src = if other.small_slabs[idx].empty_slabs.elements > 0 {
+            idx
+        } else {
+            (0..ZoneAllocator::MAX_BASE_SIZE_CLASSES)
+                .max_by_key(|&i| other.small_slabs[i].empty_slabs.elements)
+                .unwrap_or(idx)
+        };
+        let page = other.small_slabs[src].take_empty_page().ok_or(AllocationError::OutOfMemory)?;
+        if let Err(page) = self.small_slabs[idx].adopt_empty_page(page, self.heap_id) {
+            // The page just left this size class, so it has room for it
+            let _ = other.small_slabs[src].adopt_empty_page(page, other.heap_id);
+            return Err(AllocationError::LimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Seeds the size class serving `layout` with the pages of a static buffer,
+    /// before `MappedPages` can be created (see `SCAllocator::refill_bootstrap`).
+    ///
+    /// Returns the number of pages that were added. Once the memory subsystem is up,
+    /// hand the pages their `MappedPages` with `adopt_mapped_pages`.
+    pub fn refill_bootstrap<const N: usize>(&mut self, layout: Layout, region: &'static mut [u8; N]) -> Result<usize, AllocationError> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
             Slab::Base(idx) => {
-                self.small_slabs[idx].refill(mp, self.heap_id)
+                if self.exceeds_budget(ObjectPage8k::SIZE) {
+                    return Err(AllocationError::LimitExceeded);
+                }
+                self.small_slabs[idx].refill_bootstrap(region, self.heap_id)
+            }
+            Slab::Large(idx) => {
+                if self.exceeds_budget(ObjectPage32k::SIZE) {
+                    return Err(AllocationError::LimitExceeded);
+                }
+                self.big_slabs[idx].refill_bootstrap(region, self.heap_id)
+            }
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Hands `mp` to the bootstrap page of this zone it covers (see `SCAllocator::adopt_mapped_pages`).
+    ///
+    /// `mp` is handed back with `AllocationError::InvalidPointer` if no size class has
+    /// a bootstrap page at its address.
+    pub fn adopt_mapped_pages(&mut self, mp: MappedPages) -> Result<(), (AllocationError, MappedPages)> {
+        let addr = mp.start_address().value();
+        let page = BasePage::from_address(addr);
+        if mp.size_in_bytes() == BasePage::SIZE && !page.is_null() {
+            if let Some(slab) = self.small_slabs.iter_mut().find_map(|slab| slab.owns_page(page).then_some(slab)) {
+                return slab.adopt_mapped_pages(mp);
+            }
+        }
+        let slab_page = ObjectPage32k::from_address(addr);
+        if mp.size_in_bytes() == ObjectPage32k::SIZE {
+            if let Some(slab) = self.big_slabs.iter_mut().find_map(|slab| slab.owns_page(slab_page).then_some(slab)) {
+                return slab.adopt_mapped_pages(mp);
+            }
+        }
+        Err((AllocationError::InvalidPointer, mp))
+    }
+
+    /// Returns the physical address `ptr` is mapped to, e.g., to hand an object
+    /// (like a DMA descriptor) to a device.
+    ///
+    /// Returns `None` if `ptr` doesn't point into a page of this zone or the physical
+    /// address of the page is unknown (caller supplied regions need `MappedPages::with_physical_address`).
+    /// The page is looked up in the page lists, so this takes time linear in the number of pages.
+    pub fn physical_address_of(&mut self, ptr: NonNull<u8>) -> Option<PhysicalAddress> {
+        let addr = ZoneAllocator::object_address(ptr);
+        self.mapped_pages_of(addr).and_then(|mp| physical_address(mp, addr))
+    }
+
+    /// Returns the flags of the mapping backing `ptr`, or `None` if `ptr` doesn't point into
+    /// a page of this zone.
+    ///
+    /// Like `physical_address_of`, this takes time linear in the number of pages.
+    pub fn page_flags_of(&mut self, ptr: NonNull<u8>) -> Option<PageFlags> {
+        self.mapped_pages_of(ZoneAllocator::object_address(ptr)).map(|mp| mp.flags())
+    }
+
+    /// Returns the usable size of the object at `ptr` (the object size of its size class,
+    /// which may be bigger than the size it was allocated with), like `malloc_usable_size`.
+    ///
+    /// Returns `None` if `ptr` is not the start of a live object of this zone. With the
+    /// `red-zones` feature only the size in the `Layout` is usable, so it always returns `None`.
+    /// Like `physical_address_of`, this takes time linear in the number of pages.
+    pub fn size_of_allocation(&mut self, ptr: NonNull<u8>) -> Option<usize> {
+        if cfg!(feature = "red-zones") {
+            return None;
+        }
+        let addr = ZoneAllocator::object_address(ptr);
+        let page = BasePage::from_address(addr);
+        if !page.is_null() {
+            if let Some(slab) = self.small_slabs.iter_mut().find_map(|slab| slab.owns_page(page).then_some(slab)) {
+                return slab.object_size_at(unsafe { &*page }, addr);
             }
-            Slab::Large(_idx) => Err("AllocationError::InvalidLayout"),
-            Slab::Unsupported => Err("AllocationError::InvalidLayout"),
         }
+        let slab_page = ObjectPage32k::from_address(addr);
+        let slab = self.big_slabs.iter_mut().find_map(|slab| slab.owns_page(slab_page).then_some(slab))?;
+        slab.object_size_at(unsafe { &*slab_page }, addr)
+    }
+
+    /// Like `deallocate`, but also tells what happened to the page the object went back to
+    /// (see `SCAllocator::deallocate_ext`), so the embedding heap can trigger reclamation right away.
+    ///
+    /// A page that became empty is reported as `PageTransition::Empty` even if the zone already
+    /// passed it on to its empty page pool or reclaimed it. Zero-sized, forwarded and
+    /// fallback deallocations leave the pages of the zone `PageTransition::Unchanged`.
+    pub fn deallocate_ext(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<PageTransition, AllocationError> {
+        if layout.size() == 0 {
+            return Ok(PageTransition::Unchanged);
+        }
+        let ptr = ZoneAllocator::release_tag(ptr, layout)?;
+        if self.page_quarantine.contains(ptr.as_ptr() as VAddr) {
+            error!("{:p} lies in a page that was already retrieved from heap {}", ptr, self.heap_id);
+            self.page_quarantine.violations += 1;
+            return Err(AllocationError::QuarantinedPage);
+        }
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                let start = self.latency_start();
+                self.periodic_verify();
+                let transition = match self.small_slabs[idx].deallocate_ext(ptr, layout) {
+                    Err(AllocationError::InvalidPointer) => {
+                        return self.misdirected_free(ptr, layout, ZoneAllocator::page_heap_id::<BasePage>);
+                    }
+                    res => res?,
+                };
+                self.record(TraceOp::Deallocate, idx, layout, ptr.as_ptr() as usize);
+                self.update_shadow(TraceOp::Deallocate, ptr.as_ptr() as usize, layout.size());
+                self.overflow_to_pool();
+                self.reclaim_surplus();
+                self.record_latency(TraceOp::Deallocate, idx, start);
+                self.check_watermarks();
+                Ok(transition)
+            }
+            Slab::Large(idx) => {
+                let start = self.latency_start();
+                self.periodic_verify();
+                let transition = match self.big_slabs[idx].deallocate_ext(ptr, layout) {
+                    Err(AllocationError::InvalidPointer) => {
+                        return self.misdirected_free(ptr, layout, ZoneAllocator::page_heap_id::<ObjectPage32k>);
+                    }
+                    res => res?,
+                };
+                self.record(TraceOp::Deallocate, ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, layout, ptr.as_ptr() as usize);
+                self.update_shadow(TraceOp::Deallocate, ptr.as_ptr() as usize, layout.size());
+                self.reclaim_surplus();
+                self.record_latency(TraceOp::Deallocate, ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, start);
+                self.check_watermarks();
+                Ok(transition)
+            }
+            Slab::Unsupported => match self.fallback {
+                Some(fallback) => fallback.deallocate(ptr, layout).map(|_| PageTransition::Unchanged),
+                None => Err(AllocationError::TooLarge),
+            },
+        }
+    }
+
+    /// Handles a free of `ptr` that its size class rejected since the page isn't one of its own:
+    /// forwards it to the heap the page belongs to (see `set_cross_heap_free`) or
+    /// fails with `AllocationError::WrongHeap`. Only then the page header is read (with `heap_id`),
+    /// the `minimal` build trusts the caller to free objects to their own heap and doesn't read it at all.
+    fn misdirected_free(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        heap_id: fn(NonNull<u8>) -> Option<usize>,
+    ) -> Result<PageTransition, AllocationError> {
+        if cfg!(feature = "minimal") {
+            return Err(AllocationError::InvalidPointer);
+        }
+        match heap_id(ptr) {
+            Some(heap_id) if heap_id != self.heap_id => match self.cross_heap {
+                Some(cross_heap) => cross_heap.forward(heap_id, ptr, layout).map(|_| PageTransition::Unchanged),
+                None => {
+                    error!("{:p} belongs to heap {}, not heap {}", ptr, heap_id, self.heap_id);
+                    Err(AllocationError::WrongHeap(heap_id))
+                }
+            },
+            _ => Err(AllocationError::InvalidPointer),
+        }
+    }
+
+    /// Like `allocate`, but also returns how many free slots are left in the serving page and
+    /// size class (see `SCAllocator::allocate_ext`), so the caller can refill ahead of time.
+    ///
+    /// Zero-sized and fallback allocations don't take a slot, they report no free slots.
+    #[track_caller]
+    pub fn allocate_ext(&mut self, layout: Layout) -> Result<AllocationInfo, AllocationError> {
+        let ptr = self.allocate_with_hint(layout, AllocHint::ShortLived)?;
+        let addr = ZoneAllocator::object_address(ptr);
+        Ok(match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) if layout.size() > 0 => self.small_slabs[idx].allocation_info(ptr, addr),
+            Slab::Large(idx) => self.big_slabs[idx].allocation_info(ptr, addr),
+            _ => AllocationInfo { ptr, page_free: 0, class_free: 0 },
+        })
+    }
+
+    /// Like `allocate`, but also returns the generation of the object, to be checked
+    /// with `validate_handle` before a stored handle to the object is used.
+    ///
+    /// A page of the largest size class has no room for a generation table, the generation of
+    /// its object is always 0 so a handle to it only tells if the object is still allocated.
+    #[cfg(feature = "generations")]
+    #[track_caller]
+    pub fn allocate_with_generation(&mut self, layout: Layout) -> Result<(NonNull<u8>, u32), AllocationError> {
+        let ptr = self.allocate_with_hint(layout, AllocHint::ShortLived)?;
+        let addr = ZoneAllocator::object_address(ptr);
+        let generation = match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) if layout.size() > 0 => SCAllocator::<BasePage>::generation_at(addr, self.small_slabs[idx].size()),
+            Slab::Large(idx) => SCAllocator::<ObjectPage32k>::generation_at(addr, self.big_slabs[idx].size()),
+            // Zero-sized and fallback allocations have no slot to keep a generation in
+            _ => 0,
+        };
+        Ok((ptr, generation))
+    }
+
+    /// Returns `true` if `ptr` still points to the live object that had generation `expected`
+    /// when it was allocated (see `allocate_with_generation`), `false` once it was freed,
+    /// even if its slot was handed out again.
+    ///
+    /// Like `physical_address_of`, this takes time linear in the number of pages.
+    /// Objects sitting in a cache or free stack of a wrapping allocator still count as live.
+    #[cfg(feature = "generations")]
+    pub fn validate_handle(&mut self, ptr: NonNull<u8>, expected: u32) -> bool {
+        let addr = ZoneAllocator::object_address(ptr);
+        let page = BasePage::from_address(addr);
+        if !page.is_null() {
+            if let Some(slab) = self.small_slabs.iter_mut().find_map(|slab| slab.owns_page(page).then_some(slab)) {
+                return slab.handle_matches(unsafe { &*page }, addr, expected);
+            }
+        }
+        let slab_page = ObjectPage32k::from_address(addr);
+        match self.big_slabs.iter_mut().find_map(|slab| slab.owns_page(slab_page).then_some(slab)) {
+            Some(slab) => slab.handle_matches(unsafe { &*slab_page }, addr, expected),
+            None => false,
+        }
+    }
+
+    /// Grows the object at `ptr` (allocated with `old_layout`) to `new_layout` without moving it.
+    ///
+    /// This works as long as `new_layout` is served by the same size class (and `ptr` is aligned
+    /// for it), otherwise it fails with `AllocationError::InvalidLayout` and the object is left
+    /// as is, so the caller can fall back to allocating a new object and copying.
+    /// The new bytes are not initialized. From now on the object has to be freed with `new_layout`.
+    pub fn grow_in_place(&mut self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<(), AllocationError> {
+        if new_layout.size() < old_layout.size() {
+            return Err(AllocationError::InvalidLayout);
+        }
+        self.resize_in_place(ptr, old_layout, new_layout)
+    }
+
+    /// Shrinks the object at `ptr` (allocated with `old_layout`) to `new_layout` without moving it,
+    /// see `grow_in_place`.
+    pub fn shrink_in_place(&mut self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<(), AllocationError> {
+        if new_layout.size() > old_layout.size() {
+            return Err(AllocationError::InvalidLayout);
+        }
+        self.resize_in_place(ptr, old_layout, new_layout)
+    }
+
+    fn resize_in_place(&mut self, ptr: NonNull<u8>, old_layout: Layout, new_layout: Layout) -> Result<(), AllocationError> {
+        // Zero-sized objects are dangling pointers, not objects of a size class
+        if old_layout.size() == 0 || new_layout.size() == 0 {
+            return Err(AllocationError::InvalidLayout);
+        }
+        let tagged = ptr;
+        let ptr = NonNull::new(ZoneAllocator::object_address(ptr) as *mut u8).ok_or(AllocationError::InvalidPointer)?;
+        let res = match (
+            ZoneAllocator::get_slab(ZoneAllocator::slot_size(old_layout)),
+            ZoneAllocator::get_slab(ZoneAllocator::slot_size(new_layout)),
+        ) {
+            (Slab::Base(old), Slab::Base(new)) if old == new => {
+                self.small_slabs[old].resize_in_place(ptr, old_layout, new_layout)
+            }
+            (Slab::Large(old), Slab::Large(new)) if old == new => {
+                self.big_slabs[old].resize_in_place(ptr, old_layout, new_layout)
+            }
+            _ => Err(AllocationError::InvalidLayout),
+        };
+        if res.is_ok() {
+            // The sanitizer sees the old object go and the resized one come
+            self.update_shadow(TraceOp::Deallocate, ptr.as_ptr() as usize, old_layout.size());
+            self.update_shadow(TraceOp::Allocate, ptr.as_ptr() as usize, new_layout.size());
+        }
+        #[cfg(feature = "mte")]
+        if res.is_ok() {
+            // Safety: the object was resized within its slot
+            unsafe { mte::resize_tag(tagged, old_layout.size(), new_layout.size()) };
+        }
+        #[cfg(not(feature = "mte"))]
+        let _ = tagged;
+        res
+    }
+
+    /// The address of the object at `ptr`, without the MTE tag (see `set_memory_tagging`).
+    #[inline]
+    fn object_address(ptr: NonNull<u8>) -> VAddr {
+        let addr = ptr.as_ptr() as VAddr;
+        #[cfg(feature = "mte")]
+        let addr = mte::untagged(addr);
+        addr
+    }
+
+    /// Colors a tagged object of `layout` with tag 0 again before it is freed
+    /// and returns its untagged pointer (see `set_memory_tagging`).
+    #[inline]
+    fn release_tag(ptr: NonNull<u8>, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        #[cfg(feature = "mte")]
+        if !matches!(ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)), Slab::Unsupported) {
+            // Safety: a tagged pointer was handed out by a zone
+            return unsafe { mte::untag_object(ptr, layout.size()) };
+        }
+        #[cfg(not(feature = "mte"))]
+        let _ = layout;
+        Ok(ptr)
+    }
+
+    /// Finds the `MappedPages` of the page in this zone that contains `addr`.
+    fn mapped_pages_of(&mut self, addr: VAddr) -> Option<&MappedPages> {
+        let page = BasePage::from_address(addr);
+        if !page.is_null() && self.small_slabs.iter_mut().any(|slab| slab.owns_page(page)) {
+            return Some(unsafe { (*page).mapped_pages() });
+        }
+        let slab_page = ObjectPage32k::from_address(addr);
+        if self.big_slabs.iter_mut().any(|slab| slab.owns_page(slab_page)) {
+            return Some(unsafe { (*slab_page).mapped_pages() });
+        }
+        None
+    }
+
+    /// Invokes `f` with the address and size class of every live allocation in this zone.
+    ///
+    /// Can be used to find leaked objects.
+    pub fn for_each_allocated<F: FnMut(NonNull<u8>, usize)>(&mut self, mut f: F) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.for_each_allocated(&mut f);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.for_each_allocated(&mut f);
+        }
+    }
+
+    /// Rebalances the page lists of all size classes (see `SCAllocator::rebalance`).
+    ///
+    /// Returns the number of pages that were moved.
+    pub fn rebalance(&mut self) -> usize {
+        self.small_slabs.iter_mut().map(|slab| slab.rebalance()).sum::<usize>()
+            + self.big_slabs.iter_mut().map(|slab| slab.rebalance()).sum::<usize>()
+    }
+
+    /// Sets the automatic rebalance interval of all size classes
+    /// (see `SCAllocator::set_rebalance_interval`).
+    pub fn set_rebalance_interval(&mut self, interval: usize) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_rebalance_interval(interval);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_rebalance_interval(interval);
+        }
+    }
+
+    /// Redistributes the empty pages of the zone over the size classes according to
+    /// their recent demand (see `SCAllocator::update_allocation_rate`), so classes that
+    /// are allocated from a lot don't have to steal a page on their next refill.
+    ///
+    /// Every size class gets a share of the empty pages that is proportional to the pages its
+    /// allocations used up in the last periods. Meant to be called periodically, e.g.,
+    /// from a background maintenance task (the length of the period is up to the caller).
+    /// Pruned size classes get no pages.
+    ///
+    /// Returns the number of pages that were moved.
+    pub fn rebalance_classes(&mut self) -> usize {
+        let mut needed = [0usize; ZoneAllocator::MAX_BASE_SIZE_CLASSES];
+        for (need, slab) in needed.iter_mut().zip(self.small_slabs.iter_mut()) {
+            let rate = slab.update_allocation_rate();
+            *need = (rate + slab.obj_per_page - 1) / core::cmp::max(slab.obj_per_page, 1);
+        }
+        let total_needed: usize = needed.iter().sum();
+        if total_needed == 0 {
+            return 0;
+        }
+
+        let empty = self.empty_pages();
+        let mut target = [0usize; ZoneAllocator::MAX_BASE_SIZE_CLASSES];
+        for (target, need) in target.iter_mut().zip(needed.iter()) {
+            *target = empty * need / total_needed;
+        }
+
+        let mut moved = 0;
+        for to in 0..ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+            if ZoneAllocator::PRUNED_BASE_CLASSES[to] {
+                continue;
+            }
+            while self.small_slabs[to].empty_slabs.elements < target[to] {
+                let from = match (0..ZoneAllocator::MAX_BASE_SIZE_CLASSES)
+                    .find(|&from| self.small_slabs[from].empty_slabs.elements > target[from])
+                {
+                    Some(from) => from,
+                    None => return moved,
+                };
+                let page = match self.small_slabs[from].take_empty_page() {
+                    Some(page) => page,
+                    None => return moved,
+                };
+                if let Err(page) = self.small_slabs[to].adopt_empty_page(page, self.heap_id) {
+                    let _ = self.small_slabs[from].adopt_empty_page(page, self.heap_id);
+                    break;
+                }
+                moved += 1;
+            }
+        }
+        moved
+    }
+
+    /// Sets the order in which empty pages are reused, for all size classes
+    /// (see `SCAllocator::set_page_reuse_policy`).
+    pub fn set_page_reuse_policy(&mut self, policy: PageReusePolicy) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_page_reuse_policy(policy);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_page_reuse_policy(policy);
+        }
+    }
+
+    /// Sets which partial page serves the next allocation, for all size classes
+    /// (see `SCAllocator::set_page_selection_policy`).
+    pub fn set_page_selection_policy(&mut self, policy: PageSelectionPolicy) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_page_selection_policy(policy);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_page_selection_policy(policy);
+        }
+    }
+
+    /// Invokes `f` with the address, size class and call-site (if it was recorded)
+    /// of every live allocation in this zone (see `SCAllocator::for_each_allocated_callsite`).
+    #[cfg(feature = "callsites")]
+    pub fn for_each_allocated_callsite<F>(&mut self, mut f: F)
+    where
+        F: FnMut(NonNull<u8>, usize, Option<&'static Location<'static>>),
+    {
+        for slab in self.small_slabs.iter_mut() {
+            slab.for_each_allocated_callsite(&mut f);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.for_each_allocated_callsite(&mut f);
+        }
+    }
+
+    /// Randomizes the slot objects are placed in, for all size classes
+    /// (see `SCAllocator::set_randomized_slots`).
+    ///
+    /// Every size class derives its own seed from `seed`.
+    pub fn set_randomized_slots(&mut self, seed: Option<u64>) {
+        for (idx, slab) in self.small_slabs.iter_mut().enumerate() {
+            slab.set_randomized_slots(seed.map(|seed| seed.rotate_left(idx as u32 * 5) ^ idx as u64));
+        }
+        for (idx, slab) in self.big_slabs.iter_mut().enumerate() {
+            let idx = ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx;
+            slab.set_randomized_slots(seed.map(|seed| seed.rotate_left(idx as u32 * 5) ^ idx as u64));
+        }
+    }
+
+    /// Requires the pages of all size classes to have `flags` (see `SCAllocator::set_required_flags`).
+    pub fn set_required_flags(&mut self, flags: PageFlags) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_required_flags(flags);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_required_flags(flags);
+        }
+    }
+
+    /// Requires the pages of the size class serving `layout` to have `flags`,
+    /// see `SCAllocator::set_required_flags`.
+    pub fn set_required_flags_for(&mut self, layout: Layout, flags: PageFlags) -> Result<(), AllocationError> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => self.small_slabs[idx].set_required_flags(flags),
+            Slab::Large(idx) => self.big_slabs[idx].set_required_flags(flags),
+            Slab::Unsupported => return Err(AllocationError::TooLarge),
+        }
+        Ok(())
+    }
+
+    /// Requires the pages of the size class serving `layout` to be followed by an unmapped
+    /// guard page, see `SCAllocator::set_guard_pages` (`None` lifts the requirement).
+    ///
+    /// Meant for the classes of high-value kernel objects, whose overflows should fault
+    /// right away. Fails with `AllocationError::InvalidPage` for classes whose pages keep
+    /// their meta-data in-band (all of them without the `out-of-band-metadata` feature).
+    pub fn set_guard_pages_for(&mut self, layout: Layout, check: Option<&'a dyn GuardPageCheck>) -> Result<(), AllocationError> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => self.small_slabs[idx].set_guard_pages(check),
+            Slab::Large(idx) => self.big_slabs[idx].set_guard_pages(check),
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Returns the flags the pages of the size class serving `layout` must have.
+    pub fn required_flags_for(&self, layout: Layout) -> Option<PageFlags> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => Some(self.small_slabs[idx].required_flags()),
+            Slab::Large(idx) => Some(self.big_slabs[idx].required_flags()),
+            Slab::Unsupported => None,
+        }
+    }
+
+    /// Colors the fresh pages of all size classes (see `SCAllocator::set_cache_coloring`).
+    pub fn set_cache_coloring(&mut self, enabled: bool) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_cache_coloring(enabled);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_cache_coloring(enabled);
+        }
+    }
+
+    /// Sets whether freed objects and empty pages are zeroed, for all size classes.
+    /// With the `debug-poison` feature they are poisoned instead (see `ZeroOnFree`).
+    pub fn set_zero_on_free(&mut self, mode: ZeroOnFree) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_zero_on_free(mode);
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_zero_on_free(mode);
+        }
+    }
+
+    /// Sets how many freed objects each size class holds back before they can be reused
+    /// (see `SCAllocator::set_quarantine`).
+    pub fn set_quarantine(&mut self, limit: usize) -> Result<(), AllocationError> {
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_quarantine(limit)?;
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_quarantine(limit)?;
+        }
+        Ok(())
+    }
+
+    /// Returns all quarantined objects of all size classes to their pages.
+    pub fn flush_quarantine(&mut self) -> Result<(), AllocationError> {
+        for slab in self.small_slabs.iter_mut() {
+            slab.flush_quarantine()?;
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.flush_quarantine()?;
+        }
+        Ok(())
+    }
+
+    /// Allocates a block of memory described by `layout` in bounded time (e.g., from
+    /// an interrupt handler), see `SCAllocator::try_allocate`.
+    ///
+    /// Unlike `allocate` this never borrows empty pages from other size classes,
+    /// it fails with `AllocationError::WouldBlock` instead. Empty pages of the size class
+    /// are only started with an emergency reserve (see `set_emergency_reserve`).
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn try_allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        if layout.size() == 0 {
+            return Ok(ZoneAllocator::dangling(layout));
+        }
+        if self.inject_failure() {
+            return Err(AllocationError::OutOfMemory);
+        }
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                let ptr = self.small_slabs[idx].try_allocate(layout)?;
+                Ok(self.on_allocation(idx, layout, ptr))
+            }
+            Slab::Large(idx) => {
+                let ptr = self.big_slabs[idx].try_allocate(layout)?;
+                Ok(self.on_allocation(ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, layout, ptr))
+            }
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Deallocates `ptr` without access to (and without locking) the zone it came from.
+    ///
+    /// Only the bit of the object in its page's atomic bitfield is cleared.
+    /// Moving the page to the right list is deferred until the owning `SCAllocator`
+    /// runs out of partial pages on `allocate` (or `SCAllocator::rebalance`
+    /// is called). The quarantine and zero-on-free are bypassed.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated with `layout` from a `ZoneAllocator`
+    /// (or `ShardedZoneAllocator`) and its page must not be returned concurrently,
+    /// i.e., retire pages with `retire_empty_page` instead of `retrieve_empty_page`.
+    pub unsafe fn deallocate_unlocked(ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        if layout.size() == 0 {
+            return Ok(());
+        }
+        let ptr = ZoneAllocator::release_tag(ptr, layout)?;
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                ZoneAllocator::deallocate_in_page::<BasePage>(ptr, layout, ZoneAllocator::BASE_ALLOC_SIZES[idx])
+            }
+            Slab::Large(idx) => {
+                ZoneAllocator::deallocate_in_page::<ObjectPage32k>(ptr, layout, ZoneAllocator::LARGE_ALLOC_SIZES[idx])
+            }
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Clears the bit of `ptr` (an object of `size` bytes) in the bitfield of its page of type `P`.
+    unsafe fn deallocate_in_page<'p, P: AllocablePage + 'p>(
+        ptr: NonNull<u8>,
+        layout: Layout,
+        size: usize,
+    ) -> Result<(), AllocationError> {
+        #[cfg(feature = "red-zones")]
+        let (ptr, object_layout) = {
+            let slot = red_zone_slot(ptr.as_ptr() as usize, layout).ok_or(AllocationError::InvalidPointer)?;
+            (NonNull::new(slot as *mut u8).ok_or(AllocationError::InvalidPointer)?, layout)
+        };
+        #[cfg(not(feature = "red-zones"))]
+        let _ = layout;
+
+        let page = (ptr.as_ptr() as usize) & !(P::SIZE - 1);
+        let slab_page = P::from_address(page).as_ref().ok_or(AllocationError::InvalidPointer)?;
+        if !slab_page.is_intact() {
+            return Err(AllocationError::CorruptedPage);
+        }
+
+        #[cfg(feature = "red-zones")]
+        {
+            let idx = (ptr.as_ptr() as usize).wrapping_sub(slab_page.objects_start()) / size;
+            if idx < P::BITFIELD_WORDS * 64
+                && slab_page.bitfield().is_allocated(idx)
+                && !check_red_zones(ptr.as_ptr() as usize, size, object_layout)
+            {
+                return Err(AllocationError::RedZoneViolation);
+            }
+        }
+
+        let res = slab_page.deallocate(ptr, Layout::from_size_align_unchecked(size, 1));
+        #[cfg(feature = "generations")]
+        if res.is_ok() {
+            SCAllocator::<P>::bump_generation(ptr.as_ptr() as VAddr, size);
+        }
+        res
+    }
+
+    /// Allocates up to `n` objects described by `layout` and writes them into `out`
+    /// (see `SCAllocator::allocate_many`).
+    ///
+    /// Returns the number of objects allocated, which is less than `n`
+    /// (or `out.len()`) if the zone ran out of memory.
+    #[cfg_attr(feature = "callsites", track_caller)]
+    pub fn allocate_many(
+        &mut self,
+        layout: Layout,
+        n: usize,
+        out: &mut [NonNull<u8>],
+    ) -> Result<usize, AllocationError> {
+        let n = core::cmp::min(n, out.len());
+        if layout.size() == 0 {
+            for ptr in out[..n].iter_mut() {
+                *ptr = ZoneAllocator::dangling(layout);
+            }
+            return Ok(n);
+        }
+        if self.inject_failure() {
+            return Ok(0);
+        }
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                let mut count = self.small_slabs[idx].allocate_many(layout, n, out)?;
+                // Grab empty pages of other size classes (or the pool) until we are done
+                while count < n && self.grow_size_class(layout).is_ok() {
+                    count += self.small_slabs[idx].allocate_many(layout, n - count, &mut out[count..])?;
+                }
+                for ptr in out[..count].iter_mut() {
+                    *ptr = self.on_allocation(idx, layout, *ptr);
+                }
+                Ok(count)
+            }
+            Slab::Large(idx) => {
+                let mut count = self.big_slabs[idx].allocate_many(layout, n, out)?;
+                while count < n && self.grow_size_class(layout).is_ok() {
+                    count += self.big_slabs[idx].allocate_many(layout, n - count, &mut out[count..])?;
+                }
+                for ptr in out[..count].iter_mut() {
+                    *ptr = self.on_allocation(ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, layout, *ptr);
+                }
+                Ok(count)
+            }
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Sets the allocator that serves layouts bigger than `MAX_ALLOC_SIZE`.
+    ///
+    /// Deallocations of such layouts are routed back to `fallback` as well,
+    /// so it must not be changed while any of its allocations are still live.
+    pub fn set_fallback(&mut self, fallback: &'a dyn FallbackAllocator) {
+        self.fallback = Some(fallback);
+    }
+
+    /// Sets where objects of other heaps that are freed to this zone are forwarded to.
+    ///
+    /// Without it, such frees fail with `AllocationError::WrongHeap`.
+    pub fn set_cross_heap_free(&mut self, cross_heap: &'a dyn CrossHeapFree) {
+        self.cross_heap = Some(cross_heap);
+    }
+
+    /// Sets the inbox other heaps forward objects of this zone to,
+    /// its objects are freed at the beginning of every allocation.
+    pub fn set_remote_free_inbox(&mut self, inbox: &'a RemoteFreeInbox) {
+        self.remote_inbox = Some(inbox);
+    }
+
+    /// Connects the zone to a pool of empty pages shared with other zones.
+    ///
+    /// The zone keeps at most `max_empty_pages` empty pages, the surplus is moved to `pool`
+    /// as objects are freed. Allocations that can't be served with the pages of the zone
+    /// take a page from `pool` before they fail with `AllocationError::OutOfMemory`.
+    pub fn set_empty_page_pool(&mut self, pool: &'a EmptyPagePool<'a>, max_empty_pages: usize) {
+        self.page_pools = core::slice::from_ref(pool);
+        self.pool_threshold = max_empty_pages;
+    }
+
+    /// Sets where the surplus empty pages of the zone go.
+    ///
+    /// Once the zone has more than `max_empty_pages` empty pages (or more than
+    /// `max_empty_pages` empty 32 KiB slabs), `deallocate` hands up to `max_pages_per_call`
+    /// of them to `reclaimer`, so they don't have to be polled with `retrieve_empty_page`.
+    /// Bootstrap pages are never reclaimed.
+    ///
+    /// With an `EmptyPagePool`, the surplus goes to the pool first.
+    pub fn set_page_reclaimer(&mut self, reclaimer: &'a dyn PageReclaimer, max_empty_pages: usize, max_pages_per_call: usize) {
+        self.reclaimer = Some(reclaimer);
+        self.reclaim_threshold = max_empty_pages;
+        self.reclaim_batch = max_pages_per_call;
+    }
+
+    /// Hands surplus empty pages to the reclaimer (see `set_page_reclaimer`).
+    fn reclaim_surplus(&mut self) {
+        if let Some(reclaimer) = self.reclaimer {
+            for _ in 0..self.reclaim_batch {
+                let (mp, large) = match self.take_any_empty_page(self.reclaim_threshold, false) {
+                    Some(page) => page,
+                    None => break,
+                };
+                if let Some(mp) = self.page_leaves(mp, large) {
+                    reclaimer.reclaim(mp);
+                }
+            }
+        }
+    }
+
+    /// Hands empty pages to the reclaimer (see `set_page_reclaimer`) until the pages
+    /// of the zone take up at most `target_bytes` (see `page_bytes`), e.g., under memory pressure.
+    ///
+    /// Empty 32 KiB slabs go first. Returns the number of bytes released, which falls
+    /// short of the target if the zone runs out of empty pages (or has no reclaimer).
+    /// With the page quarantine enabled (see `set_page_quarantine`), released pages may reach
+    /// the reclaimer only after the next `maintain` cycle.
+    pub fn shrink(&mut self, target_bytes: usize) -> usize {
+        let reclaimer = match self.reclaimer {
+            Some(reclaimer) => reclaimer,
+            None => return 0,
+        };
+        let mut released = 0;
+        while self.page_bytes() > target_bytes {
+            let (mp, large) = match self.take_any_empty_page(0, true) {
+                Some(page) => page,
+                None => break,
+            };
+            released += mp.size_in_bytes();
+            if let Some(mp) = self.page_leaves(mp, large) {
+                reclaimer.reclaim(mp);
+            }
+        }
+        released
+    }
+
+    /// Releases the empty pages that sat unused for more than `max_idle_ticks` ticks of `clock`
+    /// when `maintain` is called.
+    ///
+    /// The pages go to the reclaimer (see `set_page_reclaimer`), without one they are kept.
+    pub fn set_idle_decay(&mut self, clock: &'a dyn ClockSource, max_idle_ticks: u64) {
+        self.clock = Some(clock);
+        self.max_idle_ticks = max_idle_ticks;
+        self.decay_start = clock.now();
+        self.start_decay_period();
+    }
+
+    /// Starts a new decay period for the empty pages of every size class.
+    fn start_decay_period(&mut self) {
+        for slab in self.small_slabs.iter_mut() {
+            slab.empty_slabs.reset_low_water();
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.empty_slabs.reset_low_water();
+        }
+    }
+
+    /// Periodic housekeeping of the zone, meant to be called by a background kernel task
+    /// (e.g., once per timer tick), so the deferred work doesn't have to happen on the allocation path.
+    ///
+    /// In this order, it
+    ///  * frees the objects other heaps handed over (see `drain_remote_frees`),
+    ///  * moves pages to the right lists after unlocked frees (see `rebalance`),
+    ///  * updates the allocation rates of all size classes (see `suggest_refill`)
+    ///    and redistributes the empty pages (see `rebalance_classes`),
+    ///  * hands all empty pages beyond the threshold of the reclaimer to it (see `set_page_reclaimer`),
+    ///    the pages quarantined in the previous cycle first (see `set_page_quarantine`),
+    ///  * and releases the empty pages that sat unused for too long (see `set_idle_decay`).
+    pub fn maintain(&mut self) -> MaintenanceReport {
+        self.page_quarantine.tick();
+        let mut report = MaintenanceReport {
+            remote_frees: self.drain_remote_frees(),
+            pages_rebalanced: self.rebalance(),
+            pages_redistributed: self.rebalance_classes(),
+            bytes_released: 0,
+        };
+        // `rebalance_classes` only updates the rates of the base size classes
+        for slab in self.big_slabs.iter_mut() {
+            slab.update_allocation_rate();
+        }
+        if let Some(reclaimer) = self.reclaimer {
+            for large in [false, true] {
+                while let Some(mp) = self.page_quarantine.pop(large, false) {
+                    report.bytes_released += mp.size_in_bytes();
+                    reclaimer.reclaim(mp);
+                }
+            }
+            while let Some((mp, large)) = self.take_any_empty_page(self.reclaim_threshold, false) {
+                if let Some(mp) = self.page_leaves(mp, large) {
+                    report.bytes_released += mp.size_in_bytes();
+                    reclaimer.reclaim(mp);
+                }
+            }
+        }
+        report.bytes_released += self.decay_idle_pages();
+        report
+    }
+
+    /// Once `max_idle_ticks` passed since the last decay (see `set_idle_decay`), every size class
+    /// hands the empty pages it didn't need during that time to the reclaimer: the fewest empty pages
+    /// the class had since the last decay. Which pages go is up to the reuse policy, only their number is tracked.
+    ///
+    /// Returns the number of bytes released.
+    fn decay_idle_pages(&mut self) -> usize {
+        let clock = match self.clock {
+            Some(clock) => clock,
+            None => return 0,
+        };
+        let now = clock.now();
+        if now.saturating_sub(self.decay_start) < self.max_idle_ticks {
+            return 0;
+        }
+        self.decay_start = now;
+
+        let mut released = 0;
+        if let Some(reclaimer) = self.reclaimer {
+            for idx in 0..ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+                for _ in 0..self.small_slabs[idx].empty_slabs.low_water() {
+                    match self.release_empty_page(idx) {
+                        Some(mp) => {
+                            released += mp.size_in_bytes();
+                            if let Some(mp) = self.page_leaves(mp, false) {
+                                reclaimer.reclaim(mp);
+                            }
+                        }
+                        None => break,
+                    }
+                }
+            }
+            for idx in 0..ZoneAllocator::MAX_LARGE_SIZE_CLASSES {
+                for _ in 0..self.big_slabs[idx].empty_slabs.low_water() {
+                    match self.release_empty_large_page(idx) {
+                        Some(mp) => {
+                            released += mp.size_in_bytes();
+                            if let Some(mp) = self.page_leaves(mp, true) {
+                                reclaimer.reclaim(mp);
+                            }
+                        }
+                        None => break,
+                    }
+                }
+            }
+        }
+        self.start_decay_period();
+        released
+    }
+
+    /// Places the zone on the NUMA node `node` (see `SCAllocator::set_local_node`).
+    ///
+    /// `refill` tags pages with this node, the pages of other nodes are added with `refill_on_node`.
+    /// New pages are started from local memory first, and the allocations served from remote
+    /// pages are counted in `SCStats::remote_allocations`.
+    pub fn set_node(&mut self, node: usize) {
+        self.node = Some(node);
+        for slab in self.small_slabs.iter_mut() {
+            slab.set_local_node(Some(node));
+        }
+        for slab in self.big_slabs.iter_mut() {
+            slab.set_local_node(Some(node));
+        }
+    }
+
+    /// Returns the NUMA node of the zone, `None` if it was never set (see `set_node`).
+    pub fn node(&self) -> Option<usize> {
+        self.node
+    }
+
+    /// Like `refill`, but `mp` is handed back with `AllocationError::LimitExceeded` if the size class
+    /// serving `layout` is at its page cap or the page would exceed the budget of the zone,
+    /// so it can be given to another zone or back to the frame allocator.
+    /// Other errors are those of `refill`, `mp` is dropped then (an empty `MappedPages` is handed back).
+    pub fn try_refill(&mut self, layout: Layout, mp: MappedPages) -> Result<(), (AllocationError, MappedPages)> {
+        let at_limit = match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => self.small_slabs[idx].at_page_limit() || self.exceeds_budget(ObjectPage8k::SIZE),
+            Slab::Large(idx) => self.big_slabs[idx].at_page_limit() || self.exceeds_budget(ObjectPage32k::SIZE),
+            Slab::Unsupported => return Err((AllocationError::TooLarge, mp)),
+        };
+        if at_limit {
+            return Err((AllocationError::LimitExceeded, mp));
+        }
+        self.refill(layout, mp).map_err(|e| (e, MappedPages::empty()))
+    }
+
+    /// Adds the memory `mp` of the NUMA node `node` to the size class serving `layout` (see `refill`).
+    pub fn refill_on_node(&mut self, layout: Layout, mp: MappedPages, node: usize) -> Result<(), AllocationError> {
+        self.refill_placed(layout, mp, node, MemoryTier::Dram)
+    }
+
+    /// Adds the memory `mp` of the memory tier `tier` (on the node of the zone)
+    /// to the size class serving `layout` (see `allocate_with_tier_preference`).
+    pub fn refill_on_tier(&mut self, layout: Layout, mp: MappedPages, tier: MemoryTier) -> Result<(), AllocationError> {
+        self.refill_placed(layout, mp, self.node.unwrap_or(0), tier)
+    }
+
+    /// Adds the memory `mp` of the NUMA node `node` and the memory tier `tier`
+    /// to the size class serving `layout`.
+    pub fn refill_placed(&mut self, layout: Layout, mp: MappedPages, node: usize, tier: MemoryTier) -> Result<(), AllocationError> {
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                if self.exceeds_budget(ObjectPage8k::SIZE) {
+                    error!("Heap {} would exceed its budget of {} bytes", self.heap_id, self.budget_bytes);
+                    return Err(AllocationError::LimitExceeded);
+                }
+                let page = mp.start_address().value();
+                self.small_slabs[idx].refill_placed(mp, self.heap_id, node, tier)?;
+                self.record(TraceOp::Refill, idx, layout, page);
+                self.update_shadow(TraceOp::Refill, page, ObjectPage8k::SIZE);
+                log_event(HeapEvent::PageRefilled { heap_id: self.heap_id, object_size: self.small_slabs[idx].size(), page });
+                Ok(())
+            }
+            Slab::Large(idx) => {
+                if self.exceeds_budget(ObjectPage32k::SIZE) {
+                    error!("Heap {} would exceed its budget of {} bytes", self.heap_id, self.budget_bytes);
+                    return Err(AllocationError::LimitExceeded);
+                }
+                let page = mp.start_address().value();
+                self.big_slabs[idx].refill_placed(mp, self.heap_id, node, tier)?;
+                self.record(TraceOp::Refill, ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, layout, page);
+                self.update_shadow(TraceOp::Refill, page, ObjectPage32k::SIZE);
+                log_event(HeapEvent::PageRefilled { heap_id: self.heap_id, object_size: self.big_slabs[idx].size(), page });
+                Ok(())
+            }
+            Slab::Unsupported => Err(AllocationError::TooLarge),
+        }
+    }
+
+    /// Connects the zone to one pool of empty pages per NUMA node, shared with the other zones
+    /// (`pools[n]` holds the pages of node `n`).
+    ///
+    /// Like with `set_empty_page_pool`, the zone keeps at most `max_empty_pages` empty pages.
+    /// The surplus goes to the pool of the node of every page, and allocations take a page
+    /// from the pool of the zone's node (see `set_node`) before they try the other pools.
+    pub fn set_node_page_pools(&mut self, pools: &'a [EmptyPagePool<'a>], max_empty_pages: usize) {
+        self.page_pools = pools;
+        self.pool_threshold = max_empty_pages;
+    }
+
+    /// Moves an empty page from the pool (see `set_empty_page_pool`) to the size class serving `layout`.
+    ///
+    /// The pool only holds 8 KiB pages, so the large size classes can't be refilled from it.
+    fn refill_from_pool(&mut self, layout: Layout) -> Result<(), AllocationError> {
+        let idx = match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => idx,
+            Slab::Large(_idx) => return Err(AllocationError::OutOfMemory),
+            Slab::Unsupported => return Err(AllocationError::TooLarge),
+        };
+        if self.page_pools.is_empty() {
+            return Err(AllocationError::OutOfMemory);
+        }
+        if self.exceeds_budget(ObjectPage8k::SIZE) {
+            return Err(AllocationError::LimitExceeded);
+        }
+        // the pool of the local node first, then the others in order
+        let local = self.node.filter(|&node| node < self.page_pools.len()).unwrap_or(0);
+        let (pool, page) = core::iter::once(&self.page_pools[local])
+            .chain(self.page_pools.iter())
+            .find_map(|pool| pool.pop().map(|page| (pool, page)))
+            .ok_or(AllocationError::OutOfMemory)?;
+        self.small_slabs[idx].adopt_empty_page(page, self.heap_id).map_err(|page| {
+            pool.push(page);
+            AllocationError::LimitExceeded
+        })
+    }
+
+    /// Moves an empty page to the pool if the zone has more than it should keep.
+    fn overflow_to_pool(&mut self) {
+        if !self.page_pools.is_empty() && self.empty_pages() > self.pool_threshold {
+            let slab = self.small_slabs.iter_mut().max_by_key(|slab| slab.empty_slabs.elements);
+            if let Some(page) = slab.and_then(|slab| slab.take_empty_page()) {
+                // pages of unknown nodes go to the first pool
+                let pool = self.page_pools.get(page.node()).unwrap_or(&self.page_pools[0]);
+                pool.push(page);
+            }
+        }
+    }
+
+    /// Makes room for another allocation of `layout`, first with an empty page of
+    /// a different size class and then with a page of the pool.
+    ///
+    /// Logs a `HeapEvent::OutOfMemory` if neither has a page left.
+    fn grow_size_class(&mut self, layout: Layout) -> Result<(), AllocationError> {
+        let res = match self.exchange_pages_within_heap(layout) {
+            Err(AllocationError::OutOfMemory) => self.refill_from_pool(layout),
+            res => res,
+        };
+        if res == Err(AllocationError::OutOfMemory) {
+            log_event(HeapEvent::OutOfMemory { heap_id: self.heap_id, size: layout.size(), align: layout.align() });
+        }
+        res
+    }
+
+    /// Returns `true` if a page of `bytes` more would exceed the budget of the zone.
+    fn exceeds_budget(&self, bytes: usize) -> bool {
+        self.budget_bytes != 0 && self.page_bytes() + bytes > self.budget_bytes
+    }
+
+    /// Frees the objects other heaps handed over to this zone (see `set_remote_free_inbox`),
+    /// returns how many there were.
+    pub fn drain_remote_frees(&mut self) -> usize {
+        match self.remote_inbox {
+            Some(inbox) if !inbox.is_empty() => inbox.drain(self),
+            _ => 0,
+        }
+    }
+
+    /// Returns the id of the heap owning the page `ptr` lies in,
+    /// or `None` if the page header is corrupted (or there is no page at `ptr`).
+    ///
+    /// This reads the header of the page, so `ptr` must lie in a page of type `P` of some heap.
+    pub(crate) fn page_heap_id<'p, P: AllocablePage + 'p>(ptr: NonNull<u8>) -> Option<usize> {
+        let page = unsafe { P::from_address(ptr.as_ptr() as usize).as_ref()? };
+        if page.is_intact() {
+            Some(page.heap_id())
+        } else {
+            None
+        }
+    }
+
+    /// Checks the integrity of the whole heap (see `SCAllocator::verify`).
+    ///
+    /// Every violation is logged, the first one found is returned.
+    pub fn verify(&mut self) -> Result<(), AllocationError> {
+        let mut ret = Ok(());
+        for slab in self.small_slabs.iter_mut() {
+            ret = ret.and(slab.verify(self.heap_id));
+        }
+        for slab in self.big_slabs.iter_mut() {
+            ret = ret.and(slab.verify(self.heap_id));
+        }
+        ret
+    }
+
+    /// In debug builds, runs `verify` after every `interval` allocations and deallocations.
+    ///
+    /// An interval of 0 disables the periodic check.
+    pub fn set_verify_interval(&mut self, interval: usize) {
+        self.verify_interval = interval;
+        self.operations = 0;
+    }
+
+    /// Runs `verify` if the periodic check is due.
+    #[inline(always)]
+    fn periodic_verify(&mut self) {
+        if cfg!(debug_assertions) && self.verify_interval != 0 {
+            self.operations += 1;
+            if self.operations >= self.verify_interval {
+                self.operations = 0;
+                let _ = self.verify();
+            }
+        }
+    }
+
+    /// Returns the allocation profiler of this zone.
+    pub fn profiler(&self) -> &AllocationProfiler {
+        &self.profiler
+    }
+
+    /// Returns the allocation profiler of this zone, e.g., to change the sample rate.
+    pub fn profiler_mut(&mut self) -> &mut AllocationProfiler {
+        &mut self.profiler
+    }
+
+    /// Sets the hooks that keep the shadow memory of a sanitizer up to date
+    /// (only with the `shadow-hooks` feature).
+    ///
+    /// Only the pages added and released from now on are reported, so the hooks
+    /// should be set before the zone is first refilled. Pages that move between zones
+    /// or through an `EmptyPagePool` stay part of the heap and are not reported.
+    #[cfg(feature = "shadow-hooks")]
+    pub fn set_shadow_hooks(&mut self, hooks: &'a dyn ShadowHooks) {
+        self.shadow = Some(hooks);
+    }
+
+    /// Tags every allocation with a random MTE tag (only with the `mte` feature).
+    ///
+    /// Freed objects get tag 0, which is never handed out, so the hardware catches accesses
+    /// through stale pointers and overflows past the (granule rounded) end of an object.
+    /// Freeing an object with a pointer that doesn't carry its tag fails with
+    /// `AllocationError::DoubleFree`.
+    ///
+    /// The pages of the zone must be mapped as Normal Tagged memory. Objects of size classes
+    /// that aren't a multiple of `MTE_GRANULE` (the 8 byte class) stay untagged, as do all
+    /// objects with the `red-zones` feature. Tagged objects can be freed with a tagged or
+    /// untagged pointer, so tagging can be turned off while objects are live.
+    #[cfg(feature = "mte")]
+    pub fn set_memory_tagging(&mut self, enabled: bool) {
+        self.memory_tagging = enabled;
+    }
+
+    /// Returns the failure injector of this zone, e.g., to fail the next allocations.
+    #[cfg(feature = "fail-injection")]
+    pub fn failure_injector_mut(&mut self) -> &mut FailureInjector {
+        &mut self.failures
+    }
+
+    /// Returns `true` if the failure injector wants the current allocation to fail.
+    #[inline(always)]
+    fn inject_failure(&mut self) -> bool {
+        #[cfg(feature = "fail-injection")]
+        {
+            if self.failures.should_fail() {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Starts recording the operations of the zone into `buffer` (see `TraceRecorder`).
+    ///
+    /// To replay the trace against a fresh zone with a `TraceReplayer`, recording has to start
+    /// before the zone got its first page and `buffer` must be big enough to hold the whole trace.
+    pub fn start_trace(&mut self, buffer: &'a mut [TraceEvent]) {
+        self.trace = Some(TraceRecorder::new(buffer));
+    }
+
+    /// Stops recording and returns the recorder with the trace.
+    pub fn stop_trace(&mut self) -> Option<TraceRecorder<'a>> {
+        self.trace.take()
+    }
+
+    /// Returns the recorder of the running trace.
+    pub fn trace(&self) -> Option<&TraceRecorder<'a>> {
+        self.trace.as_ref()
+    }
+
+    /// Records an operation on the object or page at `addr` if a trace is running.
+    #[inline(always)]
+    fn record(&mut self, op: TraceOp, size_class: usize, layout: Layout, addr: usize) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record(TraceEvent::new(op, size_class, layout, addr));
+        }
+    }
+
+    /// Accounts an allocation of `layout` in `size_class` and records it if a trace is running.
+    ///
+    /// Returns the pointer to hand out, tagged if memory tagging is enabled (see `set_memory_tagging`).
+    #[inline(always)]
+    fn on_allocation(&mut self, size_class: usize, layout: Layout, ptr: NonNull<u8>) -> NonNull<u8> {
+        let addr = ptr.as_ptr() as usize;
+        if !cfg!(feature = "minimal") {
+            self.requested_bytes[size_class] += layout.size();
+        }
+        self.record(TraceOp::Allocate, size_class, layout, addr);
+        self.update_shadow(TraceOp::Allocate, addr, layout.size());
+        #[cfg(feature = "mte")]
+        if self.memory_tagging
+            && !cfg!(feature = "red-zones")
+            && ZoneAllocator::class_geometry(size_class).0.is_multiple_of(MTE_GRANULE)
+            && addr.is_multiple_of(MTE_GRANULE)
+        {
+            // Safety: the object was just allocated and its slot is a multiple of the granule
+            return unsafe { mte::tag_object(ptr, layout.size()) };
+        }
+        ptr
+    }
+
+    /// Tells the shadow hooks (see `set_shadow_hooks`) about `op` on the `size` bytes at `addr`,
+    /// compiled out without the `shadow-hooks` feature.
+    #[inline(always)]
+    #[allow(unused_variables)]
+    fn update_shadow(&self, op: TraceOp, addr: VAddr, size: usize) {
+        #[cfg(feature = "shadow-hooks")]
+        if let Some(hooks) = self.shadow {
+            match op {
+                TraceOp::Allocate => hooks.on_alloc(NonNull::new(addr as *mut u8).unwrap(), size),
+                TraceOp::Deallocate => hooks.on_free(NonNull::new(addr as *mut u8).unwrap(), size),
+                TraceOp::Refill => hooks.on_page_added(addr, size),
+                TraceOp::ReleasePage => hooks.on_page_removed(addr, size),
+            }
+        }
+    }
+
+    /// Tells `listener` when the live bytes of the zone (see `utilization`) rise to `high_bytes`
+    /// and when they fall back to `low_bytes` afterwards (`low_bytes` is capped at `high_bytes`).
+    ///
+    /// Measuring the live bytes looks into the partial pages of every size class, so it's
+    /// only done every `interval` allocations and deallocations (0 checks on every one, like 1).
+    /// The listener is told only once per crossing, not on every check above the watermark.
+    pub fn set_watermarks(&mut self, listener: &'a dyn WatermarkListener, low_bytes: usize, high_bytes: usize, interval: usize) {
+        self.watermark_listener = Some(listener);
+        self.low_watermark = core::cmp::min(low_bytes, high_bytes);
+        self.high_watermark = high_bytes;
+        self.watermark_interval = core::cmp::max(interval, 1);
+        self.watermark_operations = 0;
+        self.above_high_watermark = false;
+    }
+
+    /// Checks the watermarks if it's due (see `set_watermarks`).
+    #[inline(always)]
+    fn check_watermarks(&mut self) {
+        if let Some(listener) = self.watermark_listener {
+            self.watermark_operations += 1;
+            if self.watermark_operations < self.watermark_interval {
+                return;
+            }
+            self.watermark_operations = 0;
+            let utilization = self.utilization();
+            if !self.above_high_watermark && utilization.live_bytes >= self.high_watermark {
+                self.above_high_watermark = true;
+                listener.crossed(Watermark::High, utilization);
+            } else if self.above_high_watermark && utilization.live_bytes <= self.low_watermark {
+                self.above_high_watermark = false;
+                listener.crossed(Watermark::Low, utilization);
+            }
+        }
+    }
+
+    /// Reads the latency clock before an operation (see `set_latency_clock`),
+    /// `None` if latencies aren't measured. Compiled out without the `latency-stats` feature.
+    #[inline(always)]
+    fn latency_start(&self) -> Option<u64> {
+        #[cfg(feature = "latency-stats")]
+        return self.latency_clock.map(|clock| clock.now());
+        #[cfg(not(feature = "latency-stats"))]
+        None
+    }
+
+    /// Counts the latency of an operation `op` of the size class `class` that started at `start`.
+    #[inline(always)]
+    #[allow(unused_variables)]
+    fn record_latency(&mut self, op: TraceOp, class: usize, start: Option<u64>) {
+        #[cfg(feature = "latency-stats")]
+        if let (Some(start), Some(clock)) = (start, self.latency_clock) {
+            self.latency[class].record(op, clock.now().saturating_sub(start));
+        }
+    }
+
+    /// Measures the latency of every allocation and deallocation of a size class with `clock`
+    /// (e.g., `TimestampCounter`), see `SizeClassStats::latency`.
+    ///
+    /// The clock is read twice per operation, so it should be cheap (like a cycle counter).
+    #[cfg(feature = "latency-stats")]
+    pub fn set_latency_clock(&mut self, clock: &'a dyn ClockSource) {
+        self.latency_clock = Some(clock);
+    }
+
+    /// Clears the latency histograms of all size classes (see `set_latency_clock`).
+    #[cfg(feature = "latency-stats")]
+    pub fn reset_latency_stats(&mut self) {
+        self.latency = [LatencyHistogram::new(); ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES];
+    }
+
+    /// Adds every size class to `registry`, classes without a name are named
+    /// after `CLASS_NAMES` first.
+    ///
+    /// Stops at the first class that doesn't fit into the registry.
+    ///
+    /// # Safety
+    /// See `SlabRegistry::register`, the zone must not move until `unregister_slabs`.
+    pub unsafe fn register_slabs(&mut self, registry: &SlabRegistry) -> Result<(), AllocationError> {
+        for (idx, slab) in self.small_slabs.iter_mut().enumerate() {
+            if slab.name().is_empty() {
+                slab.set_name(ZoneAllocator::CLASS_NAMES[idx]);
+            }
+            registry.register(slab)?;
+        }
+        for (idx, slab) in self.big_slabs.iter_mut().enumerate() {
+            if slab.name().is_empty() {
+                slab.set_name(ZoneAllocator::CLASS_NAMES[ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx]);
+            }
+            registry.register(slab)?;
+        }
+        Ok(())
+    }
+
+    /// Returns how many pages the heap should refill every size class with, so the class
+    /// can serve another period of allocations at its current rate without running out of memory.
+    ///
+    /// The rates are averaged over the past periods, a period ends with every call of `maintain`
+    /// (see `SCAllocator::update_allocation_rate`). The empty pages a class already has are
+    /// subtracted. The layouts are the ones to pass to `refill`, the large size classes
+    /// (served from 32 KiB slabs) follow the base size classes.
+    pub fn suggest_refill(&self) -> [(Layout, usize); ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES] {
+        let mut suggestions = [(Layout::new::<u8>(), 0); ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES];
+        for (class, suggestion) in suggestions.iter_mut().enumerate() {
+            let (object_size, _page_size) = ZoneAllocator::class_geometry(class);
+            let (rate, obj_per_page, empty_pages) = if class < ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+                let slab = &self.small_slabs[class];
+                (slab.allocation_rate(), slab.obj_per_page, slab.empty_slabs.elements)
+            } else {
+                let slab = &self.big_slabs[class - ZoneAllocator::MAX_BASE_SIZE_CLASSES];
+                (slab.allocation_rate(), slab.obj_per_page, slab.empty_slabs.elements)
+            };
+            let needed = (rate + obj_per_page - 1) / core::cmp::max(obj_per_page, 1);
+            *suggestion = (
+                Layout::from_size_align(object_size, 1).unwrap(),
+                needed.saturating_sub(empty_pages),
+            );
+        }
+        suggestions
+    }
+
+    /// Removes the size classes of the zone from `registry` (see `SlabRegistry::unregister`).
+    pub fn unregister_slabs(&self, registry: &SlabRegistry) {
+        for slab in self.small_slabs.iter() {
+            registry.unregister(slab);
+        }
+        for slab in self.big_slabs.iter() {
+            registry.unregister(slab);
+        }
+    }
+
+    /// Returns what every size class costs and serves, the large size classes follow the base size classes.
+    ///
+    /// Allocations of pruned classes are accounted to the class that served them.
+    pub fn size_class_stats(&self) -> [SizeClassStats; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES] {
+        let mut stats = [SizeClassStats {
+            object_size: 0,
+            pruned: false,
+            allocations: 0,
+            requested_bytes: 0,
+            pages: 0,
+            page_bytes: 0,
+            page_transitions: 0,
+            #[cfg(feature = "latency-stats")]
+            latency: LatencyHistogram::new(),
+        }; ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES];
+        for (class, stat) in stats.iter_mut().enumerate() {
+            let (object_size, page_size) = ZoneAllocator::class_geometry(class);
+            let (slab_stats, pages) = if class < ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+                let slab = &self.small_slabs[class];
+                (slab.stats(), slab.pages())
+            } else {
+                let slab = &self.big_slabs[class - ZoneAllocator::MAX_BASE_SIZE_CLASSES];
+                (slab.stats(), slab.pages())
+            };
+            *stat = SizeClassStats {
+                object_size,
+                pruned: class < ZoneAllocator::MAX_BASE_SIZE_CLASSES && ZoneAllocator::PRUNED_BASE_CLASSES[class],
+                allocations: slab_stats.allocations,
+                requested_bytes: self.requested_bytes[class],
+                pages,
+                page_bytes: pages * page_size,
+                page_transitions: slab_stats.page_transitions(),
+                #[cfg(feature = "latency-stats")]
+                latency: self.latency[class],
+            };
+        }
+        stats
+    }
+
+    /// Writes the utilization and the size class statistics of the zone into `buf` in the
+    /// versioned little-endian layout described in the `export` module, e.g., for the shell
+    /// or tools outside of the kernel.
+    ///
+    /// Returns the number of bytes written, 0 if `buf` is shorter than `STATS_EXPORT_SIZE`.
+    pub fn stats_to_bytes(&self, buf: &mut [u8]) -> usize {
+        write_stats(buf, self.utilization(), &self.size_class_stats())
+    }
+
+    /// Bytes of the pages the zone owns in every memory tier (indexed by `MemoryTier as usize`).
+    pub fn tier_footprint(&self) -> [usize; MemoryTier::COUNT] {
+        let mut footprint = [0; MemoryTier::COUNT];
+        for page in self.iter_pages() {
+            footprint[page.tier as usize] += page.page_size;
+        }
+        footprint
+    }
+
+    /// Allocates a block of memory described by `layout` (see `allocate`) from the pages
+    /// of objects with the lifetime `hint` (see `SCAllocator::allocate_with_hint`).
+    ///
+    /// Objects that outlive most others (e.g., per-task tables) should be allocated with
+    /// `AllocHint::LongLived`, so the pages of short-lived objects can empty out and be returned.
+    /// They are freed with `deallocate` like any other object.
+    #[track_caller]
+    pub fn allocate_with_hint(&mut self, layout: Layout, hint: AllocHint) -> Result<NonNull<u8>, AllocationError> {
+        self.allocate_placed(layout, hint, None)
+    }
+
+    /// Allocates a block of memory described by `layout` (see `allocate`) from a page of the
+    /// memory tier `tier` if the size class has one with room (see `refill_on_tier`).
+    ///
+    /// Lets latency-sensitive kernel objects stay in DRAM and bulk data go to a slower tier.
+    /// Falls back to pages of the other tiers rather than failing.
+    #[track_caller]
+    pub fn allocate_with_tier_preference(&mut self, layout: Layout, tier: MemoryTier) -> Result<NonNull<u8>, AllocationError> {
+        self.allocate_placed(layout, AllocHint::ShortLived, Some(tier))
+    }
+
+    /// Allocates from the pages of `hint`, preferring the memory tier `tier` (if any).
+    #[track_caller]
+    fn allocate_placed(&mut self, layout: Layout, hint: AllocHint, tier: Option<MemoryTier>) -> Result<NonNull<u8>, AllocationError> {
+        if layout.size() == 0 {
+            return Ok(ZoneAllocator::dangling(layout));
+        }
+        if self.inject_failure() {
+            return Err(AllocationError::OutOfMemory);
+        }
+
+        match ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)) {
+            Slab::Base(idx) => {
+                let start = self.latency_start();
+                self.drain_remote_frees();
+                self.periodic_verify();
+                let ptr = match self.small_slabs[idx].allocate_placed(layout, hint, tier) {
+                    Err(AllocationError::OutOfMemory) => {
+                        self.grow_size_class(layout)?;
+                        self.small_slabs[idx].allocate_placed(layout, hint, tier)
+                    }
+                    res => res,
+                }?;
+                // Only allocations that succeeded are sampled
+                self.profiler.on_allocation(layout.size(), self.small_slabs[idx].size(), Location::caller());
+                let ptr = self.on_allocation(idx, layout, ptr);
+                self.record_latency(TraceOp::Allocate, idx, start);
+                self.check_watermarks();
+                Ok(ptr)
+            }
+            Slab::Large(idx) => {
+                let start = self.latency_start();
+                self.drain_remote_frees();
+                self.periodic_verify();
+                let ptr = match self.big_slabs[idx].allocate_placed(layout, hint, tier) {
+                    Err(AllocationError::OutOfMemory) => {
+                        self.grow_size_class(layout)?;
+                        self.big_slabs[idx].allocate_placed(layout, hint, tier)
+                    }
+                    res => res,
+                }?;
+                self.profiler.on_allocation(layout.size(), self.big_slabs[idx].size(), Location::caller());
+                let ptr = self.on_allocation(ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, layout, ptr);
+                self.record_latency(TraceOp::Allocate, ZoneAllocator::MAX_BASE_SIZE_CLASSES + idx, start);
+                self.check_watermarks();
+                Ok(ptr)
+            }
+            Slab::Unsupported => match self.fallback {
+                Some(fallback) => fallback.allocate(layout),
+                None => Err(AllocationError::TooLarge),
+            },
+        }
+    }
+
+    /// Allocates a block of memory described by `layout` (see `allocate`) on behalf of the caller
+    /// described by `ctx`.
+    ///
+    /// Within an interrupt handler the allocation is done in bounded time (see `try_allocate`),
+    /// otherwise it is placed according to the hint and memory tier of `ctx`. Layouts bigger than
+    /// `MAX_ALLOC_SIZE` are passed on to the fallback allocator along with `ctx` (also within an
+    /// interrupt handler, the fallback sees the interrupt depth of `ctx`). The events a running
+    /// trace records for the allocation carry the core and task of `ctx`.
+    /// `ctx` is told about the allocation once it succeeded.
+    #[track_caller]
+    pub fn allocate_in<C: AllocationContext>(&mut self, layout: Layout, ctx: &C) -> Result<NonNull<u8>, AllocationError> {
+        self.set_trace_context(ctx.core_id(), ctx.task_id());
+        let res = match (ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)), self.fallback) {
+            (Slab::Unsupported, Some(fallback)) if layout.size() > 0 => fallback.allocate_in(layout, ctx),
+            _ if ctx.interrupt_depth() > 0 => self.try_allocate(layout),
+            _ => self.allocate_placed(layout, ctx.alloc_hint(), ctx.memory_tier()),
+        };
+        self.set_trace_context(None, None);
+        let ptr = res?;
+        ctx.on_allocate(ptr, layout);
+        Ok(ptr)
+    }
+
+    /// Deallocates a block of memory returned by `allocate_in` (or `allocate`) on behalf of
+    /// the caller described by `ctx` (see `deallocate`), `ctx` is told about it once it succeeded.
+    /// Like in `allocate_in`, the fallback allocator and a running trace get the context.
+    pub fn deallocate_in<C: AllocationContext>(&mut self, ptr: NonNull<u8>, layout: Layout, ctx: &C) -> Result<(), AllocationError> {
+        self.set_trace_context(ctx.core_id(), ctx.task_id());
+        let res = match (ZoneAllocator::get_slab(ZoneAllocator::slot_size(layout)), self.fallback) {
+            (Slab::Unsupported, Some(fallback)) if layout.size() > 0 => fallback.deallocate_in(ptr, layout, ctx),
+            _ => self.deallocate_ext(ptr, layout).map(|_| ()),
+        };
+        self.set_trace_context(None, None);
+        res?;
+        ctx.on_deallocate(ptr, layout);
+        Ok(())
+    }
+
+    /// Sets the core and task the events of a running trace are recorded for.
+    fn set_trace_context(&mut self, core_id: Option<usize>, task_id: Option<usize>) {
+        if let Some(trace) = self.trace.as_mut() {
+            trace.set_context(core_id, task_id);
+        }
+    }
+
+    /// Allocates a block of memory described by `layout` and accounts it to `tag`
+    /// (e.g., the id of the task or crate that owns the object).
+    #[track_caller]
+    pub fn allocate_tagged(&mut self, layout: Layout, tag: u64) -> Result<NonNull<u8>, AllocationError> {
+        let ptr = crate::Allocator::allocate(self, layout)?;
+        self.tags.on_allocation(tag, layout.size());
+        Ok(ptr)
+    }
+
+    /// Deallocates a block of memory returned by `allocate_tagged`,
+    /// `layout` and `tag` must be the ones it was allocated with.
+    pub fn deallocate_tagged(&mut self, ptr: NonNull<u8>, layout: Layout, tag: u64) -> Result<(), AllocationError> {
+        crate::Allocator::deallocate(self, ptr, layout)?;
+        self.tags.on_deallocation(tag, layout.size());
+        Ok(())
+    }
+
+    /// Returns the live memory per tag of allocations made with `allocate_tagged`.
+    pub fn tag_stats(&self) -> &TagAccounting {
+        &self.tags
+    }
+
+    /// The total number of empty pages in this zone allocator
+    pub fn empty_pages(&self) -> usize {
+        let mut empty_pages = 0;
+        for sca in &self.small_slabs {
+            empty_pages += sca.empty_slabs.elements;
+        }
+        empty_pages
+    }
+
+    /// The total number of empty 32 KiB slabs of the large size classes
+    pub fn empty_large_pages(&self) -> usize {
+        self.big_slabs.iter().map(|sca| sca.empty_slabs.elements).sum()
+    }
+}
+
+/// Iterator over the pages of a `ZoneAllocator`, see `ZoneAllocator::iter_pages`.
+pub struct ZonePages<'z, 'a> {
+    zone: &'z ZoneAllocator<'a>,
+    /// Size class of the current page.
+    class: usize,
+    /// Number of lists of the class that were started.
+    lists: usize,
+    /// The next page of the current list (0 once the list is done).
+    cursor: VAddr,
+}
+
+impl<'z, 'a> ZonePages<'z, 'a> {
+    /// Returns the next page of `slab`, moving on to its next list when the current one is done.
+    fn next_in<P: AllocablePage>(slab: &SCAllocator<'a, P>, lists: &mut usize, cursor: &mut VAddr) -> Option<PageInfo> {
+        while *cursor == 0 {
+            if *lists == PageState::ALL.len() {
+                return None;
+            }
+            *cursor = slab.page_list(PageState::ALL[*lists]).head_ptr() as VAddr;
+            *lists += 1;
+        }
+        let page = *cursor as *mut P;
+        // The zone is borrowed, so the lists stay as they are
+        *cursor = unsafe { PageList::next_ptr(page) } as VAddr;
+        Some(slab.page_info(unsafe { &*page }, PageState::ALL[*lists - 1]))
+    }
+}
+
+impl<'z, 'a> Iterator for ZonePages<'z, 'a> {
+    type Item = PageInfo;
+
+    fn next(&mut self) -> Option<PageInfo> {
+        while self.class < ZoneAllocator::MAX_BASE_SIZE_CLASSES + ZoneAllocator::MAX_LARGE_SIZE_CLASSES {
+            let info = if self.class < ZoneAllocator::MAX_BASE_SIZE_CLASSES {
+                Self::next_in(&self.zone.small_slabs[self.class], &mut self.lists, &mut self.cursor)
+            } else {
+                let idx = self.class - ZoneAllocator::MAX_BASE_SIZE_CLASSES;
+                Self::next_in(&self.zone.big_slabs[idx], &mut self.lists, &mut self.cursor)
+            };
+            if info.is_some() {
+                return info;
+            }
+            self.class += 1;
+            self.lists = 0;
+        }
+        None
+    }
+}
+
+/// A `ZoneAllocator` behind a single lock of the embedder's choice.
+impl<'a, R: lock_api::RawMutex> SharedAllocator for lock_api::Mutex<R, ZoneAllocator<'a>> {
+    #[track_caller]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        crate::Allocator::allocate(&mut *self.lock(), layout)
+    }
+
+    fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        crate::Allocator::deallocate(&mut *self.lock(), ptr, layout)
+    }
+}
+
+unsafe impl<'a> crate::Allocator<'a> for ZoneAllocator<'a> {
+    /// Allocate a pointer to a block of memory described by `layout`.
+    ///
+    /// A zero-sized `layout` doesn't consume any memory, it returns a dangling
+    /// pointer aligned to `layout.align()`. Layouts bigger than `MAX_ALLOC_SIZE`
+    /// are passed on to the fallback allocator if one is set (see `set_fallback`),
+    /// otherwise they fail with `AllocationError::TooLarge` so the caller can fall
+    /// back to a different allocator.
+    ///
+    /// With the `fail-injection` feature, allocations fail with `AllocationError::OutOfMemory`
+    /// whenever the failure injector says so (see `failure_injector_mut`).
+    #[track_caller]
+    fn allocate(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocationError> {
+        self.allocate_with_hint(layout, AllocHint::ShortLived)
+    }
+
+    /// Deallocates a pointer to a block of memory, which was
+    /// previously allocated by `allocate`.
+    ///
+    /// # Arguments
+    ///  * `ptr` - Address of the memory location to free.
+    ///  * `layout` - Memory layout of the block pointed to by `ptr`.
+    ///
+    /// Deallocating a zero-sized `layout` is a no-op.
+    ///
+    /// Objects of a different heap (according to their page's heap id) are passed on
+    /// to the `CrossHeapFree` if one is set (see `set_cross_heap_free`),
+    /// otherwise they fail with `AllocationError::WrongHeap`.
+    /// The heap id isn't checked with the `minimal` feature.
+    fn deallocate(&mut self, ptr: NonNull<u8>, layout: Layout) -> Result<(), AllocationError> {
+        self.deallocate_ext(ptr, layout).map(|_| ())
+    }
+
+    /// Refills the SCAllocator for a given Layout with an ObjectPage.
+    ///
+    /// Fails with `AllocationError::LimitExceeded` if the size class is at its page cap
+    /// or the page would exceed the budget of the zone (`mp` is dropped in that case,
+    /// use `try_refill` to get it back).
+    ///
+    /// # Safety
+    /// ObjectPage needs to be emtpy etc.
+    fn refill(
+        &mut self,
+        layout: Layout,
+        mp: MappedPages,
+    ) -> Result<(), AllocationError> {
+        self.refill_on_node(layout, mp, self.node.unwrap_or(0))
     }
 
 